@@ -0,0 +1,77 @@
+// With --features embedded the directory in HTTPSERVER_EMBED is packed into the binary
+// for --embedded: every file with its mtime, an ETag from a hash of its bytes and a
+// gzip copy when that came out smaller. Files are sorted by path so they can be looked
+// up by binary search
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+
+fn collect(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let entries = fs::read_dir(dir).unwrap_or_else(|err| panic!("failed to read {} by {err}", dir.display()));
+    for entry in entries {
+        let entry = entry.unwrap_or_else(|err| panic!("failed to read {} by {err}", dir.display()));
+        let path = entry.path();
+        let name = entry.file_name().into_string().unwrap_or_else(|name| panic!("{name:?} is no utf-8, it couldn't be asked for"));
+        let meta = fs::metadata(&path).unwrap_or_else(|err| panic!("failed to stat {} by {err}", path.display()));
+        match meta.is_dir() {
+            true => collect(&path, &format!("{prefix}/{name}"), files),
+            false => {
+                println!("cargo:rerun-if-changed={}", path.display());
+                files.push((format!("{prefix}/{name}"), path));
+            }
+        }
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).expect("writing to memory");
+    encoder.finish().expect("writing to memory")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=HTTPSERVER_EMBED");
+    if env::var_os("CARGO_FEATURE_EMBEDDED").is_none() {
+        return;
+    }
+    let dir = env::var("HTTPSERVER_EMBED").expect("the embedded feature needs HTTPSERVER_EMBED=DIR, the directory to pack");
+    let dir = fs::canonicalize(&dir).unwrap_or_else(|err| panic!("can't embed {dir}, {err}"));
+    let out = PathBuf::from(env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let gzipped = out.join("embedded");
+    fs::create_dir_all(&gzipped).expect("creating a directory in OUT_DIR");
+
+    let mut files = Vec::new();
+    collect(&dir, "", &mut files);
+    files.sort();
+    let mut code = String::from("static FILES: &[File] = &[\n");
+    for (i, (path, file)) in files.iter().enumerate() {
+        let data = fs::read(file).unwrap_or_else(|err| panic!("failed to read {} by {err}", file.display()));
+        let modified = fs::metadata(file).and_then(|meta| meta.modified()).ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let hash = Sha256::digest(&data);
+        let etag: String = hash[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+        // Worth it only when at least a tenth is saved, the rest is compressed already
+        let compressed = gzip(&data);
+        let gzip = match compressed.len() < data.len() - data.len() / 10 {
+            true => {
+                let copy = gzipped.join(format!("{i}.gz"));
+                fs::write(&copy, compressed).expect("writing to OUT_DIR");
+                format!("Some(include_bytes!({:?}))", copy.display().to_string())
+            }
+            false => String::from("None"),
+        };
+        code.push_str(&format!(
+            "    File {{ path: {path:?}, data: include_bytes!({:?}), gzip: {gzip}, modified: {modified}, etag: \"\\\"{etag}\\\"\" }},\n",
+            file.display().to_string(),
+        ));
+    }
+    code.push_str("];\n");
+    fs::write(out.join("embedded.rs"), code).expect("writing to OUT_DIR");
+}
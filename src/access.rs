@@ -0,0 +1,232 @@
+// --access-log: a line for every request in the Common Log Format, or the combined
+// one that adds the Referer and User-Agent. With --log-format json it is a record
+// like those of the other logs. Written and flushed once the request is done,
+// however it ended
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+use httpserver::date;
+use crate::log::{self, Level, Record};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Common,
+    Combined,
+}
+
+// What the line says about a request, filled in as far as handle_request got
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: String,  // Of the request, the one it ended up with
+    pub client: String,  // Address of the peer
+    pub started: Instant,  // Once the request line was there
+    pub line: String,  // As it was sent, even one too broken to parse
+    pub method: Option<String>,
+    pub path: Option<String>,  // Decoded and normalized
+    pub vhost: Option<String>,
+    pub user: Option<String>,  // Who authenticated with Basic auth
+    pub referer: Option<String>,
+    pub agent: Option<String>,
+    pub err: Option<String>,  // Why it ended without a whole response
+    pub logged: bool,  // By the middleware, once the response was written
+}
+
+impl Entry {
+    pub fn new(id: &str, client: &str) -> Self {
+        Entry {
+            id: String::from(id),
+            client: String::from(client),
+            started: Instant::now(),
+            line: String::new(),
+            method: None,
+            path: None,
+            vhost: None,
+            user: None,
+            referer: None,
+            agent: None,
+            err: None,
+            logged: false,
+        }
+    }
+}
+
+// Opened at startup, before any chroot, and again on SIGUSR1
+static OUTPUT: Mutex<Option<(PathBuf, Box<dyn Write + Send>)>> = Mutex::new(None);
+
+// "-" is stdout, anything else a file appended to
+pub fn open(target: &Path) -> io::Result<()> {
+    // Never written to, the lines go through log::output
+    let output: Box<dyn Write + Send> = match target.to_str() {
+        Some("-") => Box::new(io::sink()),
+        _ => Box::new(std::fs::OpenOptions::new().create(true).append(true).open(target)?),
+    };
+    *OUTPUT.lock().unwrap() = Some((target.to_path_buf(), output));
+    Ok(())
+}
+
+// After logrotate moved the file away, stdout is taken care of with the log file
+pub fn reopen() -> io::Result<()> {
+    let target = OUTPUT.lock().unwrap().as_ref().map(|(target, _)| target.clone());
+    match target {
+        Some(target) if target != Path::new("-") => open(&target),
+        _ => Ok(()),
+    }
+}
+
+// Quoted fields escape what would end them or break the line, like Apache does
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for b in value.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Only the address, without the port. A unix socket peer stays as it is, without spaces
+pub fn host(client: &str) -> String {
+    match client.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => client.replace(' ', "_"),
+    }
+}
+
+// The status is None when nothing was answered, like after a timeout. Bytes are
+// those of the body, "-" for none
+pub fn line(format: Format, entry: &Entry, request_line: &str, status: Option<i32>, bytes: u64, time: SystemTime) -> String {
+    if log::format() == log::Format::Json {
+        return record(entry, request_line, status, bytes, time);
+    }
+    let status = status.map(|code| code.to_string()).unwrap_or_else(|| String::from("-"));
+    let bytes = if bytes == 0 { String::from("-") } else { bytes.to_string() };
+    let user = match entry.user.as_deref() {
+        Some(user) if !user.is_empty() => quote(user).trim_matches('"').replace(' ', "_"),
+        _ => String::from("-"),
+    };
+    let mut line = format!("{} - {user} [{}] {} {status} {bytes}", host(&entry.client), date::common_log(time), quote(request_line));
+    if format == Format::Combined {
+        let field = |value: &Option<String>| value.as_deref().map(quote).unwrap_or_else(|| String::from("\"-\""));
+        line.push_str(&format!(" {} {}", field(&entry.referer), field(&entry.agent)));
+    }
+    line
+}
+
+// Without a method and path they come from the request line as it was, even one
+// too broken to parse
+fn record(entry: &Entry, request_line: &str, status: Option<i32>, bytes: u64, time: SystemTime) -> String {
+    let mut parts = request_line.split(' ');
+    let (method, path) = (parts.next(), parts.next());
+    let record = Record {
+        level: Some(Level::Info),
+        id: Some(&entry.id),
+        peer: Some(&entry.client),
+        method: entry.method.as_deref().or(method),
+        path: entry.path.as_deref().or(path),
+        status,
+        bytes: Some(bytes),
+        duration_ms: Some(entry.started.elapsed().as_millis() as u64),
+        user: entry.user.as_deref(),
+        vhost: entry.vhost.as_deref(),
+        referer: entry.referer.as_deref(),
+        agent: entry.agent.as_deref(),
+        err: entry.err.as_deref(),
+        ..Record::default()
+    };
+    record.to_json(time)
+}
+
+// A log that can't be written must not take requests down with it, the line is lost.
+// On stdout it goes with the other logs, through their thread
+pub fn write(line: String) {
+    let mut output = OUTPUT.lock().unwrap();
+    match output.as_mut() {
+        Some((target, _)) if target == Path::new("-") => {
+            drop(output);
+            log::output(line);
+        }
+        Some((_, output)) => {
+            let _ = writeln!(output, "{line}").and_then(|_| output.flush());
+        }
+        None => {}
+    }
+}
+
+pub fn is_open() -> bool {
+    OUTPUT.lock().unwrap().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    fn example() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(784111777)
+    }
+
+    fn entry() -> Entry {
+        Entry::new("r1", "192.0.2.7:51234")
+    }
+
+    #[test]
+    fn common_line_of_a_request() {
+        let line = line(Format::Common, &entry(), "GET /a.txt HTTP/1.1", Some(200), 1234, example());
+        assert_eq!(line, "192.0.2.7 - - [06/Nov/1994:08:49:37 +0000] \"GET /a.txt HTTP/1.1\" 200 1234");
+    }
+
+    #[test]
+    fn nothing_sent_or_answered_is_a_dash() {
+        let line = line(Format::Common, &entry(), "GET /slow HTTP/1.1", None, 0, example());
+        assert!(line.ends_with("\"GET /slow HTTP/1.1\" - -"), "{line}");
+    }
+
+    #[test]
+    fn combined_adds_referer_and_agent() {
+        let mut entry = entry();
+        entry.agent = Some(String::from("curl/8.5.0"));
+        let combined = line(Format::Combined, &entry, "GET / HTTP/1.1", Some(304), 0, example());
+        assert!(combined.ends_with("\"GET / HTTP/1.1\" 304 - \"-\" \"curl/8.5.0\""), "{combined}");
+        entry.referer = Some(String::from("http://example.com/"));
+        let combined = line(Format::Combined, &entry, "GET / HTTP/1.1", Some(200), 5, example());
+        assert!(combined.ends_with(" 200 5 \"http://example.com/\" \"curl/8.5.0\""), "{combined}");
+    }
+
+    #[test]
+    fn quoted_fields_escape_what_would_end_them() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote("back\\slash"), "\"back\\\\slash\"");
+        assert_eq!(quote("a\r\nb\t\u{7f}"), "\"a\\x0d\\x0ab\\x09\\x7f\"");
+        assert_eq!(quote("é"), "\"\\xc3\\xa9\"");
+        let mut entry = entry();
+        entry.agent = Some(String::from("evil\" 200 0 \"x"));
+        let line = line(Format::Combined, &entry, "GET /\"x\" HTTP/1.1", Some(404), 0, example());
+        assert!(line.ends_with("\"GET /\\\"x\\\" HTTP/1.1\" 404 - \"-\" \"evil\\\" 200 0 \\\"x\""), "{line}");
+    }
+
+    #[test]
+    fn user_is_one_field() {
+        let mut entry = entry();
+        entry.user = Some(String::from("ann marie\"x"));
+        let line = line(Format::Common, &entry, "GET / HTTP/1.1", Some(200), 1, example());
+        assert!(line.starts_with("192.0.2.7 - ann_marie\\\"x [06/Nov/1994"), "{line}");
+        entry.user = Some(String::new());
+        assert!(super::line(Format::Common, &entry, "GET / HTTP/1.1", Some(200), 1, example()).starts_with("192.0.2.7 - - ["));
+    }
+
+    #[test]
+    fn host_drops_the_port() {
+        assert_eq!(host("192.0.2.7:51234"), "192.0.2.7");
+        assert_eq!(host("[2001:db8::1]:443"), "2001:db8::1");
+        assert_eq!(host("unix socket"), "unix_socket");
+    }
+}
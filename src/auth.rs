@@ -0,0 +1,105 @@
+// HTTP Basic authentication (RFC 7617) against the --auth credentials
+
+use crate::headers::Headers;
+
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+// Standard base64 with padding, None on anything malformed
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, group) in s.chunks(4).enumerate() {
+        let last = i == s.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &c in &group[..4 - padding] {
+            bits = bits << 6 | base64_value(c)? as u32;
+        }
+        bits <<= 6 * padding as u32;
+        let bytes = bits.to_be_bytes();
+        out.extend_from_slice(&bytes[1..4 - padding]);
+    }
+    Some(out)
+}
+
+// Standard base64 with padding
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let mut bits = [0u8; 4];
+        bits[1..=group.len()].copy_from_slice(group);
+        let bits = u32::from_be_bytes(bits);
+        for i in 0..4 {
+            match i <= group.len() {
+                true => out.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+// Compare without leaving at the first difference, so timing says nothing about the password
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// The user of a valid Authorization header, None when it's missing or wrong
+pub fn check_basic(header: Option<&str>, credentials: &[(String, String)]) -> Option<String> {
+    let (scheme, token) = header?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(base64_decode(token.trim())?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    // Check every entry, same reason as above
+    let matched = credentials.iter()
+        .fold(false, |matched, (u, p)| (same(u, user) & same(p, password)) | matched);
+    matched.then(|| String::from(user))
+}
+
+// Whether a form post comes from one of our own pages. Browsers send Origin with
+// every cross-site POST (older ones a Referer), so a page elsewhere can't make a
+// logged in user's browser submit it. Clients sending neither aren't browsers
+pub fn same_origin(headers: &Headers) -> bool {
+    let source = match (headers.get("Origin"), headers.get("Referer")) {
+        (Some(origin), _) => origin,
+        (None, Some(referer)) => referer,
+        (None, None) => return true,
+    };
+    let authority = source.strip_prefix("http://").or_else(|| source.strip_prefix("https://"))
+        .map(|rest| rest.split('/').next().unwrap_or_default());
+    match (authority, headers.get("Host")) {
+        (Some(authority), Some(host)) => authority.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trip() {
+        for (bytes, encoded) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foob", "Zm9vYg=="), (b"\xff\xfe\x00", "//4A")] {
+            assert_eq!(base64_encode(bytes), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), bytes);
+        }
+    }
+}
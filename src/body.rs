@@ -0,0 +1,148 @@
+use std::fmt;
+use crate::headers::Headers;
+
+// How the length of the request body is determined (RFC 7230 3.3.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+}
+
+// Every one of these is a 400, the connection must be closed afterwards
+// because we can't know where the next request starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FramingError {
+    LengthWithTransferEncoding,
+    ConflictingLengths,
+    InvalidLength(String),
+    UnsupportedTransferEncoding(String),
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FramingError::LengthWithTransferEncoding => write!(f, "both Content-Length and Transfer-Encoding present"),
+            FramingError::ConflictingLengths => write!(f, "conflicting Content-Length values"),
+            FramingError::InvalidLength(v) => write!(f, "invalid Content-Length '{v}'"),
+            FramingError::UnsupportedTransferEncoding(v) => write!(f, "unsupported Transfer-Encoding '{v}'"),
+        }
+    }
+}
+
+// "abc", "-5", "+3", an empty value and lengths past u64 are all invalid, the caller
+// answers 400 and closes since the body can't be skipped
+fn parse_length(value: &str) -> Result<u64, FramingError> {
+    // Only plain digits, u64::from_str would also take a leading '+'
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(FramingError::InvalidLength(String::from(value)));
+    }
+    value.parse::<u64>().map_err(|_| FramingError::InvalidLength(String::from(value)))
+}
+
+pub fn body_framing(headers: &Headers) -> Result<Framing, FramingError> {
+    let codings: Vec<&str> = headers.list("Transfer-Encoding").collect();
+    let has_te = headers.get("Transfer-Encoding").is_some();
+    let has_length = headers.get("Content-Length").is_some();
+
+    if has_te {
+        if has_length {
+            return Err(FramingError::LengthWithTransferEncoding);
+        }
+        // We only decode chunked, and it must be applied exactly once
+        return match codings.as_slice() {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Err(FramingError::UnsupportedTransferEncoding(codings.join(", "))),
+        };
+    }
+    if !has_length {
+        return Ok(Framing::Empty);
+    }
+
+    // Exactly one value. A list like "5, 5" may be taken as one (RFC 9112 6.3), but
+    // another server in the path may not, and two different ones are a smuggling attempt
+    let mut values = headers.get_all("Content-Length");
+    let value = values.next().unwrap_or_default();
+    if values.next().is_some() {
+        return Err(FramingError::ConflictingLengths);
+    }
+    match parse_length(value.trim_matches(|c| c == ' ' || c == '\t'))? {
+        0 => Ok(Framing::Empty),
+        n => Ok(Framing::Length(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framing(headers: &[(&str, &str)]) -> Result<Framing, FramingError> {
+        let mut parsed = Headers::new();
+        for (name, value) in headers {
+            parsed.insert(name, value);
+        }
+        body_framing(&parsed)
+    }
+
+    #[test]
+    fn plain_digits_are_a_length() {
+        assert_eq!(framing(&[("Content-Length", "42")]), Ok(Framing::Length(42)));
+        assert_eq!(framing(&[("Content-Length", "007")]), Ok(Framing::Length(7)));
+        assert_eq!(framing(&[("Content-Length", "0")]), Ok(Framing::Empty));
+        assert_eq!(framing(&[]), Ok(Framing::Empty));
+        assert_eq!(framing(&[("Content-Length", &u64::MAX.to_string())]), Ok(Framing::Length(u64::MAX)));
+    }
+
+    #[test]
+    fn anything_else_is_invalid() {
+        for value in ["abc", "-5", "+3", "", " ", "1,1", "5, 5", "1 2", "0x10", "3.0", "18446744073709551616", "99999999999999999999999"] {
+            assert!(matches!(framing(&[("Content-Length", value)]), Err(FramingError::InvalidLength(_))), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn two_lengths_conflict_even_when_equal() {
+        assert_eq!(framing(&[("Content-Length", "5"), ("Content-Length", "5")]), Err(FramingError::ConflictingLengths));
+        assert_eq!(framing(&[("Content-Length", "5"), ("Content-Length", "6")]), Err(FramingError::ConflictingLengths));
+    }
+
+    #[test]
+    fn transfer_encoding_is_chunked_once_or_nothing() {
+        assert_eq!(framing(&[("Transfer-Encoding", "chunked")]), Ok(Framing::Chunked));
+        assert_eq!(framing(&[("Transfer-Encoding", "Chunked")]), Ok(Framing::Chunked));
+        assert_eq!(framing(&[("Transfer-Encoding", "chunked"), ("Content-Length", "5")]), Err(FramingError::LengthWithTransferEncoding));
+        assert!(matches!(framing(&[("Transfer-Encoding", "gzip, chunked")]), Err(FramingError::UnsupportedTransferEncoding(_))));
+        assert!(matches!(framing(&[("Transfer-Encoding", "chunked, chunked")]), Err(FramingError::UnsupportedTransferEncoding(_))));
+    }
+
+    #[test]
+    fn length_with_transfer_encoding_is_refused_in_any_order() {
+        for headers in [[("Content-Length", "5"), ("Transfer-Encoding", "chunked")], [("Transfer-Encoding", "chunked"), ("Content-Length", "5")]] {
+            assert_eq!(framing(&headers), Err(FramingError::LengthWithTransferEncoding));
+        }
+        // Even a length of nothing and a coding that isn't chunked
+        assert_eq!(framing(&[("Transfer-Encoding", "gzip"), ("Content-Length", "0")]), Err(FramingError::LengthWithTransferEncoding));
+    }
+
+    #[test]
+    fn every_other_coding_is_unsupported() {
+        for codings in ["identity", "gzip", "chunked, gzip", "chunked;q=1", "", " ", "xchunked", "chunked x"] {
+            assert!(matches!(framing(&[("Transfer-Encoding", codings)]), Err(FramingError::UnsupportedTransferEncoding(_))), "{codings:?}");
+        }
+        // Split over two headers it is a list the same way
+        assert!(matches!(
+            framing(&[("Transfer-Encoding", "chunked"), ("Transfer-Encoding", "chunked")]),
+            Err(FramingError::UnsupportedTransferEncoding(_))
+        ));
+        assert!(matches!(
+            framing(&[("Transfer-Encoding", "gzip"), ("Transfer-Encoding", "chunked")]),
+            Err(FramingError::UnsupportedTransferEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn whitespace_around_the_length_is_dropped() {
+        assert_eq!(framing(&[("Content-Length", " 5\t")]), Ok(Framing::Length(5)));
+        assert_eq!(framing(&[("Transfer-Encoding", " chunked ")]), Ok(Framing::Chunked));
+    }
+}
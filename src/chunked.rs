@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use crate::headers::is_token;
+
+// Chunk-size and trailer lines longer than this are treated as malformed
+const MAX_LINE_LEN: usize = 4096;
+const MAX_TRAILERS: usize = 64;
+
+// Returned (wrapped in an io::Error) when the body grows beyond the limit
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body too large")
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+pub fn is_body_too_large(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<BodyTooLarge>())
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed chunked body: {what}"))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body truncated")
+}
+
+enum State {
+    Size,       // Waiting for the chunk-size line
+    Data(u64),  // Inside a chunk, with the bytes left in it
+    DataEnd,    // The CRLF after the chunk data
+    Trailer,    // Trailer headers after the zero chunk
+    Done,
+}
+
+// Decodes a `Transfer-Encoding: chunked` body from the buffered reader,
+// it stops exactly at the end of the body so the next request can be read after it
+pub struct ChunkedReader<R> {
+    inner: R,
+    state: State,
+    line: Vec<u8>,
+    trailers: usize,
+    total: u64,
+    limit: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> ChunkedReader<R> {
+    pub fn new(inner: R, limit: u64) -> Self {
+        ChunkedReader {
+            inner,
+            state: State::Size,
+            line: Vec::new(),
+            trailers: 0,
+            total: 0,
+            limit,
+        }
+    }
+
+    // Read a whole line into self.line, without the line ending
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+            if buf.is_empty() {
+                return Poll::Ready(Err(truncated()));
+            }
+            let (used, done) = match buf.iter().position(|&b| b == b'\n') {
+                Some(pos) => (pos + 1, true),
+                None => (buf.len(), false),
+            };
+            self.line.extend_from_slice(&buf[..used]);
+            Pin::new(&mut self.inner).consume(used);
+            if self.line.len() > MAX_LINE_LEN {
+                return Poll::Ready(Err(malformed("line too long")));
+            }
+            if done {
+                break;
+            }
+        }
+        self.line.pop();
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn parse_size(&self) -> io::Result<u64> {
+        // Drop the chunk extensions, we don't understand any of them
+        let line = match self.line.iter().position(|&b| b == b';') {
+            Some(pos) => &self.line[..pos],
+            None => &self.line[..],
+        };
+        let line = str::from_utf8(line).map_err(|_| malformed("bad chunk size"))?;
+        let line = line.trim_matches(|c| c == ' ' || c == '\t');
+        if line.is_empty() || !line.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(malformed("bad chunk size"));
+        }
+        u64::from_str_radix(line, 16).map_err(|_| malformed("chunk size overflow"))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for ChunkedReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match this.state {
+                State::Size => {
+                    ready!(this.poll_line(cx))?;
+                    let size = this.parse_size()?;
+                    this.line.clear();
+                    if size == 0 {
+                        this.state = State::Trailer;
+                        continue;
+                    }
+                    this.total = match this.total.checked_add(size) {
+                        Some(total) if total <= this.limit => total,
+                        _ => return Poll::Ready(Err(io::Error::other(BodyTooLarge))),
+                    };
+                    this.state = State::Data(size);
+                }
+                State::Data(left) => {
+                    if out.remaining() == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                    let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+                    if buf.is_empty() {
+                        return Poll::Ready(Err(truncated()));
+                    }
+                    let n = buf.len().min(out.remaining()).min(left.try_into().unwrap_or(usize::MAX));
+                    out.put_slice(&buf[..n]);
+                    Pin::new(&mut this.inner).consume(n);
+                    let left = left - n as u64;
+                    this.state = if left == 0 { State::DataEnd } else { State::Data(left) };
+                    return Poll::Ready(Ok(()));
+                }
+                State::DataEnd => {
+                    ready!(this.poll_line(cx))?;
+                    if !this.line.is_empty() {
+                        return Poll::Ready(Err(malformed("missing CRLF after chunk data")));
+                    }
+                    this.state = State::Size;
+                }
+                State::Trailer => {
+                    ready!(this.poll_line(cx))?;
+                    if this.line.is_empty() { // The final CRLF
+                        this.state = State::Done;
+                        continue;
+                    }
+                    // Trailers are read and dropped, we only care about the framing
+                    if !this.line.contains(&b':') {
+                        return Poll::Ready(Err(malformed("bad trailer line")));
+                    }
+                    this.line.clear();
+                    this.trailers += 1;
+                    if this.trailers > MAX_TRAILERS {
+                        return Poll::Ready(Err(malformed("too many trailers")));
+                    }
+                }
+                State::Done => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+// Fields that must not be sent as trailers (RFC 9110 6.5.1), they frame the message,
+// route or control it, authenticate or describe the content and are needed up front
+const FORBIDDEN_TRAILERS: &[&str] = &[
+    "Age", "Authorization", "Cache-Control", "Connection", "Content-Encoding", "Content-Length",
+    "Content-Range", "Content-Type", "Cookie", "Date", "Expect", "Expires", "Host", "Keep-Alive",
+    "Location", "Max-Forwards", "Pragma", "Proxy-Authenticate", "Proxy-Authorization", "Range",
+    "Retry-After", "Set-Cookie", "TE", "Trailer", "Transfer-Encoding", "Upgrade", "Vary",
+    "WWW-Authenticate",
+];
+
+// One per response, so a name can only go out with the response it was announced for
+static ANNOUNCEMENTS: AtomicU64 = AtomicU64::new(0);
+
+// The trailer fields of a response, announced in its `Trailer` header before the head
+// is written and then handed to the ChunkedWriter of its body
+pub struct Trailers {
+    id: u64,
+    names: Vec<String>,
+}
+
+// A field announced by Trailers::announce, the only way to get one
+pub struct TrailerName {
+    name: String,
+    announcement: u64,
+}
+
+impl Trailers {
+    pub fn new() -> Self {
+        Trailers { id: ANNOUNCEMENTS.fetch_add(1, Ordering::Relaxed), names: Vec::new() }
+    }
+
+    pub fn announce(&mut self, name: &str) -> io::Result<TrailerName> {
+        if !is_token(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid trailer name '{name}'")));
+        }
+        if FORBIDDEN_TRAILERS.iter().any(|forbidden| forbidden.eq_ignore_ascii_case(name)) || name.len() > 3 && name[..3].eq_ignore_ascii_case("if-") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{name} can't be a trailer")));
+        }
+        if !self.names.iter().any(|other| other.eq_ignore_ascii_case(name)) {
+            self.names.push(String::from(name));
+        }
+        Ok(TrailerName { name: String::from(name), announcement: self.id })
+    }
+
+    // Value of the `Trailer` header, None when nothing was announced
+    pub fn header(&self) -> Option<String> {
+        if self.names.is_empty() {
+            return None;
+        }
+        Some(self.names.join(", "))
+    }
+}
+
+// Encodes a response body with the chunked transfer coding,
+// for bodies whose length isn't known when the head is sent
+pub struct ChunkedWriter<W> {
+    inner: W,
+    trailers: Trailers,
+}
+
+impl<W: AsyncWrite + Unpin> ChunkedWriter<W> {
+    // The trailers are those the head announced, Trailers::new() for none
+    pub fn new(inner: W, trailers: Trailers) -> Self {
+        ChunkedWriter { inner, trailers }
+    }
+
+    pub async fn write_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() { // A zero sized chunk would end the body
+            return Ok(());
+        }
+        self.inner.write_all(format!("{:X}\r\n", data.len()).as_bytes()).await?;
+        self.inner.write_all(data).await?;
+        self.inner.write_all(b"\r\n").await
+    }
+
+    // Write the zero chunk, the trailers and the final CRLF. Checked before anything
+    // is written, the body can still be cut off cleanly then
+    pub async fn finish(mut self, trailers: Vec<(TrailerName, String)>) -> io::Result<W> {
+        let mut tail = String::from("0\r\n");
+        for (TrailerName { name, announcement }, value) in trailers {
+            if announcement != self.trailers.id {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("trailer {name} wasn't announced for this response")));
+            }
+            if value.contains(['\r', '\n']) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad value for trailer {name}")));
+            }
+            tail.push_str(&format!("{name}: {value}\r\n"));
+        }
+        tail.push_str("\r\n");
+        self.inner.write_all(tail.as_bytes()).await?;
+        self.inner.flush().await?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn framing_of_chunks_and_trailers() {
+        let mut trailers = Trailers::new();
+        let digest = trailers.announce("Content-Digest").unwrap();
+        let checksum = trailers.announce("X-Checksum").unwrap();
+        assert_eq!(trailers.header().as_deref(), Some("Content-Digest, X-Checksum"));
+        let mut body = ChunkedWriter::new(Vec::new(), trailers);
+        body.write_chunk(b"hello ").await.unwrap();
+        body.write_chunk(b"").await.unwrap();
+        body.write_chunk(&[b'x'; 26]).await.unwrap();
+        let raw = body.finish(vec![(digest, String::from("sha-256=:abc:")), (checksum, String::from("1"))]).await.unwrap();
+        let expected = format!("6\r\nhello \r\n1A\r\n{}\r\n0\r\nContent-Digest: sha-256=:abc:\r\nX-Checksum: 1\r\n\r\n", "x".repeat(26));
+        assert_eq!(String::from_utf8(raw).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn without_trailers_it_ends_with_the_zero_chunk() {
+        let mut body = ChunkedWriter::new(Vec::new(), Trailers::new());
+        body.write_chunk(b"abc").await.unwrap();
+        assert_eq!(body.finish(Vec::new()).await.unwrap(), b"3\r\nabc\r\n0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn what_is_written_reads_back() {
+        let mut trailers = Trailers::new();
+        let name = trailers.announce("X-Checksum").unwrap();
+        let mut body = ChunkedWriter::new(Vec::new(), trailers);
+        body.write_chunk(b"one").await.unwrap();
+        body.write_chunk(b"two").await.unwrap();
+        let mut raw = body.finish(vec![(name, String::from("2"))]).await.unwrap();
+        raw.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        let mut reader = ChunkedReader::new(raw.as_slice(), 1024);
+        let mut decoded = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut decoded).await.unwrap();
+        assert_eq!(decoded, b"onetwo");
+        assert_eq!(reader.inner, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn trailer_of_another_response_is_refused() {
+        let mut other = Trailers::new();
+        let name = other.announce("X-Checksum").unwrap();
+        let mut trailers = Trailers::new();
+        trailers.announce("X-Checksum").unwrap();
+        let body = ChunkedWriter::new(Vec::new(), trailers);
+        let err = body.finish(vec![(name, String::from("1"))]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn value_with_a_line_break_is_refused() {
+        let mut trailers = Trailers::new();
+        let name = trailers.announce("X-Checksum").unwrap();
+        let body = ChunkedWriter::new(Vec::new(), trailers);
+        assert!(body.finish(vec![(name, String::from("1\r\nX-Evil: 1"))]).await.is_err());
+    }
+
+    #[test]
+    fn names_have_to_be_tokens() {
+        let mut trailers = Trailers::new();
+        for name in ["", "X Checksum", "X-Checksum:", "Ä", "X-Check\r\nsum"] {
+            assert!(trailers.announce(name).is_err(), "{name:?}");
+        }
+        assert!(trailers.announce("X-Check_sum.1").is_ok());
+    }
+
+    #[test]
+    fn framing_and_control_fields_are_refused() {
+        let mut trailers = Trailers::new();
+        for name in ["Content-Length", "transfer-encoding", "Host", "Trailer", "Content-Type", "Authorization", "Set-Cookie", "If-Match", "if-none-match"] {
+            assert!(trailers.announce(name).is_err(), "{name}");
+        }
+        assert_eq!(trailers.header(), None);
+    }
+
+    #[test]
+    fn announced_once_per_name() {
+        let mut trailers = Trailers::new();
+        trailers.announce("X-Checksum").unwrap();
+        trailers.announce("x-checksum").unwrap();
+        assert_eq!(trailers.header().as_deref(), Some("X-Checksum"));
+    }
+
+    async fn decode(raw: &[u8], limit: u64) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut reader = ChunkedReader::new(raw, limit);
+        let mut decoded = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut decoded).await?;
+        Ok((decoded, reader.inner.to_vec()))
+    }
+
+    #[tokio::test]
+    async fn chunks_are_joined_and_the_rest_is_left() {
+        let (body, rest) = decode(b"3\r\nabc\r\na\r\n0123456789\r\n1\r\nz\r\n0\r\n\r\nNEXT", 1024).await.unwrap();
+        assert_eq!(body, b"abc0123456789z");
+        assert_eq!(rest, b"NEXT");
+        // Bare LF line endings and hex in either case too
+        let (body, _) = decode(b"A\n0123456789\n1\nx\n0\n\n", 1024).await.unwrap();
+        assert_eq!(body, b"0123456789x");
+    }
+
+    #[tokio::test]
+    async fn extensions_are_ignored() {
+        let (body, _) = decode(b"3;name=value\r\nabc\r\n2 ; quoted=\"a;b\"\r\nde\r\n0;last\r\n\r\n", 1024).await.unwrap();
+        assert_eq!(body, b"abcde");
+    }
+
+    #[tokio::test]
+    async fn trailers_are_read_past() {
+        let (body, rest) = decode(b"2\r\nok\r\n0\r\nX-Checksum: 1\r\nContent-Digest: sha-256=:abc:\r\n\r\nNEXT", 1024).await.unwrap();
+        assert_eq!((body.as_slice(), rest.as_slice()), (&b"ok"[..], &b"NEXT"[..]));
+        let many = format!("0\r\n{}\r\n", "X-A: 1\r\n".repeat(MAX_TRAILERS + 1));
+        assert_eq!(decode(many.as_bytes(), 1024).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode(b"0\r\nno colon\r\n\r\n", 1024).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_bodies_are_eof_errors() {
+        for raw in [&b""[..], b"5\r\nab", b"5", b"2\r\nab", b"2\r\nab\r\n", b"0\r\n", b"0\r\nX-A: 1\r\n"] {
+            let err = decode(raw, 1024).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{:?}", String::from_utf8_lossy(raw));
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_framing_is_invalid_data() {
+        for raw in [&b"x\r\n"[..], b"\r\n", b"-1\r\n", b"0x3\r\nabc\r\n0\r\n\r\n", b"3\r\nabcd\r\n0\r\n\r\n", b"11111111111111111\r\n"] {
+            let err = decode(raw, u64::MAX).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", String::from_utf8_lossy(raw));
+        }
+        let long = format!("1;{}\r\na\r\n0\r\n\r\n", "x".repeat(MAX_LINE_LEN));
+        assert_eq!(decode(long.as_bytes(), 1024).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn body_past_the_limit_is_too_large() {
+        assert!(decode(b"4\r\nabcd\r\n0\r\n\r\n", 4).await.is_ok());
+        let err = decode(b"4\r\nabcd\r\n1\r\ne\r\n0\r\n\r\n", 4).await.unwrap_err();
+        assert!(is_body_too_large(&err));
+        // The largest size is taken and waits for its bytes, adding to it is too large
+        let err = decode(b"ffffffffffffffff\r\n", u64::MAX).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = decode(b"1\r\na\r\nffffffffffffffff\r\n", u64::MAX).await.unwrap_err();
+        assert!(is_body_too_large(&err));
+    }
+}
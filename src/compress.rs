@@ -0,0 +1,161 @@
+use std::io::Write;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::headers::Headers;
+
+// gzip levels, 1 is the fastest and 9 the smallest
+pub const MIN_LEVEL: u32 = 1;
+pub const MAX_LEVEL: u32 = 9;
+
+// gzip for files that compress well, off unless --compress
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub enabled: bool,
+    pub level: u32,
+    pub min_size: u64,  // Smaller files go uncompressed
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        // What gzip itself defaults to, most of the gain for a fraction of the time of 9
+        CompressOptions {
+            enabled: false,
+            level: 6,
+            min_size: 1024,
+        }
+    }
+}
+
+// Text and the structured formats, the rest is mostly compressed already
+pub fn is_compressible_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/") || mime.ends_with("+json") || mime.ends_with("+xml")
+        || matches!(mime.as_str(), "application/json" | "application/javascript" | "application/xml" | "application/wasm")
+}
+
+// Whether Accept-Encoding allows gzip, "gzip;q=0" is a refusal (RFC 7231 5.3.4).
+// An explicit gzip wins over "*"
+pub fn accepts_gzip(headers: &Headers) -> bool {
+    let mut wildcard = None;
+    for item in headers.list("Accept-Encoding") {
+        let (coding, params) = item.split_once(';').unwrap_or((item, ""));
+        let q = params.split(';')
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => return q > 0.0,
+            "*" => wildcard = Some(q > 0.0),
+            _ => {}
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+// Whether a response of this type and size gets gzipped. Ranges are asked of the
+// uncompressed one, when they are served. The file path asks before the file is
+// read, for the ETag to check conditions with, the middleware once it is
+pub fn wanted(options: &CompressOptions, headers: &Headers, content_type: Option<&str>, len: u64, ranges: bool) -> bool {
+    options.enabled && content_type.is_some_and(is_compressible_type) && len >= options.min_size
+        && !(ranges && headers.get("Range").is_some()) && accepts_gzip(headers)
+}
+
+// The compressed bytes differ, so their strong ETag has to as well
+pub fn etag(identity: &str) -> String {
+    match identity.strip_suffix('"') {
+        Some(tag) => format!("{tag}-gzip\""),
+        None => String::from(identity),
+    }
+}
+
+pub fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::new(level));
+    // Writing into a Vec doesn't fail
+    encoder.write_all(data).expect("writing to memory");
+    encoder.finish().expect("writing to memory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    fn headers(accept_encoding: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.insert("Accept-Encoding", accept_encoding);
+        headers
+    }
+
+    // Text with enough variety for the levels to make a difference
+    fn text() -> Vec<u8> {
+        let words = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta"];
+        let mut seed = 7u32;
+        let mut out = String::new();
+        for _ in 0..20000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            out.push_str(words[(seed >> 16) as usize % words.len()]);
+            out.push(if seed.is_multiple_of(13) { '\n' } else { ' ' });
+        }
+        out.into_bytes()
+    }
+
+    #[test]
+    fn higher_level_is_not_bigger() {
+        let data = text();
+        let fastest = gzip(&data, MIN_LEVEL);
+        let smallest = gzip(&data, MAX_LEVEL);
+        assert!(smallest.len() <= fastest.len(), "{} > {}", smallest.len(), fastest.len());
+        assert!(fastest.len() < data.len());
+        for compressed in [fastest, smallest] {
+            let mut back = Vec::new();
+            GzDecoder::new(compressed.as_slice()).read_to_end(&mut back).unwrap();
+            assert_eq!(back, data);
+        }
+    }
+
+    #[test]
+    fn compressible_types() {
+        for content_type in ["text/html; charset=utf-8", "application/json", "image/svg+xml", "application/ld+json", "APPLICATION/JAVASCRIPT"] {
+            assert!(is_compressible_type(content_type), "{content_type}");
+        }
+        for content_type in ["image/png", "application/zip", "video/mp4", "application/octet-stream"] {
+            assert!(!is_compressible_type(content_type), "{content_type}");
+        }
+    }
+
+    #[test]
+    fn accept_encoding_with_q_values() {
+        assert!(accepts_gzip(&headers("gzip, deflate, br")));
+        assert!(accepts_gzip(&headers("x-gzip")));
+        assert!(accepts_gzip(&headers("br;q=1.0, gzip;q=0.5")));
+        assert!(accepts_gzip(&headers("*")));
+        assert!(!accepts_gzip(&headers("gzip;q=0")));
+        assert!(!accepts_gzip(&headers("*;q=0.5, gzip;q=0")));
+        assert!(!accepts_gzip(&headers("*;q=0")));
+        assert!(!accepts_gzip(&headers("identity")));
+        assert!(!accepts_gzip(&Headers::new()));
+    }
+
+    #[test]
+    fn small_bodies_and_ranges_go_uncompressed() {
+        let options = CompressOptions { enabled: true, ..CompressOptions::default() };
+        let gzip = headers("gzip");
+        assert!(wanted(&options, &gzip, Some("text/plain"), 1024, true));
+        assert!(!wanted(&options, &gzip, Some("text/plain"), 100, true));
+        assert!(!wanted(&options, &gzip, Some("image/png"), 1 << 20, true));
+        assert!(!wanted(&options, &gzip, None, 1 << 20, true));
+        assert!(!wanted(&CompressOptions::default(), &gzip, Some("text/plain"), 1 << 20, true));
+        let mut ranged = headers("gzip");
+        ranged.insert("Range", "bytes=0-10");
+        assert!(!wanted(&options, &ranged, Some("text/plain"), 1 << 20, true));
+        // Without ranges being served the Range is ignored anyway
+        assert!(wanted(&options, &ranged, Some("text/plain"), 1 << 20, false));
+    }
+
+    #[test]
+    fn etag_of_the_gzip_one() {
+        assert_eq!(etag("\"abc\""), "\"abc-gzip\"");
+        assert_eq!(etag("W/\"abc\""), "W/\"abc-gzip\"");
+    }
+}
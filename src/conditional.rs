@@ -0,0 +1,103 @@
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+use crate::headers::Headers;
+use crate::method::Method;
+
+// ETag of a file computed from its metadata, changes when the file is rewritten
+pub fn etag(meta: &Metadata) -> String {
+    let mtime = meta.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_micros())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", mtime, meta.len())
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+fn weak_eq(a: &str, b: &str) -> bool {
+    opaque(a) == opaque(b)
+}
+
+// Evaluate If-Match and If-None-Match (RFC 7232 6) against the current ETag,
+// None means the resource doesn't exist. Err carries the status to answer with
+pub fn check_preconditions(headers: &Headers, method: Method, current: Option<&str>) -> Result<(), i32> {
+    if headers.get("If-Match").is_some() {
+        let matched = match current {
+            Some(cur) => headers.list("If-Match").any(|tag| tag == "*" || strong_eq(tag, cur)),
+            None => false,
+        };
+        if !matched {
+            return Err(412);
+        }
+    }
+    if headers.get("If-None-Match").is_some() {
+        // "If-None-Match: *" turns a write into create-only
+        let matched = match current {
+            Some(cur) => headers.list("If-None-Match").any(|tag| tag == "*" || weak_eq(tag, cur)),
+            None => false,
+        };
+        if matched {
+            return Err(if method == Method::Get || method == Method::Head { 304 } else { 412 });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(headers: &[(&str, &str)], method: Method, current: Option<&str>) -> Result<(), i32> {
+        let mut parsed = Headers::new();
+        for (name, value) in headers {
+            parsed.insert(name, value);
+        }
+        check_preconditions(&parsed, method, current)
+    }
+
+    #[test]
+    fn if_match_needs_the_same_strong_tag() {
+        let current = Some("\"abc\"");
+        assert_eq!(check(&[("If-Match", "\"abc\"")], Method::Put, current), Ok(()));
+        assert_eq!(check(&[("If-Match", "\"old\", \"abc\"")], Method::Put, current), Ok(()));
+        assert_eq!(check(&[("If-Match", "\"old\"")], Method::Put, current), Err(412));
+        // Weak tags never match strongly, on either side
+        assert_eq!(check(&[("If-Match", "W/\"abc\"")], Method::Put, current), Err(412));
+        assert_eq!(check(&[("If-Match", "W/\"abc\"")], Method::Put, Some("W/\"abc\"")), Err(412));
+    }
+
+    #[test]
+    fn if_match_star_needs_the_resource() {
+        assert_eq!(check(&[("If-Match", "*")], Method::Delete, Some("\"abc\"")), Ok(()));
+        assert_eq!(check(&[("If-Match", "*")], Method::Delete, None), Err(412));
+        assert_eq!(check(&[("If-Match", "\"abc\"")], Method::Put, None), Err(412));
+    }
+
+    #[test]
+    fn if_none_match_is_a_304_for_reads_and_412_for_writes() {
+        let current = Some("\"abc\"");
+        assert_eq!(check(&[("If-None-Match", "\"abc\"")], Method::Get, current), Err(304));
+        assert_eq!(check(&[("If-None-Match", "W/\"abc\"")], Method::Head, current), Err(304));
+        assert_eq!(check(&[("If-None-Match", "\"abc\"")], Method::Put, current), Err(412));
+        assert_eq!(check(&[("If-None-Match", "\"old\"")], Method::Get, current), Ok(()));
+    }
+
+    #[test]
+    fn if_none_match_star_makes_a_write_create_only() {
+        assert_eq!(check(&[("If-None-Match", "*")], Method::Put, None), Ok(()));
+        assert_eq!(check(&[("If-None-Match", "*")], Method::Put, Some("\"abc\"")), Err(412));
+    }
+
+    #[test]
+    fn failed_if_match_goes_first() {
+        let headers = [("If-Match", "\"old\""), ("If-None-Match", "\"abc\"")];
+        assert_eq!(check(&headers, Method::Get, Some("\"abc\"")), Err(412));
+        assert_eq!(check(&[], Method::Put, None), Ok(()));
+    }
+}
@@ -0,0 +1,2000 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use httpserver::glob::Pattern;
+use httpserver::rewrite::Rule;
+use httpserver::router::Router;
+use httpserver::url;
+use crate::access;
+use crate::config_file;
+use crate::embedded;
+use crate::compress::{self, CompressOptions};
+use crate::form::FormLimits;
+use crate::handler::{self, Handlers, Middleware};
+use crate::headers;
+use crate::inject::{self, Injection};
+use crate::listener::{self, Keepalive, ListenAddr, SocketOptions};
+use crate::log::{self, info, warn, Level};
+use crate::privileges::{self, Privileges};
+use crate::request::{FoldPolicy, LineEndings, ParseOptions};
+use crate::response::{self, ErrorPages};
+use crate::webhook::Webhook;
+
+const DEFAULT_LISTEN: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 25565);
+
+const USAGE: &str = "\
+Usage: httpserver [OPTIONS] [ROOT]...
+
+Serves the files below ROOT (the current directory by default), several roots
+are searched in order. A single ROOT that is a file is served at / and under its
+name, and nothing else is. TRACE and CONNECT are always refused, by design: TRACE
+echoes credentials back to scripts (cross-site tracing) and we are no proxy.
+
+Listening:
+  --bind ADDR               Address to listen on, 127.0.0.1 by default
+  --port N                  Port to listen on, 25565 by default, 0 picks a free one
+  --listen ADDR:PORT        Both at once, instead of --bind and --port, repeatable.
+                            [::] is IPv6 only, add 0.0.0.0 for IPv4 as well.
+                            unix:PATH listens on a unix socket
+  --socket-mode MODE        Permissions of unix sockets in octal, like 660
+  --no-nodelay              Let small writes wait to be sent together (Nagle)
+  --keepalive SECS          Probe idle connections after SECS, off by default
+  --keepalive-interval SECS Between the probes
+  --keepalive-count N       Unanswered probes before giving up on the client
+  --backlog N               Connections waiting to be accepted, 1024 by default
+  --recv-buffer BYTES       SO_RCVBUF of the sockets
+  --send-buffer BYTES       SO_SNDBUF of the sockets
+  --reuseport N             N sockets with SO_REUSEPORT per address, Linux only.
+                            Another server on the same port shares the connections
+  --open                    Open the server in the browser once it listens
+  --qr                      Show a QR code of the url for the local network
+  --mdns                    Announce the server over mDNS as an _http._tcp service
+  --mdns-name NAME          Its name, 'Files on HOST' by default. Implies --mdns
+  --threads N               Worker threads, 1 runs everything on one thread
+  --redirect-https ADDR     Also listen on ADDR and redirect everything to https
+  --https-port N            Port in those redirects, 443 by default
+  --request-timeout SECS    Close connections whose request takes longer, 0 (off) by default
+  --drain-timeout SECS      On SIGINT or SIGTERM, wait that long for running requests
+                            before exiting, 10 by default. A second signal exits at once
+  --user USER               Switch to USER and its group once listening, Unix only
+  --group GROUP             Switch to GROUP instead of the group of USER
+  --chroot DIR              Confine the server to DIR before switching, the roots and
+                            a reloaded --config are paths inside it then
+  --daemon                  Detach from the terminal and run in the background, Unix only
+  --pid-file FILE           Write the pid there, locked so a second instance won't start
+  --log-file FILE           Append the output there, with --daemon it is lost otherwise.
+                            SIGUSR1 opens it again, and the access log file
+  --log-max-size BYTES      Rotate the log file once it is this big, to FILE.1 and on
+  --log-keep N              Rotated files to keep, 5 by default
+  --log-compress            Compress the rotated files with gzip
+
+Serving:
+  --root DIR                Another root, same as a positional one
+  --create-root             Create roots and mounted directories that don't exist,
+                            starting without them is an error otherwise
+  --embedded                Serve the directory packed into the binary instead of
+                            a ROOT, mounts still come from the disk. Needs a build
+                            with --features embedded and HTTPSERVER_EMBED=DIR.
+                            What was packed gzipped goes out gzipped when accepted
+  --mount PREFIX=DIR        Serve DIR below PREFIX instead of the roots, repeatable.
+                            The longest prefix wins, --route options apply to it
+  --canonical-host HOST     301 requests for any other Host to HOST[:PORT], same target.
+                            Before --vhost picks a site
+  --canonical-scheme SCHEME Also move them to http or https. Nothing here speaks TLS,
+                            without --trust-forwarded-proto every request is http
+  --canonical-exempt PREFIX Served on whatever host, repeatable. The ACME challenges in
+                            /.well-known/acme-challenge and /metrics are by default
+  --vhost HOST OPTS         Serve another site for requests to HOST, *.example.com for
+                            the names below it. root=DIR,listing=on|off,auth=on|off,
+                            write=on|off, the rest is shared with the roots. Repeatable
+  --unknown-host MODE       For a Host no --vhost has: default (the roots), 404 or 421
+  --count N                 Serving a single file, exit after N complete downloads of it
+  --try-files LIST          Candidates for GET and HEAD, the first one that exists is
+                            served: $path,$path/index.html,$path.html,/404.html=404.
+                            A directory only matches with a trailing '/', FILE=CODE
+                            goes out with CODE and a last =CODE is answered as it is
+  --spa                     Serve the index of a single-page app for GET and HEAD of
+                            paths that aren't files, when they accept HTML and have no
+                            extension. It goes out with Cache-Control: no-cache
+  --spa-index FILE          The index, index.html by default. Implies --spa
+  --spa-scope PREFIX        Only below PREFIX, like a mount, the index is the one in
+                            there. Implies --spa
+  --trailing-slash MODE     For GET and HEAD: add 301s directories to the path with a
+                            '/', strip 301s files asked for with one to the path
+                            without it, ignore (the default) serves both as they are
+  --follow-symlinks         Serve through links that lead out of the roots
+  --exclude PATTERN         Never list or serve names matching it, repeatable
+  --download-ext EXT,...    Serve these extensions as downloads
+  --favicon MODE            off, builtin or empty for a missing /favicon.ico
+  --listing-dates FORMAT    Modification times in listings: iso (2026-10-14 09:30, the
+                            default), text (14 Oct 2026 09:30) or relative (2 days ago), UTC
+  --listing-lang LANG       lang of the HTML listings, like de or pt-BR, en by default
+  --no-ranges               Send Accept-Ranges: none and ignore Range, whole files only
+  --compress                gzip text files for clients that accept it
+  --compression-level N     1 (fastest) to 9 (smallest), 6 by default
+  --compression-min-size N  Bytes a file needs to be compressed, 1024 by default
+  --upgrade MODE            refuse (426) or close for protocol upgrades
+  --error-page STATUS FILE  Body of errors with STATUS, like 404, or a class like 4xx.
+                            {{code}} and {{reason}} in it are filled in
+  --metrics                 Serve Prometheus metrics at /metrics
+  --echo ROUTE              Answer ROUTE with the body of the request, for trying out
+                            clients and proxies. /* at the end takes what is below it,
+                            sent back in X-Echo-Rest. The longest prefix wins
+  --rewrite PATTERN TARGET  Serve TARGET instead when the whole path matches PATTERN, a
+                            glob whose *s are $1 to $9 in TARGET. '301 URL' (or 302,
+                            307, 308) redirects there instead, keeping the query unless
+                            URL has one. Applied in order before anything else
+  --route PREFIX OPTS       cache=N|no,auth=on|off,listing=on|off,write=on|off,
+                            ranges=on|off below PREFIX
+
+Writing:
+  --write                   Accept PUT, DELETE, MKCOL, MOVE, COPY and form uploads
+  --partial-ttl SECS        How long unfinished resumable uploads are kept
+  --partial-max-size BYTES  Refuse resumable uploads of larger files, no limit by default
+  --recursive-delete        Allow DELETE ?recursive and overwriting directories
+  --form-max-fields N       Fields in a urlencoded form, 100 by default
+  --form-max-field-size N   Bytes in one of them, 16384 by default
+  --webhook URL             Post changes to this http:// url, repeatable
+  --webhook-secret SECRET   Sign the webhook payloads with HMAC-SHA256
+
+Security:
+  --auth USER:PASS          Require Basic auth, repeatable
+  --trust-forwarded-proto   Take X-Forwarded-Proto from a proxy in front that does TLS,
+                            https there makes the request count as one over TLS
+  --hsts SECS               Strict-Transport-Security over TLS, needs --trust-forwarded-proto
+  --hsts-subdomains         Add includeSubDomains to it
+  --hsts-preload            Add preload to it
+  --frame-options VALUE     X-Frame-Options, DENY by default, off to drop it
+  --referrer-policy VALUE   Referrer-Policy, no-referrer by default, off to drop it
+  --csp VALUE               Content-Security-Policy, none by default
+  --no-nosniff              Don't send X-Content-Type-Options: nosniff
+  -H, --header 'NAME: VALUE'
+                            Send this header with every response, errors and
+                            redirects too. A later one replaces one of the same name
+  --append-header 'NAME: VALUE'
+                            The same, next to those of the same name
+
+Parsing:
+  --max-headers N           Headers in a request
+  --max-line-size BYTES     Of the request line and each header line, 8192 by default.
+                            Longer ones are answered with 414 or 431
+  --unfold-headers          Accept obsolete folded header lines
+  --strict-line-endings     Only accept CRLF line endings, bare LF is taken too by default
+  --trust-request-id        Take X-Request-Id from clients
+
+Testing:
+  --delay TIME              Wait before each response, like 200ms or 1s, or a random
+                            time in a range like 100ms-2s
+  --throttle RATE           Send responses at most this fast, like 50kbps or 2mbps
+  --fail-rate P             Answer this fraction of requests with a 500 or 503, like 0.05
+  --inject-path PATTERN     Only do those to paths matching it, like '/api/*', repeatable
+
+Logging:
+  -q, --quiet               Only warnings and errors
+  -v, --verbose             Connections and request details too, -vv adds the headers
+  --log-level LEVEL         off, error, warn, info (the default), debug or trace.
+                            RUST_LOG overrides all of these when it is set
+  --trace-io                Also log the bytes of requests and responses at trace
+                            level, escaped, up to 4096 of each per request
+  --log-format FORMAT       text (the default) or json, an object on each line with
+                            the access log's lines among them
+  --access-log FILE         Append a line for every request there, - for stdout
+  --access-log-format FORMAT
+                            common (the default) or combined, which adds the
+                            Referer and User-Agent
+
+  --config FILE             Read settings from a TOML file, flags override it
+  --strict-config           Unknown keys in it are errors instead of warnings
+  --watch-config            Reload it when it changes, besides on SIGHUP
+  --print-config            Show the effective settings as TOML and exit
+  --check                   Check the settings without starting, unknown keys
+                            included. Shows them like --print-config when fine,
+                            otherwise says what is wrong and exits nonzero
+
+  -h, --help                Show this and exit
+  -V, --version             Show the version and exit
+";
+
+// Whether file, or as much of it as exists, is still below root with all links followed.
+// What doesn't exist yet can't be a link, the existing part is what has to be checked
+async fn is_inside(root: &str, file: &str) -> bool {
+    let Ok(root) = tokio::fs::canonicalize(if root.is_empty() { "/" } else { root }).await else {
+        return false;
+    };
+    let mut path = Path::new(file);
+    loop {
+        match tokio::fs::canonicalize(path).await {
+            Ok(real) => return real.starts_with(&root),
+            Err(_) => match path.parent() {
+                Some(parent) => path = parent,
+                None => return false,
+            },
+        }
+    }
+}
+
+// What to answer for /favicon.ico when there is no such file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaviconMode {
+    Off,
+    Builtin,
+    Empty,
+}
+
+// How listings show when entries were modified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateFormat {
+    Iso,
+    Text,
+    Relative,
+}
+
+// How to answer requests asking to switch protocols (WebSocket),
+// we can't do that either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeMode {
+    Refuse,  // 426 Upgrade Required
+    Close,   // Serve it as a plain request
+}
+
+// Strict-Transport-Security for responses over TLS (RFC 6797)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    pub max_age: u64,  // Seconds
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl Hsts {
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+// Headers sent on every response to keep browsers from doing something silly
+// with what we serve, None turns one off
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    pub nosniff: bool,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub content_security_policy: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        // A CSP would break the pages being shared, so that one is opt in
+        SecurityHeaders {
+            nosniff: true,
+            frame_options: Some(String::from("DENY")),
+            referrer_policy: Some(String::from("no-referrer")),
+            content_security_policy: None,
+        }
+    }
+}
+
+impl SecurityHeaders {
+    pub fn headers(&self) -> Vec<(&str, &str)> {
+        let mut headers = Vec::new();
+        if self.nosniff {
+            headers.push(("X-Content-Type-Options", "nosniff"));
+        }
+        if let Some(value) = &self.frame_options {
+            headers.push(("X-Frame-Options", value.as_str()));
+        }
+        if let Some(value) = &self.referrer_policy {
+            headers.push(("Referrer-Policy", value.as_str()));
+        }
+        if let Some(value) = &self.content_security_policy {
+            headers.push(("Content-Security-Policy", value.as_str()));
+        }
+        headers
+    }
+}
+
+// What the server works out on its own, or what is about this one connection. Any
+// of them set by hand would break the framing or contradict the response
+const RESERVED_HEADERS: &[&str] = &[
+    "connection", "content-encoding", "content-length", "content-range", "date", "keep-alive",
+    "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade",
+];
+
+// -H and --append-header, "Name: value"
+fn custom_header(arg: &str, value: Option<String>) -> Result<(String, String), String> {
+    let value = value.ok_or(format!("{arg} requires 'NAME: VALUE'"))?;
+    let (name, content) = value.split_once(':').ok_or(format!("invalid header '{value}', expected 'NAME: VALUE'"))?;
+    if !headers::is_token(name) {
+        return Err(format!("invalid header name '{name}'"));
+    }
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(format!("{name} is set by the server itself, it can't be given with {arg}"));
+    }
+    let content = content.trim_matches([' ', '\t']);
+    if content.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("invalid value for the header {name}, it must stay on one line"));
+    }
+    Ok((String::from(name), String::from(content)))
+}
+
+// Value of a header option, "off" disables it. It goes out as is, so no line breaks
+fn header_option(arg: &str, value: Option<String>) -> Result<Option<String>, String> {
+    let value = value.ok_or(format!("{arg} requires a value"))?;
+    if value.chars().any(|c| c.is_control()) {
+        return Err(format!("invalid value for {arg}"));
+    }
+    Ok(Some(value).filter(|value| value != "off"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    MaxAge(u64),  // Seconds
+    NoStore,
+}
+
+impl CachePolicy {
+    pub fn header_value(&self) -> String {
+        match self {
+            CachePolicy::MaxAge(secs) => format!("max-age={secs}"),
+            CachePolicy::NoStore => String::from("no-store"),
+        }
+    }
+}
+
+// Overrides for everything below a path prefix, None keeps the global behavior
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: String,  // Without the trailing '/', empty for the whole tree
+    pub cache: Option<CachePolicy>,
+    pub auth: Option<bool>,
+    pub listing: Option<bool>,
+    pub write: Option<bool>,
+    pub ranges: Option<bool>,
+}
+
+impl Route {
+    // "/assets", "/assets/" and "/assets/*" all mean everything below /assets
+    fn parse(prefix: &str, options: &str) -> Result<Route, String> {
+        if !prefix.starts_with('/') {
+            return Err(format!("route '{prefix}' must start with '/'"));
+        }
+        let prefix = prefix.trim_end_matches('*').trim_end_matches('/');
+        let mut route = Route { prefix: String::from(prefix), ..Route::default() };
+        let switch = |value: &str| match value {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(format!("invalid value '{value}' in route '{prefix}', expected on or off")),
+        };
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or(format!("invalid route option '{option}'"))?;
+            match key {
+                "cache" if value == "no" => route.cache = Some(CachePolicy::NoStore),
+                "cache" => {
+                    let secs = value.parse().map_err(|_| format!("invalid max-age '{value}' in route '{prefix}'"))?;
+                    route.cache = Some(CachePolicy::MaxAge(secs));
+                }
+                "auth" => route.auth = Some(switch(value)?),
+                "listing" => route.listing = Some(switch(value)?),
+                "write" => route.write = Some(switch(value)?),
+                "ranges" => route.ranges = Some(switch(value)?),
+                _ => return Err(format!("unknown route option '{key}', expected cache, auth, listing, write or ranges")),
+            }
+        }
+        Ok(route)
+    }
+
+    // On segment boundaries, /assets covers /assets/a.css but not /assets2
+    fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+// A directory served below a path prefix instead of the roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub prefix: String,  // Like a route's, without the trailing '/' and never empty
+    pub dir: String,  // Without a trailing '/'
+}
+
+impl Mount {
+    fn parse(value: &str) -> Result<Mount, String> {
+        let (prefix, dir) = value.split_once('=').ok_or(format!("invalid mount '{value}', expected /prefix=/dir"))?;
+        if !prefix.starts_with('/') {
+            return Err(format!("mount '{prefix}' must start with '/'"));
+        }
+        // Compared against normalized paths, so it has to be one
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            return Err(String::from("/ can't be mounted, it is the root"));
+        }
+        if prefix.split('/').skip(1).any(|segment| matches!(segment, "" | "." | "..")) {
+            return Err(format!("invalid mount '{prefix}', expected a plain path"));
+        }
+        if dir.is_empty() {
+            return Err(format!("mount '{prefix}' needs a directory"));
+        }
+        // Trimmed like a root, "/" becomes "" for the filesystem root
+        Ok(Mount { prefix: String::from(prefix), dir: String::from(dir.trim_end_matches('/')) })
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+// Another site on the same listeners, picked by the Host header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VirtualHost {
+    pub name: String,  // Lowercase, "*.example.com" for any name below example.com
+    pub root: String,  // Without a trailing '/'
+    pub listing: Option<bool>,
+    pub auth: Option<bool>,
+    pub write: Option<bool>,
+}
+
+impl VirtualHost {
+    fn parse(name: &str, options: &str) -> Result<VirtualHost, String> {
+        let domain = name.strip_prefix("*.").unwrap_or(name);
+        if domain.contains(':') || url::host_name(domain).is_none_or(|valid| valid.starts_with('[')) {
+            return Err(format!("invalid host name '{name}' for --vhost"));
+        }
+        let mut vhost = VirtualHost { name: name.trim_end_matches('.').to_ascii_lowercase(), ..VirtualHost::default() };
+        let mut root = None;
+        let switch = |value: &str| match value {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(format!("invalid value '{value}' for host {name}, expected on or off")),
+        };
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or(format!("invalid host option '{option}'"))?;
+            match key {
+                "root" if !value.is_empty() => root = Some(String::from(value.trim_end_matches('/'))),
+                "listing" => vhost.listing = Some(switch(value)?),
+                "auth" => vhost.auth = Some(switch(value)?),
+                "write" => vhost.write = Some(switch(value)?),
+                _ => return Err(format!("invalid host option '{option}', expected root, listing, auth or write")),
+            }
+        }
+        vhost.root = root.ok_or(format!("host {name} needs a root=DIR"))?;
+        Ok(vhost)
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self.name.strip_prefix('*') {
+            Some(domain) => host.len() > domain.len() && host.ends_with(domain),
+            None => host == self.name,
+        }
+    }
+}
+
+// One candidate of --try-files, $path in it is the path of the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryFile {
+    Path(String, Option<i32>),  // Served with the status when there is one
+    Status(i32),  // Nothing was there, always the last one
+}
+
+impl TryFile {
+    fn parse(value: &str) -> Result<TryFile, String> {
+        let status = |code: &str| match code.parse::<i32>() {
+            Ok(code) if (code == 200 || (400..600).contains(&code)) && response::reason_phrase(code).is_some() => Ok(code),
+            _ => Err(format!("invalid status '{code}' in --try-files, expected 200 or an error like 404")),
+        };
+        if let Some(code) = value.strip_prefix('=') {
+            return Ok(TryFile::Status(status(code)?));
+        }
+        let (template, code) = match value.rsplit_once('=') {
+            Some((template, code)) => (template, Some(status(code)?)),
+            None => (value, None),
+        };
+        // Whatever the path is, the candidate must stay a normalized one
+        let example = template.replace("$path", "/x");
+        if !example.starts_with('/') || example.split('/').skip(1).any(|segment| matches!(segment, "." | "..")) {
+            return Err(format!("invalid candidate '{template}' in --try-files, expected a path like $path.html or /404.html"));
+        }
+        Ok(TryFile::Path(String::from(template), code))
+    }
+
+    // How it is given, for --print-config
+    pub fn as_string(&self) -> String {
+        match self {
+            TryFile::Path(template, Some(code)) => format!("{template}={code}"),
+            TryFile::Path(template, None) => template.clone(),
+            TryFile::Status(code) => format!("={code}"),
+        }
+    }
+}
+
+// What --try-files settled on
+pub enum Tried {
+    File { path: String, file: String, meta: Box<std::fs::Metadata>, status: Option<i32> },
+    Status(i32),
+}
+
+// What a Host no --vhost has gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownHost {
+    Default,  // The roots, like without virtual hosts
+    NotFound,  // 404
+    Misdirected,  // 421, the client may try another connection
+}
+
+// Whether paths are redirected to have a '/' at the end, or not to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    Add,  // For directories
+    Strip,  // For files
+    Ignore,
+}
+
+// Settings of the server, filled from the command line
+#[derive(Clone)]
+pub struct Config {
+    pub listen: Vec<ListenAddr>,  // All bound at startup, never empty
+    pub socket_mode: Option<u32>,  // Permissions of unix sockets, the umask decides otherwise
+    pub socket: SocketOptions,
+    pub reuse_port: Option<usize>,  // Sockets per tcp address with SO_REUSEPORT, it is off without
+    pub threads: usize,
+    pub privileges: Privileges,  // Dropped after binding
+    pub daemon: bool,  // Fork into the background before starting
+    pub pid_file: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,  // Where stdout and stderr go
+    pub log_max_size: Option<u64>,  // Rotated when it gets this big
+    pub log_keep: u32,
+    pub log_compress: bool,  // Rotated files are gzipped
+    pub open: bool,  // Launch a browser at startup
+    pub qr: bool,  // Print a QR code of the url at startup
+    pub mdns: Option<String>,  // Name of the service announced over mDNS, "" for the default
+    pub parser: ParseOptions,
+    pub favicon: FaviconMode,
+    pub listing_dates: DateFormat,
+    pub listing_lang: String,  // A language tag for <html lang>
+    pub download_extensions: Vec<String>,  // Lowercase, without the dot
+    pub upgrade: UpgradeMode,
+    pub exclude: Vec<Pattern>,  // Names neither listed nor served
+    pub write: bool,  // Accept uploads with PUT
+    pub partial_ttl: Duration,  // How long an unfinished resumable upload is kept
+    pub partial_max_size: Option<u64>,  // Largest total a resumable upload may announce
+    pub request_timeout: Option<Duration>,  // Cap on reading, handling and answering one request
+    pub drain_timeout: Duration,  // How long a shutdown waits for the connections
+    pub recursive_delete: bool,  // DELETE with ?recursive removes whole directories
+    pub trust_request_id: bool,  // Use X-Request-Id from clients instead of our own
+    pub trust_forwarded_proto: bool,  // Requests whose X-Forwarded-Proto is https came over TLS
+    pub log_level: Level,
+    pub log_format: log::Format,
+    pub trace_io: bool,  // The bytes on the connections, at trace level
+    pub access_log: Option<PathBuf>,  // "-" for stdout, opened at startup
+    pub access_format: access::Format,
+    pub inject: Injection,
+    pub redirect_https: Option<String>,  // Address of a plaintext listener redirecting to https
+    pub https_port: u16,  // Port in the redirects, left out when it's 443
+    pub hsts: Option<Hsts>,
+    pub security: SecurityHeaders,
+    pub headers: Vec<(String, String)>,  // -H and --append-header, sent in this order
+    pub credentials: Vec<(String, String)>,  // Users and passwords for Basic auth
+    pub routes: Vec<Route>,
+    pub mounts: Vec<Mount>,
+    pub canonical_host: Option<String>,  // Lowercase name, with the port when it isn't the default
+    pub canonical_scheme: Option<&'static str>,  // "http" or "https"
+    pub canonical_exempt: Vec<String>,  // Prefixes like a route's
+    pub rewrites: Vec<Rule>,
+    pub vhosts: Vec<VirtualHost>,
+    pub unknown_host: UnknownHost,
+    // The settings of each of the vhosts, in the same order. Built once parsing is
+    // done, so a request only has to pick one
+    sites: Vec<Arc<Config>>,
+    pub error_pages: Arc<ErrorPages>,
+    pub metrics: bool,  // Answer /metrics instead of looking for a file
+    pub echo: Vec<String>,  // Routes answered with the request's own body
+    pub handlers: Handlers,  // Built from the rest once parsing is done, like the sites
+    pub file_layers: Vec<Arc<dyn Middleware>>,  // The middleware around files, built with the handlers
+    pub form: FormLimits,
+    pub ranges: bool,  // Accept-Ranges: bytes, parts of files are served
+    pub compress: CompressOptions,
+    pub follow_symlinks: bool,  // Serve through links leading out of the roots
+    pub roots: Vec<String>,  // Searched in order, without a trailing '/', "" is the filesystem root
+    pub create_root: bool,  // Missing roots are created instead of refused
+    pub embedded: bool,  // What no mount covers is served from the binary
+    pub single_file: Option<String>,  // Name of the only file served, in the only root
+    pub count: Option<u64>,  // Downloads of the single file before exiting
+    pub try_files: Vec<TryFile>,
+    pub spa: Option<String>,  // Path of the app's index below its scope, for what has no file
+    pub spa_scope: String,  // Like a route's prefix, empty for the whole tree
+    pub trailing_slash: TrailingSlash,
+    pub webhooks: Vec<Webhook>,  // Told about every change made through the server
+    pub webhook_secret: Option<String>,  // Key of the HMAC signing their payloads
+    pub config_file: Option<String>,  // Where --config read the settings from, for reloading
+    pub watch_config: bool,  // Reload when the file changes, not only on SIGHUP
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![ListenAddr::Tcp(DEFAULT_LISTEN)],
+            socket_mode: None,
+            socket: SocketOptions::default(),
+            reuse_port: None,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            privileges: Privileges::default(),
+            daemon: false,
+            pid_file: None,
+            log_file: None,
+            log_max_size: None,
+            log_keep: 5,
+            log_compress: false,
+            open: false,
+            qr: false,
+            mdns: None,
+            parser: ParseOptions::default(),
+            favicon: FaviconMode::Off,
+            listing_dates: DateFormat::Iso,
+            listing_lang: String::from("en"),
+            download_extensions: Vec::new(),
+            upgrade: UpgradeMode::Close,
+            exclude: Vec::new(),
+            write: false,
+            partial_ttl: Duration::from_secs(24 * 60 * 60),
+            partial_max_size: None,
+            request_timeout: None,
+            drain_timeout: Duration::from_secs(10),
+            recursive_delete: false,
+            trust_request_id: false,
+            trust_forwarded_proto: false,
+            log_level: Level::Info,
+            log_format: log::Format::Text,
+            trace_io: false,
+            access_log: None,
+            access_format: access::Format::Common,
+            inject: Injection::default(),
+            redirect_https: None,
+            https_port: 443,
+            hsts: None,
+            security: SecurityHeaders::default(),
+            headers: Vec::new(),
+            credentials: Vec::new(),
+            routes: Vec::new(),
+            mounts: Vec::new(),
+            canonical_host: None,
+            canonical_scheme: None,
+            canonical_exempt: vec![String::from("/.well-known/acme-challenge"), String::from("/metrics")],
+            rewrites: Vec::new(),
+            vhosts: Vec::new(),
+            unknown_host: UnknownHost::Default,
+            sites: Vec::new(),
+            error_pages: Arc::default(),
+            metrics: false,
+            echo: Vec::new(),
+            handlers: Handlers::new(),
+            file_layers: Vec::new(),
+            form: FormLimits::default(),
+            ranges: true,
+            compress: CompressOptions::default(),
+            follow_symlinks: false,
+            roots: Vec::new(),
+            create_root: false,
+            embedded: false,
+            single_file: None,
+            count: None,
+            try_files: Vec::new(),
+            spa: None,
+            spa_scope: String::new(),
+            trailing_slash: TrailingSlash::Ignore,
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            config_file: None,
+            watch_config: false,
+        }
+    }
+}
+
+impl Config {
+    // Whether any segment of the path is excluded, which hides everything below it too
+    pub fn is_excluded(&self, path: &str) -> bool {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .any(|name| self.exclude.iter().any(|pattern| pattern.matches(name)))
+    }
+
+    // The route with the longest prefix covering the path
+    pub fn route(&self, path: &str) -> Option<&Route> {
+        self.routes.iter()
+            .filter(|route| route.covers(path))
+            .max_by_key(|route| route.prefix.len())
+    }
+
+    // With credentials everything needs them, unless a route says otherwise
+    pub fn needs_auth(&self, path: &str) -> bool {
+        self.route(path).and_then(|route| route.auth).unwrap_or(!self.credentials.is_empty())
+    }
+
+    pub fn listing_enabled(&self, path: &str) -> bool {
+        self.route(path).and_then(|route| route.listing).unwrap_or(true)
+    }
+
+    pub fn cache_policy(&self, path: &str) -> Option<CachePolicy> {
+        self.route(path).and_then(|route| route.cache)
+    }
+
+    // Unless --no-ranges or a route turns them off
+    pub fn ranges(&self, path: &str) -> bool {
+        self.route(path).and_then(|route| route.ranges).unwrap_or(self.ranges)
+    }
+
+    // --write, unless a route makes it read-only or writable below its prefix
+    pub fn writes(&self, path: &str) -> bool {
+        !self.is_embedded(path) && self.route(path).and_then(|route| route.write).unwrap_or(self.write)
+    }
+
+    // Whether any path takes writes, a route may allow them when the rest doesn't
+    pub fn writes_anywhere(&self) -> bool {
+        self.write || self.routes.iter().any(|route| route.write == Some(true))
+    }
+
+    // -H and --append-header, each with whether it is the first of its name. That one
+    // replaces a header the server sends on its own, the others go next to it
+    pub fn custom_headers(&self) -> impl Iterator<Item = (&str, &str, bool)> {
+        self.headers.iter().enumerate().map(|(i, (name, value))| {
+            let first = !self.headers[..i].iter().any(|(other, _)| other.eq_ignore_ascii_case(name));
+            (name.as_str(), value.as_str(), first)
+        })
+    }
+
+    // Whether the path is served from the binary, nothing about it is on disk then
+    pub fn is_embedded(&self, path: &str) -> bool {
+        self.embedded && self.mount(path).is_none()
+    }
+
+    // The settings for the name in a Host header, and the vhost it picked unless it is
+    // the roots. Err is the status to answer with. Exact names go before wildcards, the
+    // longest of those wins
+    pub fn site(&self, host: Option<&str>) -> Result<(&Config, Option<&str>), i32> {
+        if self.vhosts.is_empty() {
+            return Ok((self, None));
+        }
+        let Some(host) = host else {
+            return self.unknown_site();
+        };
+        let host = url::host_name(host).ok_or(400)?;
+        let found = self.vhosts.iter().position(|vhost| vhost.name == host).or_else(|| {
+            self.vhosts.iter().enumerate()
+                .filter(|(_, vhost)| vhost.matches(&host))
+                .max_by_key(|(_, vhost)| vhost.name.len())
+                .map(|(index, _)| index)
+        });
+        match found {
+            Some(index) => Ok((&self.sites[index], Some(self.vhosts[index].name.as_str()))),
+            None => self.unknown_site(),
+        }
+    }
+
+    fn unknown_site(&self) -> Result<(&Config, Option<&str>), i32> {
+        match self.unknown_host {
+            UnknownHost::Default => Ok((self, None)),
+            UnknownHost::NotFound => Err(404),
+            UnknownHost::Misdirected => Err(421),
+        }
+    }
+
+    // A vhost shares everything with the roots except what is served and how. Routes,
+    // mounts and the rest that is about paths stay with the roots
+    fn vhost_site(&self, vhost: &VirtualHost) -> Config {
+        let mut site = self.clone();
+        site.roots = vec![vhost.root.clone()];
+        site.embedded = false;
+        site.routes = vec![Route { prefix: String::new(), auth: vhost.auth, listing: vhost.listing, write: vhost.write, ..Route::default() }];
+        site.mounts = Vec::new();
+        site.rewrites = Vec::new();
+        site.single_file = None;
+        site.count = None;
+        site.spa = None;
+        site.vhosts = Vec::new();
+        site.sites = Vec::new();
+        site
+    }
+
+    // The first --try-files candidate that exists for a path, stat once each and that
+    // metadata is what it is served with. Candidates someone couldn't ask for directly,
+    // outside the roots, excluded or behind auth, count as missing. None is the path as
+    // it is, when nothing matched and there is no status to fall back to
+    pub async fn try_files(&self, path: &str, authenticated: bool) -> Option<Tried> {
+        let base = path.trim_end_matches('/');
+        for candidate in &self.try_files {
+            let (template, status) = match candidate {
+                TryFile::Status(code) => return Some(Tried::Status(*code)),
+                TryFile::Path(template, status) => (template, status),
+            };
+            let candidate = match template.replace("$path", base) {
+                empty if empty.is_empty() => String::from("/"),
+                candidate => candidate,
+            };
+            // $path.html of the root
+            if !candidate.starts_with('/') || self.is_excluded(&candidate) || (!authenticated && self.needs_auth(&candidate)) {
+                continue;
+            }
+            let Some(file) = self.resolve(&candidate).await else {
+                continue;
+            };
+            let Ok(meta) = tokio::fs::metadata(&file).await else {
+                continue;
+            };
+            // $path of the root is "/" like $path/, it still only takes a file
+            if meta.is_dir() == template.ends_with('/') {
+                return Some(Tried::File { path: candidate, file, meta: Box::new(meta), status: *status });
+            }
+        }
+        None
+    }
+
+    // The index of the app when the path is one of its routes
+    pub fn spa_index(&self, path: &str) -> Option<String> {
+        let index = self.spa.as_ref()?;
+        let inside = path.strip_prefix(self.spa_scope.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        inside.then(|| format!("{}/{index}", self.spa_scope))
+    }
+
+    // Everything served has to be a directory that is there, a typo in a root would
+    // otherwise only show as 404s. With --create-root the missing ones are made
+    pub fn check_roots(&self) -> Result<(), String> {
+        for path in self.served_dirs() {
+            match std::fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {}
+                Ok(_) => return Err(format!("{path} is not a directory")),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound && self.create_root => {
+                    std::fs::create_dir_all(path).map_err(|err| format!("failed to create {path} by {err}"))?;
+                    info!("created {path}");
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(format!("{path} does not exist, add --create-root to create it"));
+                }
+                Err(err) => return Err(format!("can't serve {path}, {err}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn served_dirs(&self) -> impl Iterator<Item = &str> {
+        let mounts = self.mounts.iter().map(|mount| &mount.dir);
+        let vhosts = self.vhosts.iter().map(|vhost| &vhost.root);
+        // The roots are unused while the binary is served
+        let roots = self.roots.iter().filter(|_| !self.embedded);
+        roots.chain(mounts).chain(vhosts).map(|dir| if dir.is_empty() { "/" } else { dir.as_str() })
+    }
+
+    // For --check: what would keep the server from starting, short of binding. Nothing
+    // is created or changed, a root --create-root would make is fine
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        #[cfg(unix)]
+        if let Err(err) = privileges::check(&self.privileges) {
+            problems.push(err);
+        }
+        // Roots are looked up inside the chroot, once it has been entered
+        let chroot = self.privileges.chroot.as_deref().unwrap_or(Path::new("/"));
+        for dir in self.served_dirs() {
+            let path = chroot.join(dir.trim_start_matches('/'));
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => {}
+                Ok(_) => problems.push(format!("{dir} is not a directory")),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound && self.create_root => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    problems.push(format!("{dir} does not exist, add --create-root to create it"));
+                }
+                Err(err) => problems.push(format!("can't serve {dir}, {err}")),
+            }
+        }
+        problems.extend(self.rotation_conflict());
+        // Written at startup, before any chroot
+        let sockets = self.listen.iter().filter_map(|addr| match addr {
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => Some(path.as_path()),
+            _ => None,
+        });
+        for file in sockets.chain(self.pid_file.as_deref()).chain(self.log_file.as_deref()).chain(self.access_log.as_deref().filter(|path| *path != Path::new("-"))) {
+            let parent = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !parent.is_dir() {
+                problems.push(format!("{} can't be written, {} is not a directory", file.display(), parent.display()));
+            }
+        }
+        problems
+    }
+
+    // The log file is named outside the chroot, from inside it rotating would rename
+    // and create whatever has that path there
+    fn rotation_conflict(&self) -> Option<String> {
+        let chroot = self.privileges.chroot.as_ref().filter(|_| self.log_max_size.is_some())?;
+        Some(format!("--log-max-size can't rotate the log file from inside --chroot {}, logrotate with copytruncate can do it from outside", chroot.display()))
+    }
+
+    // The mount with the longest prefix covering the path, None leaves it to the roots
+    pub fn mount(&self, path: &str) -> Option<&Mount> {
+        self.mounts.iter()
+            .filter(|mount| mount.covers(path))
+            .max_by_key(|mount| mount.prefix.len())
+    }
+
+    pub fn is_mount_point(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        self.mounts.iter().any(|mount| mount.prefix == path)
+    }
+
+    // Names of the mounts right below a directory, listed like its own entries
+    pub fn mounts_in<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let parent = path.trim_end_matches('/');
+        self.mounts.iter().filter_map(move |mount| {
+            let name = mount.prefix.strip_prefix(parent)?.strip_prefix('/')?;
+            (!name.contains('/')).then_some(name)
+        })
+    }
+
+    // Where a request path is on disk: in the first root that has it, or in the first
+    // root when none does so new files go there. The path is normalized already, joining
+    // it can't climb out of a root, but a link can. Without --follow-symlinks None when
+    // it does. Without roots (only before parsing) paths are taken as they are. Serving
+    // a single file, it is at / and under its name and nothing else is there
+    pub async fn resolve(&self, path: &str) -> Option<String> {
+        if let (Some(name), Some(root)) = (&self.single_file, self.roots.first()) {
+            return (path == "/" || path.strip_prefix('/') == Some(name)).then(|| format!("{root}/{name}"));
+        }
+        // Confined to its own directory, neither a link nor anything else leads into
+        // another mount or a root
+        if let Some(mount) = self.mount(path) {
+            let file = format!("{}{}", mount.dir, &path[mount.prefix.len()..]);
+            return match self.follow_symlinks || is_inside(&mount.dir, &file).await {
+                true => Some(file),
+                false => None,
+            };
+        }
+        let mut found = None;
+        for root in &self.roots {
+            let file = format!("{root}{path}");
+            if tokio::fs::symlink_metadata(&file).await.is_ok() {
+                found = Some((root, file));
+                break;
+            }
+        }
+        let (root, file) = match (found, self.roots.first()) {
+            (Some(found), _) => found,
+            (None, Some(root)) => (root, format!("{root}{path}")),
+            (None, None) => return Some(String::from(path)),
+        };
+        match self.follow_symlinks || is_inside(root, &file).await {
+            true => Some(file),
+            false => None,
+        }
+    }
+
+    // The file of --config first if there is one, then the flags on top of it
+    pub fn from_args() -> Result<Config, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let file = args.iter().position(|arg| arg == "--config")
+            .map(|i| args.get(i + 1).ok_or("--config requires a path"))
+            .transpose()?;
+        // Checking, an unknown key is as wrong as anything else
+        let strict = args.iter().any(|arg| arg == "--strict-config" || arg == "--check");
+        let mut config = match file {
+            Some(path) => config_file::load(path, strict)?,
+            None => Config::default(),
+        };
+        config.config_file = file.cloned();
+        let config = config.merge(args.iter().cloned())?;
+        if args.iter().any(|arg| arg == "--check") {
+            let problems = config.validate();
+            for problem in &problems {
+                eprintln!("httpserver: {problem}");
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+            print!("{}", config_file::print(&config));
+            std::process::exit(0);
+        }
+        if args.iter().any(|arg| arg == "--print-config") {
+            print!("{}", config_file::print(&config));
+            std::process::exit(0);
+        }
+        Ok(config)
+    }
+
+    // Settings only read when the server starts, a reload keeps the old ones and
+    // says what it couldn't change
+    fn keep_startup_settings(&self, new: &mut Config) -> Vec<&'static str> {
+        let mut kept = Vec::new();
+        let bound = |config: &Config| (config.socket.backlog, config.socket.recv_buffer, config.socket.send_buffer);
+        if new.listen != self.listen || new.socket_mode != self.socket_mode || new.reuse_port != self.reuse_port || bound(new) != bound(self) {
+            kept.push("listen address");
+        }
+        if new.threads != self.threads {
+            kept.push("threads");
+        }
+        if new.privileges != self.privileges {
+            kept.push("user, group and chroot");
+        }
+        let rotation = |config: &Config| (config.log_max_size, config.log_keep, config.log_compress);
+        if new.daemon != self.daemon || new.pid_file != self.pid_file || new.log_file != self.log_file || rotation(new) != rotation(self) {
+            kept.push("daemon, pid and log files");
+        }
+        if new.access_log != self.access_log {
+            kept.push("access log");
+        }
+        if new.redirect_https != self.redirect_https || new.https_port != self.https_port {
+            kept.push("https redirects");
+        }
+        if new.mdns != self.mdns {
+            kept.push("mDNS");
+        }
+        let urls = |config: &Config| config.webhooks.iter().map(|hook| hook.url.clone()).collect::<Vec<_>>();
+        if urls(new) != urls(self) || new.webhook_secret != self.webhook_secret {
+            kept.push("webhooks");
+        }
+        new.listen = self.listen.clone();
+        new.socket_mode = self.socket_mode;
+        new.reuse_port = self.reuse_port;
+        new.socket.backlog = self.socket.backlog;
+        new.socket.recv_buffer = self.socket.recv_buffer;
+        new.socket.send_buffer = self.socket.send_buffer;
+        new.threads = self.threads;
+        new.privileges = self.privileges.clone();
+        new.daemon = self.daemon;
+        new.pid_file = self.pid_file.clone();
+        new.log_file = self.log_file.clone();
+        new.log_max_size = self.log_max_size;
+        new.log_keep = self.log_keep;
+        new.log_compress = self.log_compress;
+        new.access_log = self.access_log.clone();
+        new.redirect_https = self.redirect_https.clone();
+        new.https_port = self.https_port;
+        new.mdns = self.mdns.clone();
+        new.webhooks = self.webhooks.clone();
+        new.webhook_secret = self.webhook_secret.clone();
+        kept
+    }
+
+    // Apply flags over what is set already. Later flags win, repeatable ones add up,
+    // except that roots given here replace the earlier ones
+    pub fn merge(self, args: impl IntoIterator<Item = String>) -> Result<Config, String> {
+        let mut config = self;
+        let mut args = args.into_iter();
+        let (mut bind, mut port, mut listen) = (None, None, Vec::new());
+        let (mut keepalive_interval, mut keepalive_count) = (None, None);
+        let mut roots = Vec::new();
+        let mut try_files = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    std::process::exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("httpserver {}", env!("CARGO_PKG_VERSION"));
+                    std::process::exit(0);
+                }
+                // Dealt with by from_args
+                "--config" => {
+                    args.next();
+                }
+                "--strict-config" | "--print-config" | "--check" => {}
+                "--watch-config" => config.watch_config = true,
+                "--bind" => {
+                    let value = args.next().ok_or("--bind requires an address")?;
+                    bind = Some(value.parse::<IpAddr>().map_err(|_| format!("invalid address '{value}'"))?);
+                }
+                "--port" => {
+                    let value = args.next().ok_or("--port requires a value")?;
+                    port = Some(value.parse::<u16>().map_err(|_| format!("invalid port '{value}'"))?);
+                }
+                "--listen" => {
+                    let value = args.next().ok_or("--listen requires ADDR:PORT")?;
+                    listen.push(listener::parse(&value)?);
+                }
+                "--threads" => {
+                    let value = args.next().ok_or("--threads requires a value")?;
+                    config.threads = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid thread count '{value}'")),
+                    };
+                }
+                "--favicon" => {
+                    let value = args.next().ok_or("--favicon requires a value")?;
+                    config.favicon = match value.as_str() {
+                        "off" => FaviconMode::Off,
+                        "builtin" => FaviconMode::Builtin,
+                        "empty" => FaviconMode::Empty,
+                        _ => return Err(format!("invalid favicon mode '{value}', expected off, builtin or empty")),
+                    };
+                }
+                "--listing-lang" => {
+                    let value = args.next().ok_or("--listing-lang requires a language")?;
+                    // Letters, digits and hyphens is all a tag (BCP 47) has, and all that
+                    // is safe in the attribute without escaping
+                    if value.split('-').any(|part| part.is_empty() || part.len() > 8 || !part.chars().all(|ch| ch.is_ascii_alphanumeric())) {
+                        return Err(format!("invalid language '{value}', expected a tag like en or pt-BR"));
+                    }
+                    config.listing_lang = value;
+                }
+                "--listing-dates" => {
+                    let value = args.next().ok_or("--listing-dates requires a format")?;
+                    config.listing_dates = match value.as_str() {
+                        "iso" => DateFormat::Iso,
+                        "text" => DateFormat::Text,
+                        "relative" => DateFormat::Relative,
+                        _ => return Err(format!("invalid date format '{value}', expected iso, text or relative")),
+                    };
+                }
+                "--max-headers" => {
+                    let value = args.next().ok_or("--max-headers requires a value")?;
+                    config.parser.max_headers = value.parse()
+                        .map_err(|_| format!("invalid header count '{value}'"))?;
+                }
+                "--max-line-size" => {
+                    let value = args.next().ok_or("--max-line-size requires a value")?;
+                    config.parser.max_line = value.parse().ok().filter(|&size| size > 0)
+                        .ok_or_else(|| format!("invalid line size '{value}'"))?;
+                }
+                "--download-ext" => {
+                    let value = args.next().ok_or("--download-ext requires a value")?;
+                    let extensions = value.split(',')
+                        .map(|ext| ext.trim().trim_start_matches('.').to_ascii_lowercase())
+                        .filter(|ext| !ext.is_empty());
+                    config.download_extensions.extend(extensions);
+                }
+                "--upgrade" => {
+                    let value = args.next().ok_or("--upgrade requires a value")?;
+                    config.upgrade = match value.as_str() {
+                        "refuse" => UpgradeMode::Refuse,
+                        "close" => UpgradeMode::Close,
+                        _ => return Err(format!("invalid upgrade mode '{value}', expected refuse or close")),
+                    };
+                }
+                "--exclude" => {
+                    let value = args.next().ok_or("--exclude requires a pattern")?;
+                    config.exclude.push(Pattern::new(&value).map_err(|err| err.to_string())?);
+                }
+                "--redirect-https" => {
+                    config.redirect_https = Some(args.next().ok_or("--redirect-https requires an address")?);
+                }
+                "--https-port" => {
+                    let value = args.next().ok_or("--https-port requires a value")?;
+                    config.https_port = match value.parse::<u16>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid port '{value}'")),
+                    };
+                }
+                "--hsts" => {
+                    let value = args.next().ok_or("--hsts requires a max-age")?;
+                    let max_age = value.parse().map_err(|_| format!("invalid max-age '{value}'"))?;
+                    let hsts = config.hsts.get_or_insert(Hsts { max_age, include_subdomains: false, preload: false });
+                    hsts.max_age = max_age;
+                }
+                "--hsts-subdomains" | "--hsts-preload" => {
+                    let hsts = config.hsts.as_mut().ok_or(format!("{arg} requires --hsts before it"))?;
+                    match arg.as_str() {
+                        "--hsts-subdomains" => hsts.include_subdomains = true,
+                        _ => hsts.preload = true,
+                    }
+                }
+                "--frame-options" => config.security.frame_options = header_option(&arg, args.next())?,
+                "--referrer-policy" => config.security.referrer_policy = header_option(&arg, args.next())?,
+                "--csp" => config.security.content_security_policy = header_option(&arg, args.next())?,
+                "--no-nosniff" => config.security.nosniff = false,
+                "-H" | "--header" => {
+                    let (name, value) = custom_header(&arg, args.next())?;
+                    config.headers.retain(|(other, _)| !other.eq_ignore_ascii_case(&name));
+                    config.headers.push((name, value));
+                }
+                "--append-header" => config.headers.push(custom_header(&arg, args.next())?),
+                "--auth" => {
+                    let value = args.next().ok_or("--auth requires user:password")?;
+                    let (user, password) = value.split_once(':').ok_or("--auth requires user:password")?;
+                    config.credentials.push((String::from(user), String::from(password)));
+                }
+                "--mount" => {
+                    let value = args.next().ok_or("--mount requires /prefix=/dir")?;
+                    let mount = Mount::parse(&value)?;
+                    if config.mounts.iter().any(|other| other.prefix == mount.prefix) {
+                        return Err(format!("{} is mounted twice", mount.prefix));
+                    }
+                    config.mounts.push(mount);
+                }
+                "--canonical-host" => {
+                    let value = args.next().ok_or("--canonical-host requires a host")?;
+                    if value.contains('/') || url::host_name(&value).is_none() {
+                        return Err(format!("invalid canonical host '{value}', expected a name like example.com[:8080]"));
+                    }
+                    config.canonical_host = Some(value.to_ascii_lowercase());
+                }
+                "--canonical-scheme" => {
+                    let value = args.next().ok_or("--canonical-scheme requires http or https")?;
+                    config.canonical_scheme = match value.as_str() {
+                        "http" => Some("http"),
+                        "https" => Some("https"),
+                        _ => return Err(format!("invalid canonical scheme '{value}', expected http or https")),
+                    };
+                }
+                "--canonical-exempt" => {
+                    let value = args.next().ok_or("--canonical-exempt requires a prefix")?;
+                    if !value.starts_with('/') {
+                        return Err(format!("canonical exemption '{value}' must start with '/'"));
+                    }
+                    let prefix = String::from(value.trim_end_matches('/'));
+                    if !config.canonical_exempt.contains(&prefix) {
+                        config.canonical_exempt.push(prefix);
+                    }
+                }
+                "--rewrite" => {
+                    let pattern = args.next().ok_or("--rewrite requires a pattern and a target")?;
+                    let target = args.next().ok_or("--rewrite requires a pattern and a target")?;
+                    let rule = Rule::parse(&pattern, &target).map_err(|err| err.to_string())?;
+                    config.rewrites.retain(|other| other.pattern() != rule.pattern());
+                    config.rewrites.push(rule);
+                }
+                "--vhost" => {
+                    let name = args.next().ok_or("--vhost requires a host name")?;
+                    let options = args.next().ok_or("--vhost requires options")?;
+                    let vhost = VirtualHost::parse(&name, &options)?;
+                    // Given again, like from the command line over a config file
+                    config.vhosts.retain(|other| other.name != vhost.name);
+                    config.vhosts.push(vhost);
+                }
+                "--trailing-slash" => {
+                    let value = args.next().ok_or("--trailing-slash requires a mode")?;
+                    config.trailing_slash = match value.as_str() {
+                        "add" => TrailingSlash::Add,
+                        "strip" => TrailingSlash::Strip,
+                        "ignore" => TrailingSlash::Ignore,
+                        _ => return Err(format!("invalid trailing slash mode '{value}', expected add, strip or ignore")),
+                    };
+                }
+                "--unknown-host" => {
+                    let value = args.next().ok_or("--unknown-host requires a mode")?;
+                    config.unknown_host = match value.as_str() {
+                        "default" => UnknownHost::Default,
+                        "404" => UnknownHost::NotFound,
+                        "421" => UnknownHost::Misdirected,
+                        _ => return Err(format!("invalid unknown host mode '{value}', expected default, 404 or 421")),
+                    };
+                }
+                "--route" => {
+                    let prefix = args.next().ok_or("--route requires a prefix and options")?;
+                    let options = args.next().ok_or("--route requires a prefix and options")?;
+                    config.routes.push(Route::parse(&prefix, &options)?);
+                }
+                "--error-page" => {
+                    let status = args.next().ok_or("--error-page requires a status and a file")?;
+                    let file = args.next().ok_or("--error-page requires a status and a file")?;
+                    Arc::make_mut(&mut config.error_pages).add(&status, &file)?;
+                }
+                "--root" => {
+                    let value = args.next().ok_or("--root requires a directory")?;
+                    roots.push(String::from(value.trim_end_matches('/')));
+                }
+                "--webhook" => {
+                    let value = args.next().ok_or("--webhook requires a url")?;
+                    let hook = Webhook::parse(&value).ok_or_else(|| format!("invalid webhook url '{value}', expected http://host[:port]/path"))?;
+                    config.webhooks.push(hook);
+                }
+                "--webhook-secret" => config.webhook_secret = Some(args.next().ok_or("--webhook-secret requires a value")?),
+                "--follow-symlinks" => config.follow_symlinks = true,
+                "--create-root" => config.create_root = true,
+                "--embedded" if !embedded::AVAILABLE => {
+                    return Err(String::from("--embedded needs a build with --features embedded and HTTPSERVER_EMBED=DIR"));
+                }
+                "--embedded" => config.embedded = true,
+                "--write" => config.write = true,
+                "--count" => {
+                    let value = args.next().ok_or("--count requires a number")?;
+                    config.count = match value.parse::<u64>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid count '{value}'")),
+                    };
+                }
+                "--open" => config.open = true,
+                "--qr" => config.qr = true,
+                "--mdns" => {
+                    config.mdns.get_or_insert_with(String::new);
+                }
+                "--mdns-name" => {
+                    let value = args.next().ok_or("--mdns-name requires a name")?;
+                    // A DNS label, the rest of the name is ._http._tcp.local
+                    if value.is_empty() || value.len() > 63 {
+                        return Err(format!("invalid mDNS name '{value}', it takes 1 to 63 bytes"));
+                    }
+                    config.mdns = Some(value);
+                }
+                "--try-files" => {
+                    let value = args.next().ok_or("--try-files requires candidates")?;
+                    for candidate in value.split(',').map(str::trim).filter(|candidate| !candidate.is_empty()) {
+                        if matches!(try_files.last(), Some(TryFile::Status(_))) {
+                            return Err(String::from("a =CODE in --try-files has to be the last candidate"));
+                        }
+                        try_files.push(TryFile::parse(candidate)?);
+                    }
+                }
+                "--spa" => {
+                    config.spa.get_or_insert_with(|| String::from("index.html"));
+                }
+                "--spa-index" => {
+                    let value = args.next().ok_or("--spa-index requires a file")?;
+                    let file = value.trim_start_matches('/');
+                    if file.is_empty() || file.split('/').any(|segment| matches!(segment, "" | "." | "..")) {
+                        return Err(format!("invalid SPA index '{value}', expected a file below the root"));
+                    }
+                    config.spa = Some(String::from(file));
+                }
+                "--spa-scope" => {
+                    let value = args.next().ok_or("--spa-scope requires a prefix")?;
+                    let prefix = value.trim_end_matches('/');
+                    if !value.starts_with('/') || prefix.split('/').skip(1).any(|segment| matches!(segment, "" | "." | "..")) {
+                        return Err(format!("invalid SPA scope '{value}', expected a path like /app"));
+                    }
+                    config.spa_scope = String::from(prefix);
+                    config.spa.get_or_insert_with(|| String::from("index.html"));
+                }
+                "--metrics" => config.metrics = true,
+                "--echo" => {
+                    let value = args.next().ok_or("--echo requires a route")?;
+                    Router::new().add(&value, ()).map_err(|err| err.to_string())?;
+                    config.echo.push(value);
+                }
+                "--no-nodelay" => config.socket.nodelay = false,
+                "--keepalive" => {
+                    let value = args.next().ok_or("--keepalive requires seconds")?;
+                    let idle = match value.parse::<u64>() {
+                        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                        _ => return Err(format!("invalid keepalive time '{value}'")),
+                    };
+                    let keepalive = config.socket.keepalive.get_or_insert(Keepalive { idle, interval: None, count: None });
+                    keepalive.idle = idle;
+                }
+                "--keepalive-interval" => {
+                    let value = args.next().ok_or("--keepalive-interval requires seconds")?;
+                    keepalive_interval = match value.parse::<u64>() {
+                        Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+                        _ => return Err(format!("invalid keepalive interval '{value}'")),
+                    };
+                }
+                "--keepalive-count" => {
+                    let value = args.next().ok_or("--keepalive-count requires a count")?;
+                    keepalive_count = match value.parse::<u32>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid keepalive count '{value}'")),
+                    };
+                }
+                "--backlog" => {
+                    let value = args.next().ok_or("--backlog requires a value")?;
+                    config.socket.backlog = match value.parse::<i32>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid backlog '{value}'")),
+                    };
+                }
+                "--recv-buffer" | "--send-buffer" => {
+                    let value = args.next().ok_or_else(|| format!("{arg} requires bytes"))?;
+                    let size = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid buffer size '{value}'")),
+                    };
+                    match arg.as_str() {
+                        "--recv-buffer" => config.socket.recv_buffer = Some(size),
+                        _ => config.socket.send_buffer = Some(size),
+                    }
+                }
+                "--user" | "--group" | "--chroot" => {
+                    let value = args.next().ok_or(format!("{arg} requires a value"))?;
+                    if cfg!(not(unix)) {
+                        return Err(format!("{arg} is only supported on Unix"));
+                    }
+                    match arg.as_str() {
+                        "--user" => config.privileges.user = Some(value),
+                        "--group" => config.privileges.group = Some(value),
+                        _ => config.privileges.chroot = Some(PathBuf::from(value)),
+                    }
+                }
+                "--daemon" => {
+                    if cfg!(not(unix)) {
+                        return Err(String::from("--daemon is only supported on Unix"));
+                    }
+                    config.daemon = true;
+                }
+                "--pid-file" | "--log-file" => {
+                    let value = args.next().ok_or(format!("{arg} requires a file"))?;
+                    if cfg!(not(unix)) {
+                        return Err(format!("{arg} is only supported on Unix"));
+                    }
+                    match arg.as_str() {
+                        "--pid-file" => config.pid_file = Some(PathBuf::from(value)),
+                        _ => config.log_file = Some(PathBuf::from(value)),
+                    }
+                }
+                "--log-max-size" => {
+                    let value = args.next().ok_or("--log-max-size requires bytes")?;
+                    config.log_max_size = match value.parse::<u64>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid size '{value}'")),
+                    };
+                }
+                "--log-keep" => {
+                    let value = args.next().ok_or("--log-keep requires a count")?;
+                    config.log_keep = match value.parse::<u32>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid count of log files '{value}'")),
+                    };
+                }
+                "--log-compress" => config.log_compress = true,
+                "--reuseport" => {
+                    let value = args.next().ok_or("--reuseport requires a socket count")?;
+                    if cfg!(not(target_os = "linux")) {
+                        return Err(String::from("--reuseport is only supported on Linux"));
+                    }
+                    config.reuse_port = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid socket count '{value}'")),
+                    };
+                }
+                "--socket-mode" => {
+                    let value = args.next().ok_or("--socket-mode requires a mode")?;
+                    config.socket_mode = match u32::from_str_radix(&value, 8) {
+                        Ok(mode) if mode <= 0o777 => Some(mode),
+                        _ => return Err(format!("invalid mode '{value}', expected octal like 660")),
+                    };
+                }
+                "--partial-ttl" => {
+                    let value = args.next().ok_or("--partial-ttl requires seconds")?;
+                    config.partial_ttl = match value.parse::<u64>() {
+                        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                        _ => return Err(format!("invalid ttl '{value}'")),
+                    };
+                }
+                "--partial-max-size" => {
+                    let value = args.next().ok_or("--partial-max-size requires bytes")?;
+                    config.partial_max_size = match value.parse::<u64>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid size '{value}'")),
+                    };
+                }
+                "--request-timeout" => {
+                    let value = args.next().ok_or("--request-timeout requires seconds")?;
+                    config.request_timeout = match value.parse::<u64>() {
+                        Ok(0) => None,
+                        Ok(secs) => Some(Duration::from_secs(secs)),
+                        _ => return Err(format!("invalid timeout '{value}'")),
+                    };
+                }
+                "--drain-timeout" => {
+                    let value = args.next().ok_or("--drain-timeout requires seconds")?;
+                    let secs = value.parse::<u64>().map_err(|_| format!("invalid timeout '{value}'"))?;
+                    config.drain_timeout = Duration::from_secs(secs);
+                }
+                "--compress" => config.compress.enabled = true,
+                "--no-ranges" => config.ranges = false,
+                "--compression-level" => {
+                    let value = args.next().ok_or("--compression-level requires a level")?;
+                    let level = value.parse::<i64>().map_err(|_| format!("invalid compression level '{value}'"))?;
+                    let clamped = level.clamp(compress::MIN_LEVEL as i64, compress::MAX_LEVEL as i64);
+                    if clamped != level {
+                        eprintln!("warning: compression level {level} is out of range, using {clamped}");
+                    }
+                    config.compress.level = clamped as u32;
+                }
+                "--compression-min-size" => {
+                    let value = args.next().ok_or("--compression-min-size requires bytes")?;
+                    config.compress.min_size = value.parse()
+                        .map_err(|_| format!("invalid size '{value}'"))?;
+                }
+                "--form-max-fields" => {
+                    let value = args.next().ok_or("--form-max-fields requires a value")?;
+                    config.form.max_fields = value.parse()
+                        .map_err(|_| format!("invalid field count '{value}'"))?;
+                }
+                "--form-max-field-size" => {
+                    let value = args.next().ok_or("--form-max-field-size requires bytes")?;
+                    config.form.max_field_size = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid field size '{value}'")),
+                    };
+                }
+                "--recursive-delete" => config.recursive_delete = true,
+                "--trust-request-id" => config.trust_request_id = true,
+                "--trust-forwarded-proto" => config.trust_forwarded_proto = true,
+                "-q" | "--quiet" => config.log_level = Level::Warn,
+                "-v" | "--verbose" => config.log_level = config.log_level.more(),
+                "-vv" => config.log_level = config.log_level.more().more(),
+                "--log-level" => {
+                    let value = args.next().ok_or("--log-level requires a level")?;
+                    config.log_level = Level::parse(&value).ok_or(format!("invalid log level '{value}', expected off, error, warn, info, debug or trace"))?;
+                }
+                "--trace-io" => config.trace_io = true,
+                "--log-format" => {
+                    let value = args.next().ok_or("--log-format requires a format")?;
+                    config.log_format = match value.as_str() {
+                        "text" => log::Format::Text,
+                        "json" => log::Format::Json,
+                        _ => return Err(format!("invalid log format '{value}', expected text or json")),
+                    };
+                }
+                "--access-log" => {
+                    let value = args.next().ok_or("--access-log requires a file")?;
+                    config.access_log = Some(PathBuf::from(value));
+                }
+                "--access-log-format" => {
+                    let value = args.next().ok_or("--access-log-format requires a format")?;
+                    config.access_format = match value.as_str() {
+                        "common" => access::Format::Common,
+                        "combined" => access::Format::Combined,
+                        _ => return Err(format!("invalid access log format '{value}', expected common or combined")),
+                    };
+                }
+                "--delay" => {
+                    let value = args.next().ok_or("--delay requires a time")?;
+                    config.inject.delay = Some(inject::parse_delay(&value)?);
+                }
+                "--throttle" => {
+                    let value = args.next().ok_or("--throttle requires a rate")?;
+                    config.inject.throttle = Some(inject::parse_rate(&value)?);
+                }
+                "--fail-rate" => {
+                    let value = args.next().ok_or("--fail-rate requires a fraction")?;
+                    config.inject.fail_rate = inject::parse_fail_rate(&value)?;
+                }
+                "--inject-path" => {
+                    let value = args.next().ok_or("--inject-path requires a pattern")?;
+                    config.inject.paths.push(Pattern::new(&value).map_err(|err| err.to_string())?);
+                }
+                "--unfold-headers" => config.parser.fold = FoldPolicy::Unfold,
+                "--strict-line-endings" => config.parser.line_endings = LineEndings::Strict,
+                _ if arg.starts_with('-') => return Err(format!("unknown argument '{arg}', see --help")),
+                _ => {
+                    roots.push(String::from(arg.trim_end_matches('/')));
+                }
+            }
+        }
+        if !listen.is_empty() && (bind.is_some() || port.is_some()) {
+            return Err(String::from("--listen can't be combined with --bind or --port"));
+        }
+        if !listen.is_empty() {
+            config.listen = listen;
+        }
+        // --bind and --port make it a single address again
+        else if bind.is_some() || port.is_some() {
+            let first = match config.listen.first() {
+                Some(ListenAddr::Tcp(addr)) => *addr,
+                _ => DEFAULT_LISTEN,
+            };
+            config.listen = vec![ListenAddr::Tcp(SocketAddr::new(bind.unwrap_or(first.ip()), port.unwrap_or(first.port())))];
+        }
+        // They tune the probes --keepalive turns on, whichever comes first
+        if keepalive_interval.is_some() || keepalive_count.is_some() {
+            let keepalive = config.socket.keepalive.as_mut().ok_or("--keepalive-interval and --keepalive-count need --keepalive")?;
+            keepalive.interval = keepalive_interval.or(keepalive.interval);
+            keepalive.count = keepalive_count.or(keepalive.count);
+        }
+        // Root could leave a chroot again, and a group alone would still be root
+        if config.privileges.user.is_none() && config.privileges.is_set() {
+            return Err(String::from("--group and --chroot need --user"));
+        }
+        // Like the roots, an order is replaced as a whole
+        if !try_files.is_empty() {
+            config.try_files = try_files;
+        }
+        if config.embedded && !roots.is_empty() {
+            return Err(String::from("--embedded serves the binary instead of a ROOT, --mount PREFIX=DIR adds directories next to it"));
+        }
+        if !roots.is_empty() {
+            config.roots = roots;
+            config.single_file = None;
+        }
+        if config.roots.is_empty() {
+            config.roots.push(String::from("."));
+        }
+        // A file instead of a directory, just that one is served
+        if config.single_file.is_none() && config.roots.len() == 1 && Path::new(&config.roots[0]).is_file() {
+            let root = config.roots.remove(0);
+            let (dir, name) = root.rsplit_once('/').unwrap_or((".", &root));
+            config.single_file = Some(String::from(name));
+            config.roots.push(String::from(dir));
+        }
+        if config.single_file.is_some() && config.write {
+            return Err(String::from("--write needs a directory to write to, not a single file"));
+        }
+        if config.single_file.is_some() && !config.mounts.is_empty() {
+            return Err(String::from("--mount needs a directory as the root, not a single file"));
+        }
+        if config.single_file.is_some() && config.spa.is_some() {
+            return Err(String::from("--spa needs a directory as the root, not a single file"));
+        }
+        // Only ever sent over TLS, which only a proxy in front can tell us about
+        if config.hsts.is_some() && !config.trust_forwarded_proto {
+            return Err(String::from("--hsts is only sent over TLS, which needs --trust-forwarded-proto behind a proxy doing it"));
+        }
+        if config.log_max_size.is_some() && config.log_file.is_none() {
+            return Err(String::from("--log-max-size needs a --log-file to rotate"));
+        }
+        if let Some(conflict) = config.rotation_conflict() {
+            return Err(conflict);
+        }
+        if config.count.is_some() && config.single_file.is_none() {
+            return Err(String::from("--count needs a single file to serve"));
+        }
+        if config.credentials.is_empty() && config.routes.iter().any(|route| route.auth == Some(true)) {
+            return Err(String::from("a route requires auth but no --auth credentials are given"));
+        }
+        if config.credentials.is_empty() && config.vhosts.iter().any(|vhost| vhost.auth == Some(true)) {
+            return Err(String::from("a vhost requires auth but no --auth credentials are given"));
+        }
+        config.handlers = handler::builtin(&config);
+        config.file_layers = handler::for_files(&config);
+        config.sites = config.vhosts.iter().map(|vhost| Arc::new(config.vhost_site(vhost))).collect();
+        Ok(config)
+    }
+}
+
+// The settings in use. A reload swaps them as a whole, every request takes the
+// current ones when it starts and keeps them until it's done
+pub struct LiveConfig {
+    current: RwLock<Arc<Config>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> LiveConfig {
+        LiveConfig { current: RwLock::new(Arc::new(config)) }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Read the file and the flags again. A config that doesn't parse changes nothing
+    pub fn reload(&self) {
+        let old = self.get();
+        if old.config_file.is_none() {
+            info!("nothing to reload, the settings come from the command line only");
+            return;
+        }
+        let mut new = match Config::from_args() {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("keeping the current settings, reloading failed: {err}");
+                return;
+            }
+        };
+        if let Err(err) = new.check_roots() {
+            warn!("keeping the current settings, {err}");
+            return;
+        }
+        let kept = old.keep_startup_settings(&mut new);
+        if !kept.is_empty() {
+            info!("changing the {} needs a restart, keeping the old ones", kept.join(", "));
+        }
+        log::init(new.log_level, new.log_format);
+        *self.current.write().unwrap() = Arc::new(new);
+        info!("reloaded the settings from {}", old.config_file.as_deref().unwrap_or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::default().merge(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn security_headers_by_default_and_by_flag() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.security.headers(), [("X-Content-Type-Options", "nosniff"), ("X-Frame-Options", "DENY"), ("Referrer-Policy", "no-referrer")]);
+        let config = parse(&["--no-nosniff", "--frame-options", "off", "--referrer-policy", "same-origin", "--csp", "default-src 'self'"]).unwrap();
+        assert_eq!(config.security.headers(), [("Referrer-Policy", "same-origin"), ("Content-Security-Policy", "default-src 'self'")]);
+        assert!(parse(&["--csp", "a\r\nSet-Cookie: x"]).is_err());
+        assert_eq!(parse(&["--csp"]).map(|_| ()), Err(String::from("--csp requires a value")));
+    }
+
+    #[test]
+    fn custom_headers_replace_or_append() {
+        let config = parse(&["-H", "X-Robots-Tag: noindex", "-H", "Access-Control-Allow-Origin:*", "--header", "x-robots-tag:  none\t"]).unwrap();
+        assert_eq!(config.custom_headers().collect::<Vec<_>>(), [("Access-Control-Allow-Origin", "*", true), ("x-robots-tag", "none", true)]);
+        let config = parse(&["-H", "Link: </a.css>; rel=preload", "--append-header", "Link: </b.js>; rel=preload"]).unwrap();
+        assert_eq!(config.custom_headers().collect::<Vec<_>>(), [("Link", "</a.css>; rel=preload", true), ("Link", "</b.js>; rel=preload", false)]);
+        // A later -H replaces the appended ones too
+        assert_eq!(parse(&["--append-header", "X-A: 1", "--append-header", "X-A: 2", "-H", "X-A: 3"]).unwrap().headers, [(String::from("X-A"), String::from("3"))]);
+        assert_eq!(parse(&["-H", "X-Empty:"]).unwrap().headers, [(String::from("X-Empty"), String::new())]);
+    }
+
+    #[test]
+    fn invalid_custom_headers_are_refused() {
+        for (value, expected) in [
+            ("NoColon", "invalid header 'NoColon', expected 'NAME: VALUE'"),
+            ("Bad Name: x", "invalid header name 'Bad Name'"),
+            (": x", "invalid header name ''"),
+            ("X-A: 1\r\nSet-Cookie: a=b", "invalid value for the header X-A, it must stay on one line"),
+            ("X-A: \u{7f}", "invalid value for the header X-A, it must stay on one line"),
+            ("Content-Length: 0", "Content-Length is set by the server itself, it can't be given with -H"),
+            ("date: now", "date is set by the server itself, it can't be given with -H"),
+            ("Transfer-Encoding: chunked", "Transfer-Encoding is set by the server itself, it can't be given with -H"),
+            ("Connection: keep-alive", "Connection is set by the server itself, it can't be given with -H"),
+        ] {
+            assert_eq!(parse(&["-H", value]).map(|_| ()), Err(String::from(expected)), "{value:?}");
+        }
+        assert_eq!(parse(&["--append-header", "Upgrade: h2c"]).map(|_| ()), Err(String::from("Upgrade is set by the server itself, it can't be given with --append-header")));
+        assert_eq!(parse(&["-H"]).map(|_| ()), Err(String::from("-H requires 'NAME: VALUE'")));
+    }
+
+    #[test]
+    fn longest_route_prefix_wins() {
+        let config = parse(&["--auth", "u:p", "--route", "/assets/*", "cache=3600,auth=off", "--route", "/assets/private", "auth=on,cache=no", "--route", "/admin/", "listing=off"]).unwrap();
+        assert_eq!(config.route("/assets/a.css").map(|route| route.prefix.as_str()), Some("/assets"));
+        assert_eq!(config.route("/assets").map(|route| route.prefix.as_str()), Some("/assets"));
+        assert_eq!(config.route("/assets/private/x").map(|route| route.prefix.as_str()), Some("/assets/private"));
+        // Only on segment boundaries
+        assert!(config.route("/assets2/a.css").is_none());
+        assert_eq!(config.cache_policy("/assets/a.css"), Some(CachePolicy::MaxAge(3600)));
+        assert_eq!(config.cache_policy("/assets/private/x"), Some(CachePolicy::NoStore));
+        assert!(!config.needs_auth("/assets/a.css") && config.needs_auth("/assets/private/x"));
+        // Not said in the route is the server wide setting
+        assert!(config.needs_auth("/admin/") && config.needs_auth("/other"));
+        assert!(!config.listing_enabled("/admin/x") && config.listing_enabled("/assets/"));
+    }
+
+    #[test]
+    fn ranges_by_route_over_the_default() {
+        let config = parse(&["--route", "/live", "ranges=off"]).unwrap();
+        assert!(config.ranges("/a.txt") && !config.ranges("/live/feed"));
+        let config = parse(&["--no-ranges", "--route", "/media", "ranges=on"]).unwrap();
+        assert!(!config.ranges("/a.txt") && config.ranges("/media/clip"));
+        assert!(parse(&["--route", "/a", "ranges=maybe"]).is_err());
+    }
+
+    #[test]
+    fn bad_routes_are_refused() {
+        for (prefix, options) in [("assets", "cache=1"), ("/a", "cache=soon"), ("/a", "auth=yes"), ("/a", "color=red"), ("/a", "cache")] {
+            assert!(parse(&["--route", prefix, options]).is_err(), "{prefix} {options}");
+        }
+        assert!(parse(&["--route", "/a"]).is_err());
+        assert!(parse(&["--route", "/a", ""]).is_ok());
+    }
+
+    #[test]
+    fn longest_mount_prefix_wins() {
+        let config = parse(&["--mount", "/media=/mnt/nas/media/", "--mount", "/media/music=/srv/music", "--mount", "/docs/=/home/ann/documents"]).unwrap();
+        assert_eq!(config.mounts[0], Mount { prefix: String::from("/media"), dir: String::from("/mnt/nas/media") });
+        assert_eq!(config.mount("/media/a.mp4").map(|mount| mount.dir.as_str()), Some("/mnt/nas/media"));
+        assert_eq!(config.mount("/media").map(|mount| mount.dir.as_str()), Some("/mnt/nas/media"));
+        assert_eq!(config.mount("/media/music/a.ogg").map(|mount| mount.dir.as_str()), Some("/srv/music"));
+        assert_eq!(config.mount("/docs/").map(|mount| mount.prefix.as_str()), Some("/docs"));
+        // Only on segment boundaries, the rest is the roots'
+        assert!(config.mount("/mediafiles/a").is_none() && config.mount("/").is_none());
+        assert!(config.is_mount_point("/media/") && config.is_mount_point("/media/music"));
+        assert!(!config.is_mount_point("/media/a.mp4"));
+        assert_eq!(config.mounts_in("/").collect::<Vec<_>>(), ["media", "docs"]);
+        assert_eq!(config.mounts_in("/media/").collect::<Vec<_>>(), ["music"]);
+        assert_eq!(config.mounts_in("/docs/").count(), 0);
+    }
+
+    #[test]
+    fn bad_mounts_are_refused() {
+        for (mount, expected) in [
+            ("media", "invalid mount 'media', expected /prefix=/dir"),
+            ("media=/srv", "mount 'media' must start with '/'"),
+            ("/=/srv", "/ can't be mounted, it is the root"),
+            ("/a/../b=/srv", "invalid mount '/a/../b', expected a plain path"),
+            ("/a//b=/srv", "invalid mount '/a//b', expected a plain path"),
+            ("/a=", "mount '/a' needs a directory"),
+        ] {
+            assert_eq!(parse(&["--mount", mount]).map(|_| ()), Err(String::from(expected)), "{mount}");
+        }
+        assert_eq!(parse(&["--mount", "/a=/x", "--mount", "/a/=/y"]).map(|_| ()), Err(String::from("/a is mounted twice")));
+    }
+
+    #[tokio::test]
+    async fn mounted_paths_resolve_in_their_own_directory() {
+        let base = std::env::temp_dir().join(format!("httpserver-mounts-{}", std::process::id()));
+        let (root, media) = (base.join("root"), base.join("media"));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&media).unwrap();
+        std::fs::write(root.join("secret.txt"), "root").unwrap();
+        let (root, media) = (root.to_str().unwrap(), media.to_str().unwrap());
+        let config = parse(&[root, "--mount", &format!("/media={media}")]).unwrap();
+        assert_eq!(config.resolve("/media/a.mp4").await, Some(format!("{media}/a.mp4")));
+        assert_eq!(config.resolve("/media").await, Some(String::from(media)));
+        assert_eq!(config.resolve("/a.txt").await, Some(format!("{root}/a.txt")));
+        // A link in the mount doesn't lead out of it
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(format!("{root}/secret.txt"), format!("{media}/leak.txt")).unwrap();
+            assert_eq!(config.resolve("/media/leak.txt").await, None);
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn try_files_templates() {
+        let config = parse(&["--try-files", "$path, $path/index.html,$path.html,/404.html=404,=403"]).unwrap();
+        assert_eq!(config.try_files, [
+            TryFile::Path(String::from("$path"), None),
+            TryFile::Path(String::from("$path/index.html"), None),
+            TryFile::Path(String::from("$path.html"), None),
+            TryFile::Path(String::from("/404.html"), Some(404)),
+            TryFile::Status(403),
+        ]);
+        let printed: Vec<String> = config.try_files.iter().map(TryFile::as_string).collect();
+        assert_eq!(printed, ["$path", "$path/index.html", "$path.html", "/404.html=404", "=403"]);
+        for (value, expected) in [
+            ("=404,$path", "a =CODE in --try-files has to be the last candidate"),
+            ("$path=302", "invalid status '302' in --try-files, expected 200 or an error like 404"),
+            ("=418", "invalid status '418' in --try-files, expected 200 or an error like 404"),
+            ("$path=abc", "invalid status 'abc' in --try-files, expected 200 or an error like 404"),
+            ("$path/../x", "invalid candidate '$path/../x' in --try-files, expected a path like $path.html or /404.html"),
+            ("404.html", "invalid candidate '404.html' in --try-files, expected a path like $path.html or /404.html"),
+        ] {
+            assert_eq!(parse(&["--try-files", value]).map(|_| ()), Err(String::from(expected)), "{value}");
+        }
+    }
+
+    #[tokio::test]
+    async fn try_files_takes_the_first_that_exists() {
+        let base = std::env::temp_dir().join(format!("httpserver-try-files-{}", std::process::id()));
+        std::fs::create_dir_all(base.join("docs")).unwrap();
+        std::fs::write(base.join("docs/index.html"), "").unwrap();
+        std::fs::write(base.join("about.html"), "").unwrap();
+        std::fs::write(base.join("404.html"), "").unwrap();
+        let config = parse(&[base.to_str().unwrap(), "--try-files", "$path,$path/index.html,$path.html,/404.html=404"]).unwrap();
+        let tried = |path: &'static str| {
+            let config = &config;
+            async move {
+                match config.try_files(path, false).await {
+                    Some(Tried::File { path, status, .. }) => Some((path, status)),
+                    Some(Tried::Status(code)) => Some((String::new(), Some(code))),
+                    None => None,
+                }
+            }
+        };
+        assert_eq!(tried("/about.html").await, Some((String::from("/about.html"), None)));
+        // A directory is only the candidate ending in '/'
+        assert_eq!(tried("/docs").await, Some((String::from("/docs/index.html"), None)));
+        assert_eq!(tried("/about").await, Some((String::from("/about.html"), None)));
+        assert_eq!(tried("/nothing").await, Some((String::from("/404.html"), Some(404))));
+        let config = parse(&[base.to_str().unwrap(), "--try-files", "$path,=503"]).unwrap();
+        assert!(matches!(config.try_files("/nothing", false).await, Some(Tried::Status(503))));
+        let config = parse(&[base.to_str().unwrap(), "--try-files", "$path.html"]).unwrap();
+        assert!(config.try_files("/nothing", false).await.is_none());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn spa_index_of_the_whole_tree() {
+        assert_eq!(parse(&[]).unwrap().spa_index("/route"), None);
+        let config = parse(&["--spa"]).unwrap();
+        assert_eq!(config.spa_index("/some/app/route").as_deref(), Some("/index.html"));
+        assert_eq!(config.spa_index("/").as_deref(), Some("/index.html"));
+        assert_eq!(parse(&["--spa-index", "/app/shell.html"]).unwrap().spa_index("/x").as_deref(), Some("/app/shell.html"));
+        for bad in ["", "/", "a/../b.html", "a//b.html"] {
+            assert!(parse(&["--spa-index", bad]).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn scoped_spa_index_is_inside_the_scope() {
+        let config = parse(&["--spa-scope", "/shop/"]).unwrap();
+        assert_eq!(config.spa_index("/shop/cart/1").as_deref(), Some("/shop/index.html"));
+        assert_eq!(config.spa_index("/shop").as_deref(), Some("/shop/index.html"));
+        assert_eq!(config.spa_index("/shopping"), None);
+        assert_eq!(config.spa_index("/"), None);
+        let config = parse(&["--spa-scope", "/shop", "--spa-index", "app.html"]).unwrap();
+        assert_eq!(config.spa_index("/shop/cart").as_deref(), Some("/shop/app.html"));
+        for bad in ["shop", "/shop/../x", "/a//b"] {
+            assert!(parse(&["--spa-scope", bad]).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn exact_host_goes_before_the_longest_wildcard() {
+        let config = parse(&["--vhost", "*.example.com", "root=/a", "--vhost", "*.cdn.example.com", "root=/b", "--vhost", "x.cdn.example.com", "root=/c"]).unwrap();
+        let root = |host| config.site(Some(host)).map(|(site, _)| site.roots[0].clone());
+        assert_eq!(root("www.example.com"), Ok(String::from("/a")));
+        assert_eq!(root("y.cdn.example.com"), Ok(String::from("/b")));
+        assert_eq!(root("X.cdn.example.com:80"), Ok(String::from("/c")));
+        assert_eq!(config.site(Some("x.cdn.example.com")).unwrap().1, Some("x.cdn.example.com"));
+        // The roots for the rest, example.com itself among them
+        assert_eq!(config.site(Some("example.com")).unwrap().1, None);
+        assert_eq!(config.site(None).unwrap().1, None);
+        assert_eq!(config.site(Some("a b")).map(|_| ()), Err(400));
+        let strict = parse(&["--vhost", "a.lan", "root=/a", "--unknown-host", "421"]).unwrap();
+        assert_eq!(strict.site(Some("b.lan")).map(|_| ()), Err(421));
+        assert_eq!(strict.site(None).map(|_| ()), Err(421));
+        // A later one for the same name replaces it
+        let replaced = parse(&["--vhost", "a.lan", "root=/a", "--vhost", "A.lan", "root=/b"]).unwrap();
+        assert_eq!(replaced.vhosts.len(), 1);
+        assert_eq!(replaced.site(Some("a.lan")).unwrap().0.roots, ["/b"]);
+    }
+
+    #[test]
+    fn reload_keeps_what_needs_a_restart() {
+        let old = parse(&["--threads", "2", "--listen", "127.0.0.1:8000", "--csp", "a"]).unwrap();
+        let mut new = parse(&["--threads", "4", "--listen", "127.0.0.1:9000", "--csp", "b"]).unwrap();
+        assert_eq!(old.keep_startup_settings(&mut new), ["listen address", "threads"]);
+        assert_eq!((new.threads, &new.listen), (2, &old.listen));
+        assert_eq!(new.security.content_security_policy.as_deref(), Some("b"));
+        let mut same = parse(&["--threads", "2", "--listen", "127.0.0.1:8000"]).unwrap();
+        assert!(old.keep_startup_settings(&mut same).is_empty());
+    }
+
+    #[test]
+    fn compression_level_is_clamped_into_range() {
+        assert_eq!(parse(&[]).unwrap().compress.level, 6);
+        assert_eq!(parse(&["--compression-level", "1"]).unwrap().compress.level, 1);
+        assert_eq!(parse(&["--compression-level", "0"]).unwrap().compress.level, compress::MIN_LEVEL);
+        assert_eq!(parse(&["--compression-level", "42"]).unwrap().compress.level, compress::MAX_LEVEL);
+        assert_eq!(parse(&["--compression-level", "fast"]).map(|_| ()), Err(String::from("invalid compression level 'fast'")));
+        assert_eq!(parse(&["--compression-min-size", "100"]).unwrap().compress.min_size, 100);
+    }
+
+    #[test]
+    fn validate_finds_what_would_stop_the_start() {
+        let base = std::env::temp_dir().join(format!("httpserver-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let base = base.to_str().unwrap();
+        std::fs::write(format!("{base}/file"), "").unwrap();
+        assert_eq!(parse(&[base]).unwrap().validate(), Vec::<String>::new());
+        let config = parse(&[&format!("{base}/missing"), "--mount", &format!("/f={base}/file"), "--log-file", &format!("{base}/none/log")]).unwrap();
+        assert_eq!(config.validate(), [
+            format!("{base}/missing does not exist, add --create-root to create it"),
+            format!("{base}/file is not a directory"),
+            format!("{base}/none/log can't be written, {base}/none is not a directory"),
+        ]);
+        // Nothing is made while checking
+        assert_eq!(parse(&[&format!("{base}/missing"), "--create-root"]).unwrap().validate(), Vec::<String>::new());
+        assert!(!Path::new(&format!("{base}/missing")).exists());
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn log_rotation_is_refused_under_a_chroot() {
+        let expected = "--log-max-size can't rotate the log file from inside --chroot /tmp";
+        let jailed = ["--log-file", "/tmp/httpserver.log", "--user", "65534", "--chroot", "/tmp"];
+        assert!(parse(&[&jailed[..], &["--log-max-size", "100"]].concat()).err().unwrap().starts_with(expected));
+        let mut config = parse(&jailed).unwrap();
+        config.log_max_size = Some(100);
+        assert!(config.validate().iter().any(|problem| problem.starts_with(expected)), "{:?}", config.validate());
+    }
+
+    #[test]
+    fn trailing_slash_modes() {
+        assert_eq!(parse(&[]).unwrap().trailing_slash, TrailingSlash::Ignore);
+        assert_eq!(parse(&["--trailing-slash", "add"]).unwrap().trailing_slash, TrailingSlash::Add);
+        assert_eq!(parse(&["--trailing-slash", "add", "--trailing-slash", "strip"]).unwrap().trailing_slash, TrailingSlash::Strip);
+        assert_eq!(parse(&["--trailing-slash", "Add"]).map(|_| ()), Err(String::from("invalid trailing slash mode 'Add', expected add, strip or ignore")));
+        assert_eq!(parse(&["--trailing-slash"]).map(|_| ()), Err(String::from("--trailing-slash requires a mode")));
+    }
+
+    #[test]
+    fn socket_options() {
+        let config = parse(&["--no-nodelay", "--keepalive", "60", "--keepalive-interval", "10", "--keepalive-count", "3", "--backlog", "16", "--send-buffer", "8192"]).unwrap();
+        let keepalive = config.socket.keepalive.unwrap();
+        assert_eq!((keepalive.idle.as_secs(), keepalive.interval.map(|interval| interval.as_secs()), keepalive.count), (60, Some(10), Some(3)));
+        assert!(!config.socket.nodelay);
+        assert_eq!((config.socket.backlog, config.socket.send_buffer, config.socket.recv_buffer), (16, Some(8192), None));
+        // In either order
+        assert!(parse(&["--keepalive-count", "3", "--keepalive", "60"]).is_ok());
+        assert!(parse(&["--keepalive-count", "3"]).is_err());
+        assert!(parse(&["--keepalive", "soon"]).is_err());
+    }
+
+    #[test]
+    fn verbosity_flags_map_to_levels() {
+        assert_eq!(parse(&[]).unwrap().log_level, Level::Info);
+        assert_eq!(parse(&["-q"]).unwrap().log_level, Level::Warn);
+        assert_eq!(parse(&["--quiet"]).unwrap().log_level, Level::Warn);
+        assert_eq!(parse(&["-v"]).unwrap().log_level, Level::Debug);
+        assert_eq!(parse(&["-vv"]).unwrap().log_level, Level::Trace);
+        assert_eq!(parse(&["-v", "--verbose"]).unwrap().log_level, Level::Trace);
+        assert_eq!(parse(&["--log-level", "error"]).unwrap().log_level, Level::Error);
+        assert!(parse(&["--log-level", "loud"]).err().unwrap().starts_with("invalid log level 'loud'"));
+    }
+}
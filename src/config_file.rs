@@ -0,0 +1,555 @@
+// --config: a TOML file with the same settings as the command line. Every key
+// stands for a flag, so the file goes through exactly the parsing and checks the
+// flags do, and --print-config writes the same keys back
+use httpserver::toml::{self, Entry, Value};
+use crate::access;
+use crate::log;
+use crate::config::{CachePolicy, Config, DateFormat, FaviconMode, TrailingSlash, TryFile, UnknownHost, UpgradeMode};
+use crate::request::{FoldPolicy, LineEndings};
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,            // A string, the value of the flag
+    Number,          // An integer, the value of the flag
+    Switch,          // true gives the flag
+    Inverted,        // false gives the flag
+    List,            // Strings, the flag once for each
+}
+
+// Table, key, flag, in the order the flags are given, so "hsts" comes before
+// the switches that need it
+const KEYS: &[(&str, &str, &str, Kind)] = &[
+    ("listener", "bind", "--bind", Kind::Text),
+    ("listener", "port", "--port", Kind::Number),
+    ("listener", "listen", "--listen", Kind::List),
+    ("listener", "socket_mode", "--socket-mode", Kind::Text),
+    ("listener", "reuseport", "--reuseport", Kind::Number),
+    ("privileges", "user", "--user", Kind::Text),
+    ("privileges", "group", "--group", Kind::Text),
+    ("privileges", "chroot", "--chroot", Kind::Text),
+    ("process", "daemon", "--daemon", Kind::Switch),
+    ("process", "pid_file", "--pid-file", Kind::Text),
+    ("process", "log_file", "--log-file", Kind::Text),
+    ("process", "log_max_size", "--log-max-size", Kind::Number),
+    ("process", "log_keep", "--log-keep", Kind::Number),
+    ("process", "log_compress", "--log-compress", Kind::Switch),
+    ("socket", "nodelay", "--no-nodelay", Kind::Inverted),
+    ("socket", "keepalive", "--keepalive", Kind::Number),
+    ("socket", "keepalive_interval", "--keepalive-interval", Kind::Number),
+    ("socket", "keepalive_count", "--keepalive-count", Kind::Number),
+    ("socket", "backlog", "--backlog", Kind::Number),
+    ("socket", "recv_buffer", "--recv-buffer", Kind::Number),
+    ("socket", "send_buffer", "--send-buffer", Kind::Number),
+    ("listener", "threads", "--threads", Kind::Number),
+    ("listener", "open", "--open", Kind::Switch),
+    ("listener", "qr", "--qr", Kind::Switch),
+    ("listener", "mdns", "--mdns", Kind::Switch),
+    ("listener", "mdns_name", "--mdns-name", Kind::Text),
+    ("listener", "upgrade", "--upgrade", Kind::Text),
+    ("listener", "redirect_https", "--redirect-https", Kind::Text),
+    ("listener", "https_port", "--https-port", Kind::Number),
+    ("listener", "request_timeout", "--request-timeout", Kind::Number),
+    ("listener", "drain_timeout", "--drain-timeout", Kind::Number),
+    ("root", "paths", "--root", Kind::List),
+    ("root", "count", "--count", Kind::Number),
+    ("root", "unknown_host", "--unknown-host", Kind::Text),
+    ("root", "trailing_slash", "--trailing-slash", Kind::Text),
+    ("canonical", "host", "--canonical-host", Kind::Text),
+    ("canonical", "scheme", "--canonical-scheme", Kind::Text),
+    ("canonical", "exempt", "--canonical-exempt", Kind::List),
+    ("root", "try_files", "--try-files", Kind::List),
+    ("root", "spa", "--spa", Kind::Switch),
+    ("root", "spa_index", "--spa-index", Kind::Text),
+    ("root", "spa_scope", "--spa-scope", Kind::Text),
+    ("root", "follow_symlinks", "--follow-symlinks", Kind::Switch),
+    ("root", "create", "--create-root", Kind::Switch),
+    ("root", "embedded", "--embedded", Kind::Switch),
+    ("root", "exclude", "--exclude", Kind::List),
+    ("root", "ranges", "--no-ranges", Kind::Inverted),
+    ("compression", "enabled", "--compress", Kind::Switch),
+    ("compression", "level", "--compression-level", Kind::Number),
+    ("compression", "min_size", "--compression-min-size", Kind::Number),
+    ("listing", "favicon", "--favicon", Kind::Text),
+    ("listing", "dates", "--listing-dates", Kind::Text),
+    ("listing", "lang", "--listing-lang", Kind::Text),
+    ("listing", "download_ext", "--download-ext", Kind::List),
+    ("write", "enabled", "--write", Kind::Switch),
+    ("write", "partial_ttl", "--partial-ttl", Kind::Number),
+    ("write", "partial_max_size", "--partial-max-size", Kind::Number),
+    ("write", "recursive_delete", "--recursive-delete", Kind::Switch),
+    ("write", "webhooks", "--webhook", Kind::List),
+    ("write", "webhook_secret", "--webhook-secret", Kind::Text),
+    ("auth", "users", "--auth", Kind::List),
+    ("limits", "max_headers", "--max-headers", Kind::Number),
+    ("limits", "max_line_size", "--max-line-size", Kind::Number),
+    ("limits", "form_max_fields", "--form-max-fields", Kind::Number),
+    ("limits", "form_max_field_size", "--form-max-field-size", Kind::Number),
+    ("logging", "trust_request_id", "--trust-request-id", Kind::Switch),
+    ("logging", "metrics", "--metrics", Kind::Switch),
+    ("logging", "echo", "--echo", Kind::List),
+    ("logging", "level", "--log-level", Kind::Text),
+    ("logging", "trace_io", "--trace-io", Kind::Switch),
+    ("logging", "format", "--log-format", Kind::Text),
+    ("logging", "access_log", "--access-log", Kind::Text),
+    ("logging", "access_log_format", "--access-log-format", Kind::Text),
+    ("inject", "delay", "--delay", Kind::Text),
+    ("inject", "throttle", "--throttle", Kind::Text),
+    ("inject", "fail_rate", "--fail-rate", Kind::Text),
+    ("inject", "paths", "--inject-path", Kind::List),
+    ("tls", "trust_forwarded_proto", "--trust-forwarded-proto", Kind::Switch),
+    ("tls", "hsts", "--hsts", Kind::Number),
+    ("tls", "hsts_subdomains", "--hsts-subdomains", Kind::Switch),
+    ("tls", "hsts_preload", "--hsts-preload", Kind::Switch),
+    ("security", "nosniff", "--no-nosniff", Kind::Inverted),
+    ("security", "frame_options", "--frame-options", Kind::Text),
+    ("security", "referrer_policy", "--referrer-policy", Kind::Text),
+    ("security", "csp", "--csp", Kind::Text),
+    ("security", "headers", "--header", Kind::List),
+    ("security", "append_headers", "--append-header", Kind::List),
+    ("parser", "unfold_headers", "--unfold-headers", Kind::Switch),
+    ("parser", "strict_line_endings", "--strict-line-endings", Kind::Switch),
+];
+
+// Every key of [routes] is a prefix, its value the options of --route
+const ROUTES: &str = "routes";
+
+// Every key of [mounts] is a prefix, its value the directory. Listing, auth and
+// write for a mount are [routes] of the same prefix
+const MOUNTS: &str = "mounts";
+
+// Every key of [rewrites] is a pattern, its value the target of --rewrite. They are
+// applied in the order they are written
+const REWRITES: &str = "rewrites";
+
+// Every key of [vhosts] is a host name, its value the options of --vhost
+const VHOSTS: &str = "vhosts";
+
+// Every key of [error_pages] is a status or class, its value the file
+const ERROR_PAGES: &str = "error_pages";
+
+fn wrong_type(entry: &Entry, expected: &str) -> String {
+    format!("line {}: '{}' must be {expected}, not {}", entry.line, entry.key, entry.value.kind())
+}
+
+// The flags an entry stands for, None for keys we don't know
+fn flags(entry: &Entry) -> Option<Result<(usize, Vec<String>), String>> {
+    if entry.table == ROUTES {
+        let args = match &entry.value {
+            Value::String(options) => Ok((KEYS.len(), vec![String::from("--route"), entry.key.clone(), options.clone()])),
+            _ => Err(wrong_type(entry, "a string")),
+        };
+        return Some(args);
+    }
+    if entry.table == MOUNTS {
+        let args = match &entry.value {
+            Value::String(dir) => Ok((KEYS.len(), vec![String::from("--mount"), format!("{}={dir}", entry.key)])),
+            _ => Err(wrong_type(entry, "a string")),
+        };
+        return Some(args);
+    }
+    if entry.table == REWRITES {
+        let args = match &entry.value {
+            Value::String(target) => Ok((KEYS.len(), vec![String::from("--rewrite"), entry.key.clone(), target.clone()])),
+            _ => Err(wrong_type(entry, "a string")),
+        };
+        return Some(args);
+    }
+    if entry.table == VHOSTS {
+        let args = match &entry.value {
+            Value::String(options) => Ok((KEYS.len(), vec![String::from("--vhost"), entry.key.clone(), options.clone()])),
+            _ => Err(wrong_type(entry, "a string")),
+        };
+        return Some(args);
+    }
+    if entry.table == ERROR_PAGES {
+        let args = match &entry.value {
+            Value::String(file) => Ok((KEYS.len(), vec![String::from("--error-page"), entry.key.clone(), file.clone()])),
+            _ => Err(wrong_type(entry, "a string")),
+        };
+        return Some(args);
+    }
+    let index = KEYS.iter().position(|(table, key, _, _)| *table == entry.table && *key == entry.key)?;
+    let (_, _, flag, kind) = KEYS[index];
+    let flag = String::from(flag);
+    let args = match (kind, &entry.value) {
+        (Kind::Text, Value::String(value)) => Ok(vec![flag, value.clone()]),
+        (Kind::Number, Value::Integer(value)) => Ok(vec![flag, value.to_string()]),
+        (Kind::Switch, Value::Boolean(value)) => Ok(if *value { vec![flag] } else { Vec::new() }),
+        (Kind::Inverted, Value::Boolean(value)) => Ok(if *value { Vec::new() } else { vec![flag] }),
+        (Kind::List, Value::Array(values)) => values.iter().map(|value| match value {
+            Value::String(value) => Ok([flag.clone(), value.clone()]),
+            _ => Err(wrong_type(entry, "an array of strings")),
+        }).collect::<Result<Vec<_>, _>>().map(|pairs| pairs.concat()),
+        (Kind::List, Value::String(value)) => Ok(vec![flag, value.clone()]),
+        (Kind::Text, _) => Err(wrong_type(entry, "a string")),
+        (Kind::Number, _) => Err(wrong_type(entry, "an integer")),
+        (Kind::Switch | Kind::Inverted, _) => Err(wrong_type(entry, "a boolean")),
+        (Kind::List, _) => Err(wrong_type(entry, "an array of strings")),
+    };
+    Some(args.map(|args| (index, args)))
+}
+
+// The settings of a file as a Config, the command line goes on top of it.
+// Unknown keys are warnings, or errors when strict
+pub fn load(path: &str, strict: bool) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    let entries = toml::parse(&text).map_err(|err| format!("{path}: {err}"))?;
+    let mut groups = Vec::new();
+    for entry in &entries {
+        match flags(entry) {
+            Some(Ok((index, args))) => groups.push((index, entry, args)),
+            Some(Err(err)) => return Err(format!("{path}: {err}")),
+            None => {
+                let name = match entry.table.as_str() {
+                    "" => entry.key.clone(),
+                    table => format!("{table}.{}", entry.key),
+                };
+                match strict {
+                    true => return Err(format!("{path}: line {}: unknown key '{name}'", entry.line)),
+                    false => eprintln!("warning: {path}: line {}: unknown key '{name}'", entry.line),
+                }
+            }
+        }
+    }
+    groups.sort_by_key(|(index, _, _)| *index);
+    let args = |groups: &[(usize, &Entry, Vec<String>)]| groups.iter().flat_map(|(_, _, args)| args.clone()).collect::<Vec<_>>();
+    match Config::default().merge(args(&groups)) {
+        Ok(config) => Ok(config),
+        Err(err) => {
+            // The first key that makes it fail is the one to blame
+            let culprit = (1..=groups.len()).find_map(|n| Config::default().merge(args(&groups[..n])).err().map(|err| (groups[n - 1].1, err)));
+            match culprit {
+                Some((entry, err)) => Err(format!("{path}: line {}: '{}': {err}", entry.line, entry.key)),
+                None => Err(format!("{path}: {err}")),
+            }
+        }
+    }
+}
+
+fn list(values: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let values: Vec<String> = values.into_iter().map(|value| toml::quote(value.as_ref())).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn text(value: Option<&String>) -> String {
+    toml::quote(value.map(String::as_str).unwrap_or("off"))
+}
+
+// The effective settings in the format load reads, for --print-config
+pub fn print(config: &Config) -> String {
+    let mut out = String::new();
+    let mut table = |name: &str, keys: Vec<(&str, String)>| {
+        out.push_str(&format!("[{name}]\n"));
+        for (key, value) in keys {
+            out.push_str(&format!("{key} = {value}\n"));
+        }
+        out.push('\n');
+    };
+    let mut listener = vec![
+        ("listen", list(config.listen.iter().map(|addr| addr.to_string()))),
+        ("threads", config.threads.to_string()),
+        ("open", config.open.to_string()),
+        ("qr", config.qr.to_string()),
+        ("mdns", config.mdns.is_some().to_string()),
+        ("upgrade", toml::quote(match config.upgrade { UpgradeMode::Refuse => "refuse", UpgradeMode::Close => "close" })),
+        ("https_port", config.https_port.to_string()),
+        ("request_timeout", config.request_timeout.map(|limit| limit.as_secs()).unwrap_or(0).to_string()),
+        ("drain_timeout", config.drain_timeout.as_secs().to_string()),
+    ];
+    if let Some(name) = config.mdns.as_ref().filter(|name| !name.is_empty()) {
+        listener.push(("mdns_name", toml::quote(name)));
+    }
+    if let Some(n) = config.reuse_port {
+        listener.push(("reuseport", n.to_string()));
+    }
+    if let Some(mode) = config.socket_mode {
+        listener.push(("socket_mode", toml::quote(&format!("{mode:o}"))));
+    }
+    if let Some(addr) = &config.redirect_https {
+        listener.push(("redirect_https", toml::quote(addr)));
+    }
+    table("listener", listener);
+    let mut socket = vec![
+        ("nodelay", config.socket.nodelay.to_string()),
+        ("backlog", config.socket.backlog.to_string()),
+    ];
+    if let Some(keepalive) = &config.socket.keepalive {
+        socket.push(("keepalive", keepalive.idle.as_secs().to_string()));
+        if let Some(interval) = keepalive.interval {
+            socket.push(("keepalive_interval", interval.as_secs().to_string()));
+        }
+        if let Some(count) = keepalive.count {
+            socket.push(("keepalive_count", count.to_string()));
+        }
+    }
+    if let Some(size) = config.socket.recv_buffer {
+        socket.push(("recv_buffer", size.to_string()));
+    }
+    if let Some(size) = config.socket.send_buffer {
+        socket.push(("send_buffer", size.to_string()));
+    }
+    table("socket", socket);
+    let mut process = vec![("daemon", config.daemon.to_string())];
+    process.extend(config.pid_file.iter().map(|path| ("pid_file", toml::quote(&path.to_string_lossy()))));
+    process.extend(config.log_file.iter().map(|path| ("log_file", toml::quote(&path.to_string_lossy()))));
+    process.extend(config.log_max_size.iter().map(|size| ("log_max_size", size.to_string())));
+    process.push(("log_keep", config.log_keep.to_string()));
+    process.push(("log_compress", config.log_compress.to_string()));
+    table("process", process);
+    if config.privileges.is_set() {
+        let privileges = &config.privileges;
+        let mut keys = Vec::new();
+        keys.extend(privileges.user.iter().map(|user| ("user", toml::quote(user))));
+        keys.extend(privileges.group.iter().map(|group| ("group", toml::quote(group))));
+        keys.extend(privileges.chroot.iter().map(|dir| ("chroot", toml::quote(&dir.to_string_lossy()))));
+        table("privileges", keys);
+    }
+    let roots: Vec<String> = match &config.single_file {
+        Some(name) => config.roots.iter().map(|root| format!("{root}/{name}")).collect(),
+        None => config.roots.iter().map(|root| String::from(if root.is_empty() { "/" } else { root })).collect(),
+    };
+    let mut root = vec![
+        ("paths", list(&roots)),
+        ("follow_symlinks", config.follow_symlinks.to_string()),
+        ("create", config.create_root.to_string()),
+        ("embedded", config.embedded.to_string()),
+        ("exclude", list(config.exclude.iter().map(|pattern| pattern.as_str()))),
+        ("ranges", config.ranges.to_string()),
+    ];
+    if let Some(count) = config.count {
+        root.push(("count", count.to_string()));
+    }
+    let unknown_host = match config.unknown_host {
+        UnknownHost::Default => "default",
+        UnknownHost::NotFound => "404",
+        UnknownHost::Misdirected => "421",
+    };
+    root.push(("unknown_host", toml::quote(unknown_host)));
+    let trailing_slash = match config.trailing_slash {
+        TrailingSlash::Add => "add",
+        TrailingSlash::Strip => "strip",
+        TrailingSlash::Ignore => "ignore",
+    };
+    root.push(("trailing_slash", toml::quote(trailing_slash)));
+    root.push(("try_files", list(config.try_files.iter().map(TryFile::as_string))));
+    root.push(("spa", config.spa.is_some().to_string()));
+    if let Some(index) = &config.spa {
+        root.push(("spa_index", toml::quote(index)));
+        root.push(("spa_scope", toml::quote(if config.spa_scope.is_empty() { "/" } else { &config.spa_scope })));
+    }
+    table("root", root);
+    let mut canonical = Vec::new();
+    canonical.extend(config.canonical_host.iter().map(|host| ("host", toml::quote(host))));
+    canonical.extend(config.canonical_scheme.map(|scheme| ("scheme", toml::quote(scheme))));
+    canonical.push(("exempt", list(&config.canonical_exempt)));
+    table("canonical", canonical);
+    table("compression", vec![
+        ("enabled", config.compress.enabled.to_string()),
+        ("level", config.compress.level.to_string()),
+        ("min_size", config.compress.min_size.to_string()),
+    ]);
+    let favicon = match config.favicon {
+        FaviconMode::Off => "off",
+        FaviconMode::Builtin => "builtin",
+        FaviconMode::Empty => "empty",
+    };
+    let dates = match config.listing_dates {
+        DateFormat::Iso => "iso",
+        DateFormat::Text => "text",
+        DateFormat::Relative => "relative",
+    };
+    table("listing", vec![
+        ("favicon", toml::quote(favicon)),
+        ("dates", toml::quote(dates)),
+        ("lang", toml::quote(&config.listing_lang)),
+        ("download_ext", list(&config.download_extensions)),
+    ]);
+    let mut write = vec![
+        ("enabled", config.write.to_string()),
+        ("partial_ttl", config.partial_ttl.as_secs().to_string()),
+    ];
+    write.extend(config.partial_max_size.iter().map(|size| ("partial_max_size", size.to_string())));
+    write.extend([
+        ("recursive_delete", config.recursive_delete.to_string()),
+        ("webhooks", list(config.webhooks.iter().map(|hook| hook.url.as_str()))),
+    ]);
+    if let Some(secret) = &config.webhook_secret {
+        write.push(("webhook_secret", toml::quote(secret)));
+    }
+    table("write", write);
+    table("auth", vec![("users", list(config.credentials.iter().map(|(user, password)| format!("{user}:{password}"))))]);
+    table("limits", vec![
+        ("max_headers", config.parser.max_headers.to_string()),
+        ("max_line_size", config.parser.max_line.to_string()),
+        ("form_max_fields", config.form.max_fields.to_string()),
+        ("form_max_field_size", config.form.max_field_size.to_string()),
+    ]);
+    let log_format = match config.log_format {
+        log::Format::Text => "text",
+        log::Format::Json => "json",
+    };
+    let access_format = match config.access_format {
+        access::Format::Common => "common",
+        access::Format::Combined => "combined",
+    };
+    let mut logging = vec![
+        ("trust_request_id", config.trust_request_id.to_string()),
+        ("metrics", config.metrics.to_string()),
+        ("echo", list(&config.echo)),
+        ("level", toml::quote(config.log_level.as_str())),
+        ("trace_io", config.trace_io.to_string()),
+        ("format", toml::quote(log_format)),
+    ];
+    logging.extend(config.access_log.iter().map(|path| ("access_log", toml::quote(&path.to_string_lossy()))));
+    logging.push(("access_log_format", toml::quote(access_format)));
+    table("logging", logging);
+    let mut tls = vec![("trust_forwarded_proto", config.trust_forwarded_proto.to_string())];
+    if let Some(hsts) = &config.hsts {
+        tls.extend([
+            ("hsts", hsts.max_age.to_string()),
+            ("hsts_subdomains", hsts.include_subdomains.to_string()),
+            ("hsts_preload", hsts.preload.to_string()),
+        ]);
+    }
+    table("tls", tls);
+    let (headers, appended): (Vec<_>, Vec<_>) = config.custom_headers().partition(|(_, _, first)| *first);
+    if config.inject.is_set() {
+        let mut inject = Vec::new();
+        if let Some((min, max)) = config.inject.delay {
+            inject.push(("delay", toml::quote(&format!("{}ms-{}ms", min.as_millis(), max.as_millis()))));
+        }
+        if let Some(rate) = config.inject.throttle {
+            inject.push(("throttle", toml::quote(&format!("{}bps", rate * 8))));
+        }
+        inject.push(("fail_rate", toml::quote(&config.inject.fail_rate.to_string())));
+        inject.push(("paths", list(config.inject.paths.iter().map(|pattern| pattern.as_str()))));
+        table("inject", inject);
+    }
+    table("security", vec![
+        ("nosniff", config.security.nosniff.to_string()),
+        ("frame_options", text(config.security.frame_options.as_ref())),
+        ("referrer_policy", text(config.security.referrer_policy.as_ref())),
+        ("csp", text(config.security.content_security_policy.as_ref())),
+        ("headers", list(headers.iter().map(|(name, value, _)| format!("{name}: {value}")))),
+        ("append_headers", list(appended.iter().map(|(name, value, _)| format!("{name}: {value}")))),
+    ]);
+    table("parser", vec![
+        ("unfold_headers", (config.parser.fold == FoldPolicy::Unfold).to_string()),
+        ("strict_line_endings", (config.parser.line_endings == LineEndings::Strict).to_string()),
+    ]);
+    let pages: Vec<(&str, &str)> = config.error_pages.files().collect();
+    if !pages.is_empty() {
+        out.push_str(&format!("[{ERROR_PAGES}]\n"));
+        for (status, file) in pages {
+            out.push_str(&format!("{} = {}\n", toml::key(status), toml::quote(file)));
+        }
+        out.push('\n');
+    }
+    if !config.mounts.is_empty() {
+        out.push_str(&format!("[{MOUNTS}]\n"));
+        for mount in &config.mounts {
+            let dir = if mount.dir.is_empty() { "/" } else { mount.dir.as_str() };
+            out.push_str(&format!("{} = {}\n", toml::key(&mount.prefix), toml::quote(dir)));
+        }
+        out.push('\n');
+    }
+    if !config.rewrites.is_empty() {
+        out.push_str(&format!("[{REWRITES}]\n"));
+        for rule in &config.rewrites {
+            out.push_str(&format!("{} = {}\n", toml::key(rule.pattern()), toml::quote(&rule.target())));
+        }
+        out.push('\n');
+    }
+    if !config.vhosts.is_empty() {
+        out.push_str(&format!("[{VHOSTS}]\n"));
+        for vhost in &config.vhosts {
+            let switch = |on: bool| if on { "on" } else { "off" };
+            let mut options = vec![format!("root={}", if vhost.root.is_empty() { "/" } else { vhost.root.as_str() })];
+            options.extend(vhost.listing.map(|listing| format!("listing={}", switch(listing))));
+            options.extend(vhost.auth.map(|auth| format!("auth={}", switch(auth))));
+            options.extend(vhost.write.map(|write| format!("write={}", switch(write))));
+            out.push_str(&format!("{} = {}\n", toml::key(&vhost.name), toml::quote(&options.join(","))));
+        }
+        out.push('\n');
+    }
+    if !config.routes.is_empty() {
+        out.push_str(&format!("[{ROUTES}]\n"));
+        for route in &config.routes {
+            let mut options = Vec::new();
+            match route.cache {
+                Some(CachePolicy::MaxAge(secs)) => options.push(format!("cache={secs}")),
+                Some(CachePolicy::NoStore) => options.push(String::from("cache=no")),
+                None => {}
+            }
+            let switch = |on: bool| if on { "on" } else { "off" };
+            if let Some(auth) = route.auth {
+                options.push(format!("auth={}", switch(auth)));
+            }
+            if let Some(listing) = route.listing {
+                options.push(format!("listing={}", switch(listing)));
+            }
+            if let Some(write) = route.write {
+                options.push(format!("write={}", switch(write)));
+            }
+            if let Some(ranges) = route.ranges {
+                options.push(format!("ranges={}", switch(ranges)));
+            }
+            let prefix = if route.prefix.is_empty() { "/" } else { route.prefix.as_str() };
+            out.push_str(&format!("{} = {}\n", toml::key(prefix), toml::quote(&options.join(","))));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The flags of each line of the text, in the order load would give them
+    fn args(text: &str) -> Vec<Result<Vec<String>, String>> {
+        toml::parse(text).unwrap().iter().map(|entry| flags(entry).expect("a known key").map(|(_, args)| args)).collect()
+    }
+
+    #[test]
+    fn each_kind_gives_its_flags() {
+        let text = "[listener]\nbind = \"0.0.0.0\"\nport = 8080\nopen = true\nqr = false\n[socket]\nnodelay = false\n[root]\npaths = [\"/a\", \"/b\"]\ntry_files = \"$path\"\n";
+        let expected: Vec<&[&str]> = vec![&["--bind", "0.0.0.0"], &["--port", "8080"], &["--open"], &[], &["--no-nodelay"], &["--root", "/a", "--root", "/b"], &["--try-files", "$path"]];
+        assert_eq!(args(text), expected.iter().map(|args| Ok(args.iter().map(|arg| String::from(*arg)).collect())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tables_of_their_own_are_repeated_flags() {
+        let text = "[routes]\n\"/api\" = \"cache=off\"\n[mounts]\n\"/media\" = \"/srv/media\"\n[rewrites]\n\"/old/*\" = \"/new/$1\"\n[vhosts]\n\"files.lan\" = \"root=/srv\"\n[error_pages]\n404 = \"/srv/404.html\"\n";
+        let found: Vec<Vec<String>> = args(text).into_iter().map(Result::unwrap).collect();
+        assert_eq!(found, [
+            vec!["--route", "/api", "cache=off"],
+            vec!["--mount", "/media=/srv/media"],
+            vec!["--rewrite", "/old/*", "/new/$1"],
+            vec!["--vhost", "files.lan", "root=/srv"],
+            vec!["--error-page", "404", "/srv/404.html"],
+        ]);
+    }
+
+    #[test]
+    fn wrong_types_say_what_was_expected() {
+        for (text, expected) in [
+            ("[listener]\nport = \"80\"", "line 2: 'port' must be an integer, not a string"),
+            ("[listener]\nbind = 1", "line 2: 'bind' must be a string, not an integer"),
+            ("[listener]\nopen = \"yes\"", "line 2: 'open' must be a boolean, not a string"),
+            ("[root]\npaths = [1]", "line 2: 'paths' must be an array of strings, not an array"),
+            ("[root]\npaths = true", "line 2: 'paths' must be an array of strings, not a boolean"),
+            ("[routes]\n\"/a\" = 1", "line 2: '/a' must be a string, not an integer"),
+        ] {
+            assert_eq!(args(text), [Err(String::from(expected))], "{text}");
+        }
+        assert!(flags(&toml::parse("[listener]\nbogus = 1").unwrap()[0]).is_none());
+    }
+
+    #[test]
+    fn every_key_is_a_flag_the_command_line_knows() {
+        for (table, key, flag, _) in KEYS {
+            let err = Config::default().merge([String::from(*flag)]).err().unwrap_or_default();
+            assert!(!err.starts_with("unknown argument"), "{table}.{key}: {err}");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// --daemon, --pid-file and --log-file: running in the background without a service
+// manager. All of it happens before the runtime starts, forking a process with
+// threads leaves only the forking one behind. Rotating the log file is the
+// exception, the log thread does it for as long as we run
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::log::{self, Level};
+
+// Held open for as long as we run, the lock on it is what keeps a second instance out
+static PID_FILE: OnceLock<(PathBuf, File)> = OnceLock::new();
+// The original process waits on the other end until startup is done
+static READY: Mutex<Option<File>> = Mutex::new(None);
+// Where the output goes, to open it again. Held while rotating
+static LOG_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+static ROTATION: OnceLock<Rotation> = OnceLock::new();
+// Inside --chroot the path of the log file is another file, if any
+static CHROOTED: AtomicBool = AtomicBool::new(false);
+// Once rotating failed it isn't tried again, saying so would fail the same way
+static GAVE_UP: AtomicBool = AtomicBool::new(false);
+// The rotated file being compressed
+static COMPRESSING: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+// --log-max-size: once the log file is past it, it becomes FILE.1, FILE.1 becomes
+// FILE.2 and so on up to --log-keep of them, and FILE starts over empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub keep: u32,
+    pub compress: bool,  // The rotated ones are FILE.N.gz
+}
+
+// Locked before anything else, a second instance fails right here
+pub fn lock_pid_file(path: &Path) -> Result<(), String> {
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+        .map_err(|err| format!("failed to open the pid file {} by {err}", path.display()))?;
+    // SAFETY: a valid fd, owned by file
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let mut pid = String::new();
+        let _ = file.read_to_string(&mut pid);
+        return Err(format!("already running with pid {}, {} is locked", pid.trim(), path.display()));
+    }
+    let _ = PID_FILE.set((path.to_path_buf(), file));
+    Ok(())
+}
+
+// Once we are the process that stays, right away without --daemon
+pub fn write_pid() -> Result<(), String> {
+    let Some((path, file)) = PID_FILE.get() else {
+        return Ok(());
+    };
+    let mut file = file;
+    file.set_len(0).and_then(|_| writeln!(file, "{}", std::process::id()))
+        .map_err(|err| format!("failed to write the pid file {} by {err}", path.display()))
+}
+
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe fills in two fds, each is owned by one File from then on
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: as above
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+fn fork() -> Result<libc::pid_t, String> {
+    // SAFETY: no runtime and no other threads yet
+    match unsafe { libc::fork() } {
+        -1 => Err(format!("failed to fork by {}", io::Error::last_os_error())),
+        pid => Ok(pid),
+    }
+}
+
+// Stdout and stderr into the file, appended. Swapping the fds under them is atomic,
+// a line being written goes to the old file or the new one as a whole
+pub fn redirect_output(path: Option<&Path>) -> Result<(), String> {
+    let file = match path {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|err| format!("failed to open the log file {} by {err}", path.display()))?,
+        None => OpenOptions::new().write(true).open("/dev/null").map_err(|err| format!("failed to open /dev/null by {err}"))?,
+    };
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both fds are valid, dup2 replaces the standard one
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(format!("failed to redirect the output by {}", io::Error::last_os_error()));
+        }
+    }
+    if let Some(path) = path {
+        *LOG_FILE.lock().unwrap() = Some(path.to_path_buf());
+    }
+    Ok(())
+}
+
+// Before the output is redirected
+pub fn set_rotation(rotation: Rotation) {
+    let _ = ROTATION.set(rotation);
+}
+
+// Once the privileges were dropped into one
+pub fn entered_chroot() {
+    CHROOTED.store(true, Ordering::Relaxed);
+}
+
+// On SIGUSR1, after logrotate moved the file away
+pub fn reopen_log() -> Result<(), String> {
+    let path = LOG_FILE.lock().unwrap().clone();
+    match path {
+        Some(path) if CHROOTED.load(Ordering::Relaxed) => Err(format!("can't open the log file {} again from inside the chroot", path.display())),
+        Some(path) => redirect_output(Some(&path)),
+        None => Ok(()),
+    }
+}
+
+fn rotated(path: &Path, n: u32, compress: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{n}{}", if compress { ".gz" } else { "" }));
+    PathBuf::from(name)
+}
+
+fn compress_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(from)
+}
+
+// After every line, on the log thread, it's a stat of the output. On Err the output
+// is still the old file
+pub fn rotate_if_full() -> Result<Option<PathBuf>, String> {
+    let Some(rotation) = ROTATION.get().filter(|_| !GAVE_UP.load(Ordering::Relaxed)) else {
+        return Ok(None);
+    };
+    let log = LOG_FILE.lock().unwrap();
+    let Some(path) = log.clone() else {
+        return Ok(None);
+    };
+    // SAFETY: stat is only read after fstat filled it in
+    let size = unsafe {
+        let mut stat = std::mem::zeroed::<libc::stat>();
+        match libc::fstat(libc::STDOUT_FILENO, &mut stat) {
+            0 => stat.st_size as u64,
+            _ => return Ok(None),
+        }
+    };
+    if size < rotation.max_size {
+        return Ok(None);
+    }
+    let failed = |err: io::Error| {
+        GAVE_UP.store(true, Ordering::Relaxed);
+        format!("failed to rotate the log file {} by {err}, it grows from now on", path.display())
+    };
+    // The last FILE.1 has to be FILE.1.gz before the names move on
+    if let Some(compressing) = COMPRESSING.lock().unwrap().take() {
+        let _ = compressing.join();
+    }
+    // The oldest falls off the end
+    let _ = std::fs::remove_file(rotated(&path, rotation.keep, rotation.compress));
+    for n in (1..rotation.keep).rev() {
+        let from = rotated(&path, n, rotation.compress);
+        if from.exists() {
+            std::fs::rename(&from, rotated(&path, n + 1, rotation.compress)).map_err(failed)?;
+        }
+    }
+    let first = rotated(&path, 1, false);
+    std::fs::rename(&path, &first).map_err(failed)?;
+    drop(log);
+    redirect_output(Some(&path)).inspect_err(|_| GAVE_UP.store(true, Ordering::Relaxed))?;
+    // The new file is in place, logging goes on while the old one shrinks. What fails
+    // there goes to stderr, into the log file as well
+    if rotation.compress {
+        let to = rotated(&path, 1, true);
+        let compressing = std::thread::Builder::new().name(String::from("log-compress")).spawn(move || {
+            if let Err(err) = compress_file(&first, &to) {
+                eprintln!("{}", log::format_line(Level::Warn, format_args!("failed to compress the rotated log file {} by {err}", first.display())));
+            }
+        });
+        *COMPRESSING.lock().unwrap() = compressing.ok();
+    }
+    Ok(Some(path))
+}
+
+// Fork, setsid and fork again, so the daemon is no session leader and can't get a
+// terminal back. The original process stays until the daemon says it is listening
+// or fails, and reports that on the terminal
+pub fn detach(log_file: Option<&Path>) -> Result<(), String> {
+    let (mut reader, writer) = pipe().map_err(|err| format!("failed to create a pipe by {err}"))?;
+    if fork()? > 0 {
+        drop(writer);
+        let mut status = String::new();
+        let _ = reader.read_to_string(&mut status);
+        // Nothing at all, it died before it could say anything
+        match status.split_once(' ') {
+            Some(("ok", pid)) => {
+                eprintln!("httpserver running in the background with pid {pid}");
+                std::process::exit(0);
+            }
+            _ if !status.is_empty() => eprintln!("httpserver: {status}"),
+            _ => match log_file {
+                Some(path) => eprintln!("httpserver: stopped while starting, see {}", path.display()),
+                None => eprintln!("httpserver: stopped while starting, add --log-file to see why"),
+            },
+        }
+        std::process::exit(1);
+    }
+    drop(reader);
+    // SAFETY: a plain syscall, we just forked so we aren't a group leader
+    if unsafe { libc::setsid() } == -1 {
+        fail(writer, format!("failed to detach by {}", io::Error::last_os_error()));
+    }
+    match fork() {
+        // SAFETY: the intermediate process is done, _exit skips the atexit handlers
+        Ok(pid) if pid > 0 => unsafe { libc::_exit(0) },
+        Ok(_) => {}
+        Err(err) => fail(writer, err),
+    }
+    if let Err(err) = write_pid() {
+        fail(writer, err);
+    }
+    let stdin = File::open("/dev/null").map_err(|err| format!("failed to open /dev/null by {err}"));
+    // SAFETY: both fds are valid
+    let result = stdin.and_then(|stdin| match unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDIN_FILENO) } {
+        -1 => Err(format!("failed to redirect stdin by {}", io::Error::last_os_error())),
+        _ => Ok(()),
+    }).and_then(|_| redirect_output(log_file));
+    if let Err(err) = result {
+        fail(writer, err);
+    }
+    *READY.lock().unwrap() = Some(writer);
+    Ok(())
+}
+
+fn fail(mut writer: File, err: String) -> ! {
+    let _ = writer.write_all(err.as_bytes());
+    std::process::exit(1);
+}
+
+// Listening and with the privileges dropped, the terminal can go
+pub fn started() {
+    if let Some(mut writer) = READY.lock().unwrap().take() {
+        let _ = write!(writer, "ok {}", std::process::id());
+    }
+}
+
+// Startup failed, the terminal gets to see why
+pub fn failed(err: &str) {
+    if let Some(mut writer) = READY.lock().unwrap().take() {
+        let _ = writer.write_all(err.as_bytes());
+    }
+}
+
+pub fn remove_pid_file() {
+    if let Some((path, _)) = PID_FILE.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,156 @@
+//! Dates in the formats HTTP uses, without pulling in a date crate.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];  // 1970-01-01 was a Thursday
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// A point in time broken down into its UTC calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,  // 1 to 12
+    pub day: u32,    // 1 to 31
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32,  // 0 is Thursday, see DAYS
+}
+
+impl DateTime {
+    /// Breaks down a time, times before 1970 are clamped to the epoch.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+        let days = secs.div_euclid(86400);
+        let rest = secs.rem_euclid(86400) as u32;
+        // Civil from days, http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        DateTime {
+            year,
+            month,
+            day,
+            hour: rest / 3600,
+            minute: rest / 60 % 60,
+            second: rest % 60,
+            weekday: days.rem_euclid(7) as u32,
+        }
+    }
+}
+
+/// Formats a time as an IMF-fixdate (RFC 7231 7.1.1.1), `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[t.weekday as usize], t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute, t.second
+    )
+}
+
+/// Formats a time like access logs have it, `06/Nov/1994:08:49:37 +0000`.
+pub fn common_log(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute, t.second
+    )
+}
+
+/// Formats a time as RFC 3339 in UTC down to the millisecond, `1994-11-06T08:49:37.000Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    let millis = time.duration_since(UNIX_EPOCH).map(|d| d.subsec_millis()).unwrap_or(0);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+/// Formats a time down to the minute, `1994-11-06 08:49`, in UTC.
+pub fn iso_minutes(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!("{}-{:02}-{:02} {:02}:{:02}", t.year, t.month, t.day, t.hour, t.minute)
+}
+
+/// Formats a time with the month spelled out, `6 Nov 1994 08:49`, in UTC.
+pub fn text_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!("{} {} {} {:02}:{:02}", t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute)
+}
+
+/// Formats how long before `now` a time was, `3 hours ago`. Times after `now`
+/// are `just now` too, clocks of file systems may run a little ahead.
+pub fn relative(time: SystemTime, now: SystemTime) -> String {
+    let secs = now.duration_since(time).map(|d| d.as_secs()).unwrap_or(0);
+    let (n, unit) = match secs {
+        0..=59 => return String::from("just now"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        86400..=2591999 => (secs / 86400, "day"),
+        2592000..=31535999 => (secs / 2592000, "month"),
+        _ => (secs / 31536000, "year"),
+    };
+    format!("{n} {unit}{} ago", if n == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // The example of RFC 7231 7.1.1.1
+    fn example() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(784111777)
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn calendar_fields() {
+        let t = DateTime::from_system_time(example());
+        assert_eq!((t.year, t.month, t.day, t.hour, t.minute, t.second), (1994, 11, 6, 8, 49, 37));
+        assert_eq!(DAYS[t.weekday as usize], "Sun");
+        // Leap days, of a year divisible by 400 too,
+        assert_eq!(http_date(at(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(http_date(at(1709164800)), "Thu, 29 Feb 2024 00:00:00 GMT");
+        // and none in a year divisible by 100
+        assert_eq!(http_date(at(4107542399)), "Sun, 28 Feb 2100 23:59:59 GMT");
+        assert_eq!(http_date(at(4107542400)), "Mon, 01 Mar 2100 00:00:00 GMT");
+        // Before the epoch is the epoch
+        assert_eq!(http_date(UNIX_EPOCH - Duration::from_secs(10)), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn every_format_of_the_same_time() {
+        assert_eq!(http_date(example()), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(common_log(example()), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(rfc3339(example() + Duration::from_millis(42)), "1994-11-06T08:49:37.042Z");
+        assert_eq!(iso_minutes(example()), "1994-11-06 08:49");
+        assert_eq!(text_date(example()), "6 Nov 1994 08:49");
+    }
+
+    #[test]
+    fn relative_by_the_largest_unit() {
+        let now = at(1_000_000_000);
+        let ago = |secs: u64| relative(now - Duration::from_secs(secs), now);
+        assert_eq!(ago(0), "just now");
+        assert_eq!(ago(59), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(3599), "59 minutes ago");
+        assert_eq!(ago(3600), "1 hour ago");
+        assert_eq!(ago(2 * 86400 + 5), "2 days ago");
+        assert_eq!(ago(45 * 86400), "1 month ago");
+        assert_eq!(ago(3 * 31536000), "3 years ago");
+        // A clock a little ahead
+        assert_eq!(relative(now + Duration::from_secs(30), now), "just now");
+    }
+}
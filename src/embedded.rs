@@ -0,0 +1,47 @@
+// --embedded: the files the build script packed into the binary, served without
+// touching the disk. Only there when built with --features embedded
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct File {
+    pub path: &'static str,  // Like a request path, "/index.html"
+    pub data: &'static [u8],
+    pub gzip: Option<&'static [u8]>,  // Only when it came out smaller
+    modified: u64,  // Seconds, from the file it was packed from
+    pub etag: &'static str,  // From a hash of the data, the same for every build of it
+}
+
+impl File {
+    pub fn modified(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.modified)
+    }
+}
+
+#[cfg(feature = "embedded")]
+include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+#[cfg(not(feature = "embedded"))]
+static FILES: &[File] = &[];
+
+pub const AVAILABLE: bool = cfg!(feature = "embedded");
+
+pub fn count() -> usize {
+    FILES.len()
+}
+
+pub fn get(path: &str) -> Option<&'static File> {
+    FILES.binary_search_by(|file| file.path.cmp(path)).ok().map(|i| &FILES[i])
+}
+
+// There are no directories as such, a path with files below it is one
+pub fn is_dir(path: &str) -> bool {
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    FILES.iter().any(|file| file.path.starts_with(&prefix))
+}
+
+// What a path stands for: the file, or the index.html of a directory
+pub fn find(path: &str) -> Option<&'static File> {
+    match is_dir(path) {
+        true => get(&format!("{}/index.html", path.trim_end_matches('/'))),
+        false => get(path),
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io;
+use tokio::io::AsyncBufRead;
+use httpserver::query::{self, QueryMap};
+use crate::body::Framing;
+use crate::headers::Headers;
+use crate::{chunked, read_small_body};
+
+// How much of a urlencoded form we accept, forms are small things
+#[derive(Debug, Clone)]
+pub struct FormLimits {
+    pub max_fields: usize,
+    pub max_field_size: usize,  // Bytes of a decoded key or value
+    pub max_body_size: u64,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_fields: 100,
+            max_field_size: 16 * 1024,
+            max_body_size: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    Body(io::Error),  // Reading the body failed, the connection can't be used afterwards
+    NotAForm,         // Some other Content-Type
+    Malformed(String),
+    TooManyFields,
+    FieldTooLarge,
+}
+
+impl FormError {
+    // The status to answer with and whether the connection has to be closed
+    pub fn status(&self) -> (i32, bool) {
+        match self {
+            FormError::Body(err) if chunked::is_body_too_large(err) => (413, true),
+            FormError::Body(_) => (400, true),
+            FormError::NotAForm => (415, false),
+            FormError::Malformed(_) => (400, false),
+            FormError::TooManyFields | FormError::FieldTooLarge => (413, false),
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::Body(err) => write!(f, "failed to read the form, {err}"),
+            FormError::NotAForm => write!(f, "body is not application/x-www-form-urlencoded"),
+            FormError::Malformed(what) => write!(f, "malformed form: {what}"),
+            FormError::TooManyFields => write!(f, "too many form fields"),
+            FormError::FieldTooLarge => write!(f, "form field too large"),
+        }
+    }
+}
+
+pub fn is_form(content_type: Option<&str>) -> bool {
+    let media_type = content_type.unwrap_or_default().split(';').next().unwrap_or_default().trim();
+    media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+}
+
+// Read and decode an application/x-www-form-urlencoded body, the same format as
+// a query string. The body is always read completely, even when it's rejected
+pub async fn read(reader: &mut (impl AsyncBufRead + Unpin + Send), framing: &Framing, headers: &Headers, limits: &FormLimits) -> Result<QueryMap, FormError> {
+    let body = read_small_body(reader, framing, limits.max_body_size).await.map_err(FormError::Body)?;
+    if !is_form(headers.get("Content-Type")) {
+        return Err(FormError::NotAForm);
+    }
+    let body = std::str::from_utf8(&body).map_err(|_| FormError::Malformed(String::from("not utf-8")))?;
+    let form = query::parse(body).map_err(|err| FormError::Malformed(err.to_string()))?;
+    if form.len() > limits.max_fields {
+        return Err(FormError::TooManyFields);
+    }
+    if form.iter().any(|(key, value)| key.len().max(value.len()) > limits.max_field_size) {
+        return Err(FormError::FieldTooLarge);
+    }
+    Ok(form)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form_headers(content_type: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+        headers
+    }
+
+    async fn read_form(body: &[u8], limits: &FormLimits) -> Result<QueryMap, FormError> {
+        let mut reader = body;
+        read(&mut reader, &Framing::Length(body.len() as u64), &form_headers("application/x-www-form-urlencoded"), limits).await
+    }
+
+    #[test]
+    fn form_content_types() {
+        assert!(is_form(Some("application/x-www-form-urlencoded")));
+        assert!(is_form(Some("Application/X-WWW-Form-Urlencoded; charset=UTF-8")));
+        assert!(!is_form(Some("multipart/form-data; boundary=x")));
+        assert!(!is_form(Some("application/x-www-form-urlencoded-not")));
+        assert!(!is_form(None));
+    }
+
+    #[tokio::test]
+    async fn decoded_like_a_query() {
+        let form = read_form(b"action=mkdir&name=new+folder%21&name=second&flag", &FormLimits::default()).await.unwrap();
+        assert_eq!(form.get("action"), Some("mkdir"));
+        assert_eq!(form.get("name"), Some("new folder!"));
+        assert_eq!(form.get_all("name").collect::<Vec<_>>(), ["new folder!", "second"]);
+        assert!(form.contains("flag"));
+        assert!(read_form(b"", &FormLimits::default()).await.unwrap().is_empty());
+        assert!(matches!(read_form(b"a=%zz", &FormLimits::default()).await, Err(FormError::Malformed(_))));
+        assert!(matches!(read_form(b"a=%ff", &FormLimits::default()).await, Err(FormError::Malformed(_))));
+        assert!(matches!(read_form(b"a=\xff", &FormLimits::default()).await, Err(FormError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn chunked_form() {
+        let mut reader: &[u8] = b"4\r\na=b&\r\n3\r\nc=d\r\n0\r\n\r\n";
+        let form = read(&mut reader, &Framing::Chunked, &form_headers("application/x-www-form-urlencoded"), &FormLimits::default()).await.unwrap();
+        assert_eq!((form.get("a"), form.get("c")), (Some("b"), Some("d")));
+    }
+
+    #[tokio::test]
+    async fn limits_of_fields_and_body() {
+        let limits = FormLimits { max_fields: 2, max_field_size: 4, max_body_size: 16 };
+        assert_eq!(read_form(b"a=1&b=2", &limits).await.unwrap().len(), 2);
+        assert!(matches!(read_form(b"a=1&b=2&c=3", &limits).await, Err(FormError::TooManyFields)));
+        // Decoded sizes count, %41 is one byte
+        assert!(read_form(b"a=%41%41%41%41", &limits).await.is_ok());
+        assert!(matches!(read_form(b"a=12345", &limits).await, Err(FormError::FieldTooLarge)));
+        assert!(matches!(read_form(b"abcde=1", &limits).await, Err(FormError::FieldTooLarge)));
+        let err = read_form(b"a=1&b=2&c=3&d=4&e=5", &limits).await.unwrap_err();
+        assert_eq!(err.status(), (413, true));
+    }
+
+    #[tokio::test]
+    async fn truncated_bodies() {
+        let headers = form_headers("application/x-www-form-urlencoded");
+        let mut reader: &[u8] = b"a=1&b";
+        let err = read(&mut reader, &Framing::Length(20), &headers, &FormLimits::default()).await.unwrap_err();
+        assert!(matches!(err, FormError::Body(_)));
+        assert_eq!(err.status(), (400, true));
+        let mut reader: &[u8] = b"5\r\na=1";
+        let err = read(&mut reader, &Framing::Chunked, &headers, &FormLimits::default()).await.unwrap_err();
+        assert_eq!(err.status(), (400, true));
+        let mut reader: &[u8] = b"5\r\na=1&b\r\n";
+        assert!(matches!(read(&mut reader, &Framing::Chunked, &headers, &FormLimits::default()).await, Err(FormError::Body(_))));
+    }
+
+    #[tokio::test]
+    async fn other_content_is_read_and_refused() {
+        let mut reader: &[u8] = b"{\"a\":1}next";
+        let err = read(&mut reader, &Framing::Length(7), &form_headers("application/json"), &FormLimits::default()).await.unwrap_err();
+        assert_eq!(err.status(), (415, false));
+        // The connection can go on, the body is out of the way
+        assert_eq!(reader, b"next");
+    }
+}
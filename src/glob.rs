@@ -0,0 +1,230 @@
+//! Shell style glob patterns matched against a single file name.
+//!
+//! `*` matches any run of characters, `?` exactly one, `[abc]`, `[a-z]` and `[!x]`
+//! are character classes. Patterns are compiled once and matched many times.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    One,
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::One => true,
+            Token::Star => false,
+            Token::Class { negated, ranges } => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated,
+        }
+    }
+}
+
+/// A compiled glob pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    source: String,
+    tokens: Vec<Token>,
+}
+
+/// The pattern has a `[` without a matching `]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError(pub String);
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unterminated character class in pattern '{}'", self.0)
+    }
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, PatternError> {
+        let mut tokens = Vec::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => Token::Star,
+                '?' => Token::One,
+                '[' => {
+                    let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                    let mut ranges = Vec::new();
+                    // A ']' right at the start is a member, not the end
+                    let mut first = true;
+                    loop {
+                        let lo = match chars.next() {
+                            Some(']') if !first => break,
+                            Some(c) => c,
+                            None => return Err(PatternError(String::from(source))),
+                        };
+                        first = false;
+                        let hi = match chars.next_if_eq(&'-') {
+                            Some(_) => match chars.next() {
+                                Some(']') => { // A trailing '-' is literal
+                                    ranges.push((lo, lo));
+                                    ranges.push(('-', '-'));
+                                    break;
+                                }
+                                Some(hi) => hi,
+                                None => return Err(PatternError(String::from(source))),
+                            },
+                            None => lo,
+                        };
+                        ranges.push((lo, hi));
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Ok(Pattern { source: String::from(source), tokens })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let text: Vec<char> = name.chars().collect();
+        let (mut t, mut p) = (0, 0);
+        // Where to resume when the current attempt after the last '*' fails
+        let mut backtrack: Option<(usize, usize)> = None;
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(Token::Star) => {
+                    p += 1;
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                Some(token) if token.matches(text[t]) => {
+                    t += 1;
+                    p += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                // Let the star eat one more character and try again
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, t));
+                }
+                None => return false,
+            }
+        }
+        self.tokens[p..].iter().all(|token| *token == Token::Star)
+    }
+
+    /// What each `*` matched, in order, when all of `name` matches.
+    ///
+    /// Earlier stars take as little as they can, `a*b*` against `axbxb` gives `x` and `xb`.
+    pub fn captures<'a>(&self, name: &'a str) -> Option<Vec<&'a str>> {
+        let text: Vec<(usize, char)> = name.char_indices().collect();
+        // Which star each token is, so a star entered again starts its capture over
+        let mut star_index = Vec::with_capacity(self.tokens.len());
+        let mut stars = 0;
+        for token in &self.tokens {
+            star_index.push(stars);
+            if *token == Token::Star {
+                stars += 1;
+            }
+        }
+        // Spans in chars, the stars left over at the end match nothing
+        let mut spans = vec![(text.len(), text.len()); stars];
+        let (mut t, mut p) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(Token::Star) => {
+                    spans[star_index[p]] = (t, t);
+                    p += 1;
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                Some(token) if token.matches(text[t].1) => {
+                    t += 1;
+                    p += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, t));
+                    spans[star_index[star_p - 1]].1 = t;
+                }
+                None => return None,
+            }
+        }
+        if !self.tokens[p..].iter().all(|token| *token == Token::Star) {
+            return None;
+        }
+        let offset = |t: usize| text.get(t).map(|&(i, _)| i).unwrap_or(name.len());
+        Some(spans.into_iter().map(|(start, end)| &name[offset(start)..offset(end)]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        Pattern::new(pattern).unwrap().matches(name)
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(matches("*.log", "server.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "server.log.1"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXXbYYbc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("**x", "x"));
+    }
+
+    #[test]
+    fn question_mark_is_one_character() {
+        assert!(matches("?.txt", "a.txt"));
+        assert!(matches("?.txt", "中.txt"));
+        assert!(!matches("?.txt", ".txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+    }
+
+    #[test]
+    fn classes_ranges_and_negation() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[0-9]", "filex"));
+        assert!(matches("[!.]*", "visible"));
+        assert!(!matches("[!.]*", ".hidden"));
+        assert!(!matches("[^.]*", ".hidden"));
+        // ']' first is a member and a trailing '-' is literal
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn the_whole_name_has_to_match() {
+        assert!(!matches("secret", "secret.txt"));
+        assert!(!matches("secret", "my-secret"));
+        assert!(matches(".git", ".git"));
+        assert!(!matches(".git", ".GIT"));
+    }
+
+    #[test]
+    fn unterminated_class_is_an_error() {
+        for pattern in ["[abc", "a[", "[a-", "[]"] {
+            assert_eq!(Pattern::new(pattern), Err(PatternError(String::from(pattern))), "{pattern}");
+        }
+        assert_eq!(Pattern::new("*.txt").unwrap().as_str(), "*.txt");
+    }
+}
@@ -0,0 +1,479 @@
+// Endpoints answered by code instead of files, looked up by path before anything
+// is read from the disk. /metrics is one. Middleware goes around them for what
+// they all share, logging and compression, and around the files too
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use httpserver::router::Router;
+use crate::access;
+use crate::chunked;
+use crate::compress::{self, CompressOptions};
+use crate::config::Config;
+use crate::log::info;
+use crate::method::{self, Method};
+use crate::metrics;
+use crate::request::Request;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// What a handler gets, the request, what the '*' of its route covered and the body.
+// The entry is what the access log knows about the request so far
+pub struct Call<'a> {
+    pub request: &'a Request,
+    pub rest: &'a str,
+    pub body: Body<'a>,
+    pub entry: &'a access::Entry,
+}
+
+// The request body as it comes in, dechunked and never past its end, so the next
+// request on the connection stays where it is. What a handler leaves unread is
+// skipped after it answered
+pub struct Body<'a> {
+    reader: &'a mut (dyn AsyncRead + Unpin + Send + 'a),
+    left: Option<u64>,  // Of a Content-Length, to tell a body cut short from a whole one
+}
+
+impl<'a> Body<'a> {
+    pub fn new(reader: &'a mut (dyn AsyncRead + Unpin + Send + 'a), length: Option<u64>) -> Self {
+        Body { reader, left: length }
+    }
+
+    // All of it, the limit on bodies applies as it does to uploads
+    pub async fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        AsyncReadExt::read_to_end(self, &mut body).await?;
+        Ok(body)
+    }
+}
+
+impl AsyncRead for Body<'_> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut *this.reader).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - before) as u64;
+        match &mut this.left {
+            Some(left) if read == 0 && *left > 0 => {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request body cut short")));
+            }
+            Some(left) => *left -= read,
+            None => {}
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Written out as it is, HEAD leaves out the body but keeps its length
+pub struct Response {
+    pub status: i32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    sent: Vec<OnSent>,
+}
+
+// What went out in the end, a range of a file or an error page instead of the
+// response, and why it stopped when it did
+pub struct Sent {
+    pub status: Option<i32>,
+    pub bytes: u64,
+    pub err: Option<String>,
+}
+
+// Middleware that wants to know what was sent, it is only known after the chain
+pub type OnSent = Box<dyn FnOnce(&Sent) + Send + Sync>;
+
+impl Response {
+    pub fn new(status: i32, body: impl Into<Vec<u8>>) -> Self {
+        Response { status, headers: Vec::new(), body: body.into(), sent: Vec::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(other, _)| other.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn set_header(&mut self, name: &str, value: String) {
+        match self.headers.iter_mut().find(|(other, _)| other.eq_ignore_ascii_case(name)) {
+            Some((_, old)) => *old = value,
+            None => self.headers.push((String::from(name), value)),
+        }
+    }
+
+    pub fn on_sent(mut self, f: impl FnOnce(&Sent) + Send + Sync + 'static) -> Self {
+        self.sent.push(Box::new(f));
+        self
+    }
+
+    // For whoever writes it, to call once it is written
+    pub fn take_sent(&mut self) -> Vec<OnSent> {
+        std::mem::take(&mut self.sent)
+    }
+}
+
+pub trait Handler: Send + Sync {
+    fn call<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, Response>;
+}
+
+// Async closures and functions, from_fn helps a closure get the lifetimes right
+impl<F> Handler for F
+where
+    F: for<'a> Fn(Call<'a>) -> BoxFuture<'a, Response> + Send + Sync,
+{
+    fn call<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, Response> {
+        self(call)
+    }
+}
+
+pub fn from_fn<F>(f: F) -> Arc<dyn Handler>
+where
+    F: for<'a> Fn(Call<'a>) -> BoxFuture<'a, Response> + Send + Sync + 'static,
+{
+    Arc::new(f)
+}
+
+pub type Handlers = Router<Arc<dyn Handler>>;
+
+// Goes around a handler. It answers in its place, like a check that fails, or has
+// the rest of the chain answer and changes what comes back
+pub trait Middleware: Send + Sync {
+    fn call<'a>(&'a self, call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response>;
+}
+
+impl<F> Middleware for F
+where
+    F: for<'a> Fn(Call<'a>, Next<'a>) -> BoxFuture<'a, Response> + Send + Sync,
+{
+    fn call<'a>(&'a self, call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        self(call, next)
+    }
+}
+
+// What is left of the chain, the handler at its end
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    // The whole chain, for a handler that is no route of its own like a file
+    pub fn new(layers: &'a [Arc<dyn Middleware>], handler: &'a dyn Handler) -> Self {
+        Next { layers, handler }
+    }
+
+    pub fn run(self, call: Call<'a>) -> BoxFuture<'a, Response> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.call(call, Next { layers, handler: self.handler }),
+            None => self.handler.call(call),
+        }
+    }
+}
+
+// A handler with middleware around it, the first one is the outermost
+struct Layered {
+    layers: Vec<Arc<dyn Middleware>>,
+    handler: Arc<dyn Handler>,
+}
+
+impl Handler for Layered {
+    fn call<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, Response> {
+        Next { layers: &self.layers, handler: &*self.handler }.run(call)
+    }
+}
+
+pub fn layered(layers: &[Arc<dyn Middleware>], handler: Arc<dyn Handler>) -> Arc<dyn Handler> {
+    match layers.is_empty() {
+        true => handler,
+        false => Arc::new(Layered { layers: layers.to_vec(), handler }),
+    }
+}
+
+// What the settings turn on, each with the same middleware around it
+pub fn builtin(config: &Config) -> Handlers {
+    let mut layers = access_log(config);
+    layers.push(Arc::new(log_response));
+    layers.extend(gzip(config));
+    let mut handlers = Handlers::new();
+    if config.metrics {
+        handlers.add("/metrics", layered(&layers, from_fn(serve_metrics))).expect("a valid route");
+    }
+    for route in &config.echo {
+        handlers.add(route, layered(&layers, from_fn(serve_echo))).expect("checked by the parser");
+    }
+    handlers
+}
+
+// Around every file that is served, the same as around handlers but for the line of
+// their own. Only what is a representation of the file goes through it, not listings
+pub fn for_files(config: &Config) -> Vec<Arc<dyn Middleware>> {
+    let mut layers = access_log(config);
+    layers.extend(gzip(config));
+    layers
+}
+
+fn access_log(config: &Config) -> Vec<Arc<dyn Middleware>> {
+    match config.access_log {
+        Some(_) => vec![Arc::new(AccessLog { format: config.access_format })],
+        None => Vec::new(),
+    }
+}
+
+fn gzip(config: &Config) -> Option<Arc<dyn Middleware>> {
+    config.compress.enabled.then(|| Arc::new(Gzip { options: config.compress.clone() }) as Arc<dyn Middleware>)
+}
+
+// --access-log, outermost. The line has what was sent, which is only known once the
+// response is written: a range instead of the whole file, or how the client went away
+struct AccessLog {
+    format: access::Format,
+}
+
+impl Middleware for AccessLog {
+    fn call<'a>(&'a self, call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let mut entry = call.entry.clone();
+            let response = next.run(call).await;
+            let format = self.format;
+            response.on_sent(move |sent| {
+                entry.err = sent.err.clone();
+                access::write(access::line(format, &entry, &entry.line, sent.status, sent.bytes, SystemTime::now()));
+            })
+        })
+    }
+}
+
+// Says which requests went to a handler
+fn log_response<'a>(call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response> {
+    Box::pin(async move {
+        let request = call.request;
+        let response = next.run(call).await;
+        info!("[{}] {} answered by a handler with {}", request.id, request.path, response.status);
+        response
+    })
+}
+
+// --compress, by the type. gzip is a representation of its own with its own ETag,
+// and ranges are only served from the uncompressed one
+struct Gzip {
+    options: CompressOptions,
+}
+
+impl Middleware for Gzip {
+    fn call<'a>(&'a self, call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let headers = &call.request.headers;
+            let mut response = next.run(call).await;
+            let content_type = response.header("Content-Type").map(String::from);
+            // Whether it was compressed or not, it depends on Accept-Encoding
+            let varies = response.headers.iter().any(|(name, value)| name.eq_ignore_ascii_case("Vary") && value.to_ascii_lowercase().contains("accept-encoding"));
+            if content_type.as_deref().is_some_and(compress::is_compressible_type) && !varies {
+                response.headers.push((String::from("Vary"), String::from("Accept-Encoding")));
+            }
+            let ranges = response.header("Accept-Ranges") == Some("bytes");
+            let wanted = compress::wanted(&self.options, headers, content_type.as_deref(), response.body.len() as u64, ranges);
+            if !wanted || response.status != 200 || response.header("Content-Encoding").is_some() {
+                return response;
+            }
+            let (level, body) = (self.options.level, std::mem::take(&mut response.body));
+            let compressed = tokio::task::spawn_blocking(move || {
+                let compressed = compress::gzip(&body, level);
+                (body, compressed)
+            }).await;
+            match compressed {
+                // What is compressed already comes out bigger, then it goes as it is
+                Ok((body, compressed)) if compressed.len() >= body.len() => response.body = body,
+                Ok((_, compressed)) => {
+                    response.body = compressed;
+                    response.headers.push((String::from("Content-Encoding"), String::from("gzip")));
+                    if let Some(etag) = response.header("ETag").map(compress::etag) {
+                        response.set_header("ETag", etag);
+                    }
+                }
+                Err(_) => return Response::new(500, ""),
+            }
+            response
+        })
+    }
+}
+
+// OPTIONS with the methods a handler takes, a 405 for the others
+fn check_method(call: &Call<'_>, allowed: &[Method]) -> Option<Response> {
+    let allow = || method::allow_header(allowed);
+    match call.request.method {
+        Method::Options => Some(Response::new(204, "").with_header("Allow", &allow())),
+        method if !allowed.contains(&method) => Some(Response::new(405, "").with_header("Allow", &allow())),
+        _ => None,
+    }
+}
+
+fn serve_metrics(call: Call<'_>) -> BoxFuture<'_, Response> {
+    Box::pin(async move {
+        if let Some(response) = check_method(&call, &[Method::Get, Method::Head, Method::Options]) {
+            return response;
+        }
+        Response::new(200, metrics::render())
+            .with_header("Content-Type", "text/plain; version=0.0.4")
+            .with_header("Cache-Control", "no-store")
+    })
+}
+
+// --echo, the body back as it came with its type, and what the '*' of a prefix
+// covered. One that can't be read is answered with the error once the rest of it is skipped
+fn serve_echo(mut call: Call<'_>) -> BoxFuture<'_, Response> {
+    Box::pin(async move {
+        let allowed = [Method::Get, Method::Head, Method::Options, Method::Post, Method::Put, Method::Patch, Method::Delete];
+        if let Some(response) = check_method(&call, &allowed) {
+            return response;
+        }
+        match call.body.read_to_end().await {
+            Ok(body) => {
+                let content_type = call.request.headers.get("Content-Type").unwrap_or("application/octet-stream");
+                Response::new(200, body).with_header("Content-Type", content_type).with_header("X-Echo-Rest", call.rest)
+            }
+            Err(err) => Response::new(if chunked::is_body_too_large(&err) { 413 } else { 400 }, ""),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::headers::Headers;
+    use crate::request::Version;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut parsed = Headers::new();
+        for (name, value) in headers {
+            parsed.insert(name, value);
+        }
+        Request {
+            id: String::from("1"),
+            user: None,
+            client: String::from("127.0.0.1"),
+            version: Version::Http11,
+            method: Method::Get,
+            path: String::from("/file.txt"),
+            file: String::from("file.txt"),
+            query: Default::default(),
+            headers: parsed,
+        }
+    }
+
+    async fn run(layers: &[Arc<dyn Middleware>], handler: &dyn Handler, request: &Request) -> Response {
+        let (mut empty, entry) = (tokio::io::empty(), access::Entry::new("1", "127.0.0.1"));
+        let call = Call { request, rest: "", body: Body::new(&mut empty, Some(0)), entry: &entry };
+        Next::new(layers, handler).run(call).await
+    }
+
+    // Writes down when it is on the way in and on the way out
+    struct Trace {
+        name: &'static str,
+        steps: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Trace {
+        fn call<'a>(&'a self, call: Call<'a>, next: Next<'a>) -> BoxFuture<'a, Response> {
+            Box::pin(async move {
+                self.steps.lock().unwrap().push(format!("{} in", self.name));
+                let response = next.run(call).await;
+                self.steps.lock().unwrap().push(format!("{} out", self.name));
+                response.with_header("X-Seen-By", self.name)
+            })
+        }
+    }
+
+    fn hello(_call: Call<'_>) -> BoxFuture<'_, Response> {
+        Box::pin(async { Response::new(200, "hello").with_header("Content-Type", "text/plain") })
+    }
+
+    #[tokio::test]
+    async fn middleware_runs_outermost_first() {
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Trace { name: "outer", steps: steps.clone() }),
+            Arc::new(Trace { name: "inner", steps: steps.clone() }),
+        ];
+        let response = run(&layers, &hello, &request(&[])).await;
+        assert_eq!(*steps.lock().unwrap(), ["outer in", "inner in", "inner out", "outer out"]);
+        // The inner one changed the response first, the outer one saw that
+        let seen: Vec<&str> = response.headers.iter().filter(|(name, _)| name == "X-Seen-By").map(|(_, value)| value.as_str()).collect();
+        assert_eq!(seen, ["inner", "outer"]);
+        assert_eq!(response.body, b"hello");
+    }
+
+    fn refuse<'a>(_call: Call<'a>, _next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async { Response::new(403, "") })
+    }
+
+    #[tokio::test]
+    async fn middleware_can_answer_in_place_of_the_rest() {
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn Middleware>> = vec![Arc::new(refuse), Arc::new(Trace { name: "inner", steps: steps.clone() })];
+        let response = run(&layers, &hello, &request(&[])).await;
+        assert_eq!(response.status, 403);
+        assert!(steps.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn body_ends_where_its_reader_does() {
+        let mut reader = tokio::io::AsyncReadExt::take(&b"helloGET / HTTP/1.1\r\n"[..], 5);
+        assert_eq!(Body::new(&mut reader, Some(5)).read_to_end().await.unwrap(), b"hello");
+        // A connection that ends before the Content-Length is an error, not a short body
+        let mut reader = &b"hel"[..];
+        let err = Body::new(&mut reader, Some(5)).read_to_end().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn echo_answers_with_the_body() {
+        let request = request(&[("Content-Type", "application/json")]);
+        let (mut reader, entry) = (&b"{\"a\":1}"[..], access::Entry::new("1", "127.0.0.1"));
+        let call = Call { request: &request, rest: "users/1", body: Body::new(&mut reader, Some(7)), entry: &entry };
+        let response = serve_echo(call).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{\"a\":1}");
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.header("X-Echo-Rest"), Some("users/1"));
+    }
+
+    fn text(_call: Call<'_>) -> BoxFuture<'_, Response> {
+        Box::pin(async {
+            Response::new(200, "text ".repeat(100))
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+                .with_header("ETag", "\"abc\"")
+                .with_header("Accept-Ranges", "bytes")
+        })
+    }
+
+    fn gzip_layers() -> Vec<Arc<dyn Middleware>> {
+        vec![Arc::new(Gzip { options: CompressOptions { enabled: true, level: 6, min_size: 10 } })]
+    }
+
+    #[tokio::test]
+    async fn gzip_changes_the_etag_with_the_body() {
+        let response = run(&gzip_layers(), &text, &request(&[("Accept-Encoding", "gzip")])).await;
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("ETag"), Some("\"abc-gzip\""));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert!(response.body.len() < 500);
+    }
+
+    #[tokio::test]
+    async fn gzip_leaves_ranges_and_refusals_alone() {
+        for headers in [&[("Accept-Encoding", "gzip"), ("Range", "bytes=0-9")][..], &[("Accept-Encoding", "gzip;q=0")]] {
+            let response = run(&gzip_layers(), &text, &request(headers)).await;
+            assert_eq!(response.header("Content-Encoding"), None);
+            assert_eq!(response.header("ETag"), Some("\"abc\""));
+            // Another client may get it gzipped
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// A field name is a token, tchar of RFC 9110 5.6.2
+pub fn is_token(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Request headers in the order they were received,
+// names are compared case-insensitively and repeated names are kept
+#[derive(Debug, Default, Clone)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers { entries: Vec::new() }
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.entries.push((String::from(name), String::from(value)));
+    }
+
+    // Continue the value of the last header, used for unfolding. The length of the
+    // value it makes, None without a header to continue
+    pub fn append_to_last(&mut self, value: &str) -> Option<usize> {
+        let (_, last) = self.entries.last_mut()?;
+        if !value.is_empty() {
+            last.push(' ');
+            last.push_str(value);
+        }
+        Some(last.len())
+    }
+
+    // The first value of the header
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // All the comma separated elements of every header with this name
+    pub fn list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    }
+}
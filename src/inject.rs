@@ -0,0 +1,236 @@
+// --delay, --throttle and --fail-rate: a slow or flaky network to try a frontend
+// against, for every path or only those matching --inject-path
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::time::Sleep;
+use httpserver::glob::Pattern;
+
+#[derive(Debug, Clone, Default)]
+pub struct Injection {
+    pub delay: Option<(Duration, Duration)>,  // Somewhere in between for each response
+    pub throttle: Option<u64>,  // Bytes a second
+    pub fail_rate: f64,  // Out of 1, half of those are 500s and half 503s
+    pub paths: Vec<Pattern>,  // Empty for all of them
+}
+
+impl Injection {
+    pub fn is_set(&self) -> bool {
+        self.delay.is_some() || self.throttle.is_some() || self.fail_rate > 0.0
+    }
+
+    pub fn applies(&self, path: &str) -> bool {
+        self.is_set() && (self.paths.is_empty() || self.paths.iter().any(|pattern| pattern.matches(path)))
+    }
+
+    pub fn pick_delay(&self) -> Option<Duration> {
+        let (min, max) = self.delay?;
+        Some(min + (max - min).mul_f64(random()))
+    }
+
+    // The status to fail with, if this one fails
+    pub fn pick_failure(&self) -> Option<i32> {
+        match random() < self.fail_rate {
+            true if random() < 0.5 => Some(500),
+            true => Some(503),
+            false => None,
+        }
+    }
+}
+
+// Uniform in [0, 1), good enough to be flaky with
+fn random() -> f64 {
+    static KEY: OnceLock<RandomState> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = KEY.get_or_init(RandomState::new).hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (n >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn duration(value: &str) -> Option<Duration> {
+    let (number, scale) = match value.strip_suffix("ms") {
+        Some(ms) => (ms, 0.001),
+        None => (value.strip_suffix('s')?, 1.0),
+    };
+    let number: f64 = number.parse().ok().filter(|number: &f64| number.is_finite() && *number >= 0.0)?;
+    Some(Duration::from_secs_f64(number * scale))
+}
+
+// 200ms, 1.5s or a range like 100ms-2s
+pub fn parse_delay(value: &str) -> Result<(Duration, Duration), String> {
+    let invalid = || format!("invalid delay '{value}', expected a duration like 200ms or 1s, or a range like 100ms-2s");
+    let (min, max) = match value.split_once('-') {
+        Some((min, max)) => (duration(min).ok_or_else(invalid)?, duration(max).ok_or_else(invalid)?),
+        None => (duration(value).ok_or_else(invalid)?, duration(value).ok_or_else(invalid)?),
+    };
+    if min > max {
+        return Err(invalid());
+    }
+    Ok((min, max))
+}
+
+// In bits like network speeds are, 50kbps is 6250 bytes a second
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let (number, scale) = match lower.strip_suffix("bps") {
+        Some(rest) if rest.ends_with('k') => (&rest[..rest.len() - 1], 1000),
+        Some(rest) if rest.ends_with('m') => (&rest[..rest.len() - 1], 1000 * 1000),
+        Some(rest) => (rest, 1),
+        None => ("", 0),
+    };
+    match number.parse::<u64>().ok().and_then(|n| n.checked_mul(scale)) {
+        Some(bits) if bits >= 8 => Ok(bits / 8),
+        _ => Err(format!("invalid rate '{value}', expected bits a second like 800bps, 50kbps or 2mbps")),
+    }
+}
+
+pub fn parse_fail_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("invalid fail rate '{value}', expected a fraction from 0 to 1 like 0.05")),
+    }
+}
+
+// Set for each request, a connection may be throttled for some paths only
+pub trait Throttle {
+    fn throttle(&mut self, rate: Option<u64>);
+}
+
+// Writes at most a tenth of a second's worth at a time, then waits for as long as
+// sending it would have taken. The bytes and their order stay the same, only
+// the pace changes, so framing is never affected
+pub struct Paced<W> {
+    inner: W,
+    rate: Option<u64>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W> Paced<W> {
+    pub fn new(inner: W) -> Self {
+        Paced { inner, rate: None, sleep: None }
+    }
+}
+
+impl<W> Throttle for Paced<W> {
+    fn throttle(&mut self, rate: Option<u64>) {
+        self.rate = rate;
+        if rate.is_none() {
+            self.sleep = None;
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Paced<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(rate) = this.rate else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        if let Some(sleep) = &mut this.sleep {
+            ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+        }
+        let chunk = (rate / 10).max(1) as usize;
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..buf.len().min(chunk)]))?;
+        this.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(written as f64 / rate as f64))));
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn delays_are_fixed_or_a_range() {
+        assert_eq!(parse_delay("200ms"), Ok((Duration::from_millis(200), Duration::from_millis(200))));
+        assert_eq!(parse_delay("1.5s"), Ok((Duration::from_millis(1500), Duration::from_millis(1500))));
+        assert_eq!(parse_delay("100ms-2s"), Ok((Duration::from_millis(100), Duration::from_secs(2))));
+        assert_eq!(parse_delay("0s"), Ok((Duration::ZERO, Duration::ZERO)));
+        for value in ["200", "2m", "-1s", "2s-100ms", "1s-", "ms", "nans", "infs"] {
+            assert_eq!(parse_delay(value), Err(format!("invalid delay '{value}', expected a duration like 200ms or 1s, or a range like 100ms-2s")));
+        }
+    }
+
+    #[test]
+    fn picked_delay_is_inside_the_range() {
+        let injection = Injection { delay: Some((Duration::from_millis(100), Duration::from_millis(200))), ..Injection::default() };
+        for _ in 0..1000 {
+            let delay = injection.pick_delay().unwrap();
+            assert!((Duration::from_millis(100)..=Duration::from_millis(200)).contains(&delay), "{delay:?}");
+        }
+        assert_eq!(Injection::default().pick_delay(), None);
+    }
+
+    #[test]
+    fn rates_are_in_bits() {
+        assert_eq!(parse_rate("800bps"), Ok(100));
+        assert_eq!(parse_rate("50kbps"), Ok(6250));
+        assert_eq!(parse_rate("2Mbps"), Ok(250_000));
+        for value in ["50", "4bps", "kbps", "1.5mbps", "99999999999999999mbps"] {
+            assert_eq!(parse_rate(value), Err(format!("invalid rate '{value}', expected bits a second like 800bps, 50kbps or 2mbps")));
+        }
+    }
+
+    #[test]
+    fn fail_rate_fails_about_that_many() {
+        assert_eq!(parse_fail_rate("0.05"), Ok(0.05));
+        assert_eq!(parse_fail_rate("1"), Ok(1.0));
+        for value in ["1.5", "-0.1", "5%", "NaN"] {
+            assert_eq!(parse_fail_rate(value), Err(format!("invalid fail rate '{value}', expected a fraction from 0 to 1 like 0.05")));
+        }
+        let never = Injection::default();
+        assert!((0..1000).all(|_| never.pick_failure().is_none()));
+        let always = Injection { fail_rate: 1.0, ..Injection::default() };
+        let codes: Vec<i32> = (0..1000).map(|_| always.pick_failure().unwrap()).collect();
+        assert!(codes.iter().all(|code| [500, 503].contains(code)));
+        assert!(codes.contains(&500) && codes.contains(&503));
+        let some = Injection { fail_rate: 0.2, ..Injection::default() };
+        let failed = (0..10000).filter(|_| some.pick_failure().is_some()).count();
+        assert!((1700..=2300).contains(&failed), "{failed} out of 10000");
+    }
+
+    #[test]
+    fn only_the_paths_matching_when_some_are_given() {
+        let mut injection = Injection { fail_rate: 0.5, ..Injection::default() };
+        assert!(injection.applies("/a.txt"));
+        injection.paths.push(Pattern::new("/api/*").unwrap());
+        assert!(injection.applies("/api/users"));
+        assert!(!injection.applies("/a.txt"));
+        injection.fail_rate = 0.0;
+        assert!(!injection.applies("/api/users"));
+    }
+
+    #[tokio::test]
+    async fn paced_writes_are_the_same_bytes_later() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(600).collect();
+        let mut paced = Paced::new(Vec::new());
+        paced.throttle(Some(2000));
+        let start = Instant::now();
+        paced.write_all(&bytes).await.unwrap();
+        // Three chunks of 200 bytes, the wait after the last one is still pending
+        assert!(start.elapsed() >= Duration::from_millis(190), "{:?}", start.elapsed());
+        assert_eq!(paced.inner, bytes);
+        paced.throttle(None);
+        let start = Instant::now();
+        paced.write_all(&bytes).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(paced.inner.len(), 1200);
+    }
+}
@@ -0,0 +1,9 @@
+// The reusable parts of the server, main.rs is built on top of these
+pub mod date;
+pub mod glob;
+pub mod qr;
+pub mod query;
+pub mod rewrite;
+pub mod router;
+pub mod toml;
+pub mod url;
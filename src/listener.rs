@@ -0,0 +1,464 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use crate::log::{info, warn};
+
+// Tuning of the tcp sockets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub nodelay: bool,  // Send small responses right away instead of waiting for more (Nagle)
+    pub keepalive: Option<Keepalive>,
+    pub backlog: i32,  // Connections waiting to be accepted
+    pub recv_buffer: Option<usize>,  // SO_RCVBUF, the system decides otherwise
+    pub send_buffer: Option<usize>,  // SO_SNDBUF
+}
+
+// TCP keepalive probes find clients that went away without closing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub idle: Duration,  // Quiet time before the first probe
+    pub interval: Option<Duration>,  // Between probes
+    pub count: Option<u32>,  // Unanswered probes before the connection is dropped
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        // The backlog is what tokio uses as well
+        SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            backlog: 1024,
+            recv_buffer: None,
+            send_buffer: None,
+        }
+    }
+}
+
+impl fmt::Display for SocketOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nodelay {}, backlog {}", if self.nodelay { "on" } else { "off" }, self.backlog)?;
+        match &self.keepalive {
+            Some(keepalive) => {
+                write!(f, ", keepalive after {}s", keepalive.idle.as_secs())?;
+                if let Some(interval) = keepalive.interval {
+                    write!(f, " every {}s", interval.as_secs())?;
+                }
+                if let Some(count) = keepalive.count {
+                    write!(f, " {count} times")?;
+                }
+            }
+            None => write!(f, ", keepalive off")?,
+        }
+        if let Some(size) = self.recv_buffer {
+            write!(f, ", receive buffer {size}")?;
+        }
+        if let Some(size) = self.send_buffer {
+            write!(f, ", send buffer {size}")?;
+        }
+        Ok(())
+    }
+}
+
+impl SocketOptions {
+    // For every accepted connection, they aren't inherited from the listener everywhere
+    pub fn apply(&self, stream: &tokio::net::TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = &self.keepalive {
+            let mut params = TcpKeepalive::new().with_time(keepalive.idle);
+            // Not every platform lets the probes be tuned, there the system's settings stay
+            #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+            {
+                if let Some(interval) = keepalive.interval {
+                    params = params.with_interval(interval);
+                }
+                if let Some(count) = keepalive.count {
+                    params = params.with_retries(count);
+                }
+            }
+            SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+        Ok(())
+    }
+}
+
+// Where a --listen goes, a tcp address or unix:PATH
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// A --listen address. Link-local ones need their zone, std only takes it as the
+// interface number, so [fe80::1%eth0]:8080 is looked up first
+pub fn parse(value: &str) -> Result<ListenAddr, String> {
+    if let Some(path) = value.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err(String::from("unix: requires the path of the socket"));
+        }
+        if cfg!(not(unix)) {
+            return Err(format!("unix sockets aren't supported on this platform, can't listen on '{value}'"));
+        }
+        return Ok(ListenAddr::Unix(PathBuf::from(path)));
+    }
+    parse_tcp(value).map(ListenAddr::Tcp)
+}
+
+fn parse_tcp(value: &str) -> Result<SocketAddr, String> {
+    let invalid = || format!("invalid listen address '{value}', expected ADDR:PORT or unix:PATH");
+    if let Ok(addr) = value.parse() {
+        return Ok(addr);
+    }
+    let (host, port) = value.strip_prefix('[').and_then(|rest| rest.split_once("]:")).ok_or_else(invalid)?;
+    let (ip, zone) = host.split_once('%').ok_or_else(invalid)?;
+    let index = interface_index(zone).ok_or_else(|| format!("unknown network interface '{zone}' in '{value}'"))?;
+    format!("[{ip}%{index}]:{port}").parse().map_err(|_| invalid())
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: name is a valid C string that outlives the call, which only reads it
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+// The url to reach a bound address from this machine
+pub fn local_url(addr: SocketAddr) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    url(SocketAddr::new(ip, addr.port()))
+}
+
+fn url(addr: SocketAddr) -> String {
+    // Without the zone, browsers don't take it in a url anyway
+    match addr {
+        SocketAddr::V4(addr) => format!("http://{addr}/"),
+        SocketAddr::V6(addr) => format!("http://[{}]:{}/", addr.ip(), addr.port()),
+    }
+}
+
+// Addresses other machines may use, when the address is 0.0.0.0 or [::] that is
+// every address of the interfaces that are up. Loopback and link-local ones are
+// left out, nobody else can reach them
+pub fn network_ips(addr: SocketAddr) -> Vec<IpAddr> {
+    let ips = match addr.ip() {
+        ip if ip.is_loopback() => Vec::new(),
+        ip if !ip.is_unspecified() => vec![ip],
+        ip => interface_addrs().into_iter().filter(|other| other.is_ipv4() == ip.is_ipv4()).collect(),
+    };
+    ips.into_iter().filter(|ip| !ip.is_loopback() && !is_link_local(ip)).collect()
+}
+
+pub fn network_urls(addr: SocketAddr) -> Vec<String> {
+    network_ips(addr).into_iter().map(|ip| url(SocketAddr::new(ip, addr.port()))).collect()
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+// Addresses of the interfaces that are up, none when they can't be listed
+#[cfg(unix)]
+fn interface_addrs() -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills in a list that is only read below and then freed
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return addrs;
+    }
+    let mut current = list;
+    while !current.is_null() {
+        // SAFETY: every node and the address it points to live until freeifaddrs,
+        // the family says which sockaddr the address is
+        unsafe {
+            let entry = &*current;
+            if !entry.ifa_addr.is_null() && entry.ifa_flags & libc::IFF_UP as u32 != 0 {
+                match (*entry.ifa_addr).sa_family as i32 {
+                    libc::AF_INET => {
+                        let addr = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                        addrs.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))));
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                        addrs.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                    }
+                    _ => {}
+                }
+            }
+            current = entry.ifa_next;
+        }
+    }
+    // SAFETY: the list came from getifaddrs and nothing of it is used anymore
+    unsafe { libc::freeifaddrs(list) };
+    addrs
+}
+
+#[cfg(not(unix))]
+fn interface_addrs() -> Vec<IpAddr> {
+    Vec::new()
+}
+
+// Bound the way tokio would, except that v6 sockets are always V6ONLY. Left to
+// the platform [::] also takes v4 on Linux but not on Windows or the BSDs, here
+// it never does and serving both means listening on 0.0.0.0 and [::]
+pub fn bind_tcp(addr: SocketAddr, reuse_port: bool, options: &SocketOptions) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Other sockets, ours or those of another process, may take the address too
+    #[cfg(target_os = "linux")]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(target_os = "linux"))]
+    let _ = reuse_port;
+    // Restarting shouldn't have to wait for the old connections in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    // Before listen(), the window a connection starts with depends on them
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    TcpListener::from_std(socket.into())
+}
+
+// A stale socket file is what a server that didn't get to clean up leaves behind,
+// it goes when nothing answers on it. Anything else at the path is left alone
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "something else than a socket is there"));
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse, "another server is listening on it")),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                info!("removing the stale socket {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+// Who is on the other end of a unix socket, for the logs. There is no address,
+// the credentials are the best there is and the path when even they are missing
+#[cfg(unix)]
+pub fn unix_peer(stream: &tokio::net::UnixStream, path: &std::path::Path) -> String {
+    match stream.peer_cred() {
+        Ok(cred) => match cred.pid() {
+            Some(pid) => format!("unix:{} (uid {}, pid {pid})", path.display(), cred.uid()),
+            None => format!("unix:{} (uid {})", path.display(), cred.uid()),
+        },
+        Err(_) => format!("unix:{}", path.display()),
+    }
+}
+
+// A bound listener of either kind
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+// Any accepted connection, handle_client only reads and writes it
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection for T {}
+
+impl Listener {
+    // With --reuseport a tcp address gets that many sockets, the kernel spreads the
+    // connections over them. A unix socket is always one
+    pub fn bind(addr: &ListenAddr, mode: Option<u32>, reuse_port: Option<usize>, options: &SocketOptions) -> io::Result<Vec<Listener>> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let first = bind_tcp(*addr, reuse_port.is_some(), options)?;
+                // Port 0 picks one, the other sockets have to take the same
+                let bound = first.local_addr()?;
+                let mut listeners = vec![Listener::Tcp(first)];
+                for _ in 1..reuse_port.unwrap_or(1) {
+                    listeners.push(Listener::Tcp(bind_tcp(bound, true, options)?));
+                }
+                Ok(listeners)
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => bind_unix(path, mode).map(|listener| vec![Listener::Unix(listener, path.clone())]),
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                let _ = mode;
+                Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets aren't supported on this platform"))
+            }
+        }
+    }
+
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    pub fn local_addr(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().expect("it should never fail").to_string(),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+
+    // The connection and who it is from
+    pub async fn accept(&self, options: &SocketOptions) -> io::Result<(Box<dyn Connection>, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                // Serving it untuned is better than not at all
+                if let Err(err) = options.apply(&stream) {
+                    warn!("failed to set the socket options of {addr} by {err}");
+                }
+                Ok((Box::new(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                let peer = unix_peer(&stream, path);
+                Ok((Box::new(stream), peer))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_addresses() {
+        assert_eq!(parse("0.0.0.0:8080"), Ok(ListenAddr::Tcp("0.0.0.0:8080".parse().unwrap())));
+        assert_eq!(parse("[::]:8080"), Ok(ListenAddr::Tcp("[::]:8080".parse().unwrap())));
+        assert_eq!(parse("unix:/run/web.sock"), Ok(ListenAddr::Unix(PathBuf::from("/run/web.sock"))));
+        assert!(parse("unix:").is_err());
+        assert!(parse("localhost:8080").unwrap_err().contains("invalid listen address"));
+        assert!(parse("8080").is_err());
+        assert!(parse("[::1]").is_err());
+        assert!(parse("[fe80::1%no-such-if0]:8080").unwrap_err().contains("unknown network interface"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn zone_by_interface_name() {
+        let index = interface_index("lo").expect("linux always has lo");
+        match parse("[fe80::1%lo]:8080") {
+            Ok(ListenAddr::Tcp(SocketAddr::V6(addr))) => {
+                assert_eq!(addr.scope_id(), index);
+                assert_eq!(addr.port(), 8080);
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn urls_to_reach_it() {
+        assert_eq!(local_url("0.0.0.0:80".parse().unwrap()), "http://127.0.0.1:80/");
+        assert_eq!(local_url("[::]:8080".parse().unwrap()), "http://[::1]:8080/");
+        assert_eq!(local_url("192.0.2.7:8080".parse().unwrap()), "http://192.0.2.7:8080/");
+        // The zone stays out of the url
+        assert_eq!(local_url("[fe80::1%2]:8080".parse().unwrap()), "http://[fe80::1]:8080/");
+        assert_eq!(ListenAddr::Unix(PathBuf::from("/run/web.sock")).to_string(), "unix:/run/web.sock");
+    }
+
+    #[test]
+    fn only_others_can_reach_network_ips() {
+        assert!(network_ips("127.0.0.1:80".parse().unwrap()).is_empty());
+        assert!(network_ips("[fe80::1]:80".parse().unwrap()).is_empty());
+        assert_eq!(network_ips("192.0.2.7:80".parse().unwrap()), vec!["192.0.2.7".parse::<IpAddr>().unwrap()]);
+        for ip in network_ips("[::]:80".parse().unwrap()) {
+            assert!(ip.is_ipv6() && !ip.is_loopback() && !is_link_local(&ip), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn v6_sockets_are_v6_only() {
+        let listener = bind_tcp("[::1]:0".parse().unwrap(), false, &SocketOptions::default()).unwrap();
+        assert!(SockRef::from(&listener).only_v6().unwrap());
+        let v4 = bind_tcp("127.0.0.1:0".parse().unwrap(), false, &SocketOptions::default()).unwrap();
+        assert!(v4.local_addr().unwrap().is_ipv4());
+    }
+
+    #[test]
+    fn options_as_logged() {
+        assert_eq!(SocketOptions::default().to_string(), "nodelay on, backlog 1024, keepalive off");
+        let options = SocketOptions {
+            nodelay: false,
+            keepalive: Some(Keepalive { idle: Duration::from_secs(60), interval: Some(Duration::from_secs(10)), count: Some(3) }),
+            backlog: 16,
+            recv_buffer: Some(65536),
+            send_buffer: None,
+        };
+        assert_eq!(options.to_string(), "nodelay off, backlog 16, keepalive after 60s every 10s 3 times, receive buffer 65536");
+    }
+
+    #[tokio::test]
+    async fn options_are_set_on_accepted_streams() {
+        let options = SocketOptions {
+            keepalive: Some(Keepalive { idle: Duration::from_secs(60), interval: Some(Duration::from_secs(10)), count: Some(3) }),
+            recv_buffer: Some(65536),
+            ..SocketOptions::default()
+        };
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), false, &options).unwrap();
+        // The kernel doubles it for its own bookkeeping, it is at least what was asked
+        assert!(SockRef::from(&listener).recv_buffer_size().unwrap() >= 65536);
+        let _client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        options.apply(&stream).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(stream.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(60));
+            assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(10));
+            assert_eq!(socket.keepalive_retries().unwrap(), 3);
+        }
+        SocketOptions { nodelay: false, ..SocketOptions::default() }.apply(&stream).unwrap();
+        assert!(!stream.nodelay().unwrap());
+    }
+}
@@ -0,0 +1,329 @@
+// How much goes to the output. -q, -v and -vv pick the level, RUST_LOG overrides
+// them when set. --log-format json makes every line an object, the access log's too
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use httpserver::date;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,  // Can't go on
+    Warn,  // Something on our side failed
+    Info,  // Startup, requests and what they changed
+    Debug,  // Connections and the details of requests
+    Trace,  // Everything, headers included
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Level> {
+        let level = match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Level::Off,
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            "trace" => Level::Trace,
+            _ => return None,
+        };
+        Some(level)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    // One step more, -v after -v
+    pub fn more(self) -> Level {
+        match self {
+            Level::Off => Level::Error,
+            Level::Error => Level::Warn,
+            Level::Warn => Level::Info,
+            Level::Info => Level::Debug,
+            Level::Debug | Level::Trace => Level::Trace,
+        }
+    }
+
+    // RUST_LOG as env_logger reads it, only the parts about us: a bare level like
+    // "debug" or "httpserver=debug" in a comma separated list, the latter wins
+    fn from_env() -> Option<Level> {
+        Level::from_rust_log(&std::env::var("RUST_LOG").ok()?)
+    }
+
+    fn from_rust_log(value: &str) -> Option<Level> {
+        let mut level = None;
+        for directive in value.split(',') {
+            match directive.split_once('=') {
+                Some((target, value)) if target.trim() == "httpserver" => return Level::parse(value),
+                Some(_) => {}
+                None => level = Level::parse(directive).or(level),
+            }
+        }
+        level
+    }
+}
+
+// At startup and after a reload
+pub fn init(level: Level, format: Format) {
+    LEVEL.store(Level::from_env().unwrap_or(level) as u8, Ordering::Relaxed);
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        0 => Format::Text,
+        _ => Format::Json,
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// What a line of JSON holds, an event or a request of the access log. The fields
+// are always named the same, those that don't apply to a line are left out
+#[derive(Debug, Default)]
+pub struct Record<'a> {
+    pub level: Option<Level>,
+    pub id: Option<&'a str>,  // Of the request
+    pub msg: Option<String>,
+    pub peer: Option<&'a str>,
+    pub method: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub status: Option<i32>,
+    pub bytes: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub user: Option<&'a str>,
+    pub vhost: Option<&'a str>,
+    pub referer: Option<&'a str>,
+    pub agent: Option<&'a str>,
+    pub err: Option<&'a str>,
+}
+
+impl Record<'_> {
+    // Strings are escaped by serde_json, they are valid UTF-8 by now
+    pub fn to_json(&self, time: SystemTime) -> String {
+        let mut object = serde_json::Map::new();
+        object.insert(String::from("ts"), date::rfc3339(time).into());
+        let mut field = |name: &str, value: Option<serde_json::Value>| {
+            if let Some(value) = value {
+                object.insert(String::from(name), value);
+            }
+        };
+        field("level", self.level.map(|level| level.as_str().into()));
+        field("id", self.id.map(Into::into));
+        field("msg", self.msg.as_deref().map(Into::into));
+        field("peer", self.peer.map(Into::into));
+        field("method", self.method.map(Into::into));
+        field("path", self.path.map(Into::into));
+        field("status", self.status.map(Into::into));
+        field("bytes", self.bytes.map(Into::into));
+        field("duration_ms", self.duration_ms.map(Into::into));
+        field("user", self.user.map(Into::into));
+        field("vhost", self.vhost.map(Into::into));
+        field("referer", self.referer.map(Into::into));
+        field("agent", self.agent.map(Into::into));
+        field("err", self.err.map(Into::into));
+        serde_json::Value::Object(object).to_string()
+    }
+}
+
+// One line of output for an event, as text or JSON. The request id the messages
+// start with gets a field of its own in JSON
+pub fn format_line(level: Level, args: fmt::Arguments<'_>) -> String {
+    match format() {
+        Format::Text => args.to_string(),
+        Format::Json => {
+            let msg = args.to_string();
+            let (id, msg) = match msg.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+                Some((id, rest)) if !id.contains(' ') => (Some(id), rest),
+                _ => (None, msg.as_str()),
+            };
+            let record = Record { level: Some(level), id, msg: Some(String::from(msg)), ..Record::default() };
+            record.to_json(SystemTime::now())
+        }
+    }
+}
+
+// Where every log macro ends up
+pub fn event(level: Level, args: fmt::Arguments<'_>) {
+    output(format_line(level, args));
+}
+
+enum Message {
+    Line(String),
+    Flush(mpsc::Sender<()>),
+}
+
+// Lines waiting for the log thread, with room for a burst. A full queue makes the
+// next line wait, none are dropped
+static QUEUE: OnceLock<SyncSender<Message>> = OnceLock::new();
+const QUEUE_LEN: usize = 4096;
+
+// A thread of its own writes the lines and rotates the file, whatever logged a line
+// goes on right away. Only once the process is the one that stays, a fork leaves
+// the threads behind. Lines before go out directly
+pub fn start() {
+    let (queue, lines) = mpsc::sync_channel(QUEUE_LEN);
+    if std::thread::Builder::new().name(String::from("log")).spawn(move || write_lines(lines)).is_ok() {
+        let _ = QUEUE.set(queue);
+    }
+}
+
+// A line for stdout, the access log's included when it goes there
+pub fn output(line: String) {
+    let line = match QUEUE.get() {
+        Some(queue) => match queue.send(Message::Line(line)) {
+            Ok(()) => return,
+            Err(mpsc::SendError(Message::Line(line))) => line,
+            Err(_) => return,
+        },
+        None => line,
+    };
+    println!("{line}");
+}
+
+// Before exiting, what is still queued would be lost
+pub fn flush() {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let (done, flushed) = mpsc::channel();
+    if queue.send(Message::Flush(done)).is_ok() {
+        let _ = flushed.recv_timeout(Duration::from_secs(5));
+    }
+}
+
+fn write_lines(lines: Receiver<Message>) {
+    let mut stdout = io::stdout();
+    while let Ok(message) = lines.recv() {
+        match message {
+            Message::Line(line) => {
+                let _ = writeln!(stdout, "{line}");
+                // --log-max-size may want a new file, what this logs finds it empty
+                #[cfg(unix)]
+                match crate::daemon::rotate_if_full() {
+                    Ok(Some(path)) if enabled(Level::Info) => {
+                        let _ = writeln!(stdout, "{}", format_line(Level::Info, format_args!("rotated the log file {}", path.display())));
+                    }
+                    Ok(_) => {}
+                    Err(err) if enabled(Level::Warn) => {
+                        let _ = writeln!(stdout, "{}", format_line(Level::Warn, format_args!("{err}")));
+                    }
+                    Err(_) => {}
+                }
+            }
+            Message::Flush(done) => {
+                let _ = stdout.flush();
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+macro_rules! log_at {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::$level) {
+            $crate::log::event($crate::log::Level::$level, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log_at!(Error, $($arg)*) };
+}
+
+// Named warn by the re-export, a macro_rules warn would clash with #[warn]
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log_at!(Warn, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log_at!(Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log_at!(Debug, $($arg)*) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::log_at!(Trace, $($arg)*) };
+}
+
+pub(crate) use {debug, error, info, log_at, trace, warning as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_log_directives() {
+        assert_eq!(Level::from_rust_log("debug"), Some(Level::Debug));
+        assert_eq!(Level::from_rust_log("httpserver=trace"), Some(Level::Trace));
+        // Ours wins over a bare level, in either order
+        assert_eq!(Level::from_rust_log("warn,httpserver=debug"), Some(Level::Debug));
+        assert_eq!(Level::from_rust_log("httpserver=error,info"), Some(Level::Error));
+        assert_eq!(Level::from_rust_log("tokio=trace,hyper=debug"), None);
+        assert_eq!(Level::from_rust_log("tokio=trace, WARN"), Some(Level::Warn));
+        assert_eq!(Level::from_rust_log("loud"), None);
+        assert_eq!(Level::from_rust_log(""), None);
+    }
+
+    #[test]
+    fn each_step_is_one_more() {
+        let ladder = [Level::Off, Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+        for pair in ladder.windows(2) {
+            assert_eq!(pair[0].more(), pair[1]);
+            assert!(pair[0] < pair[1]);
+        }
+        assert_eq!(Level::Trace.more(), Level::Trace);
+        for level in ladder {
+            assert_eq!(Level::parse(level.as_str()), Some(level));
+        }
+        assert_eq!(Level::parse(" DEBUG "), Some(Level::Debug));
+    }
+
+    #[test]
+    fn record_fields_are_only_those_set() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(784111777042);
+        let event = Record { level: Some(Level::Warn), msg: Some(String::from("disk full")), ..Record::default() };
+        assert_eq!(event.to_json(time), r#"{"level":"warn","msg":"disk full","ts":"1994-11-06T08:49:37.042Z"}"#);
+        let request = Record { id: Some("r1"), method: Some("GET"), path: Some("/a"), status: Some(200), bytes: Some(0), duration_ms: Some(3), ..Record::default() };
+        let parsed: serde_json::Value = serde_json::from_str(&request.to_json(time)).unwrap();
+        let mut keys: Vec<&str> = parsed.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["bytes", "duration_ms", "id", "method", "path", "status", "ts"]);
+        assert_eq!((parsed["status"].as_i64(), parsed["bytes"].as_u64()), (Some(200), Some(0)));
+    }
+
+    #[test]
+    fn record_strings_are_escaped() {
+        let path = "/a\"b\\c\n\r\t\u{1}\u{7f}é\u{fffd}";
+        let record = Record { path: Some(path), agent: Some("say \"hi\""), ..Record::default() };
+        let line = record.to_json(SystemTime::now());
+        assert!(!line.contains('\n') && !line.contains('\u{1}'), "{line}");
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["path"], path);
+        assert_eq!(parsed["agent"], "say \"hi\"");
+    }
+}
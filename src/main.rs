@@ -1,233 +1,2301 @@
-use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ErrorKind};
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
-use std::str::{self, Chars};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ErrorKind};
+use sha2::{Digest, Sha256};
 
-// Parse the given string and return the method and path
-fn parse_request_line(line: &str) -> Option<(&str, &str)> {
+mod access;
+mod auth;
+mod body;
+mod chunked;
+mod compress;
+mod conditional;
+mod config;
+mod config_file;
+#[cfg(unix)]
+mod daemon;
+mod embedded;
+mod form;
+mod handler;
+mod headers;
+mod inject;
+mod listener;
+mod log;
+mod mdns;
+mod method;
+mod metrics;
+mod mime;
+mod multipart;
+mod privileges;
+mod range;
+mod redirect;
+mod request;
+mod response;
+mod resume;
+mod shutdown;
+mod trace_io;
+mod upload;
+mod webdav;
+mod webhook;
+
+use body::Framing;
+use chunked::{ChunkedReader, ChunkedWriter, TrailerName, Trailers};
+use config::{Config, DateFormat, FaviconMode, LiveConfig, TrailingSlash, Tried, UpgradeMode};
+use handler::{Body, Call, Handler, Next, OnSent, Response, Sent};
+use headers::Headers;
+use inject::{Paced, Throttle};
+use listener::{Connection, ListenAddr, Listener};
+use method::Method;
+use multipart::{Multipart, MultipartError};
+use range::Ranges;
+use httpserver::date;
+use httpserver::qr::QrCode;
+use httpserver::url;
+use httpserver::query::{self, QueryMap};
+use httpserver::rewrite;
+use request::{HeadError, Request, Version};
+use response::ResponseWriter;
+use trace_io::Traced;
+use resume::ContentRange;
+use upload::{Upload, UploadError};
+use crate::log::{debug, error, info, trace, warn};
+
+// Served for /favicon.ico with --favicon builtin
+const FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+// Largest request body we are willing to read
+const MAX_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+// Largest single file of a form upload, the whole body is still MAX_BODY_SIZE at most
+const MAX_FORM_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+// Form fields that aren't files are read and thrown away, they should be tiny
+const MAX_FORM_FIELD_SIZE: u64 = 64 * 1024;
+
+// PROPFIND bodies list a handful of property names
+const MAX_PROPFIND_SIZE: u64 = 64 * 1024;
+
+// Internal rewrites of one request, more are a loop in the rules
+const MAX_REWRITES: usize = 10;
+
+// Parse the given string and return the method, path and version
+fn parse_request_line(line: &str) -> Option<(&str, &str, &str)> {
     let mut s = line.split(" ");
     let method = s.next()?;
     let path = s.next()?;
-    let _ver = s.next()?;
-    if !s.next().is_none() { // It should just 3 items
+    let ver = s.next()?;
+    if s.next().is_some() { // It should just 3 items
         return None;
     }
-    return Some((method, path));
-}
-
-fn status_code_to_string(code: i32) -> &'static str {
-    match code {
-        200 => "OK",
-        201 => "Created",
-        202 => "Accepted",
-        204 => "No Content",
-        301 => "Moved Permanently",
-        302 => "Found",
-        304 => "Not Modified",
-        400 => "Bad Request",
-        401 => "Unauthorized",
-        403 => "Forbidden",
-        404 => "Not Found",
-        405 => "Method Not Allowed",
-        500 => "Internal Server Error",
-        501 => "Not Implemented",
-        502 => "Bad Gateway",
-        503 => "Service Unavailable",
-        504 => "Gateway Timeout",
-        _ => panic!("WTF?")
-    }
-}
-
-async fn write_reply(stream:  &mut (impl AsyncWriteExt + Unpin), code: i32, content: &[u8]) -> io::Result<()> {
-    let reply = format!(
-        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n\r\n",
-        code,
-        status_code_to_string(code),
-        content.len()
-    );
-    stream.write_all(reply.as_bytes()).await?;
-    stream.write_all(content).await?;
-    stream.flush().await?;
+    Some((method, path, ver))
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+// Rows are collected into chunks of about this size before being sent
+const LISTING_CHUNK_SIZE: usize = 8 * 1024;
+
+// Name of a directory entry, None when it isn't utf-8. Paths are strings all the way
+// through here, a link made from a lossy name would point at a file that doesn't exist
+fn entry_name(entry: &tokio::fs::DirEntry) -> Option<String> {
+    let name = entry.file_name().into_string().ok();
+    if name.is_none() {
+        debug!("skipping {} in the listing, its name is not utf-8", entry.path().to_string_lossy());
+    }
+    name
+}
+
+// Weak validator of a listing, a hash of everything it shows. The mtime of a directory
+// isn't enough, not every filesystem bumps it when an entry changes. The variant
+// keeps the HTML and JSON forms apart
+async fn listing_etag(path: &str, file: &str, variant: &str, config: &Config) -> io::Result<String> {
+    let mut dir = tokio::fs::read_dir(file).await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if config.exclude.iter().any(|pattern| pattern.matches(&name)) {
+            continue;
+        }
+        let meta = tokio::fs::metadata(entry.path()).await.ok();
+        let modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+        entries.push((name, meta.map(|meta| (meta.is_dir(), meta.len())), modified));
+    }
+    // Listed as directories, without anything read from them
+    let mounts: Vec<&str> = config.mounts_in(path).collect();
+    // The order read_dir gives isn't stable
+    entries.sort();
+    // Relative dates change by themselves, the tag has to as well
+    let minute = (config.listing_dates == DateFormat::Relative)
+        .then(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|since| since.as_secs() / 60).unwrap_or(0));
+    let mut hasher = DefaultHasher::new();
+    (variant, config.writes(path), config.listing_dates, &config.listing_lang, minute, entries, mounts).hash(&mut hasher);
+    Ok(format!("W/\"{:016x}\"", hasher.finish()))
+}
+
+fn listing_date(modified: std::time::SystemTime, config: &Config) -> String {
+    match config.listing_dates {
+        DateFormat::Iso => date::iso_minutes(modified),
+        DateFormat::Text => date::text_date(modified),
+        DateFormat::Relative => date::relative(modified, std::time::SystemTime::now()),
+    }
+}
+
+// The body of a listing as it goes out: chunked, or for HTTP/1.0 which has no
+// chunked coding as it is, ended by closing the connection
+enum ListingBody<W> {
+    Chunked(ChunkedWriter<W>),
+    Plain(W),
+}
+
+impl<W: AsyncWrite + Unpin> ListingBody<W> {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            ListingBody::Chunked(body) => body.write_chunk(data).await,
+            ListingBody::Plain(stream) => stream.write_all(data).await,
+        }
+    }
+
+    // Trailers only go out chunked, without them the digest is just not sent
+    async fn finish(self, trailers: Vec<(TrailerName, String)>) -> io::Result<()> {
+        match self {
+            ListingBody::Chunked(body) => body.finish(trailers).await.map(drop),
+            ListingBody::Plain(mut stream) => stream.flush().await,
+        }
+    }
+}
+
+// Send the listing of a directory as it is read, with chunked encoding
+// so memory stays bounded no matter how many entries there are
+async fn write_listing(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, path: &str, file: &str, etag: &str, version: Version, head_only: bool, config: &Config) -> io::Result<()> {
+    // Open it first, so a failure can still become a proper error page
+    let mut dir = tokio::fs::read_dir(file).await?;
+    // Its digest is only known once it was all read and sent, it goes in a trailer
+    let mut trailers = Trailers::new();
+    let digest = trailers.announce("Content-Digest")?;
+    let announced = trailers.header().unwrap_or_default();
+    let chunked = version != Version::Http10;
+    let extra: &[(&str, &str)] = match chunked {
+        true => &[("Content-Type", "text/html; charset=utf-8"), ("Transfer-Encoding", "chunked"), ("ETag", etag), ("Trailer", &announced)],
+        // Nothing else can tell a 1.0 client where the body ends, even one keeping the connection
+        false => {
+            writer.set_common("Connection", "close");
+            &[("Content-Type", "text/html; charset=utf-8"), ("ETag", etag)]
+        }
+    };
+    writer.write_head(200, extra, None).await?;
+    if head_only {
+        return writer.stream.flush().await;
+    }
+
+    let mut prefix = url::encode_path(path);
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    let mut body = match chunked {
+        true => ListingBody::Chunked(ChunkedWriter::new(&mut writer.stream, trailers)),
+        false => ListingBody::Plain(&mut writer.stream),
+    };
+    let mut hasher = Sha256::new();
+    let mut content = format!(
+        "<!DOCTYPE html><html lang=\"{}\"><head><meta charset=\"utf-8\" /><title>{}</title></head><body>",
+        config.listing_lang, escape_html(path)
+    );
+    let writes = config.writes(path);
+    if writes {
+        content.push_str(&format!(
+            "<form method=\"post\" action=\"{}\" enctype=\"multipart/form-data\">\
+             <input type=\"file\" name=\"file\" multiple /> <input type=\"submit\" value=\"Upload\" /></form>\
+             <form method=\"post\" action=\"{}\"><input type=\"hidden\" name=\"action\" value=\"mkdir\" />\
+             <input name=\"name\" /> <input type=\"submit\" value=\"New folder\" /></form>",
+            escape_html(&prefix), escape_html(&prefix)
+        ));
+    }
+    content.push_str("<ul>");
+    // Mounts first, what is on disk under the same name can't be reached anyway
+    let mounts: Vec<&str> = config.mounts_in(path).collect();
+    for name in &mounts {
+        let pathname = format!("{prefix}{}/", url::encode_path_segment(name));
+        content.push_str(&format!("<li><a href=\"{}\">{}/</a></li>", escape_html(&pathname), escape_html(name)));
+    }
+    while let Some(entry) = dir.next_entry().await? {
+        let Some(name) = entry_name(&entry) else {
+            continue;
+        };
+        if config.exclude.iter().any(|pattern| pattern.matches(&name)) || mounts.contains(&name.as_str()) {
+            continue;
+        }
+        let pathname = format!("{prefix}{}", url::encode_path_segment(&name));
+        content.push_str(&format!("<li><a href=\"{}\">{}</a>", escape_html(&pathname), escape_html(&name)));
+        if let Some(modified) = tokio::fs::metadata(entry.path()).await.ok().and_then(|meta| meta.modified().ok()) {
+            content.push_str(&format!(" <time datetime=\"{}Z\">{}</time>", date::iso_minutes(modified).replace(' ', "T"), listing_date(modified, config)));
+        }
+        if writes {
+            let (action, name) = (escape_html(&prefix), escape_html(&name));
+            content.push_str(&format!(
+                " <form method=\"post\" action=\"{action}\" style=\"display:inline\"><input type=\"hidden\" name=\"action\" value=\"rename\" />\
+                 <input type=\"hidden\" name=\"from\" value=\"{name}\" /><input name=\"to\" value=\"{name}\" /> <input type=\"submit\" value=\"Rename\" /></form>\
+                 <form method=\"post\" action=\"{action}\" style=\"display:inline\"><input type=\"hidden\" name=\"action\" value=\"delete\" />\
+                 <input type=\"hidden\" name=\"name\" value=\"{name}\" /> <input type=\"submit\" value=\"Delete\" /></form>"
+            ));
+        }
+        content.push_str("</li>");
+        if content.len() >= LISTING_CHUNK_SIZE {
+            hasher.update(content.as_bytes());
+            body.write(content.as_bytes()).await?;
+            content.clear();
+        }
+    }
+    content.push_str("</ul></body></html>");
+    hasher.update(content.as_bytes());
+    body.write(content.as_bytes()).await?;
+    // RFC 9530, of the listing as sent
+    let value = format!("sha-256=:{}:", auth::base64_encode(&hasher.finalize()));
+    body.finish(vec![(digest, value)]).await
+}
+
+// Listing for scripts: ?format=json or an Accept asking for JSON. Built in one piece,
+// compact by default and indented with a trailing newline for ?pretty
+async fn write_json_listing(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, path: &str, file: &str, etag: &str, head_only: bool, pretty: bool, config: &Config) -> io::Result<()> {
+    let mut dir = tokio::fs::read_dir(file).await?;
+    let mut prefix = url::encode_path(path);
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    let mounts: Vec<&str> = config.mounts_in(path).collect();
+    let mut entries: Vec<_> = mounts.iter().map(|name| serde_json::json!({
+        "name": name,
+        "href": format!("{prefix}{}/", url::encode_path_segment(name)),
+        "type": "directory",
+        "size": null,
+        "modified": null,
+    })).collect();
+    while let Some(entry) = dir.next_entry().await? {
+        let Some(name) = entry_name(&entry) else {
+            continue;
+        };
+        if config.exclude.iter().any(|pattern| pattern.matches(&name)) || mounts.contains(&name.as_str()) {
+            continue;
+        }
+        let meta = tokio::fs::metadata(entry.path()).await.ok();
+        let is_dir = meta.as_ref().is_some_and(|meta| meta.is_dir());
+        let modified = meta.as_ref()
+            .and_then(|meta| meta.modified().ok())
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since| since.as_secs());
+        entries.push(serde_json::json!({
+            "name": name,
+            "href": format!("{prefix}{}", url::encode_path_segment(&name)),
+            "type": if is_dir { "directory" } else { "file" },
+            "size": meta.as_ref().filter(|_| !is_dir).map(|meta| meta.len()),
+            "modified": modified,
+        }));
+    }
+    let listing = serde_json::json!({ "path": path, "entries": entries });
+    let body = match pretty {
+        true => serde_json::to_string_pretty(&listing).map(|json| json + "\n"),
+        false => serde_json::to_string(&listing),
+    };
+    let body = body.map_err(io::Error::other)?;
+    let extra = [("Content-Type", "application/json"), ("ETag", etag)];
+    if head_only {
+        writer.write_head(200, &extra, Some(body.len())).await?;
+        return writer.stream.flush().await;
+    }
+    writer.write_reply_with(200, &extra, body.as_bytes()).await
+}
+
+// Whether a listing should be JSON instead of HTML
+fn wants_json(request: &Request) -> bool {
+    request.query.get("format") == Some("json")
+        || request.headers.list("Accept").any(|accept| accept.starts_with("application/json"))
+}
+
+// ?pretty, or a parameter on the JSON media type (Accept: application/json; pretty)
+fn wants_pretty(request: &Request) -> bool {
+    request.query.contains("pretty")
+        || request.headers.list("Accept")
+            .filter(|accept| accept.starts_with("application/json"))
+            .any(|accept| accept.split(';').skip(1).any(|param| param.trim().starts_with("pretty")))
+}
+
+// Value of Content-Disposition for downloading a file, a plain ascii filename
+// for old clients plus the exact one encoded per RFC 5987 when it's not ascii
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    if fallback == name {
+        return format!("attachment; filename=\"{name}\"");
+    }
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{}", url::encode_path_segment(name))
+}
+
+enum Resource {
+    File,
+    Directory,
+    Missing,  // What a PUT or MKCOL may create
+}
+
+// Methods a resource supports, for the Allow header
+fn allowed_methods(resource: Resource, writable: bool) -> Vec<Method> {
+    match resource {
+        Resource::Missing if writable => vec![Method::Options, Method::Put, Method::Mkcol],
+        Resource::Missing => Vec::new(),
+        Resource::File if writable => vec![
+            Method::Get, Method::Head, Method::Options, Method::Propfind,
+            Method::Put, Method::Delete, Method::Move, Method::Copy,
+        ],
+        Resource::Directory if writable => vec![
+            Method::Get, Method::Head, Method::Options, Method::Propfind,
+            Method::Post, Method::Delete, Method::Move, Method::Copy,
+        ],
+        Resource::File | Resource::Directory => vec![Method::Get, Method::Head, Method::Options, Method::Propfind],
+    }
+}
+
+// What one resource or another supports, for OPTIONS *
+fn server_methods(writable: bool) -> Vec<Method> {
+    let mut methods = allowed_methods(Resource::Directory, writable);
+    for method in allowed_methods(Resource::File, writable).into_iter().chain(allowed_methods(Resource::Missing, writable)) {
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+    methods
+}
+
+// The body of a request as a plain reader, whatever its framing
+fn body_reader<'a>(reader: &'a mut (impl AsyncBufRead + Unpin + Send), framing: &Framing) -> io::Result<Box<dyn AsyncRead + Unpin + Send + 'a>> {
+    match *framing {
+        Framing::Empty => Ok(Box::new(tokio::io::empty())),
+        Framing::Length(n) if n > MAX_BODY_SIZE => Err(io::Error::other(chunked::BodyTooLarge)),
+        Framing::Length(n) => Ok(Box::new(reader.take(n))),
+        Framing::Chunked => Ok(Box::new(ChunkedReader::new(reader, MAX_BODY_SIZE))),
+    }
+}
+
+// Consume the body so it doesn't get parsed as the next request. A client still
+// waiting for a 100 Continue gets the answer right away instead, whether its body
+// follows is up to it then, so the connection ends after the answer
+async fn drain_body(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, reader: &mut (impl AsyncBufRead + Unpin + Send), framing: &Framing) -> io::Result<u64> {
+    if writer.continue_pending() {
+        writer.set_common("Connection", "close");
+        return Ok(0);
+    }
+    tokio::io::copy(&mut body_reader(reader, framing)?, &mut tokio::io::sink()).await
+}
+
+// The whole body of a request that should be short, more than limit bytes is too large
+async fn read_small_body(reader: &mut (impl AsyncBufRead + Unpin + Send), framing: &Framing, limit: u64) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let n = body_reader(reader, framing)?.take(limit + 1).read_to_end(&mut body).await?;
+    if n as u64 > limit {
+        return Err(io::Error::other(chunked::BodyTooLarge));
+    }
+    // The connection ended before all of a Content-Length came
+    if matches!(*framing, Framing::Length(length) if length != n as u64) {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "request body truncated"));
+    }
+    Ok(body)
+}
+
+// Status for a body we couldn't read, the connection can't be used afterwards
+fn body_error_status(err: &io::Error) -> i32 {
+    if chunked::is_body_too_large(err) { 413 } else { 400 }
+}
+
+// The peer address is only used for logging
+async fn handle_client(stream: impl Connection, peeraddr: String, live: Arc<LiveConfig>) -> io::Result<()> {
+    // Counted out however this ends
+    let _active = metrics::ConnectionGuard::enter();
+    debug!("handling peer {peeraddr}");
+
+    let (reader, writer) = tokio::io::split(stream);
+    let mut writer = ResponseWriter::new(Traced::new(Paced::new(writer)));
+    let mut reader = Traced::new(BufReader::new(reader));
+
+    loop { // For Handle each per requests
+        // Reloaded settings apply from the next request on
+        let config = live.get();
+        let mut buffer = String::new();
+        writer.clear_common();
+        writer.set_error_pages(config.error_pages.clone());
+        let mut id = request::new_id();
+        let mut entry = access::Entry::new(&id, &peeraddr);
+        writer.reset_sent();
+        writer.set_common("X-Request-Id", &id);
+        reader.start(config.trace_io.then(|| id.clone()));
+        writer.stream.get_mut().start(config.trace_io.then(|| id.clone()));
+        writer.stream.throttle(None);
+        for (name, value) in config.security.headers() {
+            writer.set_common(name, value);
+        }
+        // After those, so -H can replace one of them
+        for (name, value, first) in config.custom_headers() {
+            match first {
+                true => writer.set_common(name, value),
+                false => writer.add_common(name, value),
+            }
+        }
+
+        // Read the request line. Empty lines before it are skipped, some clients send
+        // an extra line ending after a body (RFC 7230 3.5). An idle connection is
+        // closed when the server shuts down, there is nothing to finish
+        let read = tokio::select! {
+            read = request::read_request_line(&mut reader, &mut buffer, &config.parser) => read,
+            _ = shutdown::wait() => {
+                debug!("[{id}] shutting down, closing the idle connection");
+                return Ok(());
+            }
+        };
+        entry.line = buffer.clone();
+        match read {
+            Ok(true) => {}
+            Ok(false) => { // EOF
+                debug!("[{id}] EOF, Quiting...");
+                return Ok(());
+            }
+            Err(HeadError::Io(err)) => return Err(err),
+            Err(HeadError::Eof) => return Ok(()),
+            Err(err) => {
+                info!("[{id}] bad request line, {err}");
+                let code = if matches!(err, HeadError::LineTooLong) { 414 } else { 400 };
+                let written = writer.write_closing_error(code).await;
+                entry.err = written.as_ref().err().map(ToString::to_string);
+                log_access(&writer, &entry, &config);
+                return written;
+            }
+        }
+        // This one is still answered, it is the last one
+        let closing = shutdown::is_shutting_down();
+        if closing {
+            writer.set_common("Connection", "close");
+        }
+        // The clock starts once a request is there, waiting for one is not handling it
+        let handled = handle_request(&mut reader, &mut writer, &buffer, &mut id, &mut entry, &config);
+        let handled = match config.request_timeout {
+            Some(limit) => tokio::time::timeout(limit, handled).await.ok(),
+            None => Some(handled.await),
+        };
+        // Logged however it ended, a client that went away or a timeout included.
+        // What the middleware saw written out it logged already
+        entry.err = match &handled {
+            Some(Ok(_)) => None,
+            Some(Err(err)) => Some(err.to_string()),
+            None => Some(String::from("timed out")),
+        };
+        if !entry.logged {
+            log_access(&writer, &entry, &config);
+        }
+        let keep_alive = match handled {
+            Some(result) => result?,
+            None => {
+                // Whatever was half written can't be finished, the client sees the connection end
+                let limit = config.request_timeout.unwrap_or_default();
+                info!("[{id}] not done after {}s, closing the connection", limit.as_secs());
+                return Ok(());
+            }
+        };
+        // A body never asked for may still come, it is no next request
+        if !keep_alive || closing || writer.closing() || shutdown::is_shutting_down() || writer.continue_pending() {
+            return Ok(());
+        }
+    }
+}
+
+fn log_access<W: AsyncWrite + Unpin>(writer: &ResponseWriter<W>, entry: &access::Entry, config: &Config) {
+    if !access::is_open() {
+        return;
+    }
+    let (status, bytes) = writer.sent();
+    access::write(access::line(config.access_format, entry, &entry.line, status, bytes, SystemTime::now()));
+}
+
+// Tells the middleware what went out, the access log among them once it is written
+fn finish_response<W: AsyncWrite + Unpin>(writer: &ResponseWriter<W>, entry: &mut access::Entry, hooks: Vec<OnSent>, written: io::Result<()>) -> io::Result<()> {
+    let (status, bytes) = writer.sent();
+    let sent = Sent { status, bytes, err: written.as_ref().err().map(ToString::to_string) };
+    for hook in hooks {
+        hook(&sent);
+    }
+    entry.logged = true;
+    written
+}
+
+// Everything after the request line, Ok(false) when the connection has to be closed
+async fn handle_request(reader: &mut (impl AsyncBufRead + Unpin + Send), writer: &mut ResponseWriter<impl AsyncWrite + Throttle + Unpin>, buffer: &str, id: &mut String, entry: &mut access::Entry, config: &Config) -> io::Result<bool> {
+    // A 505 would be garbage to an HTTP/2 client, it gets told in frames it can read
+    if buffer == request::HTTP2_PREFACE {
+        info!("[{id}] HTTP/2 connection preface, only HTTP/1.1 is spoken here");
+        writer.stream.write_all(&request::HTTP2_REFUSAL).await?;
+        writer.stream.flush().await?;
+        return Ok(false);
+    }
+    let (method, path, version) = match parse_request_line(buffer) {
+        Some(some) => some,
+        None => {
+            info!("[{id}] bad request line {buffer}");
+            writer.write_closing_error(400).await?;
+            return Ok(false);
+        }
+    };
+    writer.set_head_only(Method::parse(method) == Some(Method::Head));
+    let version = match Version::parse(version) {
+        Ok(version) => version,
+        Err(code) => {
+            info!("[{id}] unsupported version {version}");
+            writer.write_closing_error(code).await?;
+            return Ok(false);
+        }
+    };
+    // Never echo a request back, TRACE would hand cookies and credentials to a
+    // script (cross-site tracing). Refused before its target or headers are even
+    // looked at, CONNECT's target isn't a path anyway
+    if matches!(Method::parse(method), Some(Method::Trace | Method::Connect)) {
+        info!("[{id}] {method} is disabled");
+        writer.write_closing_error(501).await?;
+        return Ok(false);
+    }
+    // Split first and decode the parts, so an escape can't turn into structure
+    let target = path;
+    // OPTIONS * asks about the server as a whole (RFC 7230 5.3.4), answered once the
+    // site is known. As a path it is just the root
+    let asterisk = target == "*" && Method::parse(method) == Some(Method::Options);
+    let (path, query) = url::split_target(if asterisk { "/" } else { target });
+    let slashed = path.ends_with('/');
+    let segments = match url::normalize_path(path) {
+        Ok(segments) => segments,
+        Err(err) => {
+            info!("[{id}] bad url {target}, {err}");
+            writer.write_closing_error(400).await?;
+            return Ok(false);
+        }
+    };
+    let path = format!("/{}", segments.join("/"));
+    let query = match query.map(query::parse).transpose() {
+        Ok(query) => query.unwrap_or_default(),
+        Err(err) => {
+            info!("[{id}] bad query in {target}, {err}");
+            writer.write_closing_error(400).await?;
+            return Ok(false);
+        }
+    };
+    info!("[{id}] method {method} path {path}");
+    entry.method = Some(String::from(method));
+    entry.path = Some(path.clone());
+
+    // Read all headers
+    let headers = match request::read_headers(reader, &config.parser).await {
+        Ok(headers) => headers,
+        Err(HeadError::Io(err)) => return Err(err),
+        Err(HeadError::Eof) => return Ok(false),
+        Err(err @ (HeadError::TooManyHeaders | HeadError::LineTooLong)) => {
+            info!("[{id}] parse the headers failed, {err}");
+            writer.write_closing_error(431).await?;
+            return Ok(false);
+        }
+        Err(err) => {
+            info!("[{id}] parse the headers failed, {err}");
+            writer.write_closing_error(400).await?;
+            return Ok(false);
+        }
+    };
+    trace!("[{id}] headers: {:?}", headers);
+    entry.referer = headers.get("Referer").map(String::from);
+    entry.agent = headers.get("User-Agent").map(String::from);
+
+    // Going with the id of a proxy in front of us keeps its logs and ours in step
+    if config.trust_request_id {
+        if let Some(incoming) = headers.get("X-Request-Id").filter(|incoming| request::is_valid_id(incoming)) {
+            debug!("[{id}] continuing as request {incoming}");
+            *id = String::from(incoming);
+            entry.id = id.clone();
+            writer.set_common("X-Request-Id", id);
+        }
+    }
+    // Nothing here speaks TLS itself, a proxy in front that does says so. Only then is
+    // the request https as the client sees it
+    let tls = config.trust_forwarded_proto
+        && headers.list("X-Forwarded-Proto").next().is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+    // Browsers ignore it over plain http, and a MITM could strip or forge it there anyway
+    if let Some(hsts) = config.hsts.as_ref().filter(|_| tls) {
+        writer.set_common("Strict-Transport-Security", &hsts.header_value());
+    }
+
+    // HTTP/1.1 clients must say which host they want, exactly once (RFC 7230 5.4)
+    let hosts = headers.get_all("Host").count();
+    if hosts > 1 || (hosts == 0 && version == Version::Http11) {
+        info!("[{id}] request with {hosts} Host headers");
+        writer.write_closing_error(400).await?;
+        return Ok(false);
+    }
+    // The client may want the connection to end with this response, an HTTP/1.0
+    // one does unless it asks to keep it (RFC 9112 9.3)
+    let connection = |token: &str| headers.list("Connection").any(|value| value.eq_ignore_ascii_case(token));
+    if connection("close") || (version == Version::Http10 && !connection("keep-alive")) {
+        writer.set_common("Connection", "close");
+    } else if version == Version::Http10 {
+        writer.set_common("Connection", "keep-alive");
+    }
+    // Ambiguous framing is how requests get smuggled, never guess here
+    let framing = match body::body_framing(&headers) {
+        Ok(framing) => framing,
+        Err(err) => {
+            info!("[{id}] bad request framing: {err}");
+            writer.write_closing_error(400).await?;
+            return Ok(false);
+        }
+    };
+
+    // --delay, --throttle and --fail-rate. All of it is logged, so a flaky result can
+    // be told from a real one
+    if config.inject.applies(&path) {
+        if let Some(delay) = config.inject.pick_delay() {
+            info!("[{id}] injecting a delay of {}ms", delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+        if let Some(rate) = config.inject.throttle {
+            info!("[{id}] throttling the response to {rate} bytes a second");
+            writer.stream.throttle(Some(rate));
+        }
+        if let Some(code) = config.inject.pick_failure() {
+            info!("[{id}] injecting a {code}");
+            if framing != Framing::Empty {
+                writer.set_common("Connection", "close");
+            }
+            writer.write_error_with(code, &[]).await?;
+            return Ok(framing == Framing::Empty);
+        }
+    }
+
+    // Before the vhosts, www.example.com may well be one that only exists to send
+    // everybody over
+    if let Some(location) = redirect::canonical_location(config, headers.get("Host"), &path, target, tls) {
+        info!("[{id}] redirecting to the canonical {location}");
+        if framing != Framing::Empty {
+            writer.set_common("Connection", "close");
+        }
+        writer.write_reply_with(301, &[("Location", &location)], &[]).await?;
+        return Ok(framing == Framing::Empty);
+    }
+    // Everything from here on is up to the site the request is for. There is no TLS
+    // here, so no certificate picked by SNI that could disagree with it
+    let config = match config.site(headers.get("Host")) {
+        Ok((site, vhost)) => {
+            if let Some(vhost) = vhost {
+                info!("[{id}] on vhost {vhost}");
+                entry.vhost = Some(String::from(vhost));
+            }
+            site
+        }
+        Err(code) => {
+            info!("[{id}] no site for host {}", headers.get("Host").unwrap_or_default());
+            writer.write_closing_error(code).await?;
+            return Ok(false);
+        }
+    };
+
+    if asterisk {
+        let allow = method::allow_header(&server_methods(config.writes_anywhere()));
+        if framing != Framing::Empty {
+            writer.set_common("Connection", "close");
+        }
+        writer.write_reply_with(204, &[("Allow", &allow), ("DAV", "1")], &[]).await?;
+        return Ok(framing == Framing::Empty);
+    }
+
+    // Rewrites go before anything looks at the path, auth included. An internal one
+    // may match another rule, up to a limit so two rules can't send it in circles
+    let (mut path, mut query) = (path, query);
+    let mut rewrites = 0;
+    while let Some((rule, rewritten)) = config.rewrites.iter().find_map(|rule| rule.apply(&path).map(|rewritten| (rule, rewritten))) {
+        if let rewrite::Mode::Redirect(code) = rule.mode() {
+            let location = match (rewritten.contains('?'), url::split_target(target).1) {
+                (false, Some(raw)) => format!("{rewritten}?{raw}"),
+                _ => rewritten,
+            };
+            info!("[{id}] redirecting {path} to {location}");
+            if framing != Framing::Empty {
+                writer.set_common("Connection", "close");
+            }
+            writer.write_reply_with(code, &[("Location", &location)], &[]).await?;
+            return Ok(framing == Framing::Empty);
+        }
+        rewrites += 1;
+        if rewrites > MAX_REWRITES {
+            warn!("[{id}] still rewriting after {MAX_REWRITES} rewrites, the rules are going in circles");
+            writer.write_closing_error(500).await?;
+            return Ok(false);
+        }
+        let (target_path, target_query) = url::split_target(&rewritten);
+        let segments = match url::normalize_path(target_path) {
+            Ok(segments) => segments,
+            Err(err) => {
+                info!("[{id}] {path} rewritten to the bad url {rewritten}, {err}");
+                writer.write_closing_error(400).await?;
+                return Ok(false);
+            }
+        };
+        debug!("[{id}] rewriting {path} to {rewritten}");
+        path = format!("/{}", segments.join("/"));
+        if let Some(raw) = target_query {
+            query = match query::parse(raw) {
+                Ok(query) => query,
+                Err(err) => {
+                    info!("[{id}] {path} rewritten to a bad query {raw}, {err}");
+                    writer.write_closing_error(400).await?;
+                    return Ok(false);
+                }
+            };
+        }
+    }
+
+    // Without valid credentials where they are needed nothing else happens,
+    // and their body isn't worth reading either
+    let user = auth::check_basic(headers.get("Authorization"), &config.credentials);
+    entry.user = user.clone();
+    if user.is_none() && config.needs_auth(&path) {
+        info!("[{id}] authentication required for {path}");
+        if framing != Framing::Empty {
+            writer.set_common("Connection", "close");
+        }
+        let challenge = [("WWW-Authenticate", "Basic realm=\"httpserver\", charset=\"UTF-8\"")];
+        writer.write_error_with(401, &challenge).await?;
+        if framing != Framing::Empty {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    // Curl waits for the interim response before sending a bigger body. It goes out
+    // once the request got past the checks and its body is about to be read, one that
+    // is refused gets its answer without it (see drain_body)
+    if let Some(expect) = headers.get("Expect") {
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            info!("[{id}] unknown expectation {expect}");
+            writer.write_closing_error(417).await?;
+            return Ok(false);
+        }
+        let accepted = match framing {
+            Framing::Empty => false,
+            Framing::Length(n) => n <= MAX_BODY_SIZE,
+            Framing::Chunked => true,
+        };
+        if accepted {
+            writer.expect_continue();
+        }
+    }
+
+    let parsed = Method::parse(method);
+    // A handler answers whatever its route covers, with any method it knows. Nothing
+    // on the disk is looked at for it, so it is there with a single file or a mount too
+    let found = config.handlers.find(&path).map(|found| (found.value.clone(), String::from(found.rest)));
+    if let (Some(method), Some((handler, rest))) = (parsed, found) {
+        let mut body = match body_reader(reader, &framing) {
+            Ok(body) => body,
+            Err(err) => {
+                info!("[{id}] not reading the body, {err}");
+                writer.write_closing_error(body_error_status(&err)).await?;
+                return Ok(false);
+            }
+        };
+        let length = match framing {
+            Framing::Length(n) => Some(n),
+            _ => None,
+        };
+        writer.write_continue().await?;
+        // There is no file behind it, it isn't looked for
+        let request = Request { id: id.clone(), user, client: entry.client.clone(), version, method, path, file: String::new(), query, headers };
+        let mut response = handler.call(Call { request: &request, rest: &rest, body: Body::new(&mut body, length), entry }).await;
+        // Done with the body or not, the rest of it is not the next request. Once
+        // it can't be read there's no telling where that starts
+        if let Err(err) = tokio::io::copy(&mut body, &mut tokio::io::sink()).await {
+            info!("[{id}] failed to read the body {err}");
+            writer.write_closing_error(body_error_status(&err)).await?;
+            return Ok(false);
+        }
+        let hooks = response.take_sent();
+        let written = write_response(writer, response).await;
+        finish_response(writer, entry, hooks, written)?;
+        return Ok(true);
+    }
+    // A link out of the roots looks like nothing is there, its body isn't worth reading.
+    // Neither is any other path next to a single file. What is embedded has no file
+    let resolved = match config.is_embedded(&path) {
+        true => Some(String::new()),
+        false => config.resolve(&path).await,
+    };
+    let Some(file) = resolved else {
+        match &config.single_file {
+            Some(name) => info!("[{id}] only {name} is served, not {path}"),
+            None => info!("[{id}] {path} leads out of the root through a link"),
+        }
+        if framing != Framing::Empty {
+            writer.set_common("Connection", "close");
+        }
+        writer.write_client_error(404).await?;
+        if framing != Framing::Empty {
+            return Ok(false);
+        }
+        return Ok(true);
+    };
+
+    // --trailing-slash, only for the path as the client wrote it. One that was rewritten
+    // is ours, redirecting it could send the client around in circles with the rules
+    let get = matches!(Method::parse(method), Some(Method::Get | Method::Head));
+    let location = match config.trailing_slash {
+        TrailingSlash::Ignore => None,
+        _ if !get || rewrites > 0 || path == "/" || config.is_excluded(&path) => None,
+        policy => {
+            let is_dir = match config.is_embedded(&path) {
+                true => embedded::is_dir(&path).then_some(true).or(embedded::get(&path).map(|_| false)),
+                false => tokio::fs::metadata(&file).await.ok().map(|meta| meta.is_dir()),
+            };
+            match (policy, is_dir, slashed) {
+                (TrailingSlash::Add, Some(true), false) => Some(format!("{}/", url::encode_path(&path))),
+                (TrailingSlash::Strip, Some(false), true) => Some(url::encode_path(&path)),
+                _ => None,
+            }
+        }
+    };
+    if let Some(location) = location {
+        let location = match url::split_target(target).1 {
+            Some(raw) => format!("{location}?{raw}"),
+            None => location,
+        };
+        info!("[{id}] redirecting {path} to {location}");
+        if framing != Framing::Empty {
+            writer.set_common("Connection", "close");
+        }
+        writer.write_reply_with(301, &[("Location", &location)], &[]).await?;
+        return Ok(framing == Framing::Empty);
+    }
+
+    // Uploads stream the body into the file, it has to stay unread until then
+    if parsed == Some(Method::Put) && config.writes(&path) {
+        let request = Request { id: id.clone(), user, client: entry.client.clone(), version, method: Method::Put, path, file, query, headers };
+        if !handle_put(writer, reader, &request, &framing, config).await? {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    if parsed == Some(Method::Propfind) && !config.is_embedded(&path) {
+        writer.write_continue().await?;
+        let body = match read_small_body(reader, &framing, MAX_PROPFIND_SIZE).await {
+            Ok(body) => body,
+            Err(err) => {
+                info!("[{id}] failed to read the body {err}");
+                writer.write_closing_error(body_error_status(&err)).await?;
+                return Ok(false);
+            }
+        };
+        let request = Request { id: id.clone(), user, client: entry.client.clone(), version, method: Method::Propfind, path, file, query, headers };
+        serve_propfind(writer, &request, &body, config).await?;
+        return Ok(true);
+    }
+    if parsed == Some(Method::Post) && config.writes(&path) {
+        let request = Request { id: id.clone(), user, client: entry.client.clone(), version, method: Method::Post, path, file, query, headers };
+        if !handle_form_upload(writer, reader, &request, &framing, entry, config).await? {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    if let Err(err) = drain_body(writer, reader, &framing).await {
+        info!("[{id}] failed to read the body {err}");
+        writer.write_closing_error(body_error_status(&err)).await?;
+        return Ok(false);
+    }
+
+    let method = match parsed {
+        Some(method) => method,
+        None => {
+            info!("[{id}] unknown method {method}");
+            writer.write_client_error(501).await?;
+            return Ok(true);
+        }
+    };
+    let request = Request { id: id.clone(), user, client: entry.client.clone(), version, method, path, file, query, headers };
+
+    // We never switch protocols, so never send a 101. The client may have sent
+    // frames right after its head, hang up afterwards instead of parsing them
+    if is_upgrade(&request.headers) {
+        info!("[{}] refusing upgrade to {}", request.id, request.headers.get("Upgrade").unwrap_or_default());
+        writer.set_common("Connection", "close");
+        match config.upgrade {
+            UpgradeMode::Refuse => writer.write_client_error(426).await?,
+            UpgradeMode::Close => serve_request(writer, &request, entry, config).await?,
+        }
+        return Ok(false);
+    }
+    serve_request(writer, &request, entry, config).await?;
+    Ok(true)
+}
+
+// What a handler came up with. An error without a body gets its page like ours do
+async fn write_response(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, response: Response) -> io::Result<()> {
+    let extra: Vec<(&str, &str)> = response.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    if response.status >= 400 && response.body.is_empty() {
+        return writer.write_error_with(response.status, &extra).await;
+    }
+    writer.write_reply_with(response.status, &extra, &response.body).await
+}
+
+// If-Match and If-None-Match have been answered by then, so a matching If-None-Match
+// is a 304 even with a Range: the preconditions are evaluated first and Range only
+// after them (RFC 9110 13.2.2, steps 3 and 5), the client has the representation
+// already and a part of it would be no use. What's left is whether the Range applies:
+// only to GET, and with If-Range only while it matches. The body is sliced in memory,
+// there is no cache of files yet that would need a check of its own
+fn requested_range(headers: &Headers, method: Method, etag: Option<&str>, modified: Option<SystemTime>, total: u64) -> Ranges {
+    match (headers.get("Range"), etag) {
+        (Some(value), Some(etag)) if method == Method::Get && range::if_range_matches(headers, etag, modified) => range::parse(value, total),
+        _ => Ranges::Ignored,
+    }
+}
+
+// --spa: a page the browser navigates to without a file is one of the app's routes,
+// the index routes it in the browser. Directories too, an app has no listings. One
+// with an extension or a script asking for something else is really missing
+fn is_app_route(request: &Request, path: &str) -> bool {
+    let accepts_html = request.headers.list("Accept").any(|accept| accept.starts_with("text/html") || accept.starts_with("application/xhtml+xml"));
+    matches!(request.method, Method::Get | Method::Head) && accepts_html && !path.rsplit('/').next().unwrap_or_default().contains('.')
+}
+
+// Connection is a list of tokens, "keep-alive, Upgrade" counts too
+fn is_upgrade(headers: &Headers) -> bool {
+    headers.get("Upgrade").is_some() && headers.list("Connection").any(|token| token.eq_ignore_ascii_case("upgrade"))
+}
+
+// Answer a request whose body has already been dealt with
+async fn serve_request(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, entry: &mut access::Entry, config: &Config) -> io::Result<()> {
+    let Request { id, method, path, file, query, headers, .. } = request;
+    let method = *method;
+    if !query.is_empty() {
+        debug!("[{id}] query {:?}", query);
+    }
+    // Excluded names look exactly like missing ones
+    if config.is_excluded(path) {
+        writer.write_client_error(404).await?;
+        return Ok(());
+    }
+    // Where a resumable upload is at, so the client knows which pieces to send
+    if config.writes(path) && query.contains("upload-status") && (method == Method::Get || method == Method::Head) {
+        return match resume::progress(Path::new(file)) {
+            Some((received, total)) => {
+                let total = total.to_string();
+                writer.write_reply_with(308, &[("Range", &received), ("Upload-Length", &total)], &[]).await
+            }
+            None => writer.write_client_error(404).await,
+        };
+    }
+    let meta = tokio::fs::metadata(file).await.ok();
+
+    // Browsers ask for it all the time, answer it quietly when opted in
+    let embedded = config.is_embedded(path);
+    if meta.is_none() && path == "/favicon.ico" && config.favicon != FaviconMode::Off && (!embedded || embedded::get(path).is_none()) {
+        match config.favicon {
+            FaviconMode::Builtin => writer.write_reply_with(200, &[("Content-Type", "image/x-icon")], FAVICON).await?,
+            _ => writer.write_reply(204, &[]).await?,
+        }
+        return Ok(());
+    }
+    if embedded {
+        return serve_embedded(writer, request, entry, config).await;
+    }
+    // --try-files, the candidate found stands in for the path from here on
+    let tried = match matches!(method, Method::Get | Method::Head) && !config.try_files.is_empty() {
+        true => config.try_files(path, request.user.is_some()).await,
+        false => None,
+    };
+    let (path, file, meta) = match &tried {
+        Some(Tried::Status(code)) => {
+            debug!("[{id}] no candidate for {path}, answering {code}");
+            return writer.write_error_with(*code, &[]).await;
+        }
+        Some(Tried::File { path: candidate, file, status: Some(code), .. }) => {
+            debug!("[{id}] serving {candidate} for {path} with {code}");
+            return write_status_file(writer, file, *code, method == Method::Head).await;
+        }
+        Some(Tried::File { path: candidate, file, meta, status: None }) => {
+            if candidate != path {
+                debug!("[{id}] serving {candidate} for {path}");
+            }
+            (candidate, file, Some(meta.as_ref().clone()))
+        }
+        None => (path, file, meta),
+    };
+    let fallback = match config.spa_index(path) {
+        Some(index) if is_app_route(request, path) && meta.as_ref().is_none_or(|meta| meta.is_dir()) => config.resolve(&index).await,
+        _ => None,
+    };
+    let (file, meta) = match &fallback {
+        Some(index) => {
+            debug!("[{id}] {path} is no file, serving the app at {index}");
+            // The app may change under the same path, a route's policy still goes first
+            writer.set_common("Cache-Control", "no-cache");
+            (index, tokio::fs::metadata(index).await.ok().filter(|meta| meta.is_file()))
+        }
+        None => (file, meta),
+    };
+    let is_dir = meta.as_ref().is_some_and(|meta| meta.is_dir());
+
+    let writes = config.writes(path);
+    if meta.is_none() && method == Method::Mkcol && writes {
+        // There is no body format for MKCOL we understand (RFC 4918 9.3)
+        if body::body_framing(headers) != Ok(Framing::Empty) {
+            return writer.write_client_error(415).await;
+        }
+        return make_directory(writer, request).await;
+    }
+
+    // Where an upload could create it OPTIONS says so, otherwise what doesn't exist is a 404
+    if meta.is_none() && method == Method::Options && writes {
+        let allow = method::allow_header(&allowed_methods(Resource::Missing, writes));
+        return writer.write_reply_with(204, &[("Allow", &allow), ("DAV", "1")], &[]).await;
+    }
+    // Only check the method against resources that exist, the rest are 404
+    if meta.is_some() {
+        let allowed = allowed_methods(if is_dir { Resource::Directory } else { Resource::File }, writes);
+        let allow = method::allow_header(&allowed);
+        if method == Method::Options {
+            // DAV tells WebDAV clients they can mount us (RFC 4918 10.1)
+            writer.write_reply_with(204, &[("Allow", &allow), ("DAV", "1")], &[]).await?;
+            return Ok(());
+        }
+        if !allowed.contains(&method) {
+            writer.write_error_with(405, &[("Allow", &allow)]).await?;
+            return Ok(());
+        }
+    }
+
+    // gzip is a representation of its own with its own ETag. The middleware picks it
+    // once the file is read, the conditions are checked with what it will pick
+    let name = file.rsplit('/').next().unwrap_or_default();
+    let gzip = !is_dir && matches!(method, Method::Get | Method::Head)
+        && meta.as_ref().is_some_and(|meta| compress::wanted(&config.compress, headers, mime::content_type(name), meta.len(), config.ranges(path)));
+
+    // Conditional requests, directories get theirs with the listing further down
+    let identity = meta.as_ref().filter(|_| !is_dir).map(conditional::etag);
+    let etag = identity.clone().map(|tag| if gzip { compress::etag(&tag) } else { tag });
+    if !is_dir {
+        if let Err(code) = conditional::check_preconditions(headers, method, etag.as_deref()) {
+            let extra: Vec<(&str, &str)> = etag.iter().map(|tag| ("ETag", tag.as_str())).collect();
+            writer.write_error_with(code, &extra).await?;
+            return Ok(());
+        }
+    }
+
+    if method == Method::Delete && writes {
+        return delete_path(writer, request, is_dir, config).await;
+    }
+    if matches!(method, Method::Move | Method::Copy) && writes {
+        return match &meta {
+            Some(meta) => move_or_copy(writer, request, meta, config).await,
+            None => writer.write_client_error(404).await,
+        };
+    }
+
+    if is_dir && !config.listing_enabled(path) {
+        info!("[{id}] listing of {path} is disabled");
+        return writer.write_client_error(403).await;
+    }
+    // Routes may want caching for what is served successfully, a 404 for what isn't there yet is not
+    if let Some(cache) = config.cache_policy(path).filter(|_| meta.is_some()) {
+        writer.set_common("Cache-Control", &cache.header_value());
+    }
+
+    // Dispatch path by query
+    if is_dir {
+        let (json, pretty) = (wants_json(request), wants_pretty(request));
+        let variant = match (json, pretty) {
+            (true, true) => "json-pretty",
+            (true, false) => "json",
+            (false, _) => "html",
+        };
+        let listed = match listing_etag(path, file, variant, config).await {
+            Ok(etag) => match conditional::check_preconditions(headers, method, Some(&etag)) {
+                Err(code) => writer.write_error_with(code, &[("ETag", &etag)]).await,
+                Ok(()) if json => write_json_listing(writer, path, file, &etag, method == Method::Head, pretty, config).await,
+                Ok(()) => write_listing(writer, path, file, &etag, request.version, method == Method::Head, config).await,
+            },
+            Err(err) => Err(err),
+        };
+        if let Err(err) = listed {
+            // Nothing was sent when opening the directory failed, otherwise the response
+            // is cut in the middle and the connection has to go
+            match err.kind() {
+                ErrorKind::NotFound => writer.write_client_error(404).await?,
+                ErrorKind::PermissionDenied => writer.write_client_error(403).await?,
+                _ => return Err(err),
+            }
+        }
+        return Ok(());
+    }
+    let modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+    let entity = Entity::new(request, path, name, identity, modified, Content::Disk(file.clone()), config);
+    write_entity(writer, request, path, entity, entry, config).await
+}
+
+// What is served for a file, read from the disk or embedded
+enum Content {
+    Disk(String),
+    Embedded(&'static [u8]),
+}
+
+// A file as the handler at the end of the middleware chain. The head is decided
+// once its conditions are checked, the content only read when the chain gets to it
+struct Entity {
+    headers: Vec<(String, String)>,
+    content: Content,
+    modified: Option<SystemTime>,  // For If-Range
+}
+
+impl Entity {
+    // The path is the one served, maybe another than the request's. The ETag is that
+    // of the content as it is, the middleware changes it for one gzipped
+    fn new(request: &Request, path: &str, name: &str, etag: Option<String>, modified: Option<SystemTime>, content: Content, config: &Config) -> Self {
+        let mut headers = Vec::new();
+        let mut add = |name: &str, value: &str| headers.push((String::from(name), String::from(value)));
+        if let Some(etag) = &etag {
+            add("ETag", etag);
+        }
+        if let Some(content_type) = mime::content_type(name) {
+            add("Content-Type", content_type);
+        }
+        if let Some(modified) = modified {
+            add("Last-Modified", &date::http_date(modified));
+        }
+        // Without ranges clients are told not to bother (RFC 7233 2.3)
+        add("Accept-Ranges", if config.ranges(path) { "bytes" } else { "none" });
+        // ?download or a configured extension makes the browser save it instead of showing it.
+        // A single file served at / would be saved without its name otherwise
+        let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+        let download = request.query.contains("download")
+            || extension.is_some_and(|ext| config.download_extensions.contains(&ext))
+            || (config.single_file.is_some() && path == "/");
+        if download {
+            add("Content-Disposition", &content_disposition(name));
+        }
+        Entity { headers, content, modified }
+    }
+}
+
+impl Handler for Entity {
+    fn call<'a>(&'a self, _call: Call<'a>) -> handler::BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let content = match &self.content {
+                Content::Disk(file) => match tokio::fs::read(file).await {
+                    Ok(content) => content,
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Response::new(404, ""),
+                    Err(err) => {
+                        warn!("failed to read {file} by {err}");
+                        return Response::new(500, "");
+                    }
+                },
+                Content::Embedded(data) => data.to_vec(),
+            };
+            let mut response = Response::new(200, content);
+            response.headers = self.headers.clone();
+            response
+        })
+    }
+}
+
+// A file whose conditions are checked, through the middleware for files and then
+// written out. A range of it when one was asked for
+async fn write_entity(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, path: &str, entity: Entity, entry: &mut access::Entry, config: &Config) -> io::Result<()> {
+    let mut empty = tokio::io::empty();
+    let call = Call { request, rest: "", body: Body::new(&mut empty, Some(0)), entry };
+    let mut response = Next::new(&config.file_layers, &entity).run(call).await;
+    let hooks = response.take_sent();
+    let written = match response.status {
+        200 => write_representation(writer, request, path, &response, entity.modified, config).await,
+        _ => write_response(writer, response).await,
+    };
+    finish_response(writer, entry, hooks, written)
+}
+
+// The response for the file as the middleware left it. Ranges come from the
+// uncompressed one only
+async fn write_representation(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, path: &str, response: &Response, modified: Option<SystemTime>, config: &Config) -> io::Result<()> {
+    let Request { id, method, headers, .. } = request;
+    let method = *method;
+    let mut extra: Vec<(&str, &str)> = response.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    let content = &response.body[..];
+    // An empty file is a 200 with Content-Length: 0 and nothing after the head.
+    // No range fits in it, those get the 416 with bytes */0, and gzip would only
+    // make it bigger so it goes uncompressed even with --compression-min-size 0
+    let total = content.len() as u64;
+    let range = match config.ranges(path) && response.header("Content-Encoding").is_none() {
+        true => requested_range(headers, method, response.header("ETag"), modified, total),
+        false => Ranges::Ignored,
+    };
+    let (code, body, content_range) = match range {
+        Ranges::Satisfiable(range) => (206, &content[range.start as usize..=range.end as usize], range.content_range(total)),
+        Ranges::Unsatisfiable => {
+            // Clients learn the real size from it before asking again. The body is the
+            // error's, none of the file's fields like its Content-Type go with it
+            let content_range = format!("bytes */{total}");
+            return writer.write_error_with(416, &[("Content-Range", &content_range), ("Accept-Ranges", "bytes")]).await;
+        }
+        Ranges::Ignored => (200, content, String::new()),
+    };
+    if code == 206 {
+        extra.push(("Content-Range", &content_range));
+    }
+    if method == Method::Head {
+        writer.write_head(code, &extra, Some(body.len())).await?;
+        return writer.stream.flush().await;
+    }
+    writer.write_reply_with(code, &extra, body).await?;
+    // All of it, in one go or the last piece of a range
+    let complete = code == 200 || content_range.ends_with(&format!("-{}/{total}", total.saturating_sub(1)));
+    if let (Some(limit), true) = (config.count, complete) {
+        let done = metrics::download_completed();
+        info!("[{id}] download {done} of {limit} complete");
+        if done >= limit {
+            info!("{limit} downloads done, shutting down");
+            shutdown::begin();
+        }
+    }
     Ok(())
 }
 
-async fn write_bad_reply(stream: &mut (impl AsyncWriteExt + Unpin)) -> io::Result<()> {
-    write_reply(stream, 500, "<html>bad requests</html>".as_bytes()).await?;
-    Ok(())
+// --embedded, served like a file from the disk once it is found. Directories have no
+// listing, their index.html stands in for them. Nothing of it can be changed, and
+// the gzip one was made by the build already
+async fn serve_embedded(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, entry: &mut access::Entry, config: &Config) -> io::Result<()> {
+    let Request { id, method, path, headers, .. } = request;
+    let method = *method;
+    let found = embedded::find(path);
+    let fallback = match config.spa_index(path) {
+        Some(index) if found.is_none() && is_app_route(request, path) => embedded::get(&index),
+        _ => None,
+    };
+    let Some(file) = found.or(fallback) else {
+        return writer.write_client_error(404).await;
+    };
+    if fallback.is_some() {
+        debug!("[{id}] {path} is no file, serving the app at {}", file.path);
+        writer.set_common("Cache-Control", "no-cache");
+    }
+    let allowed = [Method::Get, Method::Head, Method::Options];
+    let allow = method::allow_header(&allowed);
+    if method == Method::Options {
+        return writer.write_reply_with(204, &[("Allow", &allow)], &[]).await;
+    }
+    if !allowed.contains(&method) {
+        return writer.write_error_with(405, &[("Allow", &allow)]).await;
+    }
+    // What the build didn't compress --compress may still, the middleware decides the same way
+    let name = file.path.rsplit('/').next().unwrap_or_default();
+    let gzip = match file.gzip {
+        Some(_) => (headers.get("Range").is_none() || !config.ranges(path)) && compress::accepts_gzip(headers),
+        None => compress::wanted(&config.compress, headers, mime::content_type(name), file.data.len() as u64, config.ranges(path)),
+    };
+    let etag = match gzip {
+        true => compress::etag(file.etag),
+        false => String::from(file.etag),
+    };
+    if let Err(code) = conditional::check_preconditions(headers, method, Some(&etag)) {
+        return writer.write_error_with(code, &[("ETag", &etag)]).await;
+    }
+    if let Some(cache) = config.cache_policy(path) {
+        writer.set_common("Cache-Control", &cache.header_value());
+    }
+    // The one the build made goes as it is, the middleware gzips the rest itself
+    let precompressed = gzip && file.gzip.is_some();
+    let etag = if precompressed { etag } else { String::from(file.etag) };
+    let content = Content::Embedded(file.gzip.filter(|_| precompressed).unwrap_or(file.data));
+    let mut entity = Entity::new(request, path, name, Some(etag), Some(file.modified()), content, config);
+    if precompressed {
+        entity.headers.push((String::from("Content-Encoding"), String::from("gzip")));
+    }
+    // Caches must not hand the gzip one to clients that can't take it
+    if file.gzip.is_some() {
+        entity.headers.push((String::from("Vary"), String::from("Accept-Encoding")));
+    }
+    write_entity(writer, request, path, entity, entry, config).await
 }
 
-async fn gen_fs_page(path: &str) -> io::Result<Vec::<u8> > {
-    // Dispatch path by query
-    if tokio::fs::metadata(path).await?.is_dir() {
-        let mut content = String::new();
-        content.push_str("<html><meta charset=\"utf-8\" /><body><ul>");
-        for dir in std::fs::read_dir(path)? {
-            let name = dir?.file_name().to_string_lossy().into_owned();
-            let mut pathname = String::from(path);
-            if !pathname.ends_with("/") {
-                pathname.push('/');
+// A --try-files candidate with a status of its own, like a page for 404. It is no
+// representation of what was asked for, so no validators and no ranges
+async fn write_status_file(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, file: &str, code: i32, head_only: bool) -> io::Result<()> {
+    let content = match tokio::fs::read(file).await {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("failed to read {file} by {err}");
+            return writer.write_server_error().await;
+        }
+    };
+    let name = file.rsplit('/').next().unwrap_or_default();
+    let extra: Vec<(&str, &str)> = mime::content_type(name).map(|content_type| ("Content-Type", content_type)).into_iter().collect();
+    if head_only {
+        writer.write_head(code, &extra, Some(content.len())).await?;
+        return writer.stream.flush().await;
+    }
+    writer.write_reply_with(code, &extra, &content).await
+}
+
+// Properties of a resource and maybe its children for WebDAV clients (RFC 4918 9.1)
+async fn serve_propfind(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, body: &[u8], config: &Config) -> io::Result<()> {
+    let Request { id, path, file, headers, .. } = request;
+    let meta = match config.is_excluded(path) {
+        true => None,
+        false => tokio::fs::metadata(file).await.ok(),
+    };
+    let Some(meta) = meta else {
+        return writer.write_client_error(404).await;
+    };
+    // No Depth means infinity, walking the whole tree on request is how servers get DoSed
+    let children = match headers.get("Depth") {
+        Some("0") => false,
+        Some("1") if config.listing_enabled(path) => true,
+        depth => {
+            info!("[{id}] refusing PROPFIND with depth {}", depth.unwrap_or("infinity"));
+            return writer.write_client_error(403).await;
+        }
+    };
+    let Some(props) = webdav::parse_propfind(body) else {
+        info!("[{id}] bad PROPFIND body");
+        return writer.write_client_error(400).await;
+    };
+    match webdav::multistatus(path, file, &meta, children, &props, config).await {
+        Ok(xml) => writer.write_reply_with(207, &[("Content-Type", "application/xml; charset=utf-8")], xml.as_bytes()).await,
+        Err(err) => {
+            warn!("[{id}] PROPFIND of {path} failed, {err}");
+            writer.write_server_error().await
+        }
+    }
+}
+
+// Create a directory whose parent must exist already, Err carries the status to answer with
+async fn create_directory(id: &str, path: &str) -> Result<(), i32> {
+    match tokio::fs::create_dir(path).await {
+        Ok(()) => {
+            info!("[{id}] created directory {path}");
+            Ok(())
+        }
+        Err(err) => {
+            warn!("[{id}] failed to create directory {path}, {err}");
+            match err.kind() {
+                // Something got there first
+                ErrorKind::AlreadyExists => Err(405),
+                ErrorKind::NotFound | ErrorKind::NotADirectory => Err(409),
+                ErrorKind::PermissionDenied => Err(403),
+                _ => Err(500),
             }
-            pathname.push_str(&encode_url(name.as_str()));
-            content.push_str(&format!("<li><a href=\"{}\">{}</a></li>", pathname, name));
         }
-        content.push_str("</ul></body></html>");
-        return Ok(content.into_bytes());
     }
-    else {
-        return Ok(tokio::fs::read(path).await?);
+}
+
+async fn make_directory(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request) -> io::Result<()> {
+    match create_directory(&request.id, &request.file).await {
+        Ok(()) => {
+            webhook::notify(request, "mkcol", &request.path, None, None);
+            let location = format!("{}/", url::encode_path(&request.path));
+            writer.write_reply_with(201, &[("Location", &location)], "<html>201</html>".as_bytes()).await
+        }
+        Err(500) => writer.write_server_error().await,
+        Err(code) => writer.write_client_error(code).await,
     }
 }
 
-fn decode_url(s: &str) -> Option<String> {
-    let mut out = String::new();
-    let mut chars = s.chars();
-    let read = |chars: &mut Chars<'_> | -> Option<u8> {
-        let h1 = chars.next()?;
-        let h2 = chars.next()?;
-        let hex = format!("{h1}{h2}");
-        return Some(u8::from_str_radix(hex.as_str(), 16).ok()?);
+// MOVE and COPY to the Destination header (RFC 4918 9.8, 9.9), Err carries the status to answer with
+async fn transfer(request: &Request, meta: &std::fs::Metadata, config: &Config) -> Result<i32, i32> {
+    let Request { id, method, path, file, headers, .. } = request;
+    let destination = webdav::destination_path(headers.get("Destination").ok_or(400)?, headers.get("Host"))?;
+    // Onto itself or into itself, a directory would never stop growing
+    let inside = destination.starts_with(&format!("{}/", path.trim_end_matches('/')));
+    if path == "/" || destination == *path || inside || config.is_excluded(&destination) {
+        return Err(403);
+    }
+    // The destination may be somewhere only users can write, or nobody
+    if (request.user.is_none() && config.needs_auth(&destination)) || !config.writes(&destination) {
+        return Err(403);
+    }
+    // A mount is a directory of its own, it can't be moved like one of the root
+    if config.is_mount_point(path) || config.is_mount_point(&destination) {
+        return Err(403);
+    }
+    let overwrite = match headers.get("Overwrite") {
+        None | Some("T") => true,
+        Some("F") => false,
+        Some(_) => return Err(400),
     };
-    while let Some(c) = chars.next() {
-        if c == '%' { // Got Utf8 code point here
-            let byte = read(&mut chars)?;
-            if byte < 127 {
-                out.push(char::from_u32(byte as u32)?);
-                continue;
+    // Only COPY of a directory can be shallow
+    let shallow = match headers.get("Depth") {
+        None | Some("infinity") => false,
+        Some("0") if *method == Method::Copy => true,
+        Some(_) => return Err(400),
+    };
+    let target = config.resolve(&destination).await.ok_or(403)?;
+    let parent = Path::new(&target).parent().unwrap_or(Path::new("/"));
+    if !tokio::fs::metadata(parent).await.is_ok_and(|meta| meta.is_dir()) {
+        return Err(409);
+    }
+    let existing = tokio::fs::metadata(&target).await.ok();
+    if let Some(existing) = &existing {
+        if !overwrite {
+            return Err(412);
+        }
+        // A file replaces a file with the rename, anything else has to go first
+        if existing.is_dir() && !config.recursive_delete {
+            return Err(403);
+        }
+        let removed = match (existing.is_dir(), meta.is_dir()) {
+            (true, _) => tokio::fs::remove_dir_all(&target).await,
+            (false, true) => tokio::fs::remove_file(&target).await,
+            (false, false) => Ok(()),
+        };
+        removed.map_err(|err| status_for(id, &target, err))?;
+    }
+    let result = match method {
+        Method::Move => webdav::move_path(file, &target, meta).await,
+        _ => webdav::copy_path(file, &target, meta, shallow, config).await,
+    };
+    result.map_err(|err| status_for(id, &target, err))?;
+    info!("[{id}] {method} {path} to {destination}");
+    if *method == Method::Move {
+        webhook::notify(request, "move", path, Some(&destination), None);
+    }
+    Ok(if existing.is_some() { 204 } else { 201 })
+}
+
+fn status_for(id: &str, path: &str, err: io::Error) -> i32 {
+    warn!("[{id}] failed to write {path}, {err}");
+    match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => 409,
+        ErrorKind::PermissionDenied => 403,
+        _ if upload::is_out_of_space(&err) => 507,
+        _ => 500,
+    }
+}
+
+async fn move_or_copy(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, meta: &std::fs::Metadata, config: &Config) -> io::Result<()> {
+    match transfer(request, meta, config).await {
+        Ok(code) => writer.write_reply(code, &[]).await,
+        Err(500) => writer.write_server_error().await,
+        Err(code) => writer.write_client_error(code).await,
+    }
+}
+
+// Remove a file, or a directory when it's empty or recursive, Err carries the status to answer with
+async fn remove_path(request: &Request, path: &str, is_dir: bool, recursive: bool) -> Result<(), i32> {
+    let id = &request.id;
+    let result = match (is_dir, recursive) {
+        (false, _) => tokio::fs::remove_file(path).await,
+        (true, true) => tokio::fs::remove_dir_all(path).await,
+        (true, false) => tokio::fs::remove_dir(path).await,
+    };
+    match result {
+        Ok(()) => {
+            info!("[{id}] deleted {path} as {}", request.user.as_deref().unwrap_or("anonymous"));
+            Ok(())
+        }
+        Err(err) => {
+            warn!("[{id}] failed to delete {path}, {err}");
+            match err.kind() {
+                ErrorKind::NotFound => Err(404),
+                ErrorKind::PermissionDenied => Err(403),
+                ErrorKind::DirectoryNotEmpty => Err(409),
+                _ => Err(500),
             }
-            let mut codepoints = Vec::<u8>::new();
-            codepoints.push(byte);
-            loop {
-                match str::from_utf8(codepoints.as_slice()) {
-                    Ok(s) => {
-                        out.push_str(s);
-                        break;
-                    },
-                    Err(_) => {
-                        // Collect the next codepoint
-                        let next = chars.next()?;
-                        if next != '%' {
-                            // Utf8 sequence end !!!
-                            return None;
-                        }
-                        codepoints.push(read(&mut chars)?);
-                    }
+        }
+    }
+}
+
+// DELETE, a directory only when it's empty or ?recursive is allowed
+async fn delete_path(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, request: &Request, is_dir: bool, config: &Config) -> io::Result<()> {
+    if request.path == "/" || config.is_mount_point(&request.path) {
+        info!("[{}] refusing to delete the root of {}", request.id, request.path);
+        return writer.write_client_error(403).await;
+    }
+    let recursive = request.query.contains("recursive") && config.recursive_delete;
+    match remove_path(request, &request.file, is_dir, recursive).await {
+        Ok(()) => {
+            webhook::notify(request, "delete", &request.path, None, None);
+            writer.write_reply(204, &[]).await
+        }
+        Err(500) => writer.write_server_error().await,
+        Err(code) => writer.write_client_error(code).await,
+    }
+}
+
+// Why an upload can't go ahead, checked before reading any of the body
+async fn check_put(request: &Request, framing: &Framing, config: &Config) -> Result<(Option<std::fs::Metadata>, Option<ContentRange>), i32> {
+    if config.is_excluded(&request.path) {
+        return Err(404);
+    }
+    let meta = tokio::fs::metadata(&request.file).await.ok();
+    if meta.as_ref().is_some_and(|meta| meta.is_dir()) {
+        return Err(409);
+    }
+    // We create the file but never the directories leading to it
+    let parent = Path::new(&request.file).parent().unwrap_or(Path::new("/"));
+    if !tokio::fs::metadata(parent).await.is_ok_and(|meta| meta.is_dir()) {
+        return Err(404);
+    }
+    let etag = meta.as_ref().map(conditional::etag);
+    conditional::check_preconditions(&request.headers, Method::Put, etag.as_deref())?;
+    // A piece of a resumable upload, the body has to be exactly that piece
+    let range = match request.headers.get("Content-Range") {
+        Some(value) => Some(ContentRange::parse(value).ok_or(400)?),
+        None => None,
+    };
+    if let Some(range) = &range {
+        if matches!(*framing, Framing::Length(n) if n != range.len()) || *framing == Framing::Empty {
+            return Err(400);
+        }
+        // The whole file is set aside with the first piece
+        if config.partial_max_size.is_some_and(|max| range.total > max) {
+            return Err(413);
+        }
+        if !resume::accepts(Path::new(&request.file), range) {
+            return Err(409);
+        }
+    }
+    Ok((meta, range))
+}
+
+// Store the body of a PUT at its path, false when the connection has to be closed
+async fn handle_put(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, reader: &mut (impl AsyncBufRead + Unpin + Send), request: &Request, framing: &Framing, config: &Config) -> io::Result<bool> {
+    let id = &request.id;
+    let (meta, range) = match check_put(request, framing, config).await {
+        Ok(checked) => checked,
+        Err(code) => {
+            // Still have to get the body out of the way to answer
+            if let Err(err) = drain_body(writer, reader, framing).await {
+                info!("[{id}] failed to read the body {err}");
+                writer.write_closing_error(body_error_status(&err)).await?;
+                return Ok(false);
+            }
+            writer.write_client_error(code).await?;
+            return Ok(true);
+        }
+    };
+    let body = body_reader(reader, framing);
+    if body.is_ok() {
+        writer.write_continue().await?;
+    }
+    let mut body = match body {
+        Ok(body) => body,
+        Err(err) => {
+            info!("[{id}] refusing the upload, {err}");
+            writer.write_closing_error(body_error_status(&err)).await?;
+            return Ok(false);
+        }
+    };
+    let expected = match *framing {
+        Framing::Length(n) => Some(n),
+        _ => None,
+    };
+    let result = match &range {
+        Some(range) => resume::receive_range(&mut body, Path::new(&request.file), range).await.map(|received| (received, range.total)),
+        None => upload::receive(&mut body, Path::new(&request.file), expected).await.map(|n| (None, n)),
+    };
+    match result {
+        // There are pieces missing still, tell the client what we have (the 308 of resumable uploads)
+        Ok((Some(received), _)) => {
+            info!("[{id}] stored {:?} of {}, have {received}", range, request.path);
+            writer.write_reply_with(308, &[("Range", &received)], &[]).await?;
+            return Ok(true);
+        }
+        Ok((None, size)) => {
+            info!("[{id}] stored {}", request.path);
+            webhook::notify(request, "put", &request.path, None, Some(size));
+        }
+        Err(err) => {
+            // Whatever is left of the body is still in the way
+            warn!("[{id}] upload to {} failed, {err}", request.path);
+            let code = match &err {
+                UploadError::Body(err) => body_error_status(err),
+                UploadError::File(err) if upload::is_out_of_space(err) => 507,
+                UploadError::File(_) => 500,
+            };
+            writer.write_closing_error(code).await?;
+            return Ok(false);
+        }
+    }
+    if meta.is_some() {
+        writer.write_reply(204, &[]).await?;
+    }
+    else {
+        let location = url::encode_path(&request.path);
+        writer.write_reply_with(201, &[("Location", &location)], "<html>201</html>".as_bytes()).await?;
+    }
+    Ok(true)
+}
+
+// Store every file of a multipart body in the directory, None when all went well
+// or the status to close the connection with
+async fn receive_form(body: impl AsyncRead + Unpin, boundary: &str, request: &Request, config: &Config) -> Option<i32> {
+    let (id, dir) = (&request.id, &request.file);
+    let mut form = Multipart::new(body, boundary);
+    loop {
+        let part = match form.next_part().await {
+            Ok(Some(part)) => part,
+            Ok(None) => return None,
+            Err(err) => return Some(form_error_status(id, err)),
+        };
+        // Browsers send an empty file part when nothing was picked
+        let filename = match part.filename.as_deref() {
+            Some("") => None,
+            Some(filename) => match multipart::sanitize_filename(filename) {
+                Some(filename) => Some(filename),
+                None => {
+                    info!("[{id}] refusing upload named {filename:?}");
+                    return Some(400);
                 }
+            },
+            None => None,
+        };
+        let Some(filename) = filename else {
+            info!("[{id}] ignoring form field {}", part.name.as_deref().unwrap_or_default());
+            if let Err(err) = form.read_data(&mut tokio::io::sink(), MAX_FORM_FIELD_SIZE).await {
+                return Some(form_error_status(id, err));
             }
+            continue;
+        };
+        let target = format!("{}/{filename}", dir.trim_end_matches('/'));
+        // The directory itself has been checked, the name is what's left
+        if config.is_excluded(&filename) || tokio::fs::metadata(&target).await.is_ok_and(|meta| meta.is_dir()) {
+            info!("[{id}] refusing upload to {target}");
+            return Some(409);
         }
-        else {
-            out.push(c);
+        let mut upload = match Upload::create(Path::new(&target)).await {
+            Ok(upload) => upload,
+            Err(err) => return Some(form_error_status(id, MultipartError::Write(err))),
+        };
+        match form.read_data(&mut upload.file, MAX_FORM_FILE_SIZE).await {
+            Ok(n) => {
+                if let Err(err) = upload.commit().await {
+                    return Some(form_error_status(id, MultipartError::Write(err)));
+                }
+                info!("[{id}] stored {n} bytes at {target}");
+                webhook::notify(request, "upload", &format!("{}/{filename}", request.path.trim_end_matches('/')), None, Some(n));
+            }
+            Err(err) => {
+                upload.abort().await;
+                return Some(form_error_status(id, err));
+            }
         }
     }
+}
+
+fn form_error_status(id: &str, err: MultipartError) -> i32 {
+    warn!("[{id}] form upload failed, {err}");
+    match err {
+        MultipartError::Body(err) => body_error_status(&err),
+        MultipartError::Malformed(_) => 400,
+        MultipartError::TooLarge => 413,
+        MultipartError::Write(err) if upload::is_out_of_space(&err) => 507,
+        MultipartError::Write(_) => 500,
+    }
+}
 
-    Some(out)
+// A name from a file manager form, it has to be a plain name in the directory already,
+// unlike an upload which is just cut down to one
+fn form_name(form: &QueryMap, key: &str) -> Result<String, i32> {
+    let value = form.get(key).ok_or(400)?;
+    multipart::sanitize_filename(value).filter(|name| name == value).ok_or(400)
 }
 
-fn encode_url(s: &str) -> String {
-    let mut out = String::new();
+// Rename an entry of the directory, never over one that exists already
+async fn rename_entry(request: &Request, from: &str, to: &str, config: &Config) -> Result<(), i32> {
+    let dir = request.file.trim_end_matches('/');
+    let (src, dst) = (format!("{dir}/{from}"), format!("{dir}/{to}"));
+    if config.is_excluded(from) {
+        return Err(404);
+    }
+    if config.is_excluded(to) || tokio::fs::symlink_metadata(&dst).await.is_ok() {
+        return Err(409);
+    }
+    let meta = tokio::fs::metadata(&src).await.map_err(|_| 404)?;
+    webdav::move_path(&src, &dst, &meta).await.map_err(|err| status_for(&request.id, &dst, err))?;
+    info!("[{}] renamed {src} to {dst}", request.id);
+    let dir = request.path.trim_end_matches('/');
+    webhook::notify(request, "move", &format!("{dir}/{from}"), Some(&format!("{dir}/{to}")), None);
+    Ok(())
+}
 
-    for ch in s.chars() {
-        if ch.is_ascii() {
-            if ch.is_ascii_alphabetic() || ch.is_ascii_digit() ||  ch == '-' || ch == '_' || ch == '.' || ch == '~' {
-                // Is Part of char can directly sent
-                out.push(ch);
-                continue;
+// One action from the file manager forms of a listing, browsers can't send MKCOL,
+// DELETE or MOVE so these do the same and go back to the listing
+async fn run_file_action(request: &Request, form: &QueryMap, config: &Config) -> Result<(), i32> {
+    // Names only, the directory itself has been checked
+    let dir = request.file.trim_end_matches('/');
+    match form.get("action") {
+        Some("mkdir") => {
+            let name = form_name(form, "name")?;
+            let path = format!("{dir}/{name}");
+            if config.is_excluded(&name) {
+                return Err(409);
+            }
+            // A name that is taken is a conflict for the form, MKCOL itself answers 405
+            create_directory(&request.id, &path).await.map_err(|code| if code == 405 { 409 } else { code })?;
+            webhook::notify(request, "mkcol", &format!("{}/{name}", request.path.trim_end_matches('/')), None, None);
+            Ok(())
+        }
+        Some("delete") => {
+            let name = form_name(form, "name")?;
+            let path = format!("{dir}/{name}");
+            if config.is_excluded(&name) {
+                return Err(404);
+            }
+            let meta = tokio::fs::symlink_metadata(&path).await.map_err(|_| 404)?;
+            remove_path(request, &path, meta.is_dir(), config.recursive_delete).await?;
+            webhook::notify(request, "delete", &format!("{}/{name}", request.path.trim_end_matches('/')), None, None);
+            Ok(())
+        }
+        Some("rename") => rename_entry(request, &form_name(form, "from")?, &form_name(form, "to")?, config).await,
+        _ => Err(400),
+    }
+}
+
+async fn handle_file_action(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, reader: &mut (impl AsyncBufRead + Unpin + Send), request: &Request, framing: &Framing, config: &Config) -> io::Result<bool> {
+    let id = &request.id;
+    writer.write_continue().await?;
+    let form = match form::read(reader, framing, &request.headers, &config.form).await {
+        Ok(form) => form,
+        Err(err) => {
+            info!("[{id}] bad file manager form, {err}");
+            let (code, close) = err.status();
+            match close {
+                true => writer.write_closing_error(code).await?,
+                false => writer.write_client_error(code).await?,
+            }
+            return Ok(!close);
+        }
+    };
+    let action = form.get("action").unwrap_or_default();
+    let user = request.user.as_deref().unwrap_or("anonymous");
+    match run_file_action(request, &form, config).await {
+        Ok(()) => {
+            info!("[{id}] {action} in {} as {user}", request.path);
+            let location = format!("{}/", url::encode_path(request.path.trim_end_matches('/')));
+            writer.write_reply_with(303, &[("Location", &location)], "<html>303</html>".as_bytes()).await?;
+        }
+        Err(code) => {
+            info!("[{id}] {action} in {} as {user} failed with {code}", request.path);
+            match code {
+                500 => writer.write_server_error().await?,
+                code => writer.write_client_error(code).await?,
+            }
+        }
+    }
+    Ok(true)
+}
+
+// Files posted from the upload form of a listing, false when the connection has to be closed
+async fn handle_form_upload(writer: &mut ResponseWriter<impl AsyncWrite + Unpin>, reader: &mut (impl AsyncBufRead + Unpin + Send), request: &Request, framing: &Framing, entry: &mut access::Entry, config: &Config) -> io::Result<bool> {
+    let id = &request.id;
+    let is_dir = !config.is_excluded(&request.path)
+        && tokio::fs::metadata(&request.file).await.is_ok_and(|meta| meta.is_dir());
+    let content_type = request.headers.get("Content-Type");
+    // Someone else's page posting with the user's credentials
+    if is_dir && !auth::same_origin(&request.headers) {
+        info!("[{id}] refusing a form posted from another site");
+        if let Err(err) = drain_body(writer, reader, framing).await {
+            writer.write_closing_error(body_error_status(&err)).await?;
+            return Ok(false);
+        }
+        writer.write_client_error(403).await?;
+        return Ok(true);
+    }
+    if is_dir && form::is_form(content_type) {
+        return handle_file_action(writer, reader, request, framing, config).await;
+    }
+    let boundary = content_type.and_then(multipart::form_boundary);
+    let boundary = match (is_dir, boundary) {
+        (true, Some(boundary)) => boundary,
+        (is_dir, _) => {
+            if let Err(err) = drain_body(writer, reader, framing).await {
+                info!("[{id}] failed to read the body {err}");
+                writer.write_closing_error(body_error_status(&err)).await?;
+                return Ok(false);
+            }
+            // Anything but a form to a directory gets the same answer as without uploads
+            match is_dir {
+                true => writer.write_client_error(415).await?,
+                false => serve_request(writer, request, entry, config).await?,
             }
+            return Ok(true);
+        }
+    };
+    let body = body_reader(reader, framing);
+    if body.is_ok() {
+        writer.write_continue().await?;
+    }
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => {
+            info!("[{id}] refusing the upload, {err}");
+            writer.write_closing_error(body_error_status(&err)).await?;
+            return Ok(false);
         }
-        // We need to encode it
-        let mut buffer = [0u8; 4];
-        for uchar in ch.encode_utf8(&mut buffer).as_bytes() {
-            out.push('%');
-            out.push_str(&format!("{uchar:X}"));
+    };
+    if let Some(code) = receive_form(body, &boundary, request, config).await {
+        writer.write_closing_error(code).await?;
+        return Ok(false);
+    }
+    // Back to the listing, which now shows the new files
+    let mut location = url::encode_path(&request.path);
+    if !location.ends_with('/') {
+        location.push('/');
+    }
+    writer.write_reply_with(303, &[("Location", &location)], "<html>303</html>".as_bytes()).await?;
+    Ok(true)
+}
+
+fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("httpserver: {err}");
+            std::process::exit(2);
+        }
+    };
+    log::init(config.log_level, config.log_format);
+    if let Some(path) = &config.access_log {
+        if let Err(err) = access::open(path) {
+            eprintln!("httpserver: can't open the access log {}, {err}", path.display());
+            std::process::exit(1);
         }
     }
+    #[cfg(unix)]
+    if let Err(err) = start_process(&config) {
+        eprintln!("httpserver: {err}");
+        std::process::exit(1);
+    }
+    log::start();
+    // A single thread gets the current thread runtime, handy for benchmarking
+    let mut builder = if config.threads == 1 {
+        tokio::runtime::Builder::new_current_thread()
+    }
+    else {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.worker_threads(config.threads);
+        builder
+    };
+    let runtime = match builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            error!("failed to create the runtime by {err}");
+            log::flush();
+            std::process::exit(1);
+        }
+    };
+    info!("Using {} worker threads", runtime.metrics().num_workers());
+    runtime.block_on(serve(config));
+    log::flush();
+}
 
-    return out;
+// The pid file is locked first, with --daemon the terminal hears about any failure
+// until the daemon is listening
+#[cfg(unix)]
+fn start_process(config: &Config) -> Result<(), String> {
+    if let Some(path) = &config.pid_file {
+        daemon::lock_pid_file(path)?;
+    }
+    if let Some(max_size) = config.log_max_size {
+        daemon::set_rotation(daemon::Rotation { max_size, keep: config.log_keep, compress: config.log_compress });
+    }
+    if config.daemon {
+        return daemon::detach(config.log_file.as_deref());
+    }
+    if let Some(path) = &config.log_file {
+        daemon::redirect_output(Some(path))?;
+    }
+    daemon::write_pid()
 }
 
-async fn handle_client(mut stream: TcpStream) -> io::Result<()> {
-    // First Get the first line
-    let peeraddr = stream.peer_addr()?;
-    println!("handling peer {peeraddr}");
+// With --daemon the terminal is still waiting to hear how startup went
+fn exit_at_startup(err: &str) -> ! {
+    #[cfg(unix)]
+    daemon::failed(err);
+    let _ = err;
+    log::flush();
+    std::process::exit(1);
+}
 
-    let (reader, mut writer) = stream.split();
-    let mut reader = BufReader::new(reader);
+// Once a minute when anything happened, for capacity planning
+async fn log_connections() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let mut last = 0;
+    loop {
+        interval.tick().await;
+        let total = metrics::total();
+        if total != last {
+            last = total;
+            info!("{} connections active, {total} handled so far", metrics::active());
+        }
+    }
+}
 
-    loop { // For Handle each per requests
-        let mut buffer = String::new();
+// SIGUSR1 is what logrotate sends once it moved the files away
+#[cfg(unix)]
+async fn reopen_logs() {
+    let mut signal = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+        Ok(signal) => signal,
+        Err(err) => {
+            warn!("failed to listen for SIGUSR1 by {err}");
+            return;
+        }
+    };
+    while signal.recv().await.is_some() {
+        if let Err(err) = daemon::reopen_log() {
+            warn!("{err}");
+        }
+        if let Err(err) = access::reopen() {
+            warn!("failed to open the access log again by {err}");
+        }
+        info!("got SIGUSR1, opened the log files again");
+    }
+}
 
-        // Read All Http Headers
-        if reader.read_line(&mut buffer).await? == 0 { // EOF
-            println!("EOF, Quiting...");
-            return Ok(());
+// Swap in new settings on SIGHUP, and with --watch-config whenever the file changes
+async fn watch_config(live: Arc<LiveConfig>) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(err) => {
+            warn!("failed to listen for SIGHUP by {err}");
+            None
         }
-        let (method, path) = match parse_request_line(buffer.trim()) {
-            Some(some) => some,
-            None => return Ok(()),
-        };
-        let path = match decode_url(path) {
-            Some(what) => what,
-            None => return Ok(()),
+    };
+    let config = live.get();
+    let modified = |path: &str| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut last = config.config_file.as_deref().and_then(modified);
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    loop {
+        #[cfg(unix)]
+        let signal = async {
+            match hangup.as_mut() {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
         };
-        println!("method {method} path {path}");
+        #[cfg(not(unix))]
+        let signal = std::future::pending::<Option<()>>();
+        tokio::select! {
+            _ = signal => {
+                info!("got SIGHUP, reloading the settings");
+                live.reload();
+            }
+            _ = interval.tick(), if config.watch_config => {
+                let now = config.config_file.as_deref().and_then(modified);
+                if now != last {
+                    last = now;
+                    info!("the config file changed, reloading the settings");
+                    live.reload();
+                }
+            }
+        }
+    }
+}
 
-        // Read all headers
-        let mut headers = HashMap::new();
-        let mut line = String::new();
+async fn serve(config: Config) {
+    let live = Arc::new(LiveConfig::new(config));
+    let config = live.get();
+    // Always running, a reload may turn on writes
+    let cleanup = live.clone();
+    tokio::task::spawn(async move {
         loop {
-            if reader.read_line(&mut line).await? == 0 {
-                return Ok(());
-            }
-            let myline = line.trim();
-            if myline.len() == 0 { // The last \r\n
-                break;
+            let ttl = cleanup.get().partial_ttl;
+            tokio::time::sleep(ttl.min(Duration::from_secs(600))).await;
+            resume::remove_stale(ttl).await;
+        }
+    });
+    tokio::task::spawn(watch_config(live.clone()));
+    #[cfg(unix)]
+    tokio::task::spawn(reopen_logs());
+    tokio::task::spawn(log_connections());
+    if !config.webhooks.is_empty() {
+        webhook::start(config.webhooks.clone(), config.webhook_secret.clone());
+    }
+    // Bound right away like the rest, it may well be port 80. Asked for and missing
+    // is as bad as a --listen address missing
+    let redirects = match &config.redirect_https {
+        Some(addr) => match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                error!("failed to listen for https redirects on {addr} by {err}");
+                exit_at_startup(&format!("failed to listen for https redirects on {addr} by {err}"));
             }
-            // Split it by ': '
-            let kvs : Vec<&str> = myline.split(": ").collect();
-            if kvs.len() != 2 {
-                println!("parse the headers failed, expected 2, got {}", kvs.len());
-                write_bad_reply(&mut writer).await?;
-                return Ok(());
+        },
+        None => None,
+    };
+    // All or nothing, serving on some of the addresses would go unnoticed
+    let mut listeners = Vec::new();
+    for addr in &config.listen {
+        match Listener::bind(addr, config.socket_mode, config.reuse_port, &config.socket) {
+            Ok(bound) => listeners.extend(bound),
+            Err(err) => {
+                error!("failed to listen on {addr} by {err}");
+                exit_at_startup(&format!("failed to listen on {addr} by {err}"));
             }
-            headers.insert(String::from(kvs[0].trim()), String::from(kvs[1].trim()));
-            line.clear();
         }
-        println!("headers: {:?}", headers);
+    }
+    for (index, listener) in listeners.iter().enumerate() {
+        match config.reuse_port {
+            Some(_) => info!("Listen on {} (acceptor {index})", listener.local_addr()),
+            None => info!("Listen on {}", listener.local_addr()),
+        }
+    }
+    // Ready to click, with port 0 nobody knows the port before this
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    let mut shared = Vec::new();
+    for addr in listeners.iter().filter_map(Listener::tcp_addr) {
+        // --reuseport binds the same one several times
+        if addrs.contains(&addr) {
+            continue;
+        }
+        addrs.push(addr);
+        // Serving one file, the link is straight to it
+        let name = config.single_file.as_deref().map(url::encode_path_segment).unwrap_or_default();
+        info!("{} {}{name}", if name.is_empty() { "Open" } else { "Download" }, listener::local_url(addr));
+        for url in listener::network_urls(addr) {
+            info!("  or {url}{name}");
+            shared.push(format!("{url}{name}"));
+        }
+    }
+    if config.qr {
+        // After the urls it shows
+        log::flush();
+        print_qr(&shared, &addrs);
+    }
+    if config.open {
+        match addrs.first() {
+            Some(addr) => open_browser(&listener::local_url(*addr)),
+            None => warn!("--open needs a tcp address to open"),
+        }
+    }
+    info!("Socket options: {}", config.socket);
+    // Nothing is accepted before, a client must never be served as root by accident
+    #[cfg(unix)]
+    if config.privileges.is_set() {
+        if let Err(err) = privileges::drop(&config.privileges) {
+            error!("failed to drop privileges, {err}");
+            exit_at_startup(&format!("failed to drop privileges, {err}"));
+        }
+        if config.privileges.chroot.is_some() {
+            daemon::entered_chroot();
+        }
+        let privileges = &config.privileges;
+        let group = privileges.group.as_ref().map(|group| format!(", group {group}")).unwrap_or_default();
+        let chroot = privileges.chroot.as_ref().map(|dir| format!(" in {}", dir.display())).unwrap_or_default();
+        info!("Running as {}{group}{chroot}", privileges.user.as_deref().unwrap_or_default());
+    }
+    // Only now, inside the chroot and as the user that is going to read them
+    if let Err(err) = config.check_roots() {
+        error!("{err}");
+        exit_at_startup(&err);
+    }
+    if let Some(listener) = redirects {
+        tokio::task::spawn(redirect::serve_redirects(listener, config.clone()));
+    }
+    if let Some(name) = &config.mdns {
+        announce(name, &addrs, &config);
+    }
+    let sockets: Vec<PathBuf> = config.listen.iter().filter_map(|addr| match addr {
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => Some(path.clone()),
+        _ => None,
+    }).collect();
+    tokio::task::spawn(shut_down_on_signal(sockets, live.clone()));
+    match &config.single_file {
+        _ if config.embedded => info!("Serving {} embedded files", embedded::count()),
+        Some(name) => info!("Serving {}/{name} only", config.roots[0]),
+        None => for root in &config.roots {
+            info!("Serving {}", if root.is_empty() { "/" } else { root });
+        },
+    }
+    if config.write {
+        info!("Writes are enabled{}", if config.credentials.is_empty() { ", for everyone" } else { "" });
+    }
+    #[cfg(unix)]
+    daemon::started();
+    let accepting: Vec<_> = listeners.into_iter().enumerate()
+        .map(|(index, listener)| tokio::task::spawn(accept_loop(listener, index, live.clone())))
+        .collect();
+    for task in accepting {
+        let _ = task.await;
+    }
+    // The listeners are closed, the signal handler exits once the connections drained
+    if shutdown::is_shutting_down() {
+        std::future::pending::<()>().await;
+    }
+}
 
-        // Dispatch path by query
-        match gen_fs_page(path.as_str()).await {
-            Ok(content) => write_reply(&mut writer, 200, content.as_slice()).await?,
-            Err(err) => match err.kind() {
-                ErrorKind::NotFound => write_reply(&mut writer, 404, "<html>404</html>".as_bytes()).await?,
-                _ => write_reply(&mut writer, 500, "<html>500</html>".as_bytes()).await?
+// For phones, the url most likely to work from another device on the network. A
+// private IPv4 address is what a home or office network hands out
+fn print_qr(shared: &[String], addrs: &[SocketAddr]) {
+    let private = |url: &&String| ["http://192.168.", "http://10.", "http://172."].iter().any(|prefix| url.starts_with(prefix));
+    let best = shared.iter().find(private)
+        .or_else(|| shared.iter().find(|url| !url.starts_with("http://[")))
+        .or(shared.first());
+    let url = match best {
+        Some(url) => url.clone(),
+        None => match addrs.first() {
+            Some(addr) => {
+                info!("not reachable from other devices, the QR code is for this machine only");
+                listener::local_url(*addr)
+            }
+            None => {
+                warn!("--qr needs a tcp address");
+                return;
             }
+        },
+    };
+    match QrCode::encode(url.as_bytes()) {
+        Ok(code) => println!("{}{url}", code.to_unicode()),
+        Err(err) => warn!("no QR code for {url}, {err}"),
+    }
+}
+
+// --mdns: the first tcp address, with the IPv4 addresses others reach it on
+fn announce(name: &str, addrs: &[SocketAddr], config: &Config) {
+    let Some(addr) = addrs.first() else {
+        warn!("--mdns needs a tcp address");
+        return;
+    };
+    let ips: Vec<_> = addrs.iter().filter(|other| other.port() == addr.port())
+        .flat_map(|other| listener::network_ips(*other))
+        .filter_map(|ip| match ip {
+            std::net::IpAddr::V4(ip) => Some(ip),
+            std::net::IpAddr::V6(_) => None,
+        })
+        .collect();
+    if ips.is_empty() {
+        info!("not reachable from other devices over IPv4, not announcing over mDNS");
+        return;
+    }
+    let name = match name.is_empty() {
+        true => format!("Files on {}", mdns::hostname()),
+        false => String::from(name),
+    };
+    let mut txt = vec![String::from("path=/")];
+    if !config.credentials.is_empty() {
+        txt.push(String::from("auth=basic"));
+    }
+    tokio::task::spawn(mdns::run(mdns::Service::new(&name, addr.port(), ips, txt)));
+}
+
+// The default browser of the desktop, the server goes on whether that works or not
+fn open_browser(url: &str) {
+    let mut command = match std::env::consts::OS {
+        "macos" => std::process::Command::new("open"),
+        "windows" => {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
         }
+        _ => std::process::Command::new("xdg-open"),
+    };
+    match command.arg(url).stdin(std::process::Stdio::null()).stdout(std::process::Stdio::null()).spawn() {
+        Ok(_) => info!("Opening {url} in the browser"),
+        Err(err) => warn!("failed to open a browser by {err}, go to {url}"),
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let listener = match TcpListener::bind("127.0.0.1:25565").await {
-        Ok(what) => what,
-        Err(err) => {
-            println!("failed to create a tcp listener by {err}");
-            return;
+#[cfg(unix)]
+async fn signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let (mut interrupt, mut terminate) = (signal(SignalKind::interrupt())?, signal(SignalKind::terminate())?);
+    tokio::select! {
+        _ = interrupt.recv() => {}
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+// Killed right away, downloads would end mid-byte. No more connections are taken,
+// the open ones finish their current response and whatever is still running after
+// --drain-timeout is cut off. Stopped by a signal the socket files would stay
+// behind and others on the network would keep the mDNS records until they time out
+async fn shut_down_on_signal(sockets: Vec<PathBuf>, live: Arc<LiveConfig>) {
+    // Or --count is reached, which still ends the server when there are no signals
+    tokio::select! {
+        signaled = signal() => if let Err(err) = signaled {
+            warn!("failed to listen for SIGINT and SIGTERM by {err}, only --count ends the server cleanly");
+            shutdown::wait().await;
+        },
+        _ = shutdown::wait() => {}
+    }
+    shutdown::begin();
+    mdns::goodbye();
+    let limit = live.get().drain_timeout;
+    let open = metrics::active();
+    info!("shutting down, waiting up to {}s for {open} connections", limit.as_secs());
+    let drain = async {
+        while metrics::active() > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
     };
-    println!("Listen on {}", listener.local_addr().expect("it should never fail"));
+    tokio::select! {
+        _ = drain => {}
+        _ = tokio::time::sleep(limit) => {}
+        Ok(()) = signal() => info!("got another signal, not waiting any longer"),
+    }
+    let left = metrics::active();
+    info!("{} connections drained, {left} aborted", open.saturating_sub(left));
+    for path in &sockets {
+        let _ = std::fs::remove_file(path);
+    }
+    #[cfg(unix)]
+    daemon::remove_pid_file();
+    log::flush();
+    std::process::exit(0);
+}
+
+async fn accept_loop(listener: Listener, index: usize, live: Arc<LiveConfig>) {
     loop {
-        let (stream, addr) = match listener.accept().await {
+        // Dropping the listener refuses everyone who comes after
+        let config = live.get();
+        let accepted = tokio::select! {
+            accepted = listener.accept(&config.socket) => accepted,
+            _ = shutdown::wait() => return,
+        };
+        let (stream, peer) = match accepted {
             Ok(what) => what,
+            // Mostly out of file descriptors, which other connections ending will fix.
+            // The pause keeps the failing accept from spinning meanwhile
             Err(err) => {
-                println!("failed to accept tcp listener {err}");
-                return;
+                warn!("failed to accept tcp listener {err}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
             }
         };
-        println!("incoming client from {addr}");
+        let accepted = metrics::accepted(index);
+        debug!("incoming client from {peer} (acceptor {index}, {accepted} so far)");
+        let live = live.clone();
         tokio::task::spawn(async move {
-            if let Err(e) = handle_client(stream).await {
-                println!("Error handling client: {}", e);
+            if let Err(e) = handle_client(stream, peer, live).await {
+                warn!("Error handling client: {}", e);
             }
         });
     }
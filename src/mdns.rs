@@ -0,0 +1,557 @@
+// --mdns: just enough multicast DNS (RFC 6762) and DNS-SD (RFC 6763) to show up
+// as an _http._tcp service on the local network. IPv4 only
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket as StdUdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use crate::log::{info, warn};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const SERVICE: &str = "_http._tcp.local";
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// Set on records only we answer for, others drop what they cached of the name
+const CACHE_FLUSH: u16 = 0x8000;
+
+// What RFC 6762 10 suggests, host records change more often than service ones
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+static ANNOUNCED: OnceLock<Service> = OnceLock::new();
+
+// The service as announced, with the name it ended up with
+#[derive(Debug, Clone)]
+pub struct Service {
+    pub name: String,  // The instance, like "Files on myhost"
+    host: String,      // myhost.local
+    port: u16,
+    addrs: Vec<Ipv4Addr>,
+    txt: Vec<String>,
+}
+
+impl Service {
+    pub fn new(name: &str, port: u16, addrs: Vec<Ipv4Addr>, txt: Vec<String>) -> Service {
+        Service { name: String::from(name), host: format!("{}.local", hostname()), port, addrs, txt }
+    }
+
+    fn instance(&self) -> String {
+        format!("{}.{SERVICE}", self.name)
+    }
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is writable for its whole length, which is what is passed
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    match std::str::from_utf8(&buffer[..len]) {
+        // Only the first label, myhost.example.com is myhost.local
+        Ok(name) if result == 0 && !name.is_empty() => String::from(name.split('.').next().unwrap_or(name)),
+        _ => String::from("httpserver"),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| String::from("httpserver"))
+}
+
+// A DNS message, only what we write: no compression, one section after the other
+struct Message {
+    bytes: Vec<u8>,
+    flush: bool,  // Legacy unicast answers don't set the cache-flush bit
+}
+
+impl Message {
+    fn new(id: u16, response: bool, questions: u16, answers: u16, additional: u16) -> Message {
+        let mut bytes = Vec::with_capacity(512);
+        bytes.extend(id.to_be_bytes());
+        // A response is authoritative, always
+        bytes.extend(if response { 0x8400u16 } else { 0 }.to_be_bytes());
+        for count in [questions, answers, 0, additional] {
+            bytes.extend(count.to_be_bytes());
+        }
+        Message { bytes, flush: true }
+    }
+
+    fn name(&mut self, name: &str) {
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            let label = &label.as_bytes()[..label.len().min(63)];
+            self.bytes.push(label.len() as u8);
+            self.bytes.extend(label);
+        }
+        self.bytes.push(0);
+    }
+
+    fn question(&mut self, name: &str, kind: u16) {
+        self.name(name);
+        self.bytes.extend(kind.to_be_bytes());
+        self.bytes.extend(CLASS_IN.to_be_bytes());
+    }
+
+    fn record(&mut self, name: &str, kind: u16, unique: bool, ttl: u32, data: &[u8]) {
+        self.name(name);
+        self.bytes.extend(kind.to_be_bytes());
+        self.bytes.extend((CLASS_IN | if unique && self.flush { CACHE_FLUSH } else { 0 }).to_be_bytes());
+        self.bytes.extend(ttl.to_be_bytes());
+        self.bytes.extend((data.len() as u16).to_be_bytes());
+        self.bytes.extend(data);
+    }
+}
+
+fn encoded_name(name: &str) -> Vec<u8> {
+    let mut message = Message { bytes: Vec::new(), flush: true };
+    message.name(name);
+    message.bytes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Record {
+    Services,  // _services._dns-sd._udp.local PTR _http._tcp.local
+    Ptr,
+    Srv,
+    Txt,
+    A,
+}
+
+impl Service {
+    fn write(&self, message: &mut Message, record: Record, ttl: Option<u32>) {
+        let instance = self.instance();
+        match record {
+            Record::Services => message.record(SERVICES, TYPE_PTR, false, ttl.unwrap_or(SERVICE_TTL), &encoded_name(SERVICE)),
+            Record::Ptr => message.record(SERVICE, TYPE_PTR, false, ttl.unwrap_or(SERVICE_TTL), &encoded_name(&instance)),
+            Record::Srv => {
+                let mut data = Vec::new();
+                data.extend(0u16.to_be_bytes());  // Priority
+                data.extend(0u16.to_be_bytes());  // Weight
+                data.extend(self.port.to_be_bytes());
+                data.extend(encoded_name(&self.host));
+                message.record(&instance, TYPE_SRV, true, ttl.unwrap_or(HOST_TTL), &data);
+            }
+            Record::Txt => {
+                let mut data = Vec::new();
+                for entry in &self.txt {
+                    let entry = &entry.as_bytes()[..entry.len().min(255)];
+                    data.push(entry.len() as u8);
+                    data.extend(entry);
+                }
+                message.record(&instance, TYPE_TXT, true, ttl.unwrap_or(SERVICE_TTL), &data);
+            }
+            Record::A => {
+                for addr in &self.addrs {
+                    message.record(&self.host, TYPE_A, true, ttl.unwrap_or(HOST_TTL), &addr.octets());
+                }
+            }
+        }
+    }
+
+    fn count(&self, records: &[Record]) -> u16 {
+        records.iter().map(|&record| if record == Record::A { self.addrs.len() } else { 1 }).sum::<usize>() as u16
+    }
+
+    // A response with these answers and additional records
+    fn response(&self, id: u16, question: Option<(&str, u16)>, answers: &[Record], additional: &[Record], ttl: Option<u32>) -> Vec<u8> {
+        let mut message = Message::new(id, true, question.is_some() as u16, self.count(answers), self.count(additional));
+        if let Some((name, kind)) = question {
+            message.question(name, kind);
+            message.flush = false;
+        }
+        for &record in answers.iter().chain(additional) {
+            self.write(&mut message, record, ttl);
+        }
+        message.bytes
+    }
+
+    // What answers a question about name and kind, and what the asker will want next
+    fn answer(&self, name: &str, kind: u16) -> (Vec<Record>, Vec<Record>) {
+        let is = |other: &str| name.eq_ignore_ascii_case(other);
+        let wants = |wanted: u16| kind == wanted || kind == TYPE_ANY;
+        if is(SERVICES) && wants(TYPE_PTR) {
+            return (vec![Record::Services], Vec::new());
+        }
+        if is(SERVICE) && wants(TYPE_PTR) {
+            return (vec![Record::Ptr], vec![Record::Srv, Record::Txt, Record::A]);
+        }
+        if is(&self.instance()) {
+            let mut answers = Vec::new();
+            if wants(TYPE_SRV) {
+                answers.push(Record::Srv);
+            }
+            if wants(TYPE_TXT) {
+                answers.push(Record::Txt);
+            }
+            let additional = if answers.contains(&Record::Srv) { vec![Record::A] } else { Vec::new() };
+            return (answers, additional);
+        }
+        if is(&self.host) && wants(TYPE_A) {
+            return (vec![Record::A], Vec::new());
+        }
+        (Vec::new(), Vec::new())
+    }
+}
+
+// A name at offset, following compression pointers, and where the field ends
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    // Pointers may only go back, more jumps than that is a loop
+    for _ in 0..128 {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let pointer = (len & 0x3f) << 8 | *message.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                if pointer >= offset {
+                    return None;
+                }
+                offset = pointer;
+            }
+            len if len <= 63 => {
+                let label = message.get(offset + 1..offset + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*message.get(offset)?, *message.get(offset + 1)?]))
+}
+
+// The questions of a query, or the names answered by a response
+struct Parsed {
+    id: u16,
+    response: bool,
+    questions: Vec<(String, u16)>,
+    answers: Vec<String>,
+}
+
+fn parse(message: &[u8]) -> Option<Parsed> {
+    let id = read_u16(message, 0)?;
+    let response = read_u16(message, 2)? & 0x8000 != 0;
+    let (questions, answers) = (read_u16(message, 4)?, read_u16(message, 6)?);
+    let mut offset = 12;
+    let mut parsed = Parsed { id, response, questions: Vec::new(), answers: Vec::new() };
+    for _ in 0..questions {
+        let (name, end) = read_name(message, offset)?;
+        // The top bit of the class asks for a unicast answer, multicast is as good
+        let (kind, _class) = (read_u16(message, end)?, read_u16(message, end + 2)?);
+        parsed.questions.push((name, kind));
+        offset = end + 4;
+    }
+    for _ in 0..answers {
+        let (name, end) = read_name(message, offset)?;
+        let len = read_u16(message, end + 8)? as usize;
+        // A record cut short is as good as none, the message was truncated
+        message.get(end + 10..end + 10 + len)?;
+        parsed.answers.push(name);
+        offset = end + 10 + len;
+    }
+    Some(parsed)
+}
+
+fn multicast_socket() -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Avahi or Bonjour are likely on the port already
+    socket.set_reuse_address(true)?;
+    #[cfg(target_os = "linux")]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// Whether someone else answers for the name already (RFC 6762 8.1). Our own
+// probes come back as queries and don't count
+async fn is_taken(socket: &UdpSocket, service: &Service) -> io::Result<bool> {
+    let instance = service.instance();
+    let mut probe = Message::new(0, false, 1, 0, 0);
+    probe.question(&instance, TYPE_ANY);
+    let group = SocketAddr::from((GROUP, PORT));
+    let mut buffer = [0u8; 9000];
+    for _ in 0..3 {
+        socket.send_to(&probe.bytes, group).await?;
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(250);
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+            let (len, _) = received?;
+            let taken = parse(&buffer[..len]).is_some_and(|parsed| {
+                parsed.response && parsed.answers.iter().any(|name| name.eq_ignore_ascii_case(&instance))
+            });
+            if taken {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Find a free name, announce it and answer queries for as long as the server runs
+pub async fn run(mut service: Service) {
+    let socket = match multicast_socket() {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("failed to join mDNS by {err}, not announcing the service");
+            return;
+        }
+    };
+    let base = service.name.clone();
+    for n in 2.. {
+        match is_taken(&socket, &service).await {
+            Ok(false) => break,
+            Ok(true) => {
+                info!("mDNS name '{}' is taken", service.name);
+                service.name = format!("{base} ({n})");
+            }
+            Err(err) => {
+                warn!("failed to probe mDNS by {err}, not announcing the service");
+                return;
+            }
+        }
+    }
+    let service = ANNOUNCED.get_or_init(|| service);
+    info!("Announcing '{}' on {}:{} over mDNS", service.instance(), service.host, service.port);
+    let group = SocketAddr::from((GROUP, PORT));
+    let announcement = service.response(0, None, &[Record::Ptr, Record::Srv, Record::Txt, Record::A, Record::Services], &[], None);
+    // Twice, a second apart (RFC 6762 8.3)
+    for delay in [0, 1] {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        if let Err(err) = socket.send_to(&announcement, group).await {
+            warn!("failed to announce over mDNS by {err}");
+        }
+    }
+    let mut buffer = [0u8; 9000];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("mDNS stopped by {err}");
+                return;
+            }
+        };
+        let Some(query) = parse(&buffer[..len]).filter(|parsed| !parsed.response) else {
+            continue;
+        };
+        // A resolver that isn't on 5353 is a plain DNS client, it gets its answer
+        // directly, with its id and question (RFC 6762 6.7)
+        let legacy = from.port() != PORT;
+        for (name, kind) in &query.questions {
+            let (answers, additional) = service.answer(name, *kind);
+            if answers.is_empty() {
+                continue;
+            }
+            let result = match legacy {
+                true => socket.send_to(&service.response(query.id, Some((name, *kind)), &answers, &additional, Some(10)), from).await,
+                false => socket.send_to(&service.response(0, None, &answers, &additional, None), group).await,
+            };
+            if let Err(err) = result {
+                warn!("failed to answer over mDNS by {err}");
+            }
+        }
+    }
+}
+
+// Tell the network the service is gone, caches drop records with a TTL of 0
+pub fn goodbye() {
+    let Some(service) = ANNOUNCED.get() else {
+        return;
+    };
+    let goodbye = service.response(0, None, &[Record::Ptr, Record::Srv, Record::Txt, Record::A], &[], Some(0));
+    let sent = StdUdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.send_to(&goodbye, SocketAddrV4::new(GROUP, PORT)));
+    match sent {
+        Ok(_) => info!("Withdrew '{}' from mDNS", service.name),
+        Err(err) => warn!("failed to withdraw from mDNS by {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A record as a resolver reads it
+    #[derive(Debug, PartialEq)]
+    struct Answer {
+        name: String,
+        kind: u16,
+        class: u16,
+        ttl: u32,
+        data: Vec<u8>,
+    }
+
+    fn records_of(message: &[u8]) -> Vec<Answer> {
+        let count = read_u16(message, 6).unwrap() + read_u16(message, 10).unwrap();
+        let mut offset = 12;
+        for _ in 0..read_u16(message, 4).unwrap() {
+            offset = read_name(message, offset).unwrap().1 + 4;
+        }
+        (0..count).map(|_| {
+            let (name, end) = read_name(message, offset).unwrap();
+            let ttl = u32::from_be_bytes(message[end + 4..end + 8].try_into().unwrap());
+            let len = read_u16(message, end + 8).unwrap() as usize;
+            offset = end + 10 + len;
+            Answer { name, kind: read_u16(message, end).unwrap(), class: read_u16(message, end + 2).unwrap(), ttl, data: message[end + 10..offset].to_vec() }
+        }).collect()
+    }
+
+    fn service() -> Service {
+        Service {
+            name: String::from("Files on box"),
+            host: String::from("box.local"),
+            port: 8080,
+            addrs: vec![Ipv4Addr::new(192, 168, 1, 5), Ipv4Addr::new(10, 0, 0, 2)],
+            txt: vec![String::from("path=/"), String::from("auth=basic")],
+        }
+    }
+
+    fn query(id: u16, name: &str, kind: u16) -> Vec<u8> {
+        let mut message = Message::new(id, false, 1, 0, 0);
+        message.question(name, kind);
+        message.bytes
+    }
+
+    #[test]
+    fn names_with_and_without_compression() {
+        assert_eq!(encoded_name("box.local"), b"\x03box\x05local\x00");
+        let mut message = vec![0u8; 12];
+        message.extend(b"\x05local\x00");
+        // "box" and then a pointer back to "local" at 12
+        message.extend(b"\x03box\xc0\x0c");
+        assert_eq!(read_name(&message, 19), Some((String::from("box.local"), 25)));
+        // Pointing at itself or forward is a loop waiting to happen
+        let looping = [&[0u8; 12][..], b"\xc0\x0c"].concat();
+        assert_eq!(read_name(&looping, 12), None);
+        assert_eq!(read_name(b"\x05loc", 0), None);
+        assert_eq!(read_name(b"\x40", 0), None);
+    }
+
+    #[test]
+    fn browsing_gets_everything_to_connect() {
+        let service = service();
+        let parsed = parse(&query(7, "_http._tcp.local", TYPE_PTR)).unwrap();
+        assert!(!parsed.response);
+        assert_eq!(parsed.questions, [(String::from("_http._tcp.local"), TYPE_PTR)]);
+        let (answers, additional) = service.answer("_HTTP._tcp.local", TYPE_PTR);
+        assert_eq!(answers, [Record::Ptr]);
+        let response = service.response(0, None, &answers, &additional, None);
+        assert_eq!(read_u16(&response, 2), Some(0x8400));
+        let records = records_of(&response);
+        assert_eq!(records[0], Answer { name: String::from("_http._tcp.local"), kind: TYPE_PTR, class: CLASS_IN, ttl: SERVICE_TTL, data: encoded_name("Files on box._http._tcp.local") });
+        let srv = &records[1];
+        assert_eq!((srv.kind, srv.class, srv.ttl), (TYPE_SRV, CLASS_IN | CACHE_FLUSH, HOST_TTL));
+        assert_eq!(srv.data, [&[0, 0, 0, 0, 0x1f, 0x90][..], &encoded_name("box.local")].concat());
+        assert_eq!(records[2].data, b"\x06path=/\x0aauth=basic");
+        let addrs: Vec<&[u8]> = records[3..].iter().map(|record| record.data.as_slice()).collect();
+        assert_eq!(addrs, [&[192, 168, 1, 5][..], &[10, 0, 0, 2]]);
+        assert!(records[3..].iter().all(|record| record.name == "box.local" && record.kind == TYPE_A));
+    }
+
+    #[test]
+    fn only_our_names_are_answered() {
+        let service = service();
+        assert_eq!(service.answer("_services._dns-sd._udp.local", TYPE_PTR).0, [Record::Services]);
+        assert_eq!(service.answer("Files on box._http._tcp.local", TYPE_ANY), (vec![Record::Srv, Record::Txt], vec![Record::A]));
+        assert_eq!(service.answer("files on box._http._tcp.local", TYPE_TXT), (vec![Record::Txt], vec![]));
+        assert_eq!(service.answer("box.local", TYPE_A).0, [Record::A]);
+        assert!(service.answer("box.local", TYPE_TXT).0.is_empty());
+        assert!(service.answer("other.local", TYPE_A).0.is_empty());
+        assert!(service.answer("_ipp._tcp.local", TYPE_PTR).0.is_empty());
+    }
+
+    #[test]
+    fn legacy_answers_echo_the_question_without_cache_flush() {
+        let service = service();
+        let (answers, additional) = service.answer("box.local", TYPE_A);
+        let response = service.response(0x1234, Some(("box.local", TYPE_A)), &answers, &additional, Some(10));
+        let parsed = parse(&response).unwrap();
+        assert_eq!((parsed.id, parsed.response), (0x1234, true));
+        assert_eq!(parsed.questions, [(String::from("box.local"), TYPE_A)]);
+        let records = records_of(&response);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.class == CLASS_IN && record.ttl == 10));
+    }
+
+    #[test]
+    fn goodbye_has_a_ttl_of_zero() {
+        let service = service();
+        let goodbye = service.response(0, None, &[Record::Ptr, Record::Srv, Record::Txt, Record::A], &[], Some(0));
+        let records = records_of(&goodbye);
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|record| record.ttl == 0));
+    }
+
+    #[test]
+    fn truncated_messages_are_dropped() {
+        let message = query(1, "box.local", TYPE_A);
+        for len in [0, 5, 12, 20, message.len() - 1] {
+            assert!(parse(&message[..len]).is_none(), "{len}");
+        }
+        assert!(parse(&message).is_some());
+        let service = service();
+        let response = service.response(0, None, &[Record::Txt], &[], None);
+        assert_eq!(parse(&response).unwrap().answers, ["Files on box._http._tcp.local"]);
+        assert!(parse(&response[..response.len() - 1]).is_none());
+    }
+
+    // The loop itself on the network, it only runs where multicast gets through.
+    // Someone answers for the name first, then our resolver asks the way plain DNS
+    // clients do, from a port of its own
+    #[tokio::test]
+    async fn taken_name_gets_a_suffix_and_is_resolved() {
+        let Ok(other) = multicast_socket() else {
+            return;
+        };
+        let mut taken = service();
+        taken.name = String::from("Files on test");
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 9000];
+            while let Ok((len, _)) = other.recv_from(&mut buffer).await {
+                let asked = parse(&buffer[..len]).is_some_and(|parsed| !parsed.response && parsed.questions.iter().any(|(name, _)| name == "Files on test._http._tcp.local"));
+                if asked {
+                    let claim = taken.response(0, None, &[Record::Srv], &[], None);
+                    let _ = other.send_to(&claim, SocketAddr::from((GROUP, PORT))).await;
+                }
+            }
+        });
+        let mut ours = service();
+        ours.name = String::from("Files on test");
+        tokio::spawn(run(ours));
+        let resolver = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        let mut buffer = [0u8; 9000];
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if resolver.send_to(&query(99, "_http._tcp.local", TYPE_PTR), SocketAddr::from((GROUP, PORT))).await.is_err() {
+                return;
+            }
+            let Ok(Ok((len, _))) = tokio::time::timeout(Duration::from_millis(200), resolver.recv_from(&mut buffer)).await else {
+                continue;
+            };
+            let response = &buffer[..len];
+            assert_eq!(parse(response).unwrap().id, 99);
+            let records = records_of(response);
+            assert_eq!(records[0].data, encoded_name("Files on test (2)._http._tcp.local"));
+            assert!(records.iter().any(|record| record.kind == TYPE_SRV && record.data[4..6] == 8080u16.to_be_bytes()));
+            return;
+        }
+        panic!("no answer to the query");
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Options,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Trace,
+    Connect,
+    Mkcol,  // WebDAV, creates a directory
+    Propfind,  // WebDAV, lists properties
+    Move,  // WebDAV
+    Copy,  // WebDAV
+}
+
+impl Method {
+    // Methods are case-sensitive (RFC 7231 4.1)
+    pub fn parse(s: &str) -> Option<Method> {
+        let method = match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            "MKCOL" => Method::Mkcol,
+            "PROPFIND" => Method::Propfind,
+            "MOVE" => Method::Move,
+            "COPY" => Method::Copy,
+            _ => return None,
+        };
+        Some(method)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Mkcol => "MKCOL",
+            Method::Propfind => "PROPFIND",
+            Method::Move => "MOVE",
+            Method::Copy => "COPY",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Value of the Allow header
+pub fn allow_header(methods: &[Method]) -> String {
+    methods.iter().map(|m| m.as_str()).collect::<Vec<_>>().join(", ")
+}
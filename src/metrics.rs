@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use crate::webhook;
+
+static ACTIVE: AtomicU64 = AtomicU64::new(0);
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+// Connections taken by each acceptor, --reuseport shows how evenly they are spread
+static ACCEPTED: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+// Counts a connection as active for as long as it lives. Dropping it is the only
+// way to count it out, so an error, an early return or a panic can't leak one
+pub struct ConnectionGuard(());
+
+impl ConnectionGuard {
+    pub fn enter() -> ConnectionGuard {
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+        TOTAL.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn active() -> u64 {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn total() -> u64 {
+    TOTAL.load(Ordering::Relaxed)
+}
+
+// Another complete download of the single file, returns how many there were
+pub fn download_completed() -> u64 {
+    DOWNLOADS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// Another connection for the acceptor, returns how many it has taken
+pub fn accepted(acceptor: usize) -> u64 {
+    let mut accepted = ACCEPTED.lock().unwrap();
+    if accepted.len() <= acceptor {
+        accepted.resize(acceptor + 1, 0);
+    }
+    accepted[acceptor] += 1;
+    accepted[acceptor]
+}
+
+// The Prometheus text format, for --metrics
+pub fn render() -> String {
+    let mut out = String::new();
+    out.push_str("# HELP httpserver_connections_active Connections being handled right now.\n");
+    out.push_str("# TYPE httpserver_connections_active gauge\n");
+    out.push_str(&format!("httpserver_connections_active {}\n", active()));
+    out.push_str("# HELP httpserver_connections_total Connections handled since the start.\n");
+    out.push_str("# TYPE httpserver_connections_total counter\n");
+    out.push_str(&format!("httpserver_connections_total {}\n", total()));
+    out.push_str("# HELP httpserver_acceptor_connections_total Connections taken by each acceptor.\n");
+    out.push_str("# TYPE httpserver_acceptor_connections_total counter\n");
+    for (acceptor, count) in ACCEPTED.lock().unwrap().iter().enumerate() {
+        out.push_str(&format!("httpserver_acceptor_connections_total{{acceptor=\"{acceptor}\"}} {count}\n"));
+    }
+    out.push_str("# HELP httpserver_webhook_failed_total Webhook events given up on after every retry.\n");
+    out.push_str("# TYPE httpserver_webhook_failed_total counter\n");
+    out.push_str(&format!("httpserver_webhook_failed_total {}\n", webhook::failed()));
+    out.push_str("# HELP httpserver_webhook_dropped_total Webhook events dropped with the queue full.\n");
+    out.push_str("# TYPE httpserver_webhook_dropped_total counter\n");
+    out.push_str(&format!("httpserver_webhook_dropped_total {}\n", webhook::dropped()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acceptors_are_counted_apart() {
+        let before = accepted(2);
+        assert_eq!(accepted(2), before + 1);
+        // The ones in between show up as well, with what they took
+        let rendered = render();
+        assert!(rendered.contains(&format!("httpserver_acceptor_connections_total{{acceptor=\"2\"}} {}\n", before + 1)), "{rendered}");
+        assert!(rendered.contains("httpserver_acceptor_connections_total{acceptor=\"0\"}"));
+        assert!(rendered.contains("# TYPE httpserver_acceptor_connections_total counter\n"));
+    }
+
+    // The only test making guards, the counts are its own
+    #[test]
+    fn guards_count_out_on_every_exit() {
+        let (active_before, total_before) = (active(), total());
+        let guards: Vec<ConnectionGuard> = (0..3).map(|_| ConnectionGuard::enter()).collect();
+        assert_eq!(active(), active_before + 3);
+        drop(guards);
+        let panicked = std::thread::spawn(|| {
+            let _guard = ConnectionGuard::enter();
+            panic!("in the middle of a connection");
+        }).join();
+        assert!(panicked.is_err());
+        assert_eq!(active(), active_before);
+        assert_eq!(total(), total_before + 4);
+        assert!(render().contains(&format!("httpserver_connections_active {active_before}\n")));
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::headers::Headers;
+
+// Limits on the head of each part, they are tiny in practice
+const MAX_PART_HEAD: usize = 8 * 1024;
+const MAX_PART_HEADERS: usize = 16;
+
+// Boundaries are at most 70 characters (RFC 2046 5.1.1)
+const MAX_BOUNDARY_LEN: usize = 70;
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Body(io::Error),    // Reading the request body
+    Write(io::Error),   // Storing the data of a part
+    Malformed(String),
+    TooLarge,           // A part is bigger than allowed
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Body(err) => write!(f, "failed to read the body, {err}"),
+            MultipartError::Write(err) => write!(f, "failed to store a part, {err}"),
+            MultipartError::Malformed(what) => write!(f, "malformed multipart body: {what}"),
+            MultipartError::TooLarge => write!(f, "multipart part too large"),
+        }
+    }
+}
+
+fn malformed(what: &str) -> MultipartError {
+    MultipartError::Malformed(String::from(what))
+}
+
+// Splits `type; a=b; c="quoted; value"` into the type and its parameters, names lowercased.
+// Browsers percent-encode quotes in filenames instead of escaping them and send
+// backslashes as they are (Windows paths), so a backslash is not an escape here
+pub fn parse_params(value: &str) -> Option<(&str, Vec<(String, String)>)> {
+    let (kind, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            break;
+        }
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start_matches([' ', '\t']);
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, remaining) = quoted.split_once('"')?;
+                (String::from(value), remaining)
+            }
+            None => {
+                let (value, remaining) = after.split_once(';').unwrap_or((after, ""));
+                (String::from(value.trim()), remaining)
+            }
+        };
+        params.push((name.trim().to_ascii_lowercase(), value));
+        rest = remaining;
+    }
+    Some((kind.trim(), params))
+}
+
+// The boundary of a multipart/form-data Content-Type, None for anything else
+pub fn form_boundary(content_type: &str) -> Option<String> {
+    let (kind, params) = parse_params(content_type)?;
+    if !kind.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    let (_, boundary) = params.into_iter().find(|(name, _)| name == "boundary")?;
+    if boundary.is_empty() || boundary.len() > MAX_BOUNDARY_LEN {
+        return None;
+    }
+    Some(boundary)
+}
+
+// Reduce the filename a client sent to a plain name inside the target directory.
+// Some browsers send the whole path, so only the last component is kept,
+// None when nothing usable is left
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "." || name == ".." || name.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    // Device names on Windows, with any extension, can't be opened as files there
+    let stem = name.split('.').next().unwrap_or_default().trim_end().to_ascii_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4 && stem.as_bytes()[3].is_ascii_digit() && stem.as_bytes()[3] != b'0');
+    if reserved {
+        return None;
+    }
+    Some(String::from(name))
+}
+
+// One part of the body, its data follows with Multipart::read_data
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,  // As sent by the client, not sanitized
+}
+
+enum State {
+    Data,      // Inside the data of a part (or the preamble)
+    Boundary,  // Right after a delimiter
+    Done,
+}
+
+// Streaming parser of a multipart body (RFC 7578), only the current part's data
+// plus one delimiter worth of bytes is ever kept in memory
+pub struct Multipart<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,  // CRLF "--" boundary
+    state: State,
+}
+
+impl<R: AsyncRead + Unpin> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Multipart {
+            reader,
+            // The first delimiter has no CRLF in front of it, pretend it does
+            // so the preamble is skipped like the data of any part
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            state: State::Data,
+        }
+    }
+
+    // Read some more of the body, false at its end
+    async fn fill(&mut self) -> Result<bool, MultipartError> {
+        let mut chunk = [0u8; 8 * 1024];
+        let n = self.reader.read(&mut chunk).await.map_err(MultipartError::Body)?;
+        self.buffer.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    // Make sure at least n bytes are buffered
+    async fn fill_to(&mut self, n: usize) -> Result<(), MultipartError> {
+        while self.buffer.len() < n {
+            if !self.fill().await? {
+                return Err(malformed("missing final boundary"));
+            }
+        }
+        Ok(())
+    }
+
+    // Move to the next part, skipping whatever is left of the current one.
+    // None after the final boundary, the rest of the body has been read by then
+    pub async fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        if let State::Data = self.state {
+            self.read_data(&mut tokio::io::sink(), u64::MAX).await?;
+        }
+        if let State::Done = self.state {
+            return Ok(None);
+        }
+        // "--" after the delimiter closes the body, otherwise optional padding and CRLF
+        self.fill_to(2).await?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Done;
+            // The epilogue is meaningless, just get it off the connection
+            self.buffer.clear();
+            while self.fill().await? {
+                self.buffer.clear();
+            }
+            return Ok(None);
+        }
+        let head = self.read_head().await?;
+        let mut headers = Headers::new();
+        let mut lines = head.split("\r\n");
+        // Padding between the boundary and its CRLF
+        if !lines.next().unwrap_or_default().trim_matches([' ', '\t']).is_empty() {
+            return Err(malformed("garbage after boundary"));
+        }
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or_else(|| malformed("bad part header"))?;
+            headers.insert(name.trim(), value.trim_matches([' ', '\t']));
+        }
+        let disposition = headers.get("Content-Disposition").ok_or_else(|| malformed("part without Content-Disposition"))?;
+        let (kind, params) = parse_params(disposition).ok_or_else(|| malformed("bad Content-Disposition"))?;
+        if !kind.eq_ignore_ascii_case("form-data") {
+            return Err(malformed("part is not form-data"));
+        }
+        let param = |wanted: &str| params.iter().find(|(name, _)| name == wanted).map(|(_, value)| value.clone());
+        let (name, filename) = (param("name"), param("filename"));
+        self.state = State::Data;
+        Ok(Some(Part { name, filename }))
+    }
+
+    // Everything up to the empty line ending the part headers
+    async fn read_head(&mut self) -> Result<String, MultipartError> {
+        loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                let head = self.buffer.drain(..end + 4).collect::<Vec<_>>();
+                let head = String::from_utf8(head).map_err(|_| malformed("part headers are not utf-8"))?;
+                if head.matches("\r\n").count() > MAX_PART_HEADERS + 2 {
+                    return Err(malformed("too many part headers"));
+                }
+                return Ok(head);
+            }
+            if self.buffer.len() > MAX_PART_HEAD {
+                return Err(malformed("part headers too long"));
+            }
+            if !self.fill().await? {
+                return Err(malformed("missing final boundary"));
+            }
+        }
+    }
+
+    // Stream the data of the current part into out, failing once it's over limit bytes
+    pub async fn read_data(&mut self, out: &mut (impl AsyncWrite + Unpin), limit: u64) -> Result<u64, MultipartError> {
+        let mut total = 0u64;
+        loop {
+            // Everything before the delimiter, or before what may be the start of one
+            let (end, found) = match find(&self.buffer, &self.delimiter) {
+                Some(pos) => (pos, true),
+                None => (self.buffer.len().saturating_sub(self.delimiter.len() - 1), false),
+            };
+            total += end as u64;
+            if total > limit {
+                return Err(MultipartError::TooLarge);
+            }
+            out.write_all(&self.buffer[..end]).await.map_err(MultipartError::Write)?;
+            if found {
+                self.buffer.drain(..end + self.delimiter.len());
+                self.state = State::Boundary;
+                return Ok(total);
+            }
+            self.buffer.drain(..end);
+            if !self.fill().await? {
+                return Err(malformed("missing final boundary"));
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Parsed = Result<Vec<(Option<String>, Option<String>, Vec<u8>)>, MultipartError>;
+
+    // Every part with its name, filename and data, or the error of the body
+    async fn parse(body: &[u8], boundary: &str) -> Parsed {
+        parse_from(body, boundary).await
+    }
+
+    async fn parse_from(body: impl AsyncRead + Unpin, boundary: &str) -> Parsed {
+        let mut form = Multipart::new(body, boundary);
+        let mut parts = Vec::new();
+        while let Some(part) = form.next_part().await? {
+            let mut data = Vec::new();
+            form.read_data(&mut data, u64::MAX).await?;
+            parts.push((part.name, part.filename, data));
+        }
+        Ok(parts)
+    }
+
+    fn is_malformed(result: Parsed, what: &str) -> bool {
+        matches!(result, Err(MultipartError::Malformed(found)) if found == what)
+    }
+
+    #[test]
+    fn params_with_quotes_and_spaces() {
+        let (kind, params) = parse_params(r#"form-data; name="file"; FILENAME="a; b.txt""#).unwrap();
+        assert_eq!(kind, "form-data");
+        assert_eq!(params, [(String::from("name"), String::from("file")), (String::from("filename"), String::from("a; b.txt"))]);
+        // A backslash is kept, it's how Windows paths come in
+        let (_, params) = parse_params(r#"form-data; filename="C:\dir\a.txt""#).unwrap();
+        assert_eq!(params[0].1, r"C:\dir\a.txt");
+        let (kind, params) = parse_params("text/plain").unwrap();
+        assert_eq!((kind, params.len()), ("text/plain", 0));
+        assert!(parse_params(r#"form-data; name="open"#).is_none());
+        assert!(parse_params("form-data; name").is_none());
+    }
+
+    #[test]
+    fn boundary_of_the_content_type() {
+        assert_eq!(form_boundary("multipart/form-data; boundary=abc").as_deref(), Some("abc"));
+        assert_eq!(form_boundary(r#"Multipart/Form-Data; charset=utf-8; boundary="a b:c""#).as_deref(), Some("a b:c"));
+        assert_eq!(form_boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(form_boundary("multipart/form-data"), None);
+        assert_eq!(form_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(form_boundary(&format!("multipart/form-data; boundary={}", "x".repeat(70))).map(|b| b.len()), Some(70));
+        assert_eq!(form_boundary(&format!("multipart/form-data; boundary={}", "x".repeat(71))), None);
+    }
+
+    #[test]
+    fn filenames_are_cut_to_their_last_component() {
+        assert_eq!(sanitize_filename("report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize_filename("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_filename(r"C:\Users\me\photo.jpg").as_deref(), Some("photo.jpg"));
+        assert_eq!(sanitize_filename("  spaced.txt ").as_deref(), Some("spaced.txt"));
+        assert_eq!(sanitize_filename("COM10").as_deref(), Some("COM10"));
+        assert_eq!(sanitize_filename("console.log").as_deref(), Some("console.log"));
+        for refused in ["", " ", ".", "..", "dir/", "a/..", "tab\there", "CON", "nul.txt", "Aux .tar.gz", "com1", "LPT9.log"] {
+            assert_eq!(sanitize_filename(refused), None, "{refused:?}");
+        }
+        assert_eq!(sanitize_filename("COM0").as_deref(), Some("COM0"));
+    }
+
+    #[tokio::test]
+    async fn parts_of_a_browser_form() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a %22b%22; c.txt\"\r\nContent-Type: text/plain\r\n\r\n\
+            line one\r\nline two\r\n\r\n--XyZ\r\n\
+            content-disposition: form-data; name=\"file\"; filename=\"\"\r\n\r\n\r\n--XyZ--\r\nepilogue";
+        let parts = parse(body, "XyZ").await.unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!((parts[0].0.as_deref(), parts[0].1.as_deref(), parts[0].2.as_slice()), (Some("note"), None, &b"hi"[..]));
+        // Browsers percent-encode the quotes, what they send is the name as it is
+        assert_eq!(parts[1].1.as_deref(), Some("a %22b%22; c.txt"));
+        assert_eq!(parts[1].2, b"line one\r\nline two\r\n");
+        assert_eq!((parts[2].1.as_deref(), parts[2].2.len()), (Some(""), 0));
+    }
+
+    #[tokio::test]
+    async fn data_that_looks_like_the_boundary() {
+        // The boundary without a CRLF in front of it, or a CRLF with half of it, is data
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"x\"\r\n\r\n\
+            --b in the text\r\n-\r\n--\r\n-b\r\n--b--";
+        let parts = parse(body, "b").await.unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].2, b"--b in the text\r\n-\r\n--\r\n-b");
+        // The boundary is never in the data (RFC 2046 5.1.1), so a longer line that starts with it is no part
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\ndata\r\n--bb\r\n\r\n--b--";
+        assert!(is_malformed(parse(body, "b").await, "garbage after boundary"));
+    }
+
+    #[tokio::test]
+    async fn delimiter_split_across_reads() {
+        let data: Vec<u8> = (0..20000u32).map(|i| b"0123456789\r\n-"[i as usize % 13]).collect();
+        let mut body = b"--boundary\r\nContent-Disposition: form-data; name=\"f\"; filename=\"x.bin\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n--boundary\r\nContent-Disposition: form-data; name=\"g\"\r\n\r\nend\r\n--boundary--\r\n");
+        // Through a pipe that hands the body over a few bytes at a time
+        let (mut sender, receiver) = tokio::io::duplex(3);
+        let feed = body.clone();
+        let writer = tokio::spawn(async move { sender.write_all(&feed).await });
+        let parts = parse_from(receiver, "boundary").await.unwrap();
+        writer.await.unwrap().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].2, data);
+        assert_eq!(parts[1].2, b"end");
+    }
+
+    #[tokio::test]
+    async fn missing_final_boundary() {
+        let head = b"--b\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\n".to_vec();
+        assert!(is_malformed(parse(&head, "b").await, "missing final boundary"));
+        assert!(is_malformed(parse(&[head.as_slice(), b"data"].concat(), "b").await, "missing final boundary"));
+        assert!(is_malformed(parse(&[head.as_slice(), b"data\r\n--b"].concat(), "b").await, "missing final boundary"));
+        assert!(is_malformed(parse(b"--b\r\nContent-Disposition: form-data", "b").await, "missing final boundary"));
+        // Not even a first one
+        assert!(is_malformed(parse(b"just some text", "b").await, "missing final boundary"));
+        assert!(is_malformed(parse(b"", "b").await, "missing final boundary"));
+    }
+
+    #[tokio::test]
+    async fn malformed_part_heads() {
+        let with_head = |head: &str| format!("--b{head}\r\n\r\nx\r\n--b--").into_bytes();
+        assert!(is_malformed(parse(&with_head("\r\nContent-Type: text/plain"), "b").await, "part without Content-Disposition"));
+        assert!(is_malformed(parse(&with_head("\r\nContent-Disposition: attachment; name=\"a\""), "b").await, "part is not form-data"));
+        assert!(is_malformed(parse(&with_head("\r\nContent-Disposition: form-data; name=\"a"), "b").await, "bad Content-Disposition"));
+        assert!(is_malformed(parse(&with_head("\r\nno colon"), "b").await, "bad part header"));
+        assert!(is_malformed(parse(&with_head("junk\r\nContent-Disposition: form-data"), "b").await, "garbage after boundary"));
+        let many = "\r\nX-A: 1".repeat(MAX_PART_HEADERS + 1);
+        assert!(is_malformed(parse(&with_head(&format!("\r\nContent-Disposition: form-data{many}")), "b").await, "too many part headers"));
+        let long = format!("--b\r\nContent-Disposition: form-data; name=\"{}\"", "a".repeat(MAX_PART_HEAD + 1));
+        assert!(is_malformed(parse(long.as_bytes(), "b").await, "part headers too long"));
+        // Padding after the boundary is allowed
+        let parts = parse(&with_head(" \t\r\nContent-Disposition: form-data; name=\"a\""), "b").await.unwrap();
+        assert_eq!(parts[0].2, b"x");
+    }
+
+    #[tokio::test]
+    async fn part_over_its_limit() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"x\"\r\n\r\n0123456789\r\n--b--";
+        let mut form = Multipart::new(&body[..], "b");
+        form.next_part().await.unwrap().unwrap();
+        assert!(matches!(form.read_data(&mut Vec::new(), 9).await, Err(MultipartError::TooLarge)));
+        let mut form = Multipart::new(&body[..], "b");
+        form.next_part().await.unwrap().unwrap();
+        assert_eq!(form.read_data(&mut Vec::new(), 10).await.unwrap(), 10);
+        assert!(form.next_part().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,299 @@
+// --user, --group and --chroot: bind the low ports as root, then serve as somebody
+// who can't do much harm
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Privileges {
+    pub user: Option<String>,  // Name or uid, its group is taken unless --group
+    pub group: Option<String>,  // Name or gid
+    pub chroot: Option<PathBuf>,
+}
+
+impl Privileges {
+    pub fn is_set(&self) -> bool {
+        self.user.is_some() || self.group.is_some() || self.chroot.is_some()
+    }
+}
+
+// The uid and primary group of a user
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let name = std::ffi::CString::new(user).map_err(|_| format!("invalid user '{user}'"))?;
+    // SAFETY: the name is a valid C string, the entry is copied out before anything
+    // else could call getpwnam again
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !entry.is_null() {
+        // SAFETY: not null, so it points at the entry getpwnam found
+        return Ok(unsafe { ((*entry).pw_uid, (*entry).pw_gid) });
+    }
+    // A numeric uid without an entry, its group is the same number
+    match user.parse::<libc::uid_t>() {
+        Ok(uid) => Ok((uid, uid as libc::gid_t)),
+        Err(_) => Err(format!("no such user '{user}'")),
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    let name = std::ffi::CString::new(group).map_err(|_| format!("invalid group '{group}'"))?;
+    // SAFETY: as in lookup_user
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !entry.is_null() {
+        // SAFETY: not null, so it points at the entry getgrnam found
+        return Ok(unsafe { (*entry).gr_gid });
+    }
+    group.parse::<libc::gid_t>().map_err(|_| format!("no such group '{group}'"))
+}
+
+#[cfg(unix)]
+fn last_error(what: &str) -> String {
+    format!("{what} failed by {}", std::io::Error::last_os_error())
+}
+
+// For --check, what drop would run into short of doing it
+#[cfg(unix)]
+pub fn check(privileges: &Privileges) -> Result<(), String> {
+    if let Some(user) = &privileges.user {
+        lookup_user(user)?;
+    }
+    if let Some(group) = &privileges.group {
+        lookup_group(group)?;
+    }
+    match &privileges.chroot {
+        Some(dir) if !dir.is_dir() => Err(format!("can't chroot to {}, it is not a directory", dir.display())),
+        _ => Ok(()),
+    }
+}
+
+// The calls a drop is made of, apart from libc so their order can be checked
+// without being root
+#[cfg(unix)]
+trait System {
+    fn lookup_user(&self, user: &str) -> Result<(libc::uid_t, libc::gid_t), String>;
+    fn lookup_group(&self, group: &str) -> Result<libc::gid_t, String>;
+    fn chroot(&mut self, dir: &std::path::Path) -> Result<(), String>;  // And into its /
+    fn setgroups(&mut self, gid: libc::gid_t) -> Result<(), String>;
+    fn setgid(&mut self, gid: libc::gid_t) -> Result<(), String>;
+    fn setuid(&mut self, uid: libc::uid_t) -> Result<(), String>;
+    fn is_root(&self) -> bool;  // By any of the ids
+    fn regains_root(&mut self) -> bool;
+}
+
+#[cfg(unix)]
+struct Os;
+
+#[cfg(unix)]
+impl System for Os {
+    fn lookup_user(&self, user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+        lookup_user(user)
+    }
+
+    fn lookup_group(&self, group: &str) -> Result<libc::gid_t, String> {
+        lookup_group(group)
+    }
+
+    fn chroot(&mut self, dir: &std::path::Path) -> Result<(), String> {
+        let path = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes()).map_err(|_| format!("invalid chroot '{}'", dir.display()))?;
+        // SAFETY: both are valid C strings
+        if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+            return Err(last_error(&format!("chroot to {}", dir.display())));
+        }
+        // SAFETY: as above
+        if unsafe { libc::chdir(c"/".as_ptr()) } != 0 {
+            return Err(last_error("chdir into the chroot"));
+        }
+        Ok(())
+    }
+
+    fn setgroups(&mut self, gid: libc::gid_t) -> Result<(), String> {
+        // SAFETY: one gid, read from a valid pointer
+        if unsafe { libc::setgroups(1, &gid) } != 0 {
+            return Err(last_error("setgroups"));
+        }
+        Ok(())
+    }
+
+    fn setgid(&mut self, gid: libc::gid_t) -> Result<(), String> {
+        // SAFETY: plain syscalls, glibc and musl apply them to every thread
+        if unsafe { libc::setgid(gid) } != 0 {
+            return Err(last_error(&format!("setgid to {gid}")));
+        }
+        Ok(())
+    }
+
+    fn setuid(&mut self, uid: libc::uid_t) -> Result<(), String> {
+        // SAFETY: as above
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(last_error(&format!("setuid to {uid}")));
+        }
+        Ok(())
+    }
+
+    fn is_root(&self) -> bool {
+        // SAFETY: these only read the ids of the process
+        unsafe { libc::getuid() == 0 || libc::geteuid() == 0 || libc::getegid() == 0 }
+    }
+
+    fn regains_root(&mut self) -> bool {
+        // SAFETY: a plain syscall, it has to fail
+        unsafe { libc::setuid(0) == 0 }
+    }
+}
+
+// Everything that needs root has to be done by now: the listeners are bound. The
+// names are looked up first, the chroot likely has no /etc/passwd. Groups go before
+// the user, once it isn't root it can't change them any more. Roots are paths
+// inside the chroot from here on
+#[cfg(unix)]
+pub fn drop(privileges: &Privileges) -> Result<(), String> {
+    drop_with(&mut Os, privileges)
+}
+
+#[cfg(unix)]
+fn drop_with(system: &mut impl System, privileges: &Privileges) -> Result<(), String> {
+    let Some(user) = &privileges.user else {
+        return Ok(());
+    };
+    let (uid, mut gid) = system.lookup_user(user)?;
+    if let Some(group) = &privileges.group {
+        gid = system.lookup_group(group)?;
+    }
+    if let Some(dir) = &privileges.chroot {
+        system.chroot(dir)?;
+    }
+    // Supplementary groups of root, like disk or shadow, must not come along
+    system.setgroups(gid)?;
+    system.setgid(gid)?;
+    system.setuid(uid)?;
+    if system.is_root() {
+        return Err(String::from("still running as root, pick a user other than root"));
+    }
+    // A drop that can be undone is no drop
+    if system.regains_root() {
+        return Err(String::from("root could be regained after the drop"));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Notes every call, fails the one it is told to, and ends up root if told so
+    #[derive(Default)]
+    struct Recorded {
+        calls: Vec<String>,
+        failing: Option<&'static str>,
+        stays_root: bool,
+        regains: bool,
+    }
+
+    impl Recorded {
+        fn call(&mut self, name: &'static str, call: String) -> Result<(), String> {
+            self.calls.push(call);
+            match self.failing == Some(name) {
+                true => Err(format!("{name} failed")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl System for Recorded {
+        fn lookup_user(&self, user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+            match user {
+                "www" => Ok((33, 33)),
+                _ => Err(format!("no such user '{user}'")),
+            }
+        }
+
+        fn lookup_group(&self, group: &str) -> Result<libc::gid_t, String> {
+            match group {
+                "web" => Ok(44),
+                _ => Err(format!("no such group '{group}'")),
+            }
+        }
+
+        fn chroot(&mut self, dir: &std::path::Path) -> Result<(), String> {
+            self.call("chroot", format!("chroot {}", dir.display()))
+        }
+
+        fn setgroups(&mut self, gid: libc::gid_t) -> Result<(), String> {
+            self.call("setgroups", format!("setgroups {gid}"))
+        }
+
+        fn setgid(&mut self, gid: libc::gid_t) -> Result<(), String> {
+            self.call("setgid", format!("setgid {gid}"))
+        }
+
+        fn setuid(&mut self, uid: libc::uid_t) -> Result<(), String> {
+            self.call("setuid", format!("setuid {uid}"))
+        }
+
+        fn is_root(&self) -> bool {
+            self.stays_root
+        }
+
+        fn regains_root(&mut self) -> bool {
+            self.calls.push(String::from("setuid 0"));
+            self.regains
+        }
+    }
+
+    fn privileges(user: &str, group: Option<&str>, chroot: Option<&str>) -> Privileges {
+        Privileges { user: Some(String::from(user)), group: group.map(String::from), chroot: chroot.map(PathBuf::from) }
+    }
+
+    #[test]
+    fn chroot_then_groups_then_the_user() {
+        let mut system = Recorded::default();
+        drop_with(&mut system, &privileges("www", Some("web"), Some("/srv/jail"))).unwrap();
+        assert_eq!(system.calls, ["chroot /srv/jail", "setgroups 44", "setgid 44", "setuid 33", "setuid 0"]);
+        // The primary group of the user without --group
+        let mut system = Recorded::default();
+        drop_with(&mut system, &privileges("www", None, None)).unwrap();
+        assert_eq!(system.calls, ["setgroups 33", "setgid 33", "setuid 33", "setuid 0"]);
+    }
+
+    #[test]
+    fn names_are_looked_up_before_anything_changes() {
+        for privileges in [privileges("nobody-here", None, Some("/srv/jail")), privileges("www", Some("nothing"), Some("/srv/jail"))] {
+            let mut system = Recorded::default();
+            assert!(drop_with(&mut system, &privileges).unwrap_err().starts_with("no such"));
+            assert!(system.calls.is_empty(), "{:?}", system.calls);
+        }
+    }
+
+    #[test]
+    fn a_failed_call_stops_the_drop() {
+        for (failing, done) in [("chroot", 1), ("setgroups", 2), ("setgid", 3), ("setuid", 4)] {
+            let mut system = Recorded { failing: Some(failing), ..Recorded::default() };
+            assert_eq!(drop_with(&mut system, &privileges("www", None, Some("/jail"))), Err(format!("{failing} failed")));
+            assert_eq!(system.calls.len(), done, "{failing}: {:?}", system.calls);
+        }
+    }
+
+    #[test]
+    fn still_root_or_regaining_it_is_refused() {
+        let mut system = Recorded { stays_root: true, ..Recorded::default() };
+        assert!(drop_with(&mut system, &privileges("www", None, None)).unwrap_err().contains("still running as root"));
+        let mut system = Recorded { regains: true, ..Recorded::default() };
+        assert_eq!(drop_with(&mut system, &privileges("www", None, None)), Err(String::from("root could be regained after the drop")));
+    }
+
+    #[test]
+    fn nothing_to_drop_without_a_user() {
+        let mut system = Recorded::default();
+        assert_eq!(drop_with(&mut system, &Privileges::default()), Ok(()));
+        assert!(system.calls.is_empty());
+        assert!(!Privileges::default().is_set());
+    }
+
+    #[test]
+    fn numeric_ids_without_an_entry() {
+        assert_eq!(lookup_user("4242"), Ok((4242, 4242)));
+        assert_eq!(lookup_group("4343"), Ok(4343));
+        assert_eq!(lookup_user("no-such-user-here"), Err(String::from("no such user 'no-such-user-here'")));
+        assert_eq!(lookup_user("root").map(|(uid, _)| uid), Ok(0));
+        assert!(check(&Privileges { chroot: Some(PathBuf::from("/no/such/dir")), ..Privileges::default() }).unwrap_err().contains("not a directory"));
+    }
+}
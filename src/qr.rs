@@ -0,0 +1,586 @@
+//! QR codes for short texts like urls.
+//!
+//! Byte mode with error correction level M, versions 1 to 10, which holds up to
+//! 213 bytes. The mask is picked by the penalty rules of ISO/IEC 18004 like any
+//! encoder would, so the codes scan everywhere.
+
+use std::fmt;
+
+/// Error correction codewords per block and the blocks as (count, data codewords),
+/// for level M of versions 1 to 10
+const BLOCKS: [(usize, [(usize, usize); 2]); 10] = [
+    (10, [(1, 16), (0, 0)]),
+    (16, [(1, 28), (0, 0)]),
+    (26, [(1, 44), (0, 0)]),
+    (18, [(2, 32), (0, 0)]),
+    (24, [(2, 43), (0, 0)]),
+    (16, [(4, 27), (0, 0)]),
+    (18, [(4, 31), (0, 0)]),
+    (22, [(2, 38), (2, 39)]),
+    (22, [(3, 36), (2, 37)]),
+    (26, [(4, 43), (1, 44)]),
+];
+
+/// Centers of the alignment patterns in both directions, version 1 has none
+const ALIGNMENT: [&[usize]; 10] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// The text doesn't fit in the largest version supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooLong {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for TooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes don't fit in a QR code, {} at most", self.len, self.max)
+    }
+}
+
+/// A square of dark and light modules, without the quiet zone around it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,   // Row by row, true is dark
+    function: Vec<bool>,  // Patterns and format areas, the data goes around them
+}
+
+fn data_codewords(version: usize) -> usize {
+    let (_, groups) = BLOCKS[version - 1];
+    groups.iter().map(|(count, len)| count * len).sum()
+}
+
+// Bits of the length field in byte mode
+fn count_bits(version: usize) -> usize {
+    if version <= 9 { 8 } else { 16 }
+}
+
+fn capacity(version: usize) -> usize {
+    (data_codewords(version) * 8 - 4 - count_bits(version)) / 8
+}
+
+// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+    z as u8
+}
+
+// Coefficients of the Reed-Solomon generator polynomial, highest first without the leading 1
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root: u8 = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+/// The error correction codewords of a block
+pub fn rs_remainder(data: &[u8], degree: usize) -> Vec<u8> {
+    let divisor = rs_divisor(degree);
+    let mut result = vec![0u8; degree];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (value, coefficient) in result.iter_mut().zip(&divisor) {
+            *value ^= gf_multiply(*coefficient, factor);
+        }
+    }
+    result
+}
+
+/// The data codewords of a text, with the mode, the length and the padding
+pub fn encode_data(text: &[u8], version: usize) -> Vec<u8> {
+    let mut bits: Vec<bool> = Vec::new();
+    let mut push = |value: usize, len: usize| {
+        for i in (0..len).rev() {
+            bits.push((value >> i) & 1 == 1);
+        }
+    };
+    push(0b0100, 4);
+    push(text.len(), count_bits(version));
+    for &byte in text {
+        push(byte as usize, 8);
+    }
+    let capacity = data_codewords(version) * 8;
+    let terminator = (capacity - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+    let mut codewords: Vec<u8> = bits.chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit as u8))
+        .collect();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() == data_codewords(version) {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+/// The data split into blocks, each with its error correction, interleaved
+pub fn interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let (ecc_len, groups) = BLOCKS[version - 1];
+    let mut blocks: Vec<&[u8]> = Vec::new();
+    let mut rest = data;
+    for (count, len) in groups {
+        for _ in 0..count {
+            let (block, after) = rest.split_at(len);
+            blocks.push(block);
+            rest = after;
+        }
+    }
+    let eccs: Vec<Vec<u8>> = blocks.iter().map(|block| rs_remainder(block, ecc_len)).collect();
+    let longest = blocks.iter().map(|block| block.len()).max().unwrap_or(0);
+    let mut out = Vec::new();
+    for i in 0..longest {
+        out.extend(blocks.iter().filter_map(|block| block.get(i)));
+    }
+    for i in 0..ecc_len {
+        out.extend(eccs.iter().map(|ecc| ecc[i]));
+    }
+    out
+}
+
+fn masked(mask: u8, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y).is_multiple_of(2),
+        1 => y.is_multiple_of(2),
+        2 => x.is_multiple_of(3),
+        3 => (x + y).is_multiple_of(3),
+        4 => (x / 3 + y / 2).is_multiple_of(2),
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+        _ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+    }
+}
+
+impl QrCode {
+    /// The smallest code holding the text
+    pub fn encode(text: &[u8]) -> Result<QrCode, TooLong> {
+        let version = (1..=BLOCKS.len()).find(|&version| text.len() <= capacity(version))
+            .ok_or(TooLong { len: text.len(), max: capacity(BLOCKS.len()) })?;
+        let size = version * 4 + 17;
+        let mut code = QrCode { version, size, modules: vec![false; size * size], function: vec![false; size * size] };
+        code.draw_patterns();
+        let codewords = interleave(&encode_data(text, version), version);
+        code.draw_codewords(&codewords);
+        let best = (0..8).min_by_key(|&mask| {
+            code.apply_mask(mask);
+            code.draw_format(mask);
+            let penalty = code.penalty();
+            code.apply_mask(mask);
+            penalty
+        }).unwrap_or(0);
+        code.apply_mask(best);
+        code.draw_format(best);
+        Ok(code)
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Modules per side
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column x and row y is dark
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i.is_multiple_of(2));
+            self.set_function(i, 6, i.is_multiple_of(2));
+        }
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+        let centers = ALIGNMENT[self.version - 1];
+        let last = centers.len().saturating_sub(1);
+        for (i, &cx) in centers.iter().enumerate() {
+            for (j, &cy) in centers.iter().enumerate() {
+                // The corners are taken by the finder patterns
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function((cx as i32 + dx) as usize, (cy as i32 + dy) as usize, dark);
+                    }
+                }
+            }
+        }
+        // Reserved now, filled in with the mask
+        self.draw_format(0);
+        if self.version >= 7 {
+            let mut rem = self.version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+            }
+            let bits = (self.version as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    // Level M is 00, then the mask, with its BCH code, twice
+    fn draw_format(&mut self, mask: u8) {
+        let data = mask as u32;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = (data << 10 | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    // Up and down two columns at a time from the right, skipping the timing column
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    // Applying it twice undoes it
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if !self.function[y * self.size + x] && masked(mask, x, y) {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // Lower is easier to scan: long runs, 2x2 blocks, things looking like finder
+    // patterns and an uneven share of dark modules all count against a mask
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = |horizontal: bool| (0..size).map(move |a| (0..size).map(move |b| if horizontal { (b, a) } else { (a, b) }));
+        for horizontal in [true, false] {
+            for line in lines(horizontal) {
+                let line: Vec<bool> = line.map(|(x, y)| self.get(x, y)).collect();
+                let mut run = 1;
+                for i in 1..=size {
+                    if i < size && line[i] == line[i - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+                let pattern = [true, false, true, true, true, false, true];
+                for i in 0..size.saturating_sub(6) {
+                    if line[i..i + 7] != pattern {
+                        continue;
+                    }
+                    let light = |range: std::ops::Range<usize>| range.filter(|&j| j < size).all(|j| !line[j]);
+                    let before = i >= 4 && light(i - 4..i);
+                    let after = i + 11 <= size && light(i + 7..i + 11);
+                    if before || after {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if dark == self.get(x + 1, y) && dark == self.get(x, y + 1) && dark == self.get(x + 1, y + 1) {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let percent = dark * 100 / (size * size);
+        penalty + percent.abs_diff(50) / 5 * 10
+    }
+
+    /// Two rows per line with half blocks and a quiet zone of 4 modules. Light
+    /// modules are drawn, the way it looks right on a dark terminal
+    pub fn to_unicode(&self) -> String {
+        let quiet = 4;
+        let total = self.size + 2 * quiet;
+        let light = |x: usize, y: usize| {
+            let inside = (quiet..quiet + self.size).contains(&x) && (quiet..quiet + self.size).contains(&y);
+            !inside || !self.get(x - quiet, y - quiet)
+        };
+        let mut out = String::new();
+        for y in (0..total).step_by(2) {
+            for x in 0..total {
+                let top = light(x, y);
+                let bottom = y + 1 < total && light(x, y + 1);
+                out.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The format bits of level M by mask, from the table of the standard
+    const FORMAT_M: [u32; 8] = [
+        0b101010000010010,
+        0b101000100100101,
+        0b101111001111100,
+        0b101101101001011,
+        0b100010111111001,
+        0b100000011001110,
+        0b100111110010111,
+        0b100101010100000,
+    ];
+
+    fn format_bits(code: &QrCode) -> (u32, u32) {
+        let size = code.size();
+        let mut first: Vec<(usize, usize)> = (0..=5).map(|i| (8, i)).collect();
+        first.extend([(8, 7), (8, 8), (7, 8)]);
+        first.extend((9..15).map(|i| (14 - i, 8)));
+        let mut second: Vec<(usize, usize)> = (0..8).map(|i| (size - 1 - i, 8)).collect();
+        second.extend((8..15).map(|i| (8, size - 15 + i)));
+        let read = |cells: Vec<(usize, usize)>| cells.iter().enumerate().fold(0, |bits, (i, &(x, y))| bits | (code.get(x, y) as u32) << i);
+        (read(first), read(second))
+    }
+
+    // The codewords back out of the modules, the way a scanner reads them
+    fn read_codewords(code: &QrCode, mask: u8) -> Vec<u8> {
+        let mut code = code.clone();
+        code.apply_mask(mask);
+        let size = code.size();
+        let mut bits = Vec::new();
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward { size - 1 - vertical } else { vertical };
+                for x in [right, right - 1] {
+                    if !code.function[y * size + x] {
+                        bits.push(code.get(x, y));
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+        bits.chunks_exact(8).map(|byte| byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8)).collect()
+    }
+
+    #[test]
+    fn galois_field() {
+        assert_eq!(gf_multiply(0x02, 0x80), 0x1d);
+        assert_eq!(gf_multiply(0x53, 0x01), 0x53);
+        assert_eq!(gf_multiply(0x00, 0xff), 0x00);
+        // x^8 = x^4 + x^3 + x^2 + 1, so x^8 * x is 0x3a
+        assert_eq!(gf_multiply(0x1d, 0x02), 0x3a);
+    }
+
+    #[test]
+    fn error_correction_of_a_known_block() {
+        // HELLO WORLD as version 1-M, the example everyone checks against
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(rs_remainder(&data, 10), [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn byte_mode_codewords() {
+        // 0100, length 2, a, b, terminator, then the pad bytes
+        let mut expected = vec![0x40, 0x26, 0x16, 0x20];
+        expected.extend([0xec, 0x11].iter().cycle().take(12));
+        assert_eq!(encode_data(b"ab", 1), expected);
+        // 16 length bits from version 10 on
+        assert_eq!(encode_data(b"a", 10)[..4], [0x40, 0x00, 0x16, 0x10]);
+        assert_eq!(encode_data(b"a", 10).len(), 216);
+    }
+
+    #[test]
+    fn capacity_of_the_versions() {
+        assert_eq!((capacity(1), capacity(2), capacity(10)), (14, 26, 213));
+        assert_eq!(QrCode::encode(&[b'a'; 14]).unwrap().version(), 1);
+        assert_eq!(QrCode::encode(&[b'a'; 15]).unwrap().version(), 2);
+        assert_eq!(QrCode::encode(&[b'a'; 213]).unwrap().size(), 57);
+        let err = QrCode::encode(&[b'a'; 214]).unwrap_err();
+        assert_eq!(err, TooLong { len: 214, max: 213 });
+        assert_eq!(err.to_string(), "214 bytes don't fit in a QR code, 213 at most");
+    }
+
+    #[test]
+    fn blocks_are_interleaved() {
+        // Version 5-M is two blocks of 43 with 24 error correction codewords each
+        let data: Vec<u8> = (0..86).collect();
+        let out = interleave(&data, 5);
+        assert_eq!(out.len(), 86 + 48);
+        assert_eq!(out[..6], [0, 43, 1, 44, 2, 45]);
+        assert_eq!(out[86..88], [rs_remainder(&data[..43], 24)[0], rs_remainder(&data[43..], 24)[0]]);
+        // Version 8 has blocks of 38 and 39, the longer ones end it
+        let data: Vec<u8> = (0..154).collect();
+        let out = interleave(&data, 8);
+        assert_eq!(out[152..154], [114, 153]);
+    }
+
+    #[test]
+    fn patterns_and_format_are_in_place() {
+        let code = QrCode::encode(b"http://192.168.1.5:49231/").unwrap();
+        let size = code.size();
+        assert_eq!((code.version(), size), (2, 25));
+        for (x, y) in [(0, 0), (size - 7, 0), (0, size - 7)] {
+            assert!(code.get(x, y) && code.get(x + 6, y + 6) && !code.get(x + 1, y + 1) && code.get(x + 3, y + 3));
+        }
+        for i in 8..size - 8 {
+            assert_eq!(code.get(i, 6), i % 2 == 0);
+            assert_eq!(code.get(6, i), i % 2 == 0);
+        }
+        assert!(code.get(8, size - 8));
+        let (first, second) = format_bits(&code);
+        assert_eq!(first, second);
+        let mask = FORMAT_M.iter().position(|&bits| bits == first).expect("format bits of level M");
+        // With the mask taken off, the codewords are the ones that were encoded
+        let expected = interleave(&encode_data(b"http://192.168.1.5:49231/", 2), 2);
+        assert_eq!(read_codewords(&code, mask as u8), expected);
+    }
+
+    #[test]
+    fn format_bits_of_every_mask() {
+        let mut code = QrCode::encode(b"x").unwrap();
+        for (mask, &bits) in FORMAT_M.iter().enumerate() {
+            code.draw_format(mask as u8);
+            assert_eq!(format_bits(&code), (bits, bits), "mask {mask}");
+        }
+    }
+
+    #[test]
+    fn version_information_from_7_on() {
+        let code = QrCode::encode(&[b'a'; 120]).unwrap();
+        assert_eq!(code.version(), 7);
+        let size = code.size();
+        let read = |swap: bool| (0..18).fold(0u32, |bits, i| {
+            let (a, b) = (size - 11 + i % 3, i / 3);
+            let (x, y) = if swap { (b, a) } else { (a, b) };
+            bits | (code.get(x, y) as u32) << i
+        });
+        assert_eq!((read(false), read(true)), (0x07c94, 0x07c94));
+        let expected = interleave(&encode_data(&[b'a'; 120], 7), 7);
+        let (first, _) = format_bits(&code);
+        let mask = FORMAT_M.iter().position(|&bits| bits == first).unwrap();
+        assert_eq!(read_codewords(&code, mask as u8), expected);
+    }
+
+    #[test]
+    fn same_text_same_code() {
+        assert_eq!(QrCode::encode(b"http://10.0.0.2:8000/").unwrap(), QrCode::encode(b"http://10.0.0.2:8000/").unwrap());
+        assert_ne!(QrCode::encode(b"http://10.0.0.2:8000/").unwrap(), QrCode::encode(b"http://10.0.0.2:8001/").unwrap());
+    }
+
+    #[test]
+    fn unicode_has_two_rows_a_line_and_the_quiet_zone() {
+        let code = QrCode::encode(b"a").unwrap();
+        let text = code.to_unicode();
+        let lines: Vec<&str> = text.lines().collect();
+        // 21 modules and 4 on each side are 29, in 15 lines
+        assert_eq!(lines.len(), 15);
+        assert!(lines.iter().all(|line| line.chars().count() == 29));
+        assert!(lines[0].chars().all(|c| c == '█') && lines[1].chars().all(|c| c == '█'));
+        // Dark is left blank: the finder's corner is dark in both rows, next to it only above
+        assert_eq!(lines[2].chars().nth(4), Some(' '));
+        assert_eq!(lines[2].chars().nth(5), Some('▄'));
+    }
+}
@@ -0,0 +1,108 @@
+//! Query strings parsed into an ordered multimap.
+
+use crate::url::{self, DecodeError};
+
+/// Keys and values of a query string, in the order they were given.
+///
+/// A key may appear more than once, [`QueryMap::get`] gives the first value and
+/// [`QueryMap::get_all`] all of them. A key without `=` is present with an empty value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryMap {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryMap {
+    pub fn new() -> Self {
+        QueryMap { pairs: Vec::new() }
+    }
+
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.pairs.push((String::from(key), String::from(value)));
+    }
+
+    /// The first value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `key`, in order
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Parses a query string (without the `?`).
+///
+/// It is split on `&` and `=` first and every key and value decoded afterwards,
+/// so `%26` and `%3D` stay inside their value. Empty segments (`a=1&&b=2`) are skipped.
+pub fn parse(s: &str) -> Result<QueryMap, DecodeError> {
+    let mut map = QueryMap::new();
+    for pair in s.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        map.push(&url::decode_query(key)?, &url::decode_query(value)?);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_keep_their_order_and_repeats() {
+        let map = parse("b=2&a=1&b=3").unwrap();
+        assert_eq!(map.iter().collect::<Vec<_>>(), [("b", "2"), ("a", "1"), ("b", "3")]);
+        assert_eq!(map.get("b"), Some("2"));
+        assert_eq!(map.get_all("b").collect::<Vec<_>>(), ["2", "3"]);
+        assert_eq!(map.get("c"), None);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn key_without_a_value_is_present_and_empty() {
+        let map = parse("download&x=").unwrap();
+        assert!(map.contains("download"));
+        assert_eq!(map.get("download"), Some(""));
+        assert_eq!(map.get("x"), Some(""));
+    }
+
+    #[test]
+    fn split_comes_before_decoding() {
+        let map = parse("q=a%26b%3Dc&name=x+y").unwrap();
+        assert_eq!(map.get("q"), Some("a&b=c"));
+        assert_eq!(map.get("name"), Some("x y"));
+        assert_eq!(map.len(), 2);
+        // Only the first '=' separates
+        assert_eq!(parse("a=b=c").unwrap().get("a"), Some("b=c"));
+    }
+
+    #[test]
+    fn empty_segments_are_skipped() {
+        assert_eq!(parse("a=1&&b=2&").unwrap().len(), 2);
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("&&").unwrap().is_empty());
+    }
+
+    #[test]
+    fn broken_escape_fails_the_whole_query() {
+        assert_eq!(parse("a=1&b=%zz"), Err(DecodeError::BadHex(String::from("zz"))));
+        assert_eq!(parse("a%=1"), Err(DecodeError::Truncated));
+        assert_eq!(parse("a=%ff"), Err(DecodeError::InvalidUtf8));
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::SystemTime;
+use httpserver::date;
+use crate::headers::Headers;
+
+// A satisfiable byte range of a representation, end is inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{total}", self.start, self.end)
+    }
+}
+
+// What a Range header asks of a representation of len bytes (RFC 7233 2.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ranges {
+    Ignored,  // Malformed, another unit or several ranges, the full representation goes out
+    Satisfiable(ByteRange),
+    Unsatisfiable,
+}
+
+fn number(s: &str) -> Option<u64> {
+    s.bytes().all(|b| b.is_ascii_digit()).then(|| s.parse().ok()).flatten()
+}
+
+// Only a single range is served, several would need multipart/byteranges and a
+// server may always answer with everything instead
+pub fn parse(value: &str, len: u64) -> Ranges {
+    let Some(set) = value.trim().strip_prefix("bytes=") else {
+        return Ranges::Ignored;
+    };
+    if set.contains(',') {
+        return Ranges::Ignored;
+    }
+    let Some((first, last)) = set.trim().split_once('-') else {
+        return Ranges::Ignored;
+    };
+    let range = match (first, last) {
+        // The final bytes, a suffix longer than the file is all of it
+        ("", suffix) => match number(suffix) {
+            Some(0) => return Ranges::Unsatisfiable,
+            Some(suffix) if len > 0 => ByteRange { start: len.saturating_sub(suffix), end: len - 1 },
+            Some(_) => return Ranges::Unsatisfiable,
+            None => return Ranges::Ignored,
+        },
+        (first, "") => match number(first) {
+            Some(start) if start < len => ByteRange { start, end: len - 1 },
+            Some(_) => return Ranges::Unsatisfiable,
+            None => return Ranges::Ignored,
+        },
+        (first, last) => match (number(first), number(last)) {
+            (Some(start), Some(end)) if start > end => return Ranges::Ignored,
+            (Some(start), Some(_)) if start >= len => return Ranges::Unsatisfiable,
+            (Some(start), Some(end)) => ByteRange { start, end: end.min(len - 1) },
+            _ => return Ranges::Ignored,
+        },
+    };
+    Ranges::Satisfiable(range)
+}
+
+// Whether the Range still applies (RFC 7233 3.2). If-Range carries either a strong
+// ETag or the Last-Modified date, when it doesn't match the representation changed
+// since the client got its part and it has to start over with all of it
+pub fn if_range_matches(headers: &Headers, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(value) = headers.get("If-Range") else {
+        return true;
+    };
+    if value.starts_with('"') {
+        return value == etag;
+    }
+    if value.starts_with("W/") {
+        return false;
+    }
+    modified.is_some_and(|modified| date::http_date(modified) == value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn bytes(start: u64, end: u64) -> Ranges {
+        Ranges::Satisfiable(ByteRange { start, end })
+    }
+
+    #[test]
+    fn each_form_of_a_single_range() {
+        assert_eq!(parse("bytes=0-9", 100), bytes(0, 9));
+        assert_eq!(parse("bytes=90-", 100), bytes(90, 99));
+        assert_eq!(parse("bytes=-10", 100), bytes(90, 99));
+        // Past the end is cut to it, a suffix longer than the file is all of it
+        assert_eq!(parse("bytes=50-1000", 100), bytes(50, 99));
+        assert_eq!(parse("bytes=-1000", 100), bytes(0, 99));
+        assert_eq!(parse(" bytes= 5-5 ", 100), bytes(5, 5));
+        assert_eq!(ByteRange { start: 0, end: 9 }.content_range(100), "bytes 0-9/100");
+    }
+
+    #[test]
+    fn ranges_outside_the_file_are_unsatisfiable() {
+        assert_eq!(parse("bytes=100-", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=100-200", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-5", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn anything_else_gets_the_whole_file() {
+        for value in ["bytes=9-0", "bytes=0-9,20-29", "items=0-9", "bytes=", "bytes=-", "bytes=a-9", "bytes=+1-9", "bytes=0x1-9", "bytes=1", "bytes 0-9", "bytes=99999999999999999999-"] {
+            assert_eq!(parse(value, 100), Ranges::Ignored, "{value}");
+        }
+    }
+
+    #[test]
+    fn if_range_takes_a_strong_etag_or_the_date() {
+        let modified = UNIX_EPOCH + Duration::from_secs(784111777);
+        let headers = |value: &str| {
+            let mut headers = Headers::new();
+            headers.insert("If-Range", value);
+            headers
+        };
+        assert!(if_range_matches(&Headers::new(), "\"v1\"", None));
+        assert!(if_range_matches(&headers("\"v1\""), "\"v1\"", None));
+        assert!(!if_range_matches(&headers("\"v0\""), "\"v1\"", None));
+        // A weak one never matches for a range
+        assert!(!if_range_matches(&headers("W/\"v1\""), "W/\"v1\"", None));
+        assert!(if_range_matches(&headers("Sun, 06 Nov 1994 08:49:37 GMT"), "\"v1\"", Some(modified)));
+        assert!(!if_range_matches(&headers("Sun, 06 Nov 1994 08:49:38 GMT"), "\"v1\"", Some(modified)));
+        assert!(!if_range_matches(&headers("Sun, 06 Nov 1994 08:49:37 GMT"), "\"v1\"", None));
+    }
+}
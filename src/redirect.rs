@@ -0,0 +1,218 @@
+use std::io;
+use std::sync::Arc;
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+use httpserver::url;
+use crate::config::Config;
+use crate::request;
+use crate::response::ResponseWriter;
+use crate::log::{info, warn};
+
+// The name part of a Host header, without the port. None when it doesn't look
+// like a host, it gets pasted into the Location so be picky
+fn host_name(host: &str) -> Option<&str> {
+    let name = match host.strip_prefix('[') {
+        // IPv6 literal, the brackets stay
+        Some(rest) => &host[..rest.find(']')? + 2],
+        None => host.split(':').next()?,
+    };
+    let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'[' | b']' | b':'));
+    valid.then_some(name)
+}
+
+// Where the same request lives over https, the target is kept as it was sent
+pub fn https_location(host: &str, target: &str, https_port: u16) -> Option<String> {
+    let name = host_name(host)?;
+    if !target.starts_with('/') {
+        return None;
+    }
+    match https_port {
+        443 => Some(format!("https://{name}{target}")),
+        port => Some(format!("https://{name}:{port}{target}")),
+    }
+}
+
+// The name (lowercase) and the port of a Host header, None when there is none
+fn authority(host: &str) -> Option<(String, Option<u16>)> {
+    let name = url::host_name(host)?;
+    let port = match host.rsplit_once(':') {
+        Some((before, port)) if !before.starts_with('[') || before.ends_with(']') => Some(port.parse().ok()?),
+        _ => None,
+    };
+    Some((name, port))
+}
+
+fn default_port(scheme: &str) -> u16 {
+    if scheme == "https" { 443 } else { 80 }
+}
+
+// Where the request belongs when it didn't come to the canonical host and scheme,
+// None when it did or its path is exempt. The target is kept as it was sent
+pub fn canonical_location(config: &Config, host: Option<&str>, path: &str, target: &str, tls: bool) -> Option<String> {
+    if config.canonical_host.is_none() && config.canonical_scheme.is_none() {
+        return None;
+    }
+    let exempt = config.canonical_exempt.iter()
+        .any(|prefix| path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')));
+    if exempt || !target.starts_with('/') {
+        return None;
+    }
+    let scheme = if tls { "https" } else { "http" };
+    let wanted_scheme = config.canonical_scheme.unwrap_or(scheme);
+    let current = host.and_then(authority);
+    // Only the scheme is canonical, then the name stays and the port goes with the old scheme
+    let (wanted_name, wanted_port) = match (&config.canonical_host, &current) {
+        (Some(canonical), _) => authority(canonical)?,
+        (None, Some((name, port))) if wanted_scheme == scheme => (name.clone(), *port),
+        (None, Some((name, _))) => (name.clone(), None),
+        (None, None) => return None,
+    };
+    let wanted_port = wanted_port.unwrap_or(default_port(wanted_scheme));
+    if wanted_scheme == scheme && current.is_some_and(|(name, port)| name == wanted_name && port.unwrap_or(default_port(scheme)) == wanted_port) {
+        return None;
+    }
+    match wanted_port == default_port(wanted_scheme) {
+        true => Some(format!("{wanted_scheme}://{wanted_name}{target}")),
+        false => Some(format!("{wanted_scheme}://{wanted_name}:{wanted_port}{target}")),
+    }
+}
+
+// Answer a single request with a redirect and hang up, nothing is ever served here
+async fn redirect_client(mut stream: TcpStream, config: Arc<Config>) -> io::Result<()> {
+    let (reader, writer) = stream.split();
+    let mut writer = ResponseWriter::new(writer);
+    let mut reader = BufReader::new(reader);
+    writer.set_common("Connection", "close");
+    for (name, value, first) in config.custom_headers() {
+        match first {
+            true => writer.set_common(name, value),
+            false => writer.add_common(name, value),
+        }
+    }
+
+    let mut line = String::new();
+    let head = match request::read_line(&mut reader, &mut line, &config.parser).await {
+        Ok(true) => request::read_headers(&mut reader, &config.parser).await.ok(),
+        _ => return Ok(()),
+    };
+    let target = line.split(' ').nth(1).unwrap_or_default();
+    let location = head.as_ref()
+        .and_then(|headers| headers.get("Host"))
+        .and_then(|host| https_location(host, target, config.https_port));
+    match location {
+        Some(location) => {
+            info!("redirecting {target} to {location}");
+            writer.write_reply_with(301, &[("Location", &location)], "<html>301</html>".as_bytes()).await
+        }
+        None => writer.write_client_error(400).await,
+    }
+}
+
+// Plaintext listener that sends everyone over to https
+pub async fn serve_redirects(listener: TcpListener, config: Arc<Config>) {
+    info!("Redirecting to https from {}", listener.local_addr().expect("it should never fail"));
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = crate::shutdown::wait() => return,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("failed to accept on the redirect listener {err}");
+                continue;
+            }
+        };
+        let config = config.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = redirect_client(stream, config).await {
+                warn!("Error redirecting client: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names_without_the_port() {
+        assert_eq!(host_name("example.com"), Some("example.com"));
+        assert_eq!(host_name("example.com:8080"), Some("example.com"));
+        assert_eq!(host_name("[::1]:80"), Some("[::1]"));
+        assert_eq!(host_name("[fe80::1]"), Some("[fe80::1]"));
+        for bad in ["", ":80", "evil.com/path", "a b", "x\r\nSet-Cookie: 1", "[::1", "a@b"] {
+            assert_eq!(host_name(bad), None, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn https_location_keeps_the_target() {
+        assert_eq!(https_location("example.com:80", "/a?b=1", 443).as_deref(), Some("https://example.com/a?b=1"));
+        assert_eq!(https_location("example.com", "/", 8443).as_deref(), Some("https://example.com:8443/"));
+        assert_eq!(https_location("[::1]", "/x%20y", 443).as_deref(), Some("https://[::1]/x%20y"));
+        // Absolute forms and asterisks have no path to keep
+        assert_eq!(https_location("example.com", "http://other/", 443), None);
+        assert_eq!(https_location("example.com", "*", 443), None);
+        assert_eq!(https_location("bad host", "/", 443), None);
+    }
+
+    fn canonical(args: &[&str]) -> Config {
+        Config::default().merge(args.iter().map(|arg| String::from(*arg))).unwrap()
+    }
+
+    #[test]
+    fn other_hosts_go_to_the_canonical_one() {
+        let config = canonical(&["--canonical-host", "example.com"]);
+        let location = |host| canonical_location(&config, Some(host), "/a", "/a?b=1", false);
+        assert_eq!(location("www.example.com").as_deref(), Some("http://example.com/a?b=1"));
+        assert_eq!(location("www.example.com:80").as_deref(), Some("http://example.com/a?b=1"));
+        // With another port it is another place, even on the same name
+        assert_eq!(location("example.com:8080").as_deref(), Some("http://example.com/a?b=1"));
+        for there in ["example.com", "EXAMPLE.com", "example.com:80", "example.com."] {
+            assert_eq!(location(there), None, "{there}");
+        }
+        // HTTP/1.0 without a Host is sent there too
+        assert_eq!(canonical_location(&config, None, "/", "/", false).as_deref(), Some("http://example.com/"));
+    }
+
+    #[test]
+    fn canonical_port_is_kept_unless_it_is_the_default() {
+        let config = canonical(&["--canonical-host", "example.com:8080"]);
+        assert_eq!(canonical_location(&config, Some("www.example.com:8080"), "/", "/", false).as_deref(), Some("http://example.com:8080/"));
+        assert_eq!(canonical_location(&config, Some("example.com"), "/", "/", false).as_deref(), Some("http://example.com:8080/"));
+        assert_eq!(canonical_location(&config, Some("example.com:8080"), "/", "/", false), None);
+    }
+
+    #[test]
+    fn scheme_and_host_change_together() {
+        let config = canonical(&["--canonical-host", "example.com", "--canonical-scheme", "https"]);
+        let location = |host, tls| canonical_location(&config, Some(host), "/a", "/a", tls);
+        assert_eq!(location("www.example.com", false).as_deref(), Some("https://example.com/a"));
+        assert_eq!(location("example.com", false).as_deref(), Some("https://example.com/a"));
+        assert_eq!(location("www.example.com", true).as_deref(), Some("https://example.com/a"));
+        assert_eq!(location("example.com", true), None);
+        // The scheme alone keeps the name, the port belonged to the old scheme
+        let config = canonical(&["--canonical-scheme", "https"]);
+        assert_eq!(canonical_location(&config, Some("files.lan:8080"), "/", "/", false).as_deref(), Some("https://files.lan/"));
+        assert_eq!(canonical_location(&config, Some("files.lan:8443"), "/", "/", true), None);
+        assert_eq!(canonical_location(&config, None, "/", "/", false), None);
+    }
+
+    #[test]
+    fn exempt_paths_stay() {
+        let config = canonical(&["--canonical-host", "example.com", "--canonical-exempt", "/health/"]);
+        let location = |path| canonical_location(&config, Some("www.example.com"), path, path, false);
+        for exempt in ["/.well-known/acme-challenge/token", "/.well-known/acme-challenge", "/metrics", "/health", "/health/live"] {
+            assert_eq!(location(exempt), None, "{exempt}");
+        }
+        for moved in ["/.well-known/other", "/metrics2", "/healthy"] {
+            assert!(location(moved).is_some(), "{moved}");
+        }
+        // Nor what has no path, like OPTIONS *
+        assert_eq!(canonical_location(&config, Some("www.example.com"), "", "*", false), None);
+        // Without a canonical host or scheme nothing moves
+        assert_eq!(canonical_location(&canonical(&[]), Some("www.example.com"), "/", "/", false), None);
+    }
+}
@@ -0,0 +1,454 @@
+use std::fmt;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use httpserver::query::QueryMap;
+use crate::headers::{self, Headers};
+use crate::method::Method;
+use crate::log::debug;
+
+// A parsed request head, everything past the body framing works on this
+pub struct Request {
+    pub id: String,  // For the logs and X-Request-Id
+    pub user: Option<String>,  // Who authenticated with Basic auth
+    pub client: String,  // Address of the peer
+    pub version: Version,
+    pub method: Method,
+    pub path: String,  // Decoded and normalized, always starts with '/'
+    pub file: String,  // Where the path is on disk, see Config::resolve
+    pub query: QueryMap,
+    pub headers: Headers,
+}
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Short id for a request, a counter hashed with a random per process key
+// so ids don't repeat across restarts and don't give away the request count
+pub fn new_id() -> String {
+    static KEY: OnceLock<RandomState> = OnceLock::new();
+    let n = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}", KEY.get_or_init(RandomState::new).hash_one(n))
+}
+
+// Ids taken from clients end up in headers and logs, keep them to a harmless alphabet
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+// First line of what an HTTP/2 client with prior knowledge sends (RFC 7540 3.5),
+// "\r\nSM\r\n\r\n" follows and looks like an empty header block to us
+pub const HTTP2_PREFACE: &str = "PRI * HTTP/2.0";
+
+// A SETTINGS frame without settings, which has to come first from a server, and
+// GOAWAY with HTTP_1_1_REQUIRED for stream 0 (RFC 7540 6.5, 6.8 and 7). Clients
+// take that as the sign to retry with HTTP/1.1
+pub const HTTP2_REFUSAL: [u8; 26] = [
+    0, 0, 0, 0x4, 0, 0, 0, 0, 0,
+    0, 0, 8, 0x7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xd,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    // Later 1.x minor versions are compatible and get treated as 1.1 (RFC 7230 2.6),
+    // Err carries the status to answer with
+    pub fn parse(s: &str) -> Result<Version, i32> {
+        let digits = s.strip_prefix("HTTP/").and_then(|v| v.split_once('.'));
+        let (major, minor) = match digits {
+            Some((major, minor)) if major.len() == 1 && minor.len() == 1 => (major.as_bytes()[0], minor.as_bytes()[0]),
+            _ => return Err(400),
+        };
+        match (major, minor) {
+            (b'1', b'0') => Ok(Version::Http10),
+            (b'1', b'1'..=b'9') => Ok(Version::Http11),
+            (b'0'..=b'9', b'0'..=b'9') => Err(505),
+            _ => Err(400),
+        }
+    }
+}
+
+// What to do with obsolete line folding (RFC 7230 3.2.4),
+// a header line starting with a space or tab continues the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldPolicy {
+    Reject,
+    Unfold,
+}
+
+// Lines must end with CRLF, the lenient mode also takes a bare LF
+// as some embedded clients and test tools send that (RFC 7230 3.5).
+// Either way a line ends at the LF only and just its line ending is
+// stripped, so the request line and every header line are read alike
+// and a blank line ends the head whichever ending it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndings {
+    Lenient,
+    Strict,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub fold: FoldPolicy,
+    pub line_endings: LineEndings,
+    pub max_headers: usize,
+    pub max_line: usize,  // Bytes in the request line or a header line, without its ending
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        // Servers should reject folding, only proxies and clients have to unfold
+        ParseOptions {
+            fold: FoldPolicy::Reject,
+            line_endings: LineEndings::Lenient,
+            max_headers: 100,
+            max_line: 8192,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadError {
+    Io(io::Error),
+    Eof,
+    Malformed(String),
+    Folded,
+    BareLf,
+    StrayCr,
+    TooManyHeaders,
+    LineTooLong,
+}
+
+impl From<io::Error> for HeadError {
+    fn from(err: io::Error) -> Self {
+        HeadError::Io(err)
+    }
+}
+
+impl fmt::Display for HeadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadError::Io(err) => write!(f, "{err}"),
+            HeadError::Eof => write!(f, "connection closed in the request head"),
+            HeadError::Malformed(line) => write!(f, "malformed header line '{line}'"),
+            HeadError::Folded => write!(f, "obsolete line folding"),
+            HeadError::BareLf => write!(f, "line ended without CR"),
+            HeadError::StrayCr => write!(f, "CR in the middle of a line"),
+            HeadError::TooManyHeaders => write!(f, "too many header lines"),
+            HeadError::LineTooLong => write!(f, "line longer than the limit"),
+        }
+    }
+}
+
+// Read one line and strip exactly its line ending, false on EOF before anything was read.
+// Nothing else is trimmed, the whitespace belongs to the content. Never more than
+// max_line bytes and the ending are taken in, a line without an end can't grow forever.
+// Bytes that aren't UTF-8, like obs-text in a header value, are taken lossily
+pub async fn read_line<R>(reader: &mut R, line: &mut String, options: &ParseOptions) -> Result<bool, HeadError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    line.clear();
+    let limit = options.max_line as u64 + 2;
+    let mut bytes = Vec::new();
+    let read = (&mut *reader).take(limit).read_until(b'\n', &mut bytes).await?;
+    if read == 0 {
+        return Ok(false);
+    }
+    if !bytes.ends_with(b"\n") && read as u64 == limit {
+        return Err(HeadError::LineTooLong);
+    }
+    if bytes.ends_with(b"\r\n") {
+        bytes.truncate(bytes.len() - 2);
+    }
+    else if bytes.ends_with(b"\n") {
+        if options.line_endings == LineEndings::Strict {
+            return Err(HeadError::BareLf);
+        }
+        debug!("note: accepting a bare LF line ending");
+        bytes.pop();
+    }
+    else { // The connection closed in the middle of the line
+        return Err(HeadError::Eof);
+    }
+    if bytes.len() > options.max_line {
+        return Err(HeadError::LineTooLong);
+    }
+    if bytes.contains(&b'\r') {
+        return Err(HeadError::StrayCr);
+    }
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    Ok(true)
+}
+
+// The request line, after any empty lines. False on EOF before one arrived
+pub async fn read_request_line<R>(reader: &mut R, line: &mut String, options: &ParseOptions) -> Result<bool, HeadError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        if !read_line(reader, line, options).await? {
+            return Ok(false);
+        }
+        if !line.is_empty() {
+            return Ok(true);
+        }
+    }
+}
+
+// Read all headers up to and including the empty line
+pub async fn read_headers<R>(reader: &mut R, options: &ParseOptions) -> Result<Headers, HeadError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut headers = Headers::new();
+    let mut line = String::new();
+    let mut count = 0;
+    loop {
+        if !read_line(reader, &mut line, options).await? {
+            return Err(HeadError::Eof);
+        }
+        if line.is_empty() { // The last \r\n or \n
+            break;
+        }
+        // Don't let a flood of tiny headers grow the map without bound, folded
+        // lines count as lines too
+        count += 1;
+        if count > options.max_headers {
+            return Err(HeadError::TooManyHeaders);
+        }
+        if line.starts_with([' ', '\t']) {
+            if options.fold == FoldPolicy::Reject {
+                return Err(HeadError::Folded);
+            }
+            // A fold before any header has nothing to continue. The unfolded value
+            // is held to the limit of a single line
+            match headers.append_to_last(line.trim_matches([' ', '\t'])) {
+                None => return Err(HeadError::Folded),
+                Some(len) if len > options.max_line => return Err(HeadError::LineTooLong),
+                Some(_) => continue,
+            }
+        }
+        // The name is a token, so no whitespace between it and the colon either,
+        // the whitespace around the value isn't part of it (RFC 7230 3.2)
+        match line.split_once(':') {
+            Some((name, value)) if headers::is_token(name) => {
+                headers.insert(name, value.trim_matches([' ', '\t']));
+            }
+            _ => return Err(HeadError::Malformed(line)),
+        }
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_line: usize) -> ParseOptions {
+        ParseOptions { max_line, ..ParseOptions::default() }
+    }
+
+    #[test]
+    fn ids_are_new_each_time() {
+        let (first, second) = (new_id(), new_id());
+        assert_ne!(first, second);
+        assert!(is_valid_id(&first) && first.len() == 16);
+    }
+
+    #[test]
+    fn only_harmless_ids_are_valid() {
+        for id in ["abc", "req-1_2.3:4", &"x".repeat(64)] {
+            assert!(is_valid_id(id), "{id}");
+        }
+        for id in ["", "a b", "a\"b", "a\r\nX-Evil: 1", "caf\u{e9}", &"x".repeat(65)] {
+            assert!(!is_valid_id(id), "{id:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn line_at_the_limit_is_read() {
+        let mut reader: &[u8] = b"GET /abc HTTP/1.1\r\nrest";
+        let mut line = String::new();
+        assert!(read_line(&mut reader, &mut line, &options(17)).await.unwrap());
+        assert_eq!(line, "GET /abc HTTP/1.1");
+        assert_eq!(reader, b"rest");
+    }
+
+    #[tokio::test]
+    async fn line_past_the_limit_is_refused() {
+        let mut line = String::new();
+        let mut reader: &[u8] = b"GET /abcd HTTP/1.1\r\n";
+        assert!(matches!(read_line(&mut reader, &mut line, &options(17)).await, Err(HeadError::LineTooLong)));
+        let mut reader: &[u8] = b"GET /abcd HTTP/1.1\n";
+        assert!(matches!(read_line(&mut reader, &mut line, &options(17)).await, Err(HeadError::LineTooLong)));
+    }
+
+    #[tokio::test]
+    async fn unterminated_line_stops_at_the_limit() {
+        let endless = vec![b'a'; 1 << 20];
+        let mut reader = endless.as_slice();
+        let mut line = String::new();
+        assert!(matches!(read_line(&mut reader, &mut line, &options(100)).await, Err(HeadError::LineTooLong)));
+        assert!(line.len() <= 102);
+    }
+
+    #[tokio::test]
+    async fn folded_lines_are_unfolded() {
+        let options = ParseOptions { fold: FoldPolicy::Unfold, ..ParseOptions::default() };
+        let mut reader: &[u8] = b"X-Folded: one\r\n two\r\n\tthree\r\nHost: x\r\n\r\n";
+        let headers = read_headers(&mut reader, &options).await.unwrap();
+        assert_eq!(headers.get("X-Folded"), Some("one two three"));
+        assert_eq!(headers.get("Host"), Some("x"));
+    }
+
+    #[tokio::test]
+    async fn folded_lines_are_refused_by_default() {
+        let mut reader: &[u8] = b"X-Folded: one\r\n two\r\n\r\n";
+        assert!(matches!(read_headers(&mut reader, &ParseOptions::default()).await, Err(HeadError::Folded)));
+    }
+
+    #[tokio::test]
+    async fn fold_without_a_header_is_refused() {
+        let options = ParseOptions { fold: FoldPolicy::Unfold, ..ParseOptions::default() };
+        let mut reader: &[u8] = b" two\r\n\r\n";
+        assert!(matches!(read_headers(&mut reader, &options).await, Err(HeadError::Folded)));
+    }
+
+    #[tokio::test]
+    async fn folded_lines_count_towards_max_headers() {
+        let options = ParseOptions { fold: FoldPolicy::Unfold, max_headers: 3, ..ParseOptions::default() };
+        let mut reader: &[u8] = b"X-Folded: a\r\n b\r\n c\r\n\r\n";
+        assert!(read_headers(&mut reader, &options).await.is_ok());
+        let mut reader: &[u8] = b"X-Folded: a\r\n b\r\n c\r\n d\r\n\r\n";
+        assert!(matches!(read_headers(&mut reader, &options).await, Err(HeadError::TooManyHeaders)));
+    }
+
+    #[tokio::test]
+    async fn unfolded_value_is_held_to_the_line_limit() {
+        let options = ParseOptions { fold: FoldPolicy::Unfold, max_line: 20, max_headers: 1000, ..ParseOptions::default() };
+        let head = format!("X-Folded: a\r\n{}\r\n", " bbbbbbbbbb\r\n".repeat(3));
+        let mut reader = head.as_bytes();
+        assert!(matches!(read_headers(&mut reader, &options).await, Err(HeadError::LineTooLong)));
+    }
+
+    #[tokio::test]
+    async fn long_header_line_is_refused() {
+        let head = format!("Host: x\r\nX-Long: {}\r\n\r\n", "b".repeat(200));
+        let mut reader = head.as_bytes();
+        assert!(matches!(read_headers(&mut reader, &options(100)).await, Err(HeadError::LineTooLong)));
+    }
+
+    fn strict() -> ParseOptions {
+        ParseOptions { line_endings: LineEndings::Strict, ..ParseOptions::default() }
+    }
+
+    #[tokio::test]
+    async fn bare_lf_is_taken_by_default() {
+        let mut reader: &[u8] = b"GET / HTTP/1.1\nHost: x\nAccept: */*\r\n\nrest";
+        let mut line = String::new();
+        assert!(read_request_line(&mut reader, &mut line, &ParseOptions::default()).await.unwrap());
+        assert_eq!(line, "GET / HTTP/1.1");
+        let headers = read_headers(&mut reader, &ParseOptions::default()).await.unwrap();
+        assert_eq!((headers.get("Host"), headers.get("Accept")), (Some("x"), Some("*/*")));
+        assert_eq!(reader, b"rest");
+    }
+
+    #[tokio::test]
+    async fn strict_mode_takes_only_crlf() {
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut line = String::new();
+        assert!(read_request_line(&mut reader, &mut line, &strict()).await.unwrap());
+        assert!(read_headers(&mut reader, &strict()).await.is_ok());
+        for head in [&b"GET / HTTP/1.1\n"[..], b"\n"] {
+            let mut reader = head;
+            assert!(matches!(read_line(&mut reader, &mut line, &strict()).await, Err(HeadError::BareLf)), "{head:?}");
+        }
+        // The blank line at the end counts too
+        let mut reader: &[u8] = b"Host: x\r\n\n";
+        assert!(matches!(read_headers(&mut reader, &strict()).await, Err(HeadError::BareLf)));
+    }
+
+    #[tokio::test]
+    async fn cr_inside_a_line_is_refused_in_either_mode() {
+        for options in [ParseOptions::default(), strict()] {
+            for head in [&b"GET /a\rb HTTP/1.1\r\n"[..], b"GET / HTTP/1.1\r\r\n", b"X-A: 1\r2\r\n"] {
+                let mut reader = head;
+                let mut line = String::new();
+                assert!(matches!(read_line(&mut reader, &mut line, &options).await, Err(HeadError::StrayCr)), "{head:?}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_line_ending_is_stripped() {
+        let mut reader: &[u8] = b" GET  /\t\r\n";
+        let mut line = String::new();
+        assert!(read_line(&mut reader, &mut line, &ParseOptions::default()).await.unwrap());
+        assert_eq!(line, " GET  /\t");
+        let mut reader: &[u8] = b"no end";
+        assert!(matches!(read_line(&mut reader, &mut line, &ParseOptions::default()).await, Err(HeadError::Eof)));
+    }
+
+    #[tokio::test]
+    async fn header_name_has_to_be_a_token() {
+        for head in [&b"Bad Name: x\r\n\r\n"[..], b"Host : x\r\n\r\n", b": x\r\n\r\n", b"X(y): 1\r\n\r\n", b"NoColon\r\n\r\n"] {
+            let mut reader = head;
+            assert!(matches!(read_headers(&mut reader, &ParseOptions::default()).await, Err(HeadError::Malformed(_))), "{head:?}");
+        }
+        let mut reader: &[u8] = b"X-Odd_but.fine~!: 1\r\n\r\n";
+        assert_eq!(read_headers(&mut reader, &ParseOptions::default()).await.unwrap().get("x-odd_but.fine~!"), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn whitespace_around_values_is_trimmed() {
+        let mut reader: &[u8] = b"X-A: \t value with  inner space \t\r\nX-B:value\r\nX-C:   \r\n\r\n";
+        let headers = read_headers(&mut reader, &ParseOptions::default()).await.unwrap();
+        assert_eq!(headers.get("X-A"), Some("value with  inner space"));
+        assert_eq!(headers.get("X-B"), Some("value"));
+        assert_eq!(headers.get("X-C"), Some(""));
+    }
+
+    #[tokio::test]
+    async fn bytes_that_are_not_utf8_are_taken_lossily() {
+        let mut reader: &[u8] = b"User-Agent: \xff\xfe x\r\nX-A: caf\xc3\xa9\r\n\r\n";
+        let headers = read_headers(&mut reader, &ParseOptions::default()).await.unwrap();
+        assert_eq!(headers.get("User-Agent"), Some("\u{fffd}\u{fffd} x"));
+        assert_eq!(headers.get("X-A"), Some("café"));
+        // The limit is in bytes as they came, not as they were replaced
+        let mut line = String::new();
+        let mut reader: &[u8] = b"\xff\xff\xff\xff\xff\r\n";
+        assert!(read_line(&mut reader, &mut line, &options(5)).await.unwrap());
+        assert_eq!(line, "\u{fffd}".repeat(5));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(Version::parse("HTTP/1.0"), Ok(Version::Http10));
+        assert_eq!(Version::parse("HTTP/1.1"), Ok(Version::Http11));
+        assert_eq!(Version::parse("HTTP/1.7"), Ok(Version::Http11));
+        assert_eq!(Version::parse("HTTP/2.0"), Err(505));
+        assert_eq!(Version::parse("HTTP/0.9"), Err(505));
+        for bad in ["HTTP/1", "HTTP/11.0", "http/1.1", "HTTP/x.1", ""] {
+            assert_eq!(Version::parse(bad), Err(400), "{bad}");
+        }
+    }
+
+    #[test]
+    fn http2_refusal_is_settings_then_goaway() {
+        let (settings, goaway) = HTTP2_REFUSAL.split_at(9);
+        // Length 0, type SETTINGS, no flags, stream 0
+        assert_eq!(settings, [0, 0, 0, 0x4, 0, 0, 0, 0, 0]);
+        // Length 8, type GOAWAY, stream 0, last stream 0, HTTP_1_1_REQUIRED
+        assert_eq!(&goaway[..9], [0, 0, 8, 0x7, 0, 0, 0, 0, 0]);
+        assert_eq!(u32::from_be_bytes(goaway[9..13].try_into().unwrap()), 0);
+        assert_eq!(u32::from_be_bytes(goaway[13..].try_into().unwrap()), 0xd);
+    }
+}
@@ -0,0 +1,335 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use crate::inject::Throttle;
+
+pub fn status_code_to_string(code: i32) -> &'static str {
+    reason_phrase(code).expect("WTF?")
+}
+
+// None for the codes we never send, so settings naming one can be refused up front
+pub fn reason_phrase(code: i32) -> Option<&'static str> {
+    let reason = match code {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        507 => "Insufficient Storage",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+// 1xx, 204 and 304 responses never carry a body (RFC 7230 3.3.3)
+pub fn status_has_body(code: i32) -> bool {
+    !(100..200).contains(&code) && code != 204 && code != 304
+}
+
+// Bodies of error responses from --error-page, for one status like 404 or a whole
+// class like 4xx. {{code}} and {{reason}} in them are filled in
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    pages: Vec<(String, String, String)>,  // Status or class, the file, its template
+}
+
+impl ErrorPages {
+    // A later page for the same key replaces the earlier one
+    pub fn add(&mut self, key: &str, file: &str) -> Result<(), String> {
+        let valid = match key.as_bytes() {
+            [b'4' | b'5', b'x', b'x'] => true,
+            [b'4' | b'5', rest @ ..] => rest.len() == 2 && rest.iter().all(u8::is_ascii_digit),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("invalid error page status '{key}', expected a 4xx or 5xx code or class"));
+        }
+        let template = std::fs::read_to_string(file).map_err(|err| format!("error page {file}: {err}"))?;
+        self.pages.retain(|(other, _, _)| other != key);
+        self.pages.push((String::from(key), String::from(file), template));
+        Ok(())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pages.iter().map(|(key, file, _)| (key.as_str(), file.as_str()))
+    }
+
+    // The page of the status, or of its class when it has none
+    pub fn render(&self, code: i32) -> Option<String> {
+        let status = code.to_string();
+        let class = format!("{}xx", code / 100);
+        let (_, _, template) = self.pages.iter().find(|(key, _, _)| *key == status)
+            .or_else(|| self.pages.iter().find(|(key, _, _)| *key == class))?;
+        Some(template.replace("{{code}}", &status).replace("{{reason}}", status_code_to_string(code)))
+    }
+}
+
+// Counts the bytes written through it, for the access log
+pub struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W> Counted<W> {
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Throttle> Throttle for Counted<W> {
+    fn throttle(&mut self, rate: Option<u64>) {
+        self.inner.throttle(rate);
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Counted<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = polled {
+            this.written += written as u64;
+        }
+        polled
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// The write half of a connection. Common headers are sent with every response
+// until cleared, handle_client resets them for each request
+pub struct ResponseWriter<W> {
+    pub stream: Counted<W>,
+    common: Vec<(String, String)>,
+    error_pages: Arc<ErrorPages>,
+    status: Option<i32>,  // Of the last response head written
+    head: u64,  // Bytes of the heads among those the stream counted
+    continue_pending: bool,  // The client waits for a 100 Continue before its body
+    head_only: bool,  // Answering a HEAD, bodies are announced but not sent
+}
+
+impl<W: AsyncWrite + Unpin> ResponseWriter<W> {
+    pub fn new(stream: W) -> Self {
+        let stream = Counted { inner: stream, written: 0 };
+        ResponseWriter { stream, common: Vec::new(), error_pages: Arc::default(), status: None, head: 0, continue_pending: false, head_only: false }
+    }
+
+    // The status and body bytes sent since the last reset, for the access log
+    pub fn sent(&self) -> (Option<i32>, u64) {
+        (self.status, self.stream.written - self.head)
+    }
+
+    // For the next request, an expectation of the last one included
+    pub fn reset_sent(&mut self) {
+        self.status = None;
+        self.head = 0;
+        self.stream.written = 0;
+        self.continue_pending = false;
+        self.head_only = false;
+    }
+
+    // Set once the method is known, every reply to a HEAD keeps its Content-Length
+    // and leaves out the body, error pages and redirects included
+    pub fn set_head_only(&mut self, head_only: bool) {
+        self.head_only = head_only;
+    }
+
+    // Expect: 100-continue, the interim response goes out once the body is really read
+    pub fn expect_continue(&mut self) {
+        self.continue_pending = true;
+    }
+
+    // Still not sent, so the client may or may not send its body after the response
+    pub fn continue_pending(&self) -> bool {
+        self.continue_pending
+    }
+
+    // What error responses look like from now on, they follow reloads
+    pub fn set_error_pages(&mut self, pages: Arc<ErrorPages>) {
+        self.error_pages = pages;
+    }
+
+    // Add a common header, replacing one with the same name
+    pub fn set_common(&mut self, name: &str, value: &str) {
+        self.common.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        self.common.push((String::from(name), String::from(value)));
+    }
+
+    // Another one next to those with the same name
+    pub fn add_common(&mut self, name: &str, value: &str) {
+        self.common.push((String::from(name), String::from(value)));
+    }
+
+    // A Connection: close goes out with the response, the connection ends after it
+    pub fn closing(&self) -> bool {
+        self.common.iter().any(|(name, value)| name.eq_ignore_ascii_case("Connection") && value.eq_ignore_ascii_case("close"))
+    }
+
+    pub fn clear_common(&mut self) {
+        self.common.clear();
+    }
+
+    pub async fn write_reply(&mut self, code: i32, content: &[u8]) -> io::Result<()> {
+        self.write_reply_with(code, &[], content).await
+    }
+
+    // Same as write_reply, with extra headers after the status line
+    pub async fn write_reply_with(&mut self, code: i32, extra: &[(&str, &str)], content: &[u8]) -> io::Result<()> {
+        self.write_head(code, extra, Some(content.len())).await?;
+        if status_has_body(code) && !self.head_only {
+            self.stream.write_all(content).await?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    // The status line and headers, Content-Length announces `length` bytes of body.
+    // Without a length the caller has to frame the body itself (chunked)
+    pub async fn write_head(&mut self, code: i32, extra: &[(&str, &str)], length: Option<usize>) -> io::Result<()> {
+        let mut reply = format!("HTTP/1.1 {} {}\r\n", code, status_code_to_string(code));
+        for (name, value) in extra {
+            reply.push_str(&format!("{name}: {value}\r\n"));
+        }
+        for (name, value) in &self.common {
+            reply.push_str(&format!("{name}: {value}\r\n"));
+        }
+        // A Content-Length on a 304 would have to describe the entity we didn't send,
+        // so leave it out together with the body
+        if let Some(length) = length.filter(|_| status_has_body(code)) {
+            reply.push_str(&format!("Content-Length: {length}\r\n"));
+        }
+        reply.push_str("\r\n");
+        self.status = Some(code);
+        self.head += reply.len() as u64;
+        self.stream.write_all(reply.as_bytes()).await
+    }
+
+    // An interim response, the final one is still to come. Only when the client
+    // expects one, and once
+    pub async fn write_continue(&mut self) -> io::Result<()> {
+        if !std::mem::take(&mut self.continue_pending) {
+            return Ok(());
+        }
+        let reply = b"HTTP/1.1 100 Continue\r\n\r\n";
+        self.head += reply.len() as u64;
+        self.stream.write_all(reply).await?;
+        self.stream.flush().await
+    }
+
+    // An error status with its page, the configured one or the built-in one
+    pub async fn write_error_with(&mut self, code: i32, extra: &[(&str, &str)]) -> io::Result<()> {
+        match self.error_pages.render(code) {
+            Some(page) => {
+                let mut extra = extra.to_vec();
+                extra.push(("Content-Type", "text/html; charset=utf-8"));
+                self.write_reply_with(code, &extra, page.as_bytes()).await
+            }
+            None => self.write_reply_with(code, extra, format!("<html>{code}</html>").as_bytes()).await,
+        }
+    }
+
+    // The request was wrong (4xx), it's the client's fault
+    pub async fn write_client_error(&mut self, code: i32) -> io::Result<()> {
+        self.write_error_with(code, &[]).await
+    }
+
+    // A client error after which we hang up, the rest of the stream can't be trusted
+    // (the framing is broken or the body is unread) so say so with Connection: close
+    pub async fn write_closing_error(&mut self, code: i32) -> io::Result<()> {
+        self.set_common("Connection", "close");
+        self.write_client_error(code).await
+    }
+
+    // Something broke on our side while answering a valid request
+    pub async fn write_server_error(&mut self) -> io::Result<()> {
+        self.write_error_with(500, &[]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(entries: &[(&str, &str)]) -> ErrorPages {
+        let mut pages = ErrorPages::default();
+        for (index, (key, template)) in entries.iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("httpserver-error-page-{}-{key}-{index}", std::process::id()));
+            std::fs::write(&path, template).unwrap();
+            let added = pages.add(key, path.to_str().unwrap());
+            std::fs::remove_file(&path).unwrap();
+            added.unwrap();
+        }
+        pages
+    }
+
+    #[test]
+    fn status_wins_over_its_class() {
+        let pages = pages(&[("4xx", "{{code}} {{reason}}"), ("404", "missing, {{code}}")]);
+        assert_eq!(pages.render(403).as_deref(), Some("403 Forbidden"));
+        assert_eq!(pages.render(404).as_deref(), Some("missing, 404"));
+        assert_eq!(pages.render(500), None);
+        assert_eq!(pages.files().map(|(key, _)| key).collect::<Vec<_>>(), ["4xx", "404"]);
+    }
+
+    #[test]
+    fn later_page_replaces_the_earlier() {
+        let pages = pages(&[("5xx", "first"), ("5xx", "second {{code}}{{code}}")]);
+        assert_eq!(pages.render(503).as_deref(), Some("second 503503"));
+        assert_eq!(pages.files().count(), 1);
+    }
+
+    #[test]
+    fn only_error_statuses_and_classes() {
+        let mut pages = ErrorPages::default();
+        for key in ["3xx", "200", "4x", "40", "4000", "4xX", "abc", ""] {
+            assert!(pages.add(key, "/nonexistent").unwrap_err().contains("invalid error page status"), "{key}");
+        }
+        assert!(pages.add("404", "/nonexistent/page.html").unwrap_err().starts_with("error page /nonexistent/page.html"));
+    }
+
+    #[test]
+    fn statuses_without_a_body() {
+        for code in [100, 101, 204, 304] {
+            assert!(!status_has_body(code), "{code}");
+        }
+        for code in [200, 206, 301, 404, 500] {
+            assert!(status_has_body(code), "{code}");
+        }
+    }
+}
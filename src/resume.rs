@@ -0,0 +1,223 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use crate::upload::{self, UploadError};
+use crate::log::info;
+
+// A PUT with Content-Range: bytes start-end/total, end is inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub total: u64,
+}
+
+impl ContentRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    // The total has to be known up front, otherwise we can't tell when it's done
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let number = |s: &str| s.bytes().all(|b| b.is_ascii_digit()).then(|| s.parse::<u64>().ok()).flatten();
+        let range = ContentRange { start: number(start)?, end: number(end)?, total: number(total)? };
+        (range.start <= range.end && range.end < range.total).then_some(range)
+    }
+}
+
+// An upload that arrives in pieces, possibly out of order
+struct Partial {
+    total: u64,
+    received: Vec<(u64, u64)>,  // Sorted, merged, end exclusive
+    touched: Instant,
+    created: Arc<tokio::sync::Mutex<()>>,  // Held by the first piece until the file is there
+}
+
+impl Partial {
+    fn add(&mut self, start: u64, end: u64) {
+        self.received.push((start, end));
+        self.received.sort();
+        let mut merged: Vec<(u64, u64)> = Vec::new();
+        for &(start, end) in &self.received {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.received = merged;
+    }
+
+    fn is_complete(&self) -> bool {
+        self.received == [(0, self.total)]
+    }
+}
+
+// Keyed by the target. They live in memory only, a restart forgets them and
+// the clients start over
+fn partials() -> &'static Mutex<HashMap<PathBuf, Partial>> {
+    static PARTIALS: OnceLock<Mutex<HashMap<PathBuf, Partial>>> = OnceLock::new();
+    PARTIALS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Hidden file next to the target collecting the pieces
+fn partial_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{name}.partial"))
+}
+
+// What we have of an upload as the value of a Range header, like "bytes=0-99,200-299"
+pub fn progress(target: &Path) -> Option<(String, u64)> {
+    let partials = partials().lock().unwrap();
+    let partial = partials.get(target)?;
+    let ranges: Vec<String> = partial.received.iter().map(|(start, end)| format!("{start}-{}", end - 1)).collect();
+    Some((format!("bytes={}", ranges.join(",")), partial.total))
+}
+
+// Whether a piece fits with the pieces already received, a different total means
+// a different file and is a conflict
+pub fn accepts(target: &Path, range: &ContentRange) -> bool {
+    let partials = partials().lock().unwrap();
+    partials.get(target).is_none_or(|partial| partial.total == range.total)
+}
+
+async fn create_partial(partial: &Path, total: u64) -> io::Result<()> {
+    let file = tokio::fs::File::create(partial).await?;
+    file.set_len(total).await
+}
+
+// Write one piece at its offset. Returns the Range header value while pieces are
+// still missing, None once the file is complete and has been moved into place
+pub async fn receive_range(body: &mut (impl AsyncRead + Unpin), target: &Path, range: &ContentRange) -> Result<Option<String>, UploadError> {
+    let partial = partial_path(target);
+    // The file is made outside the lock, the other uploads don't wait for the disk.
+    // Pieces of this one that come meanwhile wait for it instead
+    let (created, first) = {
+        let mut partials = partials().lock().unwrap();
+        match partials.entry(target.to_path_buf()) {
+            // Touched now as well, so a slow piece doesn't get its upload cleaned up under it
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().touched = Instant::now();
+                (entry.get().created.clone(), None)
+            }
+            Entry::Vacant(entry) => {
+                let created = Arc::new(tokio::sync::Mutex::new(()));
+                let guard = created.clone().try_lock_owned().expect("nobody else has it yet");
+                entry.insert(Partial { total: range.total, received: Vec::new(), touched: Instant::now(), created: created.clone() });
+                (created, Some(guard))
+            }
+        }
+    };
+    match first {
+        // What a restart left behind is of an upload that is gone, its bytes must not
+        // end up in this one, the file starts over
+        Some(guard) => {
+            if let Err(err) = create_partial(&partial, range.total).await {
+                partials().lock().unwrap().remove(target);
+                return Err(UploadError::File(err));
+            }
+            drop(guard);
+        }
+        None => {
+            drop(created.lock().await);
+            if !partials().lock().unwrap().contains_key(target) {
+                return Err(UploadError::File(io::Error::other("partial upload could not be created")));
+            }
+        }
+    }
+    let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&partial).await
+        .map_err(UploadError::File)?;
+    file.seek(SeekFrom::Start(range.start)).await.map_err(UploadError::File)?;
+    let written = tokio::io::copy(&mut body.take(range.len()), &mut file).await;
+    // copy can't tell which side failed, but only the file can be out of space and
+    // only the body can end early
+    let written = written.map_err(|err| match upload::is_out_of_space(&err) {
+        true => UploadError::File(err),
+        false => UploadError::Body(err),
+    })?;
+    // A chunked body can be longer than it claims, the rest would be taken for the next request
+    let longer = body.read(&mut [0u8; 1]).await.map_err(UploadError::Body)? > 0;
+    if written != range.len() || longer {
+        return Err(UploadError::Body(io::Error::new(io::ErrorKind::UnexpectedEof, "request body doesn't match its range")));
+    }
+    file.sync_all().await.map_err(UploadError::File)?;
+
+    let complete = {
+        let mut partials = partials().lock().unwrap();
+        let Some(entry) = partials.get_mut(target) else {
+            // Cleaned up while we were writing
+            return Err(UploadError::File(io::Error::other("upload expired")));
+        };
+        entry.add(range.start, range.end + 1);
+        entry.touched = Instant::now();
+        match entry.is_complete() {
+            true => partials.remove(target).is_some(),
+            false => false,
+        }
+    };
+    if complete {
+        // Anything else would be a file that no longer is what was uploaded
+        let len = tokio::fs::metadata(&partial).await.map_err(UploadError::File)?.len();
+        if len != range.total {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(UploadError::File(io::Error::other(format!("partial upload is {len} bytes instead of {}", range.total))));
+        }
+        tokio::fs::rename(&partial, target).await.map_err(UploadError::File)?;
+        return Ok(None);
+    }
+    Ok(progress(target).map(|(received, _)| received))
+}
+
+// Forget uploads nobody added to for ttl and delete what they left behind
+pub async fn remove_stale(ttl: Duration) {
+    let stale: Vec<PathBuf> = {
+        let mut partials = partials().lock().unwrap();
+        let stale: Vec<PathBuf> = partials.iter()
+            .filter(|(_, partial)| partial.touched.elapsed() > ttl)
+            .map(|(target, _)| target.clone())
+            .collect();
+        for target in &stale {
+            partials.remove(target);
+        }
+        stale
+    };
+    for target in stale {
+        info!("removing the stale partial upload of {}", target.display());
+        let _ = tokio::fs::remove_file(partial_path(&target)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_range_needs_the_total() {
+        assert_eq!(ContentRange::parse("bytes 0-99/200"), Some(ContentRange { start: 0, end: 99, total: 200 }));
+        assert_eq!(ContentRange::parse(" bytes 199-199/200 ").map(|range| range.len()), Some(1));
+        for value in ["bytes 0-99/*", "bytes 0-99", "bytes 100-99/200", "bytes 0-200/200", "bytes */200", "items 0-9/10", "bytes -9/10", "bytes 0-+9/10"] {
+            assert_eq!(ContentRange::parse(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn pieces_merge_in_any_order() {
+        let mut partial = Partial { total: 300, received: Vec::new(), touched: Instant::now(), created: Default::default() };
+        partial.add(200, 300);
+        partial.add(0, 100);
+        assert_eq!(partial.received, [(0, 100), (200, 300)]);
+        assert!(!partial.is_complete());
+        // Overlapping and touching pieces become one
+        partial.add(50, 150);
+        partial.add(150, 200);
+        assert_eq!(partial.received, [(0, 300)]);
+        assert!(partial.is_complete());
+        partial.add(10, 20);
+        assert!(partial.is_complete());
+    }
+}
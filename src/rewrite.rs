@@ -0,0 +1,182 @@
+//! Rewrite rules for request paths.
+//!
+//! A rule is a glob [`Pattern`] matched against the whole decoded path and a target
+//! in which `$1` to `$9` stand for what the `*`s of the pattern matched. The target
+//! comes out encoded: what the captures hold is escaped, the rest of the target is
+//! taken as it is written, so `%20` in it stays a space.
+
+use std::fmt;
+use crate::glob::Pattern;
+use crate::url;
+
+/// What happens to a request whose path matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Served as if the target had been asked for
+    Internal,
+    /// Sent there with this status, one of 301, 302, 307 and 308
+    Redirect(i32),
+}
+
+/// A pattern and where it leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pattern: Pattern,
+    target: String,
+    mode: Mode,
+}
+
+/// Why a rule couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError(pub String);
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Rule {
+    /// A rule from its pattern and a target, which may start with the status of a
+    /// redirect: `301 https://example.com/$1`. Without one the rewrite is internal and
+    /// its target has to be a path.
+    pub fn parse(pattern: &str, target: &str) -> Result<Rule, RuleError> {
+        if !pattern.starts_with('/') {
+            return Err(RuleError(format!("rewrite pattern '{pattern}' must start with '/'")));
+        }
+        let compiled = Pattern::new(pattern).map_err(|err| RuleError(err.to_string()))?;
+        let (mode, target) = match target.split_once(' ') {
+            Some((code, rest)) if code.chars().all(|ch| ch.is_ascii_digit()) => match code.parse() {
+                Ok(code @ (301 | 302 | 307 | 308)) => (Mode::Redirect(code), rest.trim()),
+                _ => return Err(RuleError(format!("invalid redirect status '{code}', expected 301, 302, 307 or 308"))),
+            },
+            _ => (Mode::Internal, target),
+        };
+        let url = mode != Mode::Internal && (target.starts_with("http://") || target.starts_with("https://"));
+        if !target.starts_with('/') && !url {
+            return Err(RuleError(format!("invalid rewrite target '{target}', expected a path or, for a redirect, a url")));
+        }
+        let stars = pattern.matches('*').count();
+        let mut chars = target.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '$' {
+                continue;
+            }
+            match chars.next().and_then(|digit| digit.to_digit(10)) {
+                Some(n) if n >= 1 && n as usize <= stars => {}
+                _ => return Err(RuleError(format!("rewrite target '{target}' refers to a capture '{pattern}' doesn't have"))),
+            }
+        }
+        Ok(Rule { pattern: compiled, target: String::from(target), mode })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The pattern as it was given
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// The target as it was given, with the status of a redirect in front
+    pub fn target(&self) -> String {
+        match self.mode {
+            Mode::Internal => self.target.clone(),
+            Mode::Redirect(code) => format!("{code} {}", self.target),
+        }
+    }
+
+    /// The encoded target for a decoded path, `None` when the rule doesn't match it.
+    /// Captures are escaped as paths before a `?` in the target and as query values
+    /// after it.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let captures = self.pattern.captures(path)?;
+        let mut out = String::new();
+        let mut in_query = false;
+        let mut chars = self.target.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '$' => {
+                    // Checked by parse, there is a digit and a capture for it
+                    let n = chars.next().and_then(|digit| digit.to_digit(10)).unwrap_or(1) as usize;
+                    let capture = captures.get(n - 1).copied().unwrap_or_default();
+                    out.push_str(&match in_query {
+                        true => url::encode_query_value(capture),
+                        false => url::encode_path(capture),
+                    });
+                }
+                '?' => {
+                    in_query = true;
+                    out.push(ch);
+                }
+                ch => out.push(ch),
+            }
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, target: &str) -> Rule {
+        Rule::parse(pattern, target).unwrap()
+    }
+
+    fn error(pattern: &str, target: &str) -> String {
+        Rule::parse(pattern, target).unwrap_err().0
+    }
+
+    #[test]
+    fn modes_from_the_target() {
+        let internal = rule("/old/*", "/new/$1");
+        assert_eq!((internal.mode(), internal.pattern(), internal.target()), (Mode::Internal, "/old/*", String::from("/new/$1")));
+        let moved = rule("/blog/*", "301 https://blog.example.com/$1");
+        assert_eq!(moved.mode(), Mode::Redirect(301));
+        assert_eq!(moved.target(), "301 https://blog.example.com/$1");
+        for code in [302, 307, 308] {
+            assert_eq!(rule("/a", &format!("{code} /b")).mode(), Mode::Redirect(code));
+        }
+        // The space after the status can be more than one
+        assert_eq!(rule("/a", "302   /b").target(), "302 /b");
+    }
+
+    #[test]
+    fn bad_rules_are_refused() {
+        assert_eq!(error("old/*", "/new/$1"), "rewrite pattern 'old/*' must start with '/'");
+        assert_eq!(error("/a", "303 /b"), "invalid redirect status '303', expected 301, 302, 307 or 308");
+        assert_eq!(error("/a", "200 /b"), "invalid redirect status '200', expected 301, 302, 307 or 308");
+        assert_eq!(error("/a", "new"), "invalid rewrite target 'new', expected a path or, for a redirect, a url");
+        // Only a redirect leaves the server
+        assert_eq!(error("/a", "https://example.com/"), "invalid rewrite target 'https://example.com/', expected a path or, for a redirect, a url");
+        assert_eq!(error("/a/*", "/b/$2"), "rewrite target '/b/$2' refers to a capture '/a/*' doesn't have");
+        assert_eq!(error("/a/*", "/b/$0"), "rewrite target '/b/$0' refers to a capture '/a/*' doesn't have");
+        assert_eq!(error("/a/*", "/b/$"), "rewrite target '/b/$' refers to a capture '/a/*' doesn't have");
+        assert!(Rule::parse("/a[", "/b").is_err());
+    }
+
+    #[test]
+    fn captures_fill_the_target() {
+        let rule = rule("/old/*", "/new/$1");
+        assert_eq!(rule.apply("/old/a/b.txt").as_deref(), Some("/new/a/b.txt"));
+        assert_eq!(rule.apply("/old/").as_deref(), Some("/new/"));
+        // The whole path has to match
+        assert_eq!(rule.apply("/older/a"), None);
+        assert_eq!(rule.apply("/x/old/a"), None);
+        let swapped = Rule::parse("/*/*.html", "/$2/$1").unwrap();
+        assert_eq!(swapped.apply("/en/about.html").as_deref(), Some("/about/en"));
+    }
+
+    #[test]
+    fn captures_come_out_encoded() {
+        let rule = rule("/old/*", "/new/$1");
+        assert_eq!(rule.apply("/old/a b/ü?#").as_deref(), Some("/new/a%20b/%C3%BC%3F%23"));
+        // After a '?' as a query value, '/' and '&' included, a space is a '+'
+        let search = Rule::parse("/find/*", "/search?q=$1&page=1").unwrap();
+        assert_eq!(search.apply("/find/a&b c/d").as_deref(), Some("/search?q=a%26b+c%2Fd&page=1"));
+        // What is written in the target stays as it is
+        assert_eq!(Rule::parse("/a", "/b%20c").unwrap().apply("/a").as_deref(), Some("/b%20c"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Paths and prefixes of request paths mapped to what answers them.
+//!
+//! A route is an exact path like `/health`, or a prefix ending in `/*` that covers
+//! the prefix itself and everything below it: `/api/*` takes `/api`, `/api/` and
+//! `/api/users/1`, but not `/apis`. An exact route wins over any prefix and a longer
+//! prefix over a shorter one, so `/api/admin/*` takes what it covers from `/api/*`.
+
+use std::fmt;
+
+/// What a route was added with, and what its `*` covered of the path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Found<'r, 'p, T> {
+    pub value: &'r T,
+    /// The rest of the path after the prefix and its `/`, empty for exact routes
+    /// and for the prefix itself
+    pub rest: &'p str,
+}
+
+/// The route doesn't start with `/` or has a `*` anywhere but a trailing `/*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteError(pub String);
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid route '{}', expected a path like /health or a prefix like /api/*", self.0)
+    }
+}
+
+/// Routes in the order they were added, looked up by request path
+#[derive(Debug, Clone)]
+pub struct Router<T> {
+    routes: Vec<(String, bool, T)>,  // The path without "/*", whether it's a prefix
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Router { routes: Vec::new() }
+    }
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Adds a route, replacing the one that was there for the same pattern
+    pub fn add(&mut self, pattern: &str, value: T) -> Result<(), RouteError> {
+        let (path, prefix) = match pattern.strip_suffix("/*") {
+            Some(path) => (path, true),
+            None => (pattern, false),
+        };
+        if !pattern.starts_with('/') || path.contains('*') {
+            return Err(RouteError(String::from(pattern)));
+        }
+        self.routes.retain(|(other, other_prefix, _)| *other != path || *other_prefix != prefix);
+        self.routes.push((String::from(path), prefix, value));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The patterns as they would be written, with what they were added with
+    pub fn iter(&self) -> impl Iterator<Item = (String, &T)> {
+        self.routes.iter().map(|(path, prefix, value)| match prefix {
+            true => (format!("{path}/*"), value),
+            false => (path.clone(), value),
+        })
+    }
+
+    /// The most specific route for `path`, None when no route covers it
+    pub fn find<'r, 'p>(&'r self, path: &'p str) -> Option<Found<'r, 'p, T>> {
+        if let Some((_, _, value)) = self.routes.iter().find(|(route, prefix, _)| !prefix && route == path) {
+            return Some(Found { value, rest: "" });
+        }
+        self.routes.iter()
+            .filter(|(_, prefix, _)| *prefix)
+            .filter_map(|(route, _, value)| {
+                let rest = path.strip_prefix(route.as_str())?;
+                match rest.strip_prefix('/') {
+                    Some(rest) => Some((route.len(), Found { value, rest })),
+                    None if rest.is_empty() => Some((route.len(), Found { value, rest })),
+                    None => None,
+                }
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, found)| found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(patterns: &[&'static str]) -> Router<&'static str> {
+        let mut router = Router::new();
+        for pattern in patterns {
+            router.add(pattern, *pattern).unwrap();
+        }
+        router
+    }
+
+    fn find<'p>(router: &Router<&'static str>, path: &'p str) -> Option<(&'static str, &'p str)> {
+        router.find(path).map(|found| (*found.value, found.rest))
+    }
+
+    #[test]
+    fn longest_prefix_wins_in_any_order() {
+        for patterns in [["/api/*", "/api/admin/*"], ["/api/admin/*", "/api/*"]] {
+            let router = router(&patterns);
+            assert_eq!(find(&router, "/api/admin/users"), Some(("/api/admin/*", "users")));
+            assert_eq!(find(&router, "/api/admin"), Some(("/api/admin/*", "")));
+            assert_eq!(find(&router, "/api/adminx"), Some(("/api/*", "adminx")));
+            assert_eq!(find(&router, "/api/users/1"), Some(("/api/*", "users/1")));
+        }
+    }
+
+    #[test]
+    fn exact_route_wins_over_a_prefix() {
+        let router = router(&["/*", "/api/*", "/api/health"]);
+        assert_eq!(find(&router, "/api/health"), Some(("/api/health", "")));
+        assert_eq!(find(&router, "/api/health/x"), Some(("/api/*", "health/x")));
+        assert_eq!(find(&router, "/other"), Some(("/*", "other")));
+    }
+
+    #[test]
+    fn prefix_covers_whole_segments() {
+        let router = router(&["/api/*"]);
+        assert_eq!(find(&router, "/api"), Some(("/api/*", "")));
+        assert_eq!(find(&router, "/api/"), Some(("/api/*", "")));
+        assert_eq!(find(&router, "/apis"), None);
+        assert_eq!(find(&router, "/"), None);
+    }
+
+    #[test]
+    fn same_pattern_replaces_the_route() {
+        let mut router = Router::new();
+        router.add("/api/*", 1).unwrap();
+        router.add("/api", 2).unwrap();
+        router.add("/api/*", 3).unwrap();
+        assert_eq!(router.find("/api/x").map(|found| *found.value), Some(3));
+        assert_eq!(router.find("/api").map(|found| *found.value), Some(2));
+        assert_eq!(router.iter().count(), 2);
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        for pattern in ["api", "/api*", "/*/x", "/a/*/b/*", ""] {
+            assert_eq!(Router::new().add(pattern, ()), Err(RouteError(String::from(pattern))), "{pattern}");
+        }
+    }
+}
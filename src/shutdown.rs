@@ -0,0 +1,25 @@
+// Ctrl-C and SIGTERM: stop accepting, let the connections finish what they are
+// doing and close them after their current response
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+static SHUTDOWN: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn sender() -> &'static watch::Sender<bool> {
+    SHUTDOWN.get_or_init(|| watch::channel(false).0)
+}
+
+pub fn begin() {
+    sender().send_replace(true);
+}
+
+pub fn is_shutting_down() -> bool {
+    *sender().borrow()
+}
+
+// Returns once the shutdown began, right away when it already has
+pub async fn wait() {
+    let mut receiver = sender().subscribe();
+    // The sender lives in the static, it is never dropped
+    let _ = receiver.wait_for(|&down| down).await;
+}
@@ -0,0 +1,335 @@
+//! Just enough TOML for a configuration file.
+//!
+//! Tables (`[name]`), `key = value` lines and comments. Values are basic and
+//! literal strings, integers, booleans and arrays of those, an array may span
+//! lines. Inline tables, arrays of tables, floats and dates are not supported and
+//! are reported as errors rather than misread.
+
+use std::fmt;
+
+/// A value of a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// What kind of value it is, for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// One `key = value`, with the table it is in (empty before the first header)
+/// and the line it starts on, counting from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// What is wrong and on which line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn is_bare(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Quotes a string so [`parse`] reads it back unchanged
+pub fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Writes a key bare when it can be, quoted otherwise
+pub fn key(s: &str) -> String {
+    match !s.is_empty() && s.chars().all(is_bare) {
+        true => String::from(s),
+        false => quote(s),
+    }
+}
+
+// A basic string after its opening quote, returns it and what follows the closing one
+fn basic_string(s: &str) -> Result<(String, &str), String> {
+    let mut out = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &s[i + 1..])),
+            '\\' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(u @ ('u' | 'U')) => {
+                        let len = if u == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == len)
+                            .and_then(char::from_u32)
+                            .ok_or(format!("invalid escape \\{u}{hex}"))?
+                    }
+                    Some(c) => return Err(format!("invalid escape \\{c}")),
+                    None => break,
+                };
+                out.push(escaped);
+            }
+            c if c.is_control() && c != '\t' => return Err(String::from("control character in a string")),
+            c => out.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+// A key, bare or quoted, and what follows it
+fn parse_key(s: &str) -> Result<(String, &str), String> {
+    let s = s.trim_start();
+    if let Some(rest) = s.strip_prefix('"') {
+        return basic_string(rest);
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let (key, rest) = rest.split_once('\'').ok_or("unterminated key")?;
+        return Ok((String::from(key), rest));
+    }
+    let end = s.find(|c: char| !is_bare(c)).unwrap_or(s.len());
+    if end == 0 {
+        return Err(String::from("missing key"));
+    }
+    Ok((String::from(&s[..end]), &s[end..]))
+}
+
+// Whitespace, newlines and comments, inside an array they may be anywhere
+fn skip_blank(mut s: &str) -> &str {
+    loop {
+        s = s.trim_start();
+        match s.strip_prefix('#') {
+            Some(comment) => s = comment.split_once('\n').map(|(_, rest)| rest).unwrap_or(""),
+            None => return s,
+        }
+    }
+}
+
+// One value and what follows it
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = s.strip_prefix('"') {
+        if rest.starts_with("\"\"") {
+            return Err(String::from("multi-line strings are not supported"));
+        }
+        let (string, rest) = basic_string(rest)?;
+        return Ok((Value::String(string), rest));
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let (string, rest) = rest.split_once('\'').filter(|(string, _)| !string.contains('\n')).ok_or("unterminated string")?;
+        return Ok((Value::String(String::from(string)), rest));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = skip_blank(rest);
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            if rest.is_empty() {
+                return Err(String::from("unterminated array"));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = skip_blank(after);
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => {}
+                None if rest.is_empty() => return Err(String::from("unterminated array")),
+                None => return Err(String::from("expected ',' or ']' in an array")),
+            }
+        }
+    }
+    let end = s.find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '#')).unwrap_or(s.len());
+    let word = &s[..end];
+    let value = match word {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => {
+            let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+            let valid = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit() || b == b'_')
+                && !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__");
+            let number = valid.then(|| word.replace('_', "").parse::<i64>().ok()).flatten();
+            match number {
+                Some(number) => Value::Integer(number),
+                None if word.is_empty() => return Err(String::from("missing value")),
+                None => return Err(format!("unsupported value '{word}'")),
+            }
+        }
+    };
+    Ok((value, &s[end..]))
+}
+
+// Nothing but a comment may follow on the line
+fn end_of_line(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start_matches([' ', '\t']);
+    match rest.is_empty() || rest.starts_with('#') || rest.starts_with('\n') || rest.starts_with("\r\n") {
+        true => Ok(()),
+        false => Err(String::from("unexpected text after the value")),
+    }
+}
+
+/// Parses a file into its entries, in the order they appear.
+///
+/// A key given twice in the same table is an error, like a table given twice.
+pub fn parse(text: &str) -> Result<Vec<Entry>, ParseError> {
+    let line_at = |rest: &str| 1 + text[..text.len() - rest.len()].matches('\n').count();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut tables: Vec<String> = Vec::new();
+    let mut table = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let line = line_at(rest);
+        let error = |message: String| ParseError { line, message };
+        let (current, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        let current = current.trim();
+        if current.is_empty() || current.starts_with('#') {
+            rest = next;
+            continue;
+        }
+        if let Some(header) = current.strip_prefix('[') {
+            if header.starts_with('[') {
+                return Err(error(String::from("arrays of tables are not supported")));
+            }
+            let (name, after) = header.split_once(']').ok_or_else(|| error(String::from("unterminated table header")))?;
+            let name = name.trim();
+            if !name.split('.').all(|part| !part.trim().is_empty() && part.trim().chars().all(is_bare)) {
+                return Err(error(format!("invalid table name '{name}'")));
+            }
+            end_of_line(after).map_err(error)?;
+            if tables.iter().any(|t| t == name) {
+                return Err(error(format!("table [{name}] defined twice")));
+            }
+            table = String::from(name);
+            tables.push(table.clone());
+            rest = next;
+            continue;
+        }
+        // The value is parsed from here on, an array may go on over the next lines
+        let (key, after) = parse_key(rest).map_err(error)?;
+        let after = after.trim_start_matches([' ', '\t']);
+        let after = after.strip_prefix('=').ok_or_else(|| error(format!("expected '=' after '{key}'")))?;
+        let (value, after) = parse_value(after.trim_start_matches([' ', '\t'])).map_err(|message| ParseError { line: line_at(after), message })?;
+        end_of_line(after).map_err(|message| ParseError { line: line_at(after), message })?;
+        if entries.iter().any(|entry| entry.table == table && entry.key == key) {
+            return Err(error(format!("key '{key}' defined twice")));
+        }
+        entries.push(Entry { table: table.clone(), key, value, line });
+        rest = after.split_once('\n').map(|(_, next)| next).unwrap_or("");
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(table: &str, key: &str, value: Value, line: usize) -> Entry {
+        Entry { table: String::from(table), key: String::from(key), value, line }
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(String::from(s))
+    }
+
+    fn error_of(text: &str) -> (usize, String) {
+        let err = parse(text).unwrap_err();
+        (err.line, err.message)
+    }
+
+    #[test]
+    fn tables_keys_and_values() {
+        let text = "# settings\ntop = 1\n\n[listener]\nlisten = [\"a\", 'b',]  # two\nopen = true\n[root.inner]\n\"odd key\" = -1_000\n";
+        assert_eq!(parse(text).unwrap(), [
+            entry("", "top", Value::Integer(1), 2),
+            entry("listener", "listen", Value::Array(vec![string("a"), string("b")]), 5),
+            entry("listener", "open", Value::Boolean(true), 6),
+            entry("root.inner", "odd key", Value::Integer(-1000), 8),
+        ]);
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("\r\n# only a comment\r\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn strings_and_their_escapes() {
+        let entries = parse("a = \"tab\\there \\\"q\\\" \\\\ \\u00e9 \\U0001F600\"\nb = 'C:\\raw\\path'").unwrap();
+        assert_eq!(entries[0].value, string("tab\there \"q\" \\ \u{e9} \u{1F600}"));
+        assert_eq!(entries[1].value, string("C:\\raw\\path"));
+        assert_eq!(error_of("a = 1\nb = \"\"\"long\"\"\""), (2, String::from("multi-line strings are not supported")));
+    }
+
+    #[test]
+    fn array_over_several_lines() {
+        let entries = parse("list = [\n  \"one\",  # first\n  # nothing\n  \"two\"\n]\nafter = 2").unwrap();
+        assert_eq!(entries[0].value, Value::Array(vec![string("one"), string("two")]));
+        assert_eq!((entries[1].key.as_str(), entries[1].line), ("after", 6));
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        assert_eq!(error_of("a = 1\nb = 2\na = 3"), (3, String::from("key 'a' defined twice")));
+        assert_eq!(error_of("[t]\n[u]\n[t]"), (3, String::from("table [t] defined twice")));
+        assert_eq!(error_of("\n[[servers]]"), (2, String::from("arrays of tables are not supported")));
+        assert_eq!(error_of("[bad name]").1, "invalid table name 'bad name'");
+        assert_eq!(error_of("[open").1, "unterminated table header");
+        assert_eq!(error_of("key 1").1, "expected '=' after 'key'");
+        assert_eq!(error_of("= 1").1, "missing key");
+        assert_eq!(error_of("a =").1, "missing value");
+        assert_eq!(error_of("a = 1.5").1, "unsupported value '1.5'");
+        assert_eq!(error_of("a = 1_").1, "unsupported value '1_'");
+        assert_eq!(error_of("a = 99999999999999999999").1, "unsupported value '99999999999999999999'");
+        assert_eq!(error_of("a = {x = 1}").1, "unsupported value '{x'");
+        assert_eq!(error_of("a = \"open").1, "unterminated string");
+        assert_eq!(error_of("a = \"\\x\"").1, "invalid escape \\x");
+        assert_eq!(error_of("a = \"\\u12\"").1, "invalid escape \\u12\"");
+        assert_eq!(error_of("a = 1 2").1, "unexpected text after the value");
+        assert_eq!(error_of("a = [1 2]").1, "expected ',' or ']' in an array");
+        // On the line of its key, however far the array went
+        assert_eq!(error_of("x = 0\na = [\n1,\n2\n"), (2, String::from("unterminated array")));
+    }
+
+    #[test]
+    fn quoted_strings_read_back() {
+        for s in ["plain", "", "with \"quotes\" and \\", "line\nbreak\ttab", "bell\u{7}", "caf\u{e9} \u{1F600}"] {
+            let entries = parse(&format!("{} = {}", key(s), quote(s))).unwrap();
+            assert_eq!((entries[0].key.as_str(), &entries[0].value), (s, &string(s)), "{s:?}");
+        }
+        assert_eq!(key("bare_key-1"), "bare_key-1");
+        assert_eq!(key("a.b"), "\"a.b\"");
+    }
+}
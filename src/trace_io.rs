@@ -0,0 +1,144 @@
+// --trace-io: the bytes of every request and response at trace level, escaped, as
+// they are taken off and put onto the connection. Bodies make up most of them, past
+// LIMIT bytes per request and direction the rest is left out
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+use crate::inject::Throttle;
+use crate::log::trace;
+
+const LIMIT: usize = 4096;
+
+// Reads are logged as they are consumed, so what is buffered ahead for the next
+// request is told with that request's id
+pub struct Traced<S> {
+    inner: S,
+    id: Option<String>,  // Of the request, None while not tracing
+    left: usize,
+}
+
+impl<S> Traced<S> {
+    pub fn new(inner: S) -> Self {
+        Traced { inner, id: None, left: 0 }
+    }
+
+    // For each request, None when it isn't traced
+    pub fn start(&mut self, id: Option<String>) {
+        self.id = id;
+        self.left = LIMIT;
+    }
+
+    fn log(&mut self, direction: &str, bytes: &[u8]) {
+        log(self.id.as_deref(), &mut self.left, direction, bytes);
+    }
+}
+
+fn log(id: Option<&str>, left: &mut usize, direction: &str, bytes: &[u8]) {
+    let Some(id) = id else {
+        return;
+    };
+    if bytes.is_empty() || *left == 0 {
+        return;
+    }
+    let shown = bytes.len().min(*left);
+    *left -= shown;
+    trace!("[{id}] {direction} {}", bytes[..shown].escape_ascii());
+    if shown < bytes.len() {
+        trace!("[{id}] {direction} more than {LIMIT} bytes, the rest is left out");
+    }
+}
+
+impl<S: Throttle> Throttle for Traced<S> {
+    fn throttle(&mut self, rate: Option<u64>) {
+        self.inner.throttle(rate);
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Traced<BufReader<R>> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            this.log("<", &buf.filled()[before..]);
+        }
+        polled
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for Traced<BufReader<R>> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        log(this.id.as_deref(), &mut this.left, "<", &this.inner.buffer()[..amt]);
+        Pin::new(&mut this.inner).consume(amt);
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Traced<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = polled {
+            this.log(">", &buf[..written]);
+        }
+        polled
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn bytes_pass_through_as_they_are() {
+        let mut reader = Traced::new(BufReader::new(&b"GET / HTTP/1.1\r\n\r\nbody"[..]));
+        reader.start(Some(String::from("id")));
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "GET / HTTP/1.1\r\n");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"\r\nbody");
+        let mut writer = Traced::new(Vec::new());
+        writer.start(Some(String::from("id")));
+        writer.write_all(b"HTTP/1.1 200 OK\r\n\r\n\xff").await.unwrap();
+        assert_eq!(writer.inner, b"HTTP/1.1 200 OK\r\n\r\n\xff");
+    }
+
+    #[tokio::test]
+    async fn limit_is_per_request() {
+        let mut writer = Traced::new(Vec::new());
+        writer.start(Some(String::from("first")));
+        writer.write_all(&[b'x'; 3000]).await.unwrap();
+        assert_eq!(writer.left, LIMIT - 3000);
+        writer.write_all(&[b'x'; 3000]).await.unwrap();
+        assert_eq!(writer.left, 0);
+        // All of it is still written
+        assert_eq!(writer.inner.len(), 6000);
+        writer.start(Some(String::from("second")));
+        assert_eq!(writer.left, LIMIT);
+    }
+
+    #[tokio::test]
+    async fn untraced_requests_count_nothing() {
+        let mut reader = Traced::new(BufReader::new(&b"GET / HTTP/1.1\r\n"[..]));
+        reader.start(None);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(reader.left, LIMIT);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+// Uploads running at the same time each need their own temporary file
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Where receiving an upload went wrong, the client's fault or ours
+#[derive(Debug)]
+pub enum UploadError {
+    Body(io::Error),  // Reading the request body
+    File(io::Error),  // Writing the file
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Body(err) => write!(f, "failed to read the body, {err}"),
+            UploadError::File(err) => write!(f, "failed to write the file, {err}"),
+        }
+    }
+}
+
+// The disk or the quota is full, 507 rather than our fault (RFC 4918 11.5)
+pub fn is_out_of_space(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+// Hidden file next to the target, the rename only is atomic inside one filesystem
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let id = UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+    target.with_file_name(format!(".{name}.{}-{id}.part", std::process::id()))
+}
+
+// A file being written next to its target, it only replaces the target on commit.
+// Whatever else happens to it, a failed write or a request dropped on a timeout,
+// the temporary file goes again
+pub struct Upload {
+    pub file: File,
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl Upload {
+    pub async fn create(target: &Path) -> io::Result<Upload> {
+        let temp = temp_path(target);
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp).await?;
+        Ok(Upload { file, temp, target: target.to_path_buf(), committed: false })
+    }
+
+    // Make the data durable, then swap it in with a single rename
+    pub async fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all().await?;
+        tokio::fs::rename(&self.temp, &self.target).await?;
+        self.committed = true;
+        Ok(())
+    }
+
+    pub async fn abort(mut self) {
+        self.committed = true;
+        let _ = tokio::fs::remove_file(&self.temp).await;
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+async fn copy_body(body: &mut (impl AsyncRead + Unpin), file: &mut File) -> Result<u64, UploadError> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let n = body.read(&mut buffer).await.map_err(UploadError::Body)?;
+        if n == 0 {
+            return Ok(total);
+        }
+        file.write_all(&buffer[..n]).await.map_err(UploadError::File)?;
+        total += n as u64;
+    }
+}
+
+// Store the body at target, readers see either the old file or the complete new one
+// and never a partial upload. Returns the number of bytes written
+pub async fn receive(body: &mut (impl AsyncRead + Unpin), target: &Path, expected: Option<u64>) -> Result<u64, UploadError> {
+    let mut upload = Upload::create(target).await.map_err(UploadError::File)?;
+    match copy_body(body, &mut upload.file).await {
+        // A Content-Length body cut short ends like a complete one
+        Ok(n) if expected.is_some_and(|len| len != n) => {
+            upload.abort().await;
+            Err(UploadError::Body(io::Error::new(io::ErrorKind::UnexpectedEof, "request body truncated")))
+        }
+        Ok(n) => upload.commit().await.map(|_| n).map_err(UploadError::File),
+        Err(err) => {
+            upload.abort().await;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // A body whose connection goes away
+    struct Reset;
+
+    impl AsyncRead for Reset {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: &mut tokio::io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")))
+        }
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("httpserver-upload-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.txt"), "old").unwrap();
+        dir
+    }
+
+    // Only the target, no temporary file left next to it
+    fn names(dir: &Path) -> Vec<String> {
+        let names = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        std::fs::remove_dir_all(dir).unwrap();
+        names
+    }
+
+    #[tokio::test]
+    async fn complete_body_replaces_the_target() {
+        let dir = dir("complete");
+        let target = dir.join("doc.txt");
+        assert_eq!(receive(&mut &b"new contents"[..], &target, Some(12)).await.unwrap(), 12);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new contents");
+        assert_eq!(receive(&mut &b"chunked"[..], &dir.join("other.txt"), None).await.unwrap(), 7);
+        let mut names = names(&dir);
+        names.sort();
+        assert_eq!(names, ["doc.txt", "other.txt"]);
+    }
+
+    #[tokio::test]
+    async fn failed_body_leaves_the_old_file() {
+        let dir = dir("failed");
+        let target = dir.join("doc.txt");
+        let mut body = (&b"partial"[..]).chain(Reset);
+        assert!(matches!(receive(&mut body, &target, None).await, Err(UploadError::Body(err)) if err.kind() == io::ErrorKind::ConnectionReset));
+        // Shorter than its Content-Length
+        let result = receive(&mut &b"short"[..], &target, Some(100)).await;
+        assert!(matches!(result, Err(UploadError::Body(err)) if err.kind() == io::ErrorKind::UnexpectedEof));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "old");
+        assert_eq!(names(&dir), ["doc.txt"]);
+    }
+
+    #[tokio::test]
+    async fn dropped_upload_takes_its_temporary_file() {
+        let dir = dir("dropped");
+        let mut upload = Upload::create(&dir.join("doc.txt")).await.unwrap();
+        upload.file.write_all(b"half").await.unwrap();
+        upload.file.flush().await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        drop(upload);
+        assert_eq!(names(&dir), ["doc.txt"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn full_disk_is_a_write_error() {
+        // Every write to /dev/full fails with ENOSPC
+        let mut file = OpenOptions::new().write(true).open("/dev/full").await.unwrap();
+        let body = vec![b'x'; 256 * 1024];
+        let result = match copy_body(&mut &body[..], &mut file).await {
+            Ok(_) => file.flush().await.map_err(UploadError::File),
+            Err(err) => Err(err),
+        };
+        assert!(matches!(result, Err(UploadError::File(err)) if is_out_of_space(&err)));
+    }
+
+    #[test]
+    fn out_of_space_is_only_a_full_disk_or_quota() {
+        assert!(is_out_of_space(&io::Error::from(io::ErrorKind::StorageFull)));
+        assert!(is_out_of_space(&io::Error::from(io::ErrorKind::QuotaExceeded)));
+        assert!(!is_out_of_space(&io::Error::from(io::ErrorKind::PermissionDenied)));
+        assert!(!is_out_of_space(&io::Error::from(io::ErrorKind::FileTooLarge)));
+    }
+}
@@ -0,0 +1,377 @@
+//! Percent-encoding and decoding of request targets (RFC 3986).
+//!
+//! Everything here works on one component at a time, the target is split into the
+//! path, the query and the segments *before* anything gets decoded, so an escape
+//! like `%2F` or `%3F` can never change the structure of the request.
+
+use std::fmt;
+
+/// Why a component couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,         // '%' without two characters after it
+    BadHex(String),    // '%' followed by something that isn't hex
+    InvalidUtf8,
+    EncodedSeparator,  // %2F inside a path segment
+    ControlChar,       // C0 control characters have no business in a path
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated percent escape"),
+            DecodeError::BadHex(hex) => write!(f, "invalid percent escape '%{hex}'"),
+            DecodeError::InvalidUtf8 => write!(f, "percent escapes are not valid utf-8"),
+            DecodeError::EncodedSeparator => write!(f, "encoded '/' in a path segment"),
+            DecodeError::ControlChar => write!(f, "control character in a path segment"),
+        }
+    }
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Decode all escapes into raw bytes first and validate the utf-8 once at the end,
+// from_utf8 rejects overlong forms, surrogates and sequences longer than 4 bytes
+// so an escape run can't swallow what comes after it
+fn decode(s: &str, plus_as_space: bool) -> Result<String, DecodeError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).ok_or(DecodeError::Truncated)?;
+                match (hex_value(hex[0]), hex_value(hex[1])) {
+                    (Some(h), Some(l)) => out.push(h << 4 | l),
+                    _ => return Err(DecodeError::BadHex(String::from_utf8_lossy(hex).into_owned())),
+                }
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| DecodeError::InvalidUtf8)
+}
+
+/// Decodes one segment of the path, `+` is a plain character here.
+///
+/// Fails on an escaped `/` (the segment would turn into two) and on C0 control characters.
+pub fn decode_path_segment(s: &str) -> Result<String, DecodeError> {
+    let segment = decode(s, false)?;
+    if segment.contains('/') {
+        return Err(DecodeError::EncodedSeparator);
+    }
+    if segment.chars().any(|c| c < ' ') {
+        return Err(DecodeError::ControlChar);
+    }
+    Ok(segment)
+}
+
+/// Decodes a key or a value of the query string, `+` means space.
+pub fn decode_query(s: &str) -> Result<String, DecodeError> {
+    decode(s, true)
+}
+
+/// Splits a request target into the path and the query, without decoding anything.
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    }
+}
+
+/// The name in a Host header, lowercase and without the port or a trailing dot.
+///
+/// `Files.LAN.:8080` gives `files.lan` and `[::1]:80` gives `[::1]`. `None` when it is
+/// no valid name or address, or empty.
+pub fn host_name(host: &str) -> Option<String> {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(rest) => {
+            let (address, after) = rest.split_once(']')?;
+            if address.is_empty() || !address.chars().all(|ch| ch.is_ascii_hexdigit() || ch == ':' || ch == '.') {
+                return None;
+            }
+            (&host[..address.len() + 2], after.strip_prefix(':').or(after.is_empty().then_some(""))?)
+        }
+        None => host.split_once(':').unwrap_or((host, "")),
+    };
+    if !port.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let valid = name.starts_with('[') || name.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && label.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+    });
+    valid.then(|| name.to_ascii_lowercase())
+}
+
+/// Why a path couldn't be normalized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    Decode(DecodeError),
+    NotAbsolute,
+    AboveRoot,  // More ".." than there are segments
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Decode(err) => write!(f, "{err}"),
+            PathError::NotAbsolute => write!(f, "path doesn't start with '/'"),
+            PathError::AboveRoot => write!(f, "path goes above the root"),
+        }
+    }
+}
+
+impl From<DecodeError> for PathError {
+    fn from(err: DecodeError) -> Self {
+        PathError::Decode(err)
+    }
+}
+
+/// Turns the (still encoded) path of a request target into its decoded segments.
+///
+/// Empty and `.` segments are dropped and `..` removes the previous segment (RFC 3986 5.2.4),
+/// escaped dots count as dots, so no segment of the result is ever `.` or `..`.
+/// `/a/./b/../c%20d/` gives `["a", "c d"]`, going above the root is an error.
+pub fn normalize_path(path: &str) -> Result<Vec<String>, PathError> {
+    let rest = path.strip_prefix('/').ok_or(PathError::NotAbsolute)?;
+    let mut segments = Vec::new();
+    for raw in rest.split('/') {
+        let segment = decode_path_segment(raw)?;
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop().ok_or(PathError::AboveRoot)?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+    Ok(segments)
+}
+
+// Characters that never need escaping (RFC 3986 2.3)
+fn is_unreserved(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || matches!(ch, '-' | '.' | '_' | '~')
+}
+
+fn encode(s: &str, space: &str) -> String {
+    let mut out = String::new();
+
+    for ch in s.chars() {
+        if is_unreserved(ch) {
+            // Is Part of char can directly sent
+            out.push(ch);
+            continue;
+        }
+        if ch == ' ' {
+            out.push_str(space);
+            continue;
+        }
+        // We need to encode it, always two digits so it decodes back
+        let mut buffer = [0u8; 4];
+        for uchar in ch.encode_utf8(&mut buffer).as_bytes() {
+            out.push_str(&format!("%{uchar:02X}"));
+        }
+    }
+
+    out
+}
+
+/// Encodes a single path segment, everything outside the unreserved set is escaped
+/// including space, `%`, `/`, `?` and `#`.
+pub fn encode_path_segment(s: &str) -> String {
+    encode(s, "%20")
+}
+
+/// Encodes a whole path, segment by segment, keeping the `/` between them.
+pub fn encode_path(path: &str) -> String {
+    path.split('/').map(encode_path_segment).collect::<Vec<_>>().join("/")
+}
+
+/// Encodes a key or a value for the query string, space becomes `+`.
+pub fn encode_query_value(s: &str) -> String {
+    encode(s, "+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_escapes_decode_in_either_case() {
+        assert_eq!(decode_path_segment("a%20b").unwrap(), "a b");
+        assert_eq!(decode_path_segment("%e4%b8%ad%E6%96%87").unwrap(), "中文");
+        assert_eq!(decode_path_segment("a+b").unwrap(), "a+b");
+        assert_eq!(decode_path_segment("").unwrap(), "");
+    }
+
+    #[test]
+    fn broken_escapes_are_errors() {
+        assert_eq!(decode_path_segment("a%"), Err(DecodeError::Truncated));
+        assert_eq!(decode_path_segment("a%2"), Err(DecodeError::Truncated));
+        assert_eq!(decode_path_segment("%zz"), Err(DecodeError::BadHex(String::from("zz"))));
+        assert_eq!(decode_path_segment("%+1"), Err(DecodeError::BadHex(String::from("+1"))));
+        assert_eq!(decode_query("%g0"), Err(DecodeError::BadHex(String::from("g0"))));
+    }
+
+    #[test]
+    fn decoded_bytes_have_to_be_utf8() {
+        assert_eq!(decode_path_segment("%ff"), Err(DecodeError::InvalidUtf8));
+        // Overlong '/' and a lone surrogate
+        assert_eq!(decode_path_segment("%c0%af"), Err(DecodeError::InvalidUtf8));
+        assert_eq!(decode_path_segment("%ed%a0%80"), Err(DecodeError::InvalidUtf8));
+        // Cut short, the escape run doesn't take the next character with it
+        assert_eq!(decode_path_segment("%e4%b8a"), Err(DecodeError::InvalidUtf8));
+    }
+
+    #[test]
+    fn segment_refuses_separators_and_controls() {
+        assert_eq!(decode_path_segment("a%2Fb"), Err(DecodeError::EncodedSeparator));
+        assert_eq!(decode_path_segment("a%2fb"), Err(DecodeError::EncodedSeparator));
+        assert_eq!(decode_path_segment("a%00"), Err(DecodeError::ControlChar));
+        assert_eq!(decode_path_segment("a%0Ab"), Err(DecodeError::ControlChar));
+        // Escaped '?' and '#' are just characters of the name
+        assert_eq!(decode_path_segment("a%3Fb%23").unwrap(), "a?b#");
+    }
+
+    #[test]
+    fn query_takes_plus_as_space() {
+        assert_eq!(decode_query("a+b%2Bc").unwrap(), "a b+c");
+        assert_eq!(decode_query("%2F%00").unwrap(), "/\0");
+    }
+
+    #[test]
+    fn target_splits_at_the_first_question_mark() {
+        assert_eq!(split_target("/a/b?x=1?y"), ("/a/b", Some("x=1?y")));
+        assert_eq!(split_target("/a%3Fb"), ("/a%3Fb", None));
+        assert_eq!(split_target("/?"), ("/", Some("")));
+    }
+
+    #[test]
+    fn normalize_drops_dots_and_empty_segments() {
+        assert_eq!(normalize_path("/a/./b/../c%20d/").unwrap(), ["a", "c d"]);
+        assert_eq!(normalize_path("//a///b").unwrap(), ["a", "b"]);
+        assert_eq!(normalize_path("/").unwrap(), Vec::<String>::new());
+        assert_eq!(normalize_path("/a/..").unwrap(), Vec::<String>::new());
+        assert_eq!(normalize_path("/a..b/...").unwrap(), ["a..b", "..."]);
+    }
+
+    #[test]
+    fn escaped_dots_count_as_dots() {
+        assert_eq!(normalize_path("/a/%2e%2E/b").unwrap(), ["b"]);
+        assert_eq!(normalize_path("/a/%2e/b").unwrap(), ["a", "b"]);
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd"), Err(PathError::AboveRoot));
+    }
+
+    #[test]
+    fn normalize_errors() {
+        assert_eq!(normalize_path("/.."), Err(PathError::AboveRoot));
+        assert_eq!(normalize_path("/a/../../b"), Err(PathError::AboveRoot));
+        assert_eq!(normalize_path("a/b"), Err(PathError::NotAbsolute));
+        assert_eq!(normalize_path(""), Err(PathError::NotAbsolute));
+        assert_eq!(normalize_path("/a%2F..%2Fb"), Err(PathError::Decode(DecodeError::EncodedSeparator)));
+        assert_eq!(normalize_path("/a/%"), Err(PathError::Decode(DecodeError::Truncated)));
+    }
+
+    #[test]
+    fn encoding_escapes_all_but_unreserved() {
+        assert_eq!(encode_path_segment("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(encode_path_segment("a b/c?d#e%f"), "a%20b%2Fc%3Fd%23e%25f");
+        assert_eq!(encode_path_segment("中"), "%E4%B8%AD");
+        assert_eq!(encode_path_segment("\n"), "%0A");
+        assert_eq!(encode_path("/dir name/file#1"), "/dir%20name/file%231");
+        assert_eq!(encode_query_value("a b&c=d+"), "a+b%26c%3Dd%2B");
+    }
+
+    #[test]
+    fn encoded_text_decodes_back() {
+        for text in ["plain", "a b", "100%", "tab\there", "中文 名字", "a+b=c&d", "?#[]@!$'()*,;"] {
+            assert_eq!(decode_query(&encode_query_value(text)).unwrap(), text);
+            let segment = encode_path_segment(text);
+            if !text.contains(|c: char| c < ' ') {
+                assert_eq!(decode_path_segment(&segment).unwrap(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn host_name_is_lowercase_without_port_or_dot() {
+        assert_eq!(host_name("Files.LAN.:8080").as_deref(), Some("files.lan"));
+        assert_eq!(host_name("localhost").as_deref(), Some("localhost"));
+        assert_eq!(host_name("[::1]:80").as_deref(), Some("[::1]"));
+        assert_eq!(host_name("[::1]").as_deref(), Some("[::1]"));
+        for invalid in ["", ":80", "a..b", "a b", "host:port", "[::1]x", "[]", "[zz]", "under_score"] {
+            assert_eq!(host_name(invalid), None, "{invalid}");
+        }
+    }
+
+    // Strings from a small xorshift generator, the same ones on every run
+    fn generated(count: usize) -> Vec<String> {
+        const POOL: &[char] = &['a', 'Z', '0', ' ', '%', '+', '/', '?', '#', '&', '=', '~', '\0', '\t', '\n', '\x1f', '\x7f', 'é', '中', '😀'];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..count).map(|_| (0..next() % 12).map(|_| POOL[(next() % POOL.len() as u64) as usize]).collect()).collect()
+    }
+
+    #[test]
+    fn every_ascii_character_round_trips() {
+        for byte in 0..=0x7fu8 {
+            let text = String::from(byte as char);
+            let encoded = encode_query_value(&text);
+            assert!(encoded.len() == 1 || (encoded.len() == 3 && encoded.starts_with('%')), "{byte:#x} as {encoded}");
+            assert_eq!(decode_query(&encoded).unwrap(), text, "{byte:#x}");
+        }
+    }
+
+    #[test]
+    fn encoding_round_trips_for_generated_strings() {
+        for text in generated(2000) {
+            assert_eq!(decode_query(&encode_query_value(&text)).unwrap(), text);
+            // Each escape is '%' with exactly two hex digits
+            let segment = encode_path_segment(&text);
+            assert!(segment.split('%').skip(1).all(|hex| hex.len() >= 2 && hex[..2].bytes().all(|b| b.is_ascii_hexdigit())), "{segment}");
+            if !text.contains(|c: char| c == '/' || c < ' ') {
+                assert_eq!(decode_path_segment(&segment).unwrap(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn only_well_formed_utf8_is_decoded() {
+        assert_eq!(decode_path_segment("%F0%9F%98%80").unwrap(), "😀");
+        assert_eq!(decode_query("%f0%9f%98%80+%c3%a9").unwrap(), "😀 é");
+        for invalid in [
+            "%C0%AF",        // Overlong '/'
+            "%E0%80%AF",     // The same in three bytes
+            "%ED%A0%80",     // UTF-16 surrogate
+            "%80",           // Lone continuation bytes
+            "a%BFb",
+            "%F0%9F%98",     // Emoji cut short
+            "%F4%90%80%80",  // Past U+10FFFF
+            "%F8%88%80%80%80",
+        ] {
+            assert_eq!(decode_path_segment(invalid), Err(DecodeError::InvalidUtf8), "{invalid}");
+            assert_eq!(decode_query(invalid), Err(DecodeError::InvalidUtf8), "{invalid}");
+        }
+    }
+}
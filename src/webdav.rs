@@ -0,0 +1,393 @@
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use httpserver::date;
+use httpserver::glob::Pattern;
+use httpserver::url;
+use crate::conditional;
+use crate::config::Config;
+use crate::escape_html;
+use crate::upload::Upload;
+
+const DAV: &str = "DAV:";
+
+// The properties we know about, in the order allprop lists them
+const LIVE_PROPS: [&str; 5] = ["displayname", "getcontentlength", "getlastmodified", "resourcetype", "getetag"];
+
+// What a PROPFIND asks for (RFC 4918 9.1), properties are (namespace, local name)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropRequest {
+    AllProp,
+    PropName,
+    Props(Vec<(String, String)>),
+}
+
+// Attributes of a start tag, None when they are malformed
+fn attributes(mut s: &str) -> Option<Vec<(&str, &str)>> {
+    let mut attrs = Vec::new();
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            return Some(attrs);
+        }
+        let (name, after) = s.split_once('=')?;
+        let after = after.trim_start();
+        let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let (value, rest) = after[1..].split_once(quote)?;
+        attrs.push((name.trim(), value));
+        s = rest;
+    }
+}
+
+// Just enough XML to read a propfind body: tags, attributes and namespace declarations.
+// Declarations aren't scoped, a later one for the same prefix wins, real clients
+// declare everything on the root element anyway. Text content is ignored
+pub fn parse_propfind(body: &[u8]) -> Option<PropRequest> {
+    let text = std::str::from_utf8(body).ok()?;
+    // No body at all means allprop
+    if text.trim().is_empty() {
+        return Some(PropRequest::AllProp);
+    }
+    let mut namespaces: Vec<(String, String)> = Vec::new();  // Prefix ("" for the default) and uri
+    let mut stack: Vec<(String, String)> = Vec::new();
+    let mut request = None;
+    let mut props = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        // Declarations, comments and the like carry nothing for us
+        let skip_to = match rest.chars().next() {
+            Some('?') => Some("?>"),
+            Some('!') if rest.starts_with("!--") => Some("-->"),
+            Some('!') => Some(">"),
+            _ => None,
+        };
+        if let Some(end) = skip_to {
+            rest = &rest[rest.find(end)? + end.len()..];
+            continue;
+        }
+        let end = rest.find('>')?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('/') {
+            stack.pop()?;
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        for (attr, value) in attributes(attrs)? {
+            if attr == "xmlns" {
+                namespaces.push((String::new(), String::from(value)));
+            }
+            else if let Some(prefix) = attr.strip_prefix("xmlns:") {
+                namespaces.push((String::from(prefix), String::from(value)));
+            }
+        }
+        let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+        // Names get echoed back in the response, keep them to plain ones
+        if local.is_empty() || !local.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+            return None;
+        }
+        let namespace = namespaces.iter().rev()
+            .find(|(p, _)| p == prefix)
+            .map(|(_, uri)| uri.clone())
+            .or_else(|| prefix.is_empty().then(String::new))?;  // An undeclared prefix is an error
+        let element = (namespace, String::from(local));
+
+        let is = |element: Option<&(String, String)>, local: &str| element.is_some_and(|(ns, l)| ns == DAV && l == local);
+        let parent = stack.last();
+        if stack.is_empty() && !is(Some(&element), "propfind") {
+            return None;
+        }
+        if stack.len() == 1 && is(parent, "propfind") {
+            match local {
+                _ if element.0 != DAV => {}
+                "allprop" => request = Some(PropRequest::AllProp),
+                "propname" => request = Some(PropRequest::PropName),
+                "prop" => request = Some(PropRequest::Props(Vec::new())),
+                _ => {}
+            }
+        }
+        if stack.len() == 2 && is(parent, "prop") {
+            props.push(element.clone());
+        }
+        if !self_closing {
+            stack.push(element);
+        }
+    }
+    match request? {
+        PropRequest::Props(_) => Some(PropRequest::Props(props)),
+        request => Some(request),
+    }
+}
+
+// Value of a DAV: property as XML content, None when the resource doesn't have it
+fn live_property(prop: &str, name: &str, meta: &Metadata) -> Option<String> {
+    match prop {
+        "displayname" => Some(escape_html(name)),
+        "getcontentlength" if !meta.is_dir() => Some(meta.len().to_string()),
+        "getlastmodified" => meta.modified().ok().map(date::http_date),
+        "resourcetype" if meta.is_dir() => Some(String::from("<D:collection/>")),
+        "resourcetype" => Some(String::new()),
+        "getetag" if !meta.is_dir() => Some(escape_html(&conditional::etag(meta))),
+        _ => None,
+    }
+}
+
+fn propstat(out: &mut String, props: &str, status: &str) {
+    out.push_str(&format!("<D:propstat><D:prop>{props}</D:prop><D:status>HTTP/1.1 {status}</D:status></D:propstat>"));
+}
+
+// The <D:response> for one resource
+fn write_response(out: &mut String, href: &str, name: &str, meta: &Metadata, request: &PropRequest) {
+    out.push_str(&format!("<D:response><D:href>{}</D:href>", escape_html(href)));
+    let available = LIVE_PROPS.iter().filter_map(|prop| live_property(prop, name, meta).map(|value| (prop, value)));
+    match request {
+        PropRequest::AllProp => {
+            let props: String = available.map(|(prop, value)| format!("<D:{prop}>{value}</D:{prop}>")).collect();
+            propstat(out, &props, "200 OK");
+        }
+        PropRequest::PropName => {
+            let props: String = available.map(|(prop, _)| format!("<D:{prop}/>")).collect();
+            propstat(out, &props, "200 OK");
+        }
+        PropRequest::Props(wanted) => {
+            let (mut found, mut missing) = (String::new(), String::new());
+            for (namespace, local) in wanted {
+                let value = match namespace.as_str() {
+                    DAV => live_property(local, name, meta),
+                    _ => None,
+                };
+                match (value, namespace.as_str()) {
+                    (Some(value), _) => found.push_str(&format!("<D:{local}>{value}</D:{local}>")),
+                    (None, DAV) => missing.push_str(&format!("<D:{local}/>")),
+                    (None, "") => missing.push_str(&format!("<{local} xmlns=\"\"/>")),
+                    (None, namespace) => missing.push_str(&format!("<x:{local} xmlns:x=\"{}\"/>", escape_html(namespace))),
+                }
+            }
+            if !found.is_empty() {
+                propstat(out, &found, "200 OK");
+            }
+            if !missing.is_empty() {
+                propstat(out, &missing, "404 Not Found");
+            }
+        }
+    }
+    out.push_str("</D:response>");
+}
+
+// The 207 body for path (on disk at file) and, with children, everything directly inside it
+pub async fn multistatus(path: &str, file: &str, meta: &Metadata, children: bool, request: &PropRequest, config: &Config) -> io::Result<String> {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\">");
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let mut href = url::encode_path(path);
+    if meta.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    write_response(&mut out, &href, name, meta, request);
+
+    if children && meta.is_dir() {
+        // Mounts with the properties of their directory, one that is gone is left out
+        let mounts: Vec<&str> = config.mounts_in(path).collect();
+        for name in &mounts {
+            let Some(mount) = config.mount(&format!("{}/{name}", path.trim_end_matches('/'))) else {
+                continue;
+            };
+            let Ok(meta) = tokio::fs::metadata(if mount.dir.is_empty() { "/" } else { &mount.dir }).await else {
+                continue;
+            };
+            write_response(&mut out, &format!("{href}{}/", url::encode_path_segment(name)), name, &meta, request);
+        }
+        let mut dir = tokio::fs::read_dir(file).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let Some(name) = crate::entry_name(&entry) else {
+                continue;
+            };
+            if config.exclude.iter().any(|pattern| pattern.matches(&name)) || mounts.contains(&name.as_str()) {
+                continue;
+            }
+            // Follow links like GET does, a broken one is left out
+            let Ok(meta) = tokio::fs::metadata(entry.path()).await else {
+                continue;
+            };
+            let mut child = format!("{href}{}", url::encode_path_segment(&name));
+            if meta.is_dir() {
+                child.push('/');
+            }
+            write_response(&mut out, &child, &name, &meta, request);
+        }
+    }
+    out.push_str("</D:multistatus>");
+    Ok(out)
+}
+
+// The path a Destination header (RFC 4918 10.3) points to on this server,
+// it may be an absolute path or a full URL. Err carries the status to answer with
+pub fn destination_path(value: &str, host: Option<&str>) -> Result<String, i32> {
+    let target = match value.starts_with('/') {
+        true => value,
+        false => {
+            let rest = value.strip_prefix("http://").or_else(|| value.strip_prefix("https://")).ok_or(400)?;
+            let (authority, path) = rest.find('/').map(|i| rest.split_at(i)).unwrap_or((rest, "/"));
+            // Another server's resources aren't ours to write (RFC 4918 9.9.4)
+            if !host.is_some_and(|host| host.eq_ignore_ascii_case(authority)) {
+                return Err(502);
+            }
+            path
+        }
+    };
+    let (path, _) = url::split_target(target);
+    let segments = url::normalize_path(path).map_err(|_| 400)?;
+    Ok(format!("/{}", segments.join("/")))
+}
+
+// Copy a file through a temporary one, so dst is never seen half written
+async fn copy_file(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut source = tokio::fs::File::open(src).await?;
+    let mut upload = Upload::create(dst).await?;
+    if let Err(err) = tokio::io::copy(&mut source, &mut upload.file).await {
+        upload.abort().await;
+        return Err(err);
+    }
+    upload.commit().await
+}
+
+// Copy a directory with everything in it, or only the directory itself when shallow.
+// Links to directories are left out, they could lead back into the tree
+async fn copy_tree(src: &Path, dst: &Path, shallow: bool, exclude: &[Pattern]) -> io::Result<()> {
+    let mut pending = vec![(PathBuf::from(src), PathBuf::from(dst))];
+    while let Some((src, dst)) = pending.pop() {
+        tokio::fs::create_dir(&dst).await?;
+        if shallow {
+            break;
+        }
+        let mut dir = tokio::fs::read_dir(&src).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            if exclude.iter().any(|pattern| pattern.matches(&name.to_string_lossy())) {
+                continue;
+            }
+            let (from, to) = (entry.path(), dst.join(&name));
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((from, to));
+            }
+            else if file_type.is_file() || tokio::fs::metadata(&from).await.is_ok_and(|meta| meta.is_file()) {
+                copy_file(&from, &to).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// COPY of a file or a directory, hidden names are not copied along
+pub async fn copy_path(src: &str, dst: &str, meta: &Metadata, shallow: bool, config: &Config) -> io::Result<()> {
+    match meta.is_dir() {
+        true => copy_tree(Path::new(src), Path::new(dst), shallow, &config.exclude).await,
+        false => copy_file(Path::new(src), Path::new(dst)).await,
+    }
+}
+
+// MOVE is a rename, only when that can't cross filesystems it becomes a copy
+// of everything (hidden names too, they are deleted with the source) and a delete
+pub async fn move_path(src: &str, dst: &str, meta: &Metadata) -> io::Result<()> {
+    match tokio::fs::rename(src, dst).await {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if meta.is_dir() {
+                copy_tree(Path::new(src), Path::new(dst), false, &[]).await?;
+                tokio::fs::remove_dir_all(src).await
+            }
+            else {
+                copy_file(Path::new(src), Path::new(dst)).await?;
+                tokio::fs::remove_file(src).await
+            }
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dav(local: &str) -> (String, String) {
+        (String::from(DAV), String::from(local))
+    }
+
+    #[test]
+    fn propfind_kinds() {
+        assert_eq!(parse_propfind(b""), Some(PropRequest::AllProp));
+        assert_eq!(parse_propfind(b" \r\n"), Some(PropRequest::AllProp));
+        let allprop = br#"<?xml version="1.0" encoding="utf-8"?><D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#;
+        assert_eq!(parse_propfind(allprop), Some(PropRequest::AllProp));
+        let propname = br#"<propfind xmlns="DAV:"><!-- names only --><propname/></propfind>"#;
+        assert_eq!(parse_propfind(propname), Some(PropRequest::PropName));
+    }
+
+    #[test]
+    fn props_with_their_namespaces() {
+        let body = br#"<?xml version="1.0"?>
+            <a:propfind xmlns:a="DAV:" xmlns:z='urn:example'>
+              <a:prop><a:getetag/><z:color/><a:resourcetype></a:resourcetype><plain/></a:prop>
+            </a:propfind>"#;
+        let expected = vec![dav("getetag"), (String::from("urn:example"), String::from("color")), dav("resourcetype"), (String::new(), String::from("plain"))];
+        assert_eq!(parse_propfind(body), Some(PropRequest::Props(expected)));
+        // Only what is directly in prop counts
+        let nested = br#"<D:propfind xmlns:D="DAV:"><D:prop><D:getetag><D:deeper/></D:getetag></D:prop></D:propfind>"#;
+        assert_eq!(parse_propfind(nested), Some(PropRequest::Props(vec![dav("getetag")])));
+    }
+
+    #[test]
+    fn bad_propfind_bodies() {
+        for body in [
+            &br#"<D:propfind xmlns:D="DAV:"></D:propfind>"#[..],           // Asks for nothing
+            br#"<propfind><allprop/></propfind>"#,                           // Not in DAV:
+            br#"<D:lockinfo xmlns:D="DAV:"><D:allprop/></D:lockinfo>"#,     // Another request
+            br#"<D:propfind xmlns:D="DAV:"><D:prop><x:a/></D:prop></D:propfind>"#,  // Undeclared prefix
+            br#"<D:propfind xmlns:D="DAV:"><D:prop><D:a"b/></D:prop></D:propfind>"#,
+            br#"<D:propfind xmlns:D=DAV:><D:allprop/></D:propfind>"#,       // Unquoted attribute
+            br#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind></D:propfind>"#,
+            br#"<D:propfind xmlns:D="DAV:"><D:allprop"#,
+            b"\xff\xfe",
+            b"not xml",
+        ] {
+            assert_eq!(parse_propfind(body), None, "{}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn response_of_a_file() {
+        let path = std::env::temp_dir().join(format!("httpserver-webdav-{}", std::process::id()));
+        std::fs::write(&path, "12345").unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut out = String::new();
+        write_response(&mut out, "/a%20%26.txt", "a &.txt", &meta, &PropRequest::AllProp);
+        assert!(out.starts_with("<D:response><D:href>/a%20%26.txt</D:href><D:propstat><D:prop><D:displayname>a &amp;.txt</D:displayname>"), "{out}");
+        assert!(out.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(out.contains("<D:resourcetype></D:resourcetype>"));
+        assert!(out.ends_with("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"));
+
+        let mut out = String::new();
+        let wanted = vec![dav("getcontentlength"), dav("quota"), (String::from("urn:x"), String::from("color"))];
+        write_response(&mut out, "/a", "a", &meta, &PropRequest::Props(wanted));
+        assert!(out.contains("<D:prop><D:getcontentlength>5</D:getcontentlength></D:prop><D:status>HTTP/1.1 200 OK"), "{out}");
+        assert!(out.contains("<D:prop><D:quota/><x:color xmlns:x=\"urn:x\"/></D:prop><D:status>HTTP/1.1 404 Not Found"), "{out}");
+    }
+
+    #[test]
+    fn destinations_on_this_server() {
+        assert_eq!(destination_path("/a/b.txt", None), Ok(String::from("/a/b.txt")));
+        assert_eq!(destination_path("/a/./c/../b%20c.txt?x", None), Ok(String::from("/a/b c.txt")));
+        assert_eq!(destination_path("http://Example.com:8080/x/", Some("example.com:8080")), Ok(String::from("/x")));
+        assert_eq!(destination_path("https://localhost", Some("localhost")), Ok(String::from("/")));
+        // Someone else's, or not a URL we understand
+        assert_eq!(destination_path("http://other.com/x", Some("example.com")), Err(502));
+        assert_eq!(destination_path("http://example.com/x", None), Err(502));
+        assert_eq!(destination_path("ftp://example.com/x", Some("example.com")), Err(400));
+        assert_eq!(destination_path("x.txt", None), Err(400));
+        assert_eq!(destination_path("/../etc/passwd", None), Err(400));
+    }
+
+}
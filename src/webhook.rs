@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use crate::access;
+use crate::request::Request;
+use crate::log::warn;
+
+// Events waiting to be delivered, more than this and new ones are dropped
+// rather than piling up behind a receiver that is down
+const QUEUE_SIZE: usize = 256;
+const ATTEMPTS: u32 = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static QUEUE: OnceLock<mpsc::Sender<Event>> = OnceLock::new();
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// Where a --webhook goes, only plain http, there is no TLS client here
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    host: String,  // For the Host header, with the port when it was given
+    addr: String,  // To connect to
+    path: String,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Option<Webhook> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = rest.find('/').map(|i| rest.split_at(i)).unwrap_or((rest, "/"));
+        if authority.is_empty() || authority.contains('@') || path.bytes().any(|b| b <= b' ') {
+            return None;
+        }
+        let has_port = authority.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let addr = match has_port {
+            true => String::from(authority),
+            false => format!("{authority}:80"),
+        };
+        Some(Webhook { url: String::from(url), host: String::from(authority), addr, path: String::from(path) })
+    }
+}
+
+// Something that changed on the share
+pub struct Event {
+    kind: &'static str,
+    path: String,
+    destination: Option<String>,  // Where a move went
+    size: Option<u64>,
+    client: String,
+    user: Option<String>,
+    time: SystemTime,
+}
+
+impl Event {
+    fn to_json(&self) -> String {
+        let timestamp = self.time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+        serde_json::json!({
+            "event": self.kind,
+            "path": self.path,
+            "destination": self.destination,
+            "size": self.size,
+            "client": self.client,
+            "user": self.user,
+            "timestamp": timestamp,
+        }).to_string()
+    }
+}
+
+// Queue an event about the request, it never waits for the delivery. Does nothing
+// without webhooks
+pub fn notify(request: &Request, kind: &'static str, path: &str, destination: Option<&str>, size: Option<u64>) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let event = Event {
+        kind,
+        path: String::from(path),
+        destination: destination.map(String::from),
+        size,
+        client: access::host(&request.client),  // The IP, the port says nothing about who it was
+        user: request.user.clone(),
+        time: SystemTime::now(),
+    };
+    if queue.try_send(event).is_err() {
+        let dropped = DROPPED.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!("[{}] webhook queue is full, dropped the {kind} event ({dropped} dropped so far)", request.id);
+    }
+}
+
+// Events given up on after every attempt, for /metrics
+pub fn failed() -> u64 {
+    FAILED.load(Ordering::Relaxed)
+}
+
+// Events that didn't fit in the queue
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// One POST, Ok when the receiver answered with a 2xx
+async fn deliver(hook: &Webhook, body: &str, secret: Option<&str>) -> Result<(), String> {
+    let mut stream = TcpStream::connect(&hook.addr).await.map_err(|err| err.to_string())?;
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: httpserver\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        hook.path, hook.host, body.len()
+    );
+    // Receivers recompute it over the raw body with the shared secret
+    if let Some(secret) = secret {
+        head.push_str(&format!("X-Hub-Signature-256: sha256={}\r\n", signature(secret, body)));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await.map_err(|err| err.to_string())?;
+    stream.write_all(body.as_bytes()).await.map_err(|err| err.to_string())?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status).await.map_err(|err| err.to_string())?;
+    match status.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') && code.len() == 3 => Ok(()),
+        _ => Err(format!("answered {}", status.trim_end())),
+    }
+}
+
+// Try a few times, waiting longer after every failure
+async fn deliver_with_retries(hook: &Webhook, body: &str, secret: Option<&str>) {
+    let mut backoff = Duration::from_secs(1);
+    for attempt in 1..=ATTEMPTS {
+        let result = match tokio::time::timeout(TIMEOUT, deliver(hook, body, secret)).await {
+            Ok(result) => result,
+            Err(_) => Err(String::from("timed out")),
+        };
+        match result {
+            Ok(()) => return,
+            Err(err) if attempt < ATTEMPTS => {
+                warn!("webhook to {} failed, {err}, retrying in {}s", hook.url, backoff.as_secs());
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(err) => {
+                let failed = FAILED.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("webhook to {} failed, {err}, giving up ({failed} failed so far)", hook.url);
+            }
+        }
+    }
+}
+
+// Start delivering events, one at a time so they arrive in order
+pub fn start(hooks: Vec<Webhook>, secret: Option<String>) {
+    let (sender, mut receiver) = mpsc::channel::<Event>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        return;
+    }
+    tokio::task::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            let body = event.to_json();
+            for hook in &hooks {
+                deliver_with_retries(hook, &body, secret.as_deref()).await;
+            }
+        }
+    });
+}
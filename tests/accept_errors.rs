@@ -0,0 +1,25 @@
+// A failing accept, out of file descriptors, only pauses the listener
+mod common;
+
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use common::{Server, TempDir};
+
+#[test]
+fn listener_outlives_running_out_of_descriptors() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    assert_eq!(server.get("/a.txt").status, 200);
+    // Room for only a few more, then hold more connections than that
+    let open = std::fs::read_dir(format!("/proc/{}/fd", server.pid())).unwrap().count();
+    let limit = format!("--nofile={}", open + 3);
+    let status = Command::new("prlimit").args(["--pid", &server.pid().to_string(), &limit]).status().unwrap();
+    assert!(status.success());
+    let held: Vec<_> = (0..10).map(|_| server.connect()).collect();
+    assert!(server.wait_for_output("failed to accept tcp listener"), "{}", server.output());
+    drop(held);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(server.get("/a.txt").body, "a");
+}
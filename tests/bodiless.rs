@@ -0,0 +1,66 @@
+// 204 and 304 end with their head, and so does every reply to a HEAD; the next response comes right after it
+mod common;
+
+use common::{send_and_close, Response, Server, TempDir};
+
+fn server() -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "some text");
+    root.write("dir/b.txt", "b");
+    let server = Server::start(&["--write", "--trailing-slash", "add", root.str()]);
+    (root, server)
+}
+
+// The bodiless response and the GET after it on the same connection
+fn then_get(server: &Server, request: &str) -> Response {
+    let raw = send_and_close(&mut server.connect(), format!("{request}GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes());
+    let (head, rest) = raw.split_once("\r\n\r\n").unwrap();
+    assert!(rest.starts_with("HTTP/1.1 200"), "bytes after the head: {rest:?}");
+    assert_eq!(Response::parse(rest).body, "some text");
+    Response::parse(&format!("{head}\r\n\r\n"))
+}
+
+#[test]
+fn not_modified_has_no_body() {
+    let (_root, server) = server();
+    let etag = server.get("/a.txt").header("ETag").unwrap().to_string();
+    let response = then_get(&server, &format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {etag}\r\n\r\n"));
+    assert_eq!(response.status, 304);
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert_eq!(response.header("Content-Length"), None);
+}
+
+#[test]
+fn no_content_has_no_body() {
+    let (_root, server) = server();
+    let response = then_get(&server, "OPTIONS /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert_eq!(response.header("Content-Length"), None);
+}
+
+#[test]
+fn delete_answers_with_no_content() {
+    let (root, server) = server();
+    root.write("gone.txt", "x");
+    let response = then_get(&server, "DELETE /gone.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 204);
+    assert!(!root.path().join("gone.txt").exists());
+}
+
+#[test]
+fn head_error_keeps_its_length_and_leaves_out_the_body() {
+    let (_root, server) = server();
+    let response = then_get(&server, "HEAD /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 404);
+    let length: usize = response.header("Content-Length").unwrap().parse().unwrap();
+    assert!(length > 0);
+}
+
+#[test]
+fn head_redirect_leaves_out_the_body() {
+    let (_root, server) = server();
+    let response = then_get(&server, "HEAD /dir HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("/dir/"));
+}
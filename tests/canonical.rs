@@ -0,0 +1,78 @@
+// --canonical-host and --canonical-scheme 301 everyone to one address
+mod common;
+
+use common::{Response, Server, TempDir};
+
+fn on(server: &Server, host: &str, target: &str, headers: &str) -> Response {
+    Response::parse(&server.send(format!("GET {target} HTTP/1.1\r\nHost: {host}\r\n{headers}\r\n").as_bytes()))
+}
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    root.write(".well-known/acme-challenge/token", "proof");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn other_host_is_moved_with_path_and_query() {
+    let (_root, server) = site(&["--canonical-host", "example.com"]);
+    for host in ["www.example.com", "www.example.com:8080"] {
+        let response = on(&server, host, "/a.txt?x=1&y=%20", "");
+        assert_eq!(response.status, 301, "{host}");
+        assert_eq!(response.header("Location"), Some("http://example.com/a.txt?x=1&y=%20"), "{host}");
+    }
+    for host in ["example.com", "example.com:80", "Example.COM"] {
+        assert_eq!(on(&server, host, "/a.txt", "").body, "a", "{host}");
+    }
+}
+
+#[test]
+fn canonical_port_goes_into_the_location() {
+    let (_root, server) = site(&["--canonical-host", "example.com:8080"]);
+    assert_eq!(on(&server, "www.example.com:8080", "/a.txt", "").header("Location"), Some("http://example.com:8080/a.txt"));
+    assert_eq!(on(&server, "example.com:8080", "/a.txt", "").body, "a");
+}
+
+#[test]
+fn https_and_the_host_change_in_one_redirect() {
+    let (_root, server) = site(&["--canonical-host", "example.com", "--canonical-scheme", "https", "--trust-forwarded-proto"]);
+    let response = on(&server, "www.example.com", "/a.txt", "");
+    assert_eq!(response.header("Location"), Some("https://example.com/a.txt"));
+    // Already https at the proxy, only the name is wrong
+    let response = on(&server, "www.example.com", "/a.txt", "X-Forwarded-Proto: https\r\n");
+    assert_eq!(response.header("Location"), Some("https://example.com/a.txt"));
+    assert_eq!(on(&server, "example.com", "/a.txt", "").header("Location"), Some("https://example.com/a.txt"));
+    assert_eq!(on(&server, "example.com", "/a.txt", "X-Forwarded-Proto: https\r\n").body, "a");
+}
+
+#[test]
+fn acme_metrics_and_the_exempt_are_served_on_any_host() {
+    let (root, server) = site(&["--canonical-host", "example.com", "--metrics", "--canonical-exempt", "/health"]);
+    root.write("health/ok.txt", "ok");
+    assert_eq!(on(&server, "www.example.com", "/.well-known/acme-challenge/token", "").body, "proof");
+    assert_eq!(on(&server, "www.example.com", "/metrics", "").status, 200);
+    assert_eq!(on(&server, "www.example.com", "/health/ok.txt", "").body, "ok");
+    assert_eq!(on(&server, "www.example.com", "/healthy", "").status, 301);
+}
+
+#[test]
+fn canonical_redirect_goes_before_the_vhosts() {
+    let other = TempDir::new();
+    other.write("a.txt", "www site");
+    let (_root, server) = site(&["--canonical-host", "example.com", "--vhost", "www.example.com", &format!("root={}", other.str())]);
+    assert_eq!(on(&server, "www.example.com", "/a.txt", "").status, 301);
+}
+
+#[test]
+fn bad_canonical_settings_are_refused() {
+    for (args, expected) in [
+        (["--canonical-host", "example.com/x"], "invalid canonical host 'example.com/x', expected a name like example.com[:8080]"),
+        (["--canonical-scheme", "ftp"], "invalid canonical scheme 'ftp', expected http or https"),
+        (["--canonical-exempt", "health"], "canonical exemption 'health' must start with '/'"),
+    ] {
+        let output = common::run(&args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(expected), "{args:?}");
+    }
+}
@@ -0,0 +1,92 @@
+// --check says what is wrong with the settings and exits, without binding or creating anything
+mod common;
+
+use std::net::TcpListener;
+use std::process::Output;
+use common::{run, TempDir};
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn good_settings_print_what_would_run() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    // Nothing is bound, a port in use is no problem
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+    let output = run(&["--check", root.str(), "--port", &port, "--threads", "3"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let printed = stdout(&output);
+    assert!(printed.contains(&format!("listen = [\"127.0.0.1:{port}\"]")), "{printed}");
+    assert!(printed.contains("threads = 3"), "{printed}");
+    assert!(printed.contains(&format!("paths = [{:?}]", root.str())), "{printed}");
+    // What it prints is a config file of its own
+    let config = dir.write("printed.toml", &printed);
+    let again = run(&["--config", config.to_str().unwrap(), "--check"]);
+    assert!(again.status.success(), "{}", stderr(&again));
+    assert_eq!(stdout(&again), printed);
+}
+
+#[test]
+fn broken_config_files_are_refused() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    let unknown = dir.write("unknown.toml", format!("[root]\npaths = [{:?}]\nbogus = 1\n", root.str()));
+    let unparsable = dir.write("unparsable.toml", "[root\n");
+    for (file, expected) in [(unknown, "line 3: unknown key 'root.bogus'"), (unparsable, "line 1: unterminated table header")] {
+        let output = run(&["--config", file.to_str().unwrap(), "--check"]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "");
+    }
+    // Started without --check the unknown key is only a warning
+    let lax = run(&["--config", dir.path().join("unknown.toml").to_str().unwrap(), "--print-config"]);
+    assert!(lax.status.success(), "{}", stderr(&lax));
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let root = TempDir::new();
+    let missing = root.path().join("missing");
+    let file = root.write("a.txt", "a");
+    let output = run(&[
+        "--check", missing.to_str().unwrap(),
+        "--mount", &format!("/a={}", file.display()),
+        "--pid-file", "/no-such-dir/x.pid",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let errors = stderr(&output);
+    assert!(errors.contains(&format!("{} does not exist, add --create-root to create it", missing.display())), "{errors}");
+    assert!(errors.contains(&format!("{} is not a directory", file.display())), "{errors}");
+    assert!(errors.contains("/no-such-dir/x.pid can't be written, /no-such-dir is not a directory"), "{errors}");
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn bad_values_fail_as_they_would_at_startup() {
+    let root = TempDir::new();
+    let missing_page = root.path().join("nope.html");
+    for (args, expected) in [
+        (vec!["--error-page", "404", missing_page.to_str().unwrap()], "error page"),
+        (vec!["--rewrite", "/a/[", "/b"], "unterminated character class in pattern '/a/['"),
+        (vec!["--mount", "/a=/x", "--mount", "/a/=/y"], "/a is mounted twice"),
+        (vec!["--log-file", "/tmp/x.log", "--log-max-size", "100", "--user", "65534", "--chroot", "/tmp"], "--log-max-size can't rotate the log file from inside --chroot /tmp"),
+    ] {
+        let output = run(&[&["--check", root.str()], &args[..]].concat());
+        assert!(!output.status.success(), "{args:?}");
+        assert!(stderr(&output).contains(expected), "{args:?}: {}", stderr(&output));
+    }
+}
+
+#[test]
+fn missing_root_is_fine_with_create_root_and_stays_missing() {
+    let root = TempDir::new();
+    let missing = root.path().join("new/site");
+    let output = run(&["--check", missing.to_str().unwrap(), "--create-root"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.path().join("new").exists());
+}
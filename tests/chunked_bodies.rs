@@ -0,0 +1,37 @@
+// Request bodies sent with Transfer-Encoding: chunked
+mod common;
+
+use common::{Response, Server, TempDir};
+
+const HEAD: &str = "PUT /upload.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+#[test]
+fn chunked_upload_with_extensions_and_trailers_is_stored() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let raw = server.send(format!("{HEAD}4;ext=1\r\nhell\r\n7\r\no world\r\n0\r\nX-Checksum: 1\r\n\r\n").as_bytes());
+    assert_eq!(Response::parse(&raw).status, 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("upload.txt")).unwrap(), "hello world");
+}
+
+#[test]
+fn next_request_follows_the_chunked_body() {
+    let root = TempDir::new();
+    root.write("next.txt", "next");
+    let server = Server::start(&["--write", root.str()]);
+    let raw = server.send(format!("{HEAD}2\r\nok\r\n0\r\n\r\nGET /next.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes());
+    let (upload, rest) = Response::parse_next(&raw);
+    assert_eq!(upload.status, 201);
+    assert_eq!(Response::parse(rest).body, "next");
+}
+
+#[test]
+fn broken_chunked_bodies_store_nothing() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    for body in ["zz\r\nab\r\n0\r\n\r\n", "2\r\nabc\r\n0\r\n\r\n", "5\r\nab"] {
+        let raw = server.send(format!("{HEAD}{body}").as_bytes());
+        assert_eq!(Response::parse(&raw).status, 400, "{body:?}");
+        assert!(!root.path().join("upload.txt").exists(), "{body:?}");
+    }
+}
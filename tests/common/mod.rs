@@ -0,0 +1,307 @@
+// Runs the server binary on a free port for the tests in this directory, and talks
+// to it with raw bytes so what goes over the wire is exactly what the test says
+#![allow(dead_code)] // Each test file uses its own part of this
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Server {
+    child: Child,
+    pub addr: SocketAddr,
+    output: Arc<Mutex<String>>,  // Everything it logged so far
+}
+
+impl Server {
+    pub fn start(args: &[&str]) -> Server {
+        Server::start_listening(&[&["--port", "0"], args].concat())
+    }
+
+    // Without --port, for --listen. One of them has to be 127.0.0.1:0 to be found
+    pub fn start_listening(args: &[&str]) -> Server {
+        Server::spawn(args, &[])
+    }
+
+    // With these variables added, like a PATH with stand-ins for other programs
+    pub fn start_with_env(env: &[(&str, &str)], args: &[&str]) -> Server {
+        Server::spawn(&[&["--port", "0"], args].concat(), env)
+    }
+
+    fn spawn(args: &[&str], env: &[(&str, &str)]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_httpserver"))
+            .args(args)
+            .env_remove("RUST_LOG")
+            .envs(env.iter().copied())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("the server starts");
+        let output = Arc::new(Mutex::new(String::new()));
+        let (found, port) = std::sync::mpsc::channel();
+        // Read all along, a full pipe would stall the server
+        for stream in [Box::new(child.stdout.take().unwrap()) as Box<dyn Read + Send>, Box::new(child.stderr.take().unwrap())] {
+            let (output, found) = (output.clone(), found.clone());
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    if let Some(port) = line.split("http://127.0.0.1:").nth(1).and_then(|rest| rest.split('/').next()) {
+                        let _ = found.send(port.parse::<u16>().ok());
+                    }
+                    let mut output = output.lock().unwrap();
+                    output.push_str(&line);
+                    output.push('\n');
+                }
+                let _ = found.send(None);
+            });
+        }
+        let port = match port.recv_timeout(Duration::from_secs(10)) {
+            Ok(Some(port)) => port,
+            _ => {
+                let _ = child.kill();
+                panic!("the server didn't start:\n{}", output.lock().unwrap());
+            }
+        };
+        Server { child, addr: SocketAddr::from(([127, 0, 0, 1], port)), output }
+    }
+
+    // With --log-file the url shows up in the file instead of the output
+    pub fn start_logging_to(log: &Path, args: &[&str]) -> Server {
+        let log_arg = log.to_str().unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_httpserver"))
+            .args(["--port", "0", "--log-file", log_arg])
+            .args(args)
+            .env_remove("RUST_LOG")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the server starts");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let port = loop {
+            let logged = std::fs::read_to_string(log).unwrap_or_default();
+            if let Some(port) = logged.split("http://127.0.0.1:").nth(1).and_then(|rest| rest.split('/').next()?.parse().ok()) {
+                break port;
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                panic!("the server didn't start:\n{logged}");
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        Server { child, addr: SocketAddr::from(([127, 0, 0, 1], port)), output: Arc::default() }
+    }
+
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream
+    }
+
+    // Sends the bytes as they are, then closes the sending side so the server ends the
+    // connection once it answered everything, and reads all of that
+    pub fn send(&self, request: &[u8]) -> String {
+        send_and_close(&mut self.connect(), request)
+    }
+
+    pub fn get(&self, path: &str) -> Response {
+        Response::parse(&self.send(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()))
+    }
+
+    // One request with these headers besides Host, and a Content-Length for a body
+    pub fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let mut request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+        Response::parse(&self.send(&request))
+    }
+
+    pub fn output(&self) -> String {
+        self.output.lock().unwrap().clone()
+    }
+
+    // Until the log says so, for what happens after the response went out
+    pub fn wait_for_output(&self, text: &str) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if self.output().contains(text) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Exited by itself within the time, with the status
+    pub fn wait_exit(&mut self, limit: Duration) -> Option<std::process::ExitStatus> {
+        let deadline = Instant::now() + limit;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return Some(status);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Runs the binary to the end, for flags that never start serving or fail to
+pub fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_httpserver"))
+        .args(args)
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::null())
+        .output()
+        .expect("the server runs")
+}
+
+// The lines of a log file once there are as many, or what there is after a while
+pub fn wait_for_lines(log: &Path, count: usize) -> Vec<String> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let lines: Vec<String> = std::fs::read_to_string(log).unwrap_or_default().lines().map(String::from).collect();
+        if lines.len() >= count || Instant::now() > deadline {
+            return lines;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+pub fn read_all(stream: &mut TcpStream) -> String {
+    let mut bytes = Vec::new();
+    let _ = stream.read_to_end(&mut bytes);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+pub fn send_and_close(stream: &mut TcpStream, request: &[u8]) -> String {
+    stream.write_all(request).unwrap();
+    let _ = stream.shutdown(Shutdown::Write);
+    read_all(stream)
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    // The first response in what was read, the rest is left
+    pub fn parse(raw: &str) -> Response {
+        Response::parse_next(raw).0
+    }
+
+    // One response and what follows it, by its Content-Length
+    pub fn parse_next(raw: &str) -> (Response, &str) {
+        let (head, rest) = raw.split_once("\r\n\r\n").unwrap_or_else(|| panic!("no response head in {raw:?}"));
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        let length = headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, value)| value.parse::<usize>().unwrap());
+        let (body, rest) = match length {
+            Some(length) if length <= rest.len() => rest.split_at(length),
+            _ => (rest, ""),
+        };
+        (Response { status, headers, body: body.to_string() }, rest)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(other, _)| other.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+// A fresh directory for one test, removed with it
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let name = format!("httpserver-test-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// A chunked body as it was sent, and the trailer lines after it
+pub fn decode_chunked(mut raw: &str) -> (String, Vec<String>) {
+    let mut body = String::new();
+    loop {
+        let (size, rest) = raw.split_once("\r\n").expect("a chunk size line");
+        let size = usize::from_str_radix(size.split(';').next().unwrap(), 16).expect("a hex chunk size");
+        if size == 0 {
+            let (trailers, rest) = match rest.strip_prefix("\r\n") {
+                Some(rest) => ("", rest),
+                None => rest.split_once("\r\n\r\n").expect("the final CRLF"),
+            };
+            assert!(rest.is_empty(), "bytes after the chunked body: {rest:?}");
+            return (body, trailers.split("\r\n").filter(|line| !line.is_empty()).map(String::from).collect());
+        }
+        body.push_str(&rest[..size]);
+        raw = rest[size..].strip_prefix("\r\n").expect("CRLF after the chunk data");
+    }
+}
+
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for group in bytes.chunks(3) {
+        let n = group.iter().fold(0u32, |n, &b| n << 8 | b as u32) << (8 * (3 - group.len()));
+        for i in 0..4 {
+            out.push(if i <= group.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    out
+}
@@ -0,0 +1,126 @@
+// --compress and --access-log for files, both of them middleware around the file
+mod common;
+
+use common::{wait_for_lines, Response, Server, TempDir};
+
+fn page() -> String {
+    "<p>the same line over and over</p>\n".repeat(200)
+}
+
+#[test]
+fn gzipped_file_has_an_etag_of_its_own() {
+    let root = TempDir::new();
+    root.write("page.html", page());
+    let server = Server::start(&[root.str(), "--compress"]);
+    let plain = server.get("/page.html");
+    let gzipped = server.request("GET", "/page.html", &[("Accept-Encoding", "gzip")], b"");
+    assert_eq!(plain.header("Content-Encoding"), None);
+    assert_eq!(gzipped.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(gzipped.header("Vary"), Some("Accept-Encoding"));
+    assert!(gzipped.header("Content-Length").unwrap().parse::<usize>().unwrap() < page().len());
+    let (identity, etag) = (plain.header("ETag").unwrap(), gzipped.header("ETag").unwrap());
+    assert_eq!(etag, format!("{}-gzip\"", identity.trim_end_matches('"')));
+    // Each tag only matches its own representation
+    let revalidated = server.request("GET", "/page.html", &[("Accept-Encoding", "gzip"), ("If-None-Match", etag)], b"");
+    assert_eq!(revalidated.status, 304);
+    let other = server.request("GET", "/page.html", &[("If-None-Match", etag)], b"");
+    assert_eq!(other.status, 200);
+}
+
+#[test]
+fn ranges_come_from_the_uncompressed_file() {
+    let root = TempDir::new();
+    root.write("page.html", page());
+    let server = Server::start(&[root.str(), "--compress"]);
+    let response = server.request("GET", "/page.html", &[("Accept-Encoding", "gzip"), ("Range", "bytes=0-2")], b"");
+    assert_eq!(response.status, 206);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.body, "<p>");
+}
+
+#[test]
+fn small_and_binary_files_go_as_they_are() {
+    let root = TempDir::new();
+    root.write("small.txt", "tiny");
+    root.write("image.png", page());
+    let server = Server::start(&[root.str(), "--compress"]);
+    for path in ["/small.txt", "/image.png"] {
+        let response = server.request("GET", path, &[("Accept-Encoding", "gzip")], b"");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Encoding"), None, "{path}");
+    }
+}
+
+#[test]
+fn access_log_has_one_line_with_what_was_sent() {
+    let root = TempDir::new();
+    root.write("page.html", page());
+    let logs = TempDir::new();
+    let log = logs.path().join("access.log");
+    let server = Server::start(&[root.str(), "--access-log", log.to_str().unwrap()]);
+    // A file through the middleware, a range of it, and a 404 that never got there
+    server.get("/page.html");
+    server.request("GET", "/page.html", &[("Range", "bytes=0-9")], b"");
+    server.get("/missing");
+    let lines = wait_for_lines(&log, 3);
+    assert_eq!(lines.len(), 3, "{lines:?}");
+    assert!(lines[0].contains(&format!("\"GET /page.html HTTP/1.1\" 200 {}", page().len())), "{}", lines[0]);
+    assert!(lines[1].contains("\"GET /page.html HTTP/1.1\" 206 10"), "{}", lines[1]);
+    assert!(lines[2].contains("\"GET /missing HTTP/1.1\" 404"), "{}", lines[2]);
+}
+
+// The head only of a GET that accepts gzip, the bytes read lossily aren't the body any more
+fn gzip_head(server: &Server, path: &str) -> Response {
+    let raw = server.send(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n").as_bytes());
+    let head = raw.split("\r\n\r\n").next().unwrap();
+    Response::parse(&format!("{head}\r\n\r\n"))
+}
+
+#[test]
+fn higher_level_sends_less() {
+    let root = TempDir::new();
+    // Lines that differ, so level 9 has something to find that level 1 doesn't
+    let text: String = (0..4000u32).map(|n| format!("line {} of {}\n", n.wrapping_mul(2654435761) % 1000, n % 7)).collect();
+    root.write("page.txt", &text);
+    let size = |level: &str| {
+        let server = Server::start(&[root.str(), "--compress", "--compression-level", level]);
+        let response = gzip_head(&server, "/page.txt");
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"), "level {level}");
+        response.header("Content-Length").unwrap().parse::<usize>().unwrap()
+    };
+    let (fastest, smallest) = (size("1"), size("9"));
+    assert!(smallest <= fastest, "{smallest} > {fastest}");
+    assert!(fastest < text.len());
+}
+
+#[test]
+fn hundred_bytes_go_uncompressed_below_the_minimum() {
+    let root = TempDir::new();
+    let body = "a".repeat(100);
+    root.write("hundred.txt", &body);
+    let server = Server::start(&[root.str(), "--compress"]);
+    let response = server.request("GET", "/hundred.txt", &[("Accept-Encoding", "gzip")], b"");
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.body, body);
+    // A lower minimum is taken as well
+    let server = Server::start(&[root.str(), "--compress", "--compression-min-size", "50"]);
+    let response = server.request("GET", "/hundred.txt", &[("Accept-Encoding", "gzip")], b"");
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+}
+
+#[test]
+fn what_would_grow_goes_as_it_is() {
+    let root = TempDir::new();
+    // Text by its type, random bytes inside, gzip only adds to them
+    let mut seed = 1u64;
+    let body: Vec<u8> = (0..4096).map(|_| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as u8
+    }).collect();
+    root.write("noise.txt", &body);
+    let server = Server::start(&[root.str(), "--compress"]);
+    let response = gzip_head(&server, "/noise.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Content-Length"), Some("4096"));
+}
@@ -0,0 +1,72 @@
+// --config files, the flags on top of them and --print-config
+mod common;
+
+use common::{run, Server, TempDir};
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn flags_go_on_top_of_the_file() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let config = dir.write("site.toml", format!("[root]\npaths = [{:?}]\n\n[security]\nframe_options = \"SAMEORIGIN\"\nreferrer_policy = \"origin\"\n", root.str()));
+    let server = Server::start(&["--config", config.to_str().unwrap(), "--referrer-policy", "same-origin"]);
+    let response = server.get("/a.txt");
+    assert_eq!((response.status, response.body.as_str()), (200, "a"));
+    assert_eq!(response.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert_eq!(response.header("Referrer-Policy"), Some("same-origin"));
+}
+
+#[test]
+fn printed_config_reads_back_the_same() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    let first = run(&[
+        "--print-config", root.str(), "--threads", "3", "--exclude", ".*", "--csp", "default-src 'self' \"x\"",
+        "--route", "/assets", "cache=60", "--mount", &format!("/m={}", root.str()), "-H", "X-A: 1",
+    ]);
+    assert!(first.status.success(), "{}", stderr(&first));
+    let printed = stdout(&first);
+    assert!(printed.contains("threads = 3") && printed.contains("exclude = [\".*\"]"), "{printed}");
+    let config = dir.write("printed.toml", &printed);
+    let second = run(&["--print-config", "--config", config.to_str().unwrap()]);
+    assert!(second.status.success(), "{}", stderr(&second));
+    assert_eq!(stdout(&second), printed);
+    assert_eq!(stderr(&second), "", "no unknown keys in what was printed");
+}
+
+#[test]
+fn unknown_keys_warn_or_fail_when_strict() {
+    let dir = TempDir::new();
+    let config = dir.write("c.toml", "[listener]\nthreads = 2\ncolour = \"blue\"\n");
+    let output = run(&["--print-config", "--config", config.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("line 3: unknown key 'listener.colour'"), "{}", stderr(&output));
+    let output = run(&["--print-config", "--strict-config", "--config", config.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("line 3: unknown key 'listener.colour'"), "{}", stderr(&output));
+}
+
+#[test]
+fn errors_name_the_key_and_line() {
+    let dir = TempDir::new();
+    for (text, expected) in [
+        ("[listener]\n\nthreads = \"many\"\n", "line 3: 'threads' must be an integer, not a string"),
+        ("[listener]\nthreads = 0\n", "line 2: 'threads': invalid thread count '0'"),
+        ("[root]\npaths = [\"a\"\n", "line 2: unterminated array"),
+        ("[listener]\nthreads = 2\nthreads = 3\n", "line 3: key 'threads' defined twice"),
+    ] {
+        let config = dir.write("bad.toml", text);
+        let output = run(&["--print-config", "--config", config.to_str().unwrap()]);
+        assert!(!output.status.success(), "{text}");
+        assert!(stderr(&output).contains(expected), "{text}: {}", stderr(&output));
+    }
+    let output = run(&["--config", dir.path().join("missing.toml").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("missing.toml"));
+}
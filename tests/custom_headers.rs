@@ -0,0 +1,80 @@
+// -H and --append-header put headers on every response, errors and redirects too
+mod common;
+
+use std::net::TcpStream;
+use common::{run, send_and_close, Response, Server, TempDir};
+
+fn all<'a>(response: &'a Response, name: &str) -> Vec<&'a str> {
+    response.headers.iter().filter(|(other, _)| other.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str()).collect()
+}
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    root.write("dir/b.txt", "b");
+    let server = Server::start(&[&[root.str(), "--trailing-slash", "add"], args].concat());
+    (root, server)
+}
+
+#[test]
+fn headers_go_on_200_404_and_301() {
+    let (_root, server) = site(&["-H", "Access-Control-Allow-Origin: *", "-H", "X-Robots-Tag: noindex"]);
+    let redirect = server.get("/dir");
+    assert_eq!(redirect.status, 301);
+    for (response, status) in [(server.get("/a.txt"), 200), (server.get("/missing"), 404), (redirect, 301)] {
+        assert_eq!(response.status, status);
+        assert_eq!(all(&response, "Access-Control-Allow-Origin"), ["*"], "{status}");
+        assert_eq!(all(&response, "X-Robots-Tag"), ["noindex"], "{status}");
+    }
+    // A 400 for a request that was never understood as well
+    let response = Response::parse(&server.send(b"GARBAGE\r\n\r\n"));
+    assert_eq!((response.status, response.header("X-Robots-Tag")), (400, Some("noindex")));
+}
+
+#[test]
+fn later_ones_replace_and_appended_ones_add() {
+    let (_root, server) = site(&[
+        "-H", "X-Robots-Tag: noindex", "-H", "x-robots-tag: none",
+        "-H", "Link: </a.css>; rel=preload", "--append-header", "Link: </b.js>; rel=preload",
+    ]);
+    let response = server.get("/a.txt");
+    assert_eq!(all(&response, "X-Robots-Tag"), ["none"]);
+    assert_eq!(all(&response, "Link"), ["</a.css>; rel=preload", "</b.js>; rel=preload"]);
+}
+
+#[test]
+fn one_the_server_sends_is_replaced() {
+    let (_root, server) = site(&["-H", "X-Frame-Options: SAMEORIGIN", "-H", "Cache-Control: no-store"]);
+    let response = server.get("/a.txt");
+    assert_eq!(all(&response, "X-Frame-Options"), ["SAMEORIGIN"]);
+    assert_eq!(all(&response, "Cache-Control"), ["no-store"]);
+    // The framing is still the server's
+    assert_eq!((response.header("Content-Length"), response.body.as_str()), (Some("1"), "a"));
+}
+
+#[test]
+fn redirect_listener_sends_them_too() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--redirect-https", "127.0.0.1:0", "-H", "X-Robots-Tag: noindex"]);
+    assert!(server.wait_for_output("Redirecting to https from "), "{}", server.output());
+    let addr = server.output().split("Redirecting to https from ").nth(1).unwrap().lines().next().unwrap().to_string();
+    let raw = send_and_close(&mut TcpStream::connect(addr).unwrap(), b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let response = Response::parse(&raw);
+    assert_eq!((response.status, response.header("X-Robots-Tag")), (301, Some("noindex")));
+}
+
+#[test]
+fn invalid_headers_are_refused_at_startup() {
+    for (value, expected) in [
+        ("X-A: 1\r\nSet-Cookie: a=b", "invalid value for the header X-A, it must stay on one line"),
+        ("Bad Name: 1", "invalid header name 'Bad Name'"),
+        ("NoColon", "invalid header 'NoColon', expected 'NAME: VALUE'"),
+        ("Content-Length: 5", "Content-Length is set by the server itself, it can't be given with -H"),
+        ("Date: today", "Date is set by the server itself, it can't be given with -H"),
+        ("Connection: close", "Connection is set by the server itself, it can't be given with -H"),
+    ] {
+        let output = run(&["-H", value]);
+        assert!(!output.status.success(), "{value:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains(expected), "{value:?}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
@@ -0,0 +1,101 @@
+// --daemon and --pid-file: the terminal gets the outcome, the pid file the process
+#![cfg(unix)]
+mod common;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use common::{run, Server, TempDir};
+
+// Gone, or a zombie nobody reaped yet, which is as gone as it gets
+fn is_running(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks the process is there
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    let zombie = std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .is_ok_and(|status| status.lines().any(|line| line.starts_with("State:") && line.contains('Z')));
+    exists && !zombie
+}
+
+fn wait_until(limit: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + limit;
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn port_in(log: &Path) -> u16 {
+    let mut port = None;
+    wait_until(Duration::from_secs(10), || {
+        let logged = std::fs::read_to_string(log).unwrap_or_default();
+        port = logged.split("http://127.0.0.1:").nth(1).and_then(|rest| rest.split('/').next()?.parse().ok());
+        port.is_some()
+    });
+    port.expect("the url in the log file")
+}
+
+#[test]
+fn daemon_runs_in_the_background_until_signalled() {
+    let dir = TempDir::new();
+    dir.write("site/a.txt", "from the daemon");
+    let (pid_file, log) = (dir.path().join("server.pid"), dir.path().join("server.log"));
+    let site = dir.path().join("site");
+    let args = [site.to_str().unwrap(), "--port", "0", "--daemon", "--pid-file", pid_file.to_str().unwrap(), "--log-file", log.to_str().unwrap()];
+    let output = run(&args);
+    assert!(output.status.success(), "{output:?}");
+    let said = String::from_utf8_lossy(&output.stderr).into_owned();
+    let pid: i32 = said.trim().rsplit(' ').next().unwrap().parse().unwrap_or_else(|_| panic!("{said}"));
+    assert!(said.contains("running in the background with pid"), "{said}");
+    assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), format!("{pid}\n"));
+    assert!(is_running(pid));
+    // Serving, and detached: a session of its own
+    let port = port_in(&log);
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let raw = common::send_and_close(&mut stream, b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(raw.ends_with("from the daemon"), "{raw}");
+    // In a session of its own that it doesn't lead, the first child did
+    // SAFETY: these read the sessions of processes
+    let (session, ours) = unsafe { (libc::getsid(pid), libc::getsid(0)) };
+    assert!(session != ours && session != pid, "session {session} of {pid}, ours {ours}");
+    // A second one is kept out by the lock
+    let second = run(&args);
+    assert!(!second.status.success());
+    assert!(String::from_utf8_lossy(&second.stderr).contains(&format!("already running with pid {pid}")), "{second:?}");
+    // SAFETY: a plain syscall to the daemon
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    assert!(wait_until(Duration::from_secs(10), || !is_running(pid)), "still running");
+    assert!(!pid_file.exists(), "the pid file is left behind");
+}
+
+#[test]
+fn startup_failure_is_told_on_the_terminal() {
+    let dir = TempDir::new();
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let listen = taken.local_addr().unwrap().to_string();
+    let pid_file = dir.path().join("server.pid");
+    let output = run(&[dir.str(), "--listen", &listen, "--daemon", "--pid-file", pid_file.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let said = String::from_utf8_lossy(&output.stderr);
+    assert!(said.contains(&format!("failed to listen on {listen}")), "{said}");
+    // A pid file that can't be written is told as well
+    let output = run(&[dir.str(), "--port", "0", "--daemon", "--pid-file", "/nonexistent/dir/server.pid"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed to open the pid file /nonexistent/dir/server.pid"), "{output:?}");
+}
+
+#[test]
+fn pid_file_in_the_foreground_too() {
+    let dir = TempDir::new();
+    let pid_file = dir.path().join("server.pid");
+    let mut server = Server::start(&[dir.str(), "--pid-file", pid_file.to_str().unwrap()]);
+    assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), format!("{}\n", server.pid()));
+    // Serving means the signals are handled as well, the url is printed before
+    assert_eq!(server.get("/").status, 200);
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGTERM) }, 0);
+    assert!(server.wait_exit(Duration::from_secs(10)).is_some_and(|status| status.success()));
+    assert!(!pid_file.exists());
+}
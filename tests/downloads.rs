@@ -0,0 +1,42 @@
+// Content-Disposition, for files a browser should save instead of showing
+mod common;
+
+use common::{Server, TempDir};
+
+fn server(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("report.pdf", "pdf");
+    root.write("notes.txt", "notes");
+    root.write("résumé 中文.txt", "utf-8");
+    root.write("say \"hi\".txt", "quoted");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn download_query_makes_an_attachment() {
+    let (_root, server) = server(&[]);
+    assert_eq!(server.get("/notes.txt").header("Content-Disposition"), None);
+    let response = server.get("/notes.txt?download");
+    assert_eq!(response.body, "notes");
+    assert_eq!(response.header("Content-Disposition"), Some("attachment; filename=\"notes.txt\""));
+}
+
+#[test]
+fn utf8_name_goes_encoded_next_to_an_ascii_one() {
+    let (_root, server) = server(&[]);
+    let response = server.get("/r%C3%A9sum%C3%A9%20%E4%B8%AD%E6%96%87.txt?download");
+    assert_eq!(response.header("Content-Disposition"), Some(
+        "attachment; filename=\"r_sum_ __.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%E4%B8%AD%E6%96%87.txt"
+    ));
+    // Quotes would end the plain one early
+    let quoted = server.get("/say%20%22hi%22.txt?download");
+    assert_eq!(quoted.header("Content-Disposition"), Some("attachment; filename=\"say _hi_.txt\"; filename*=UTF-8''say%20%22hi%22.txt"));
+}
+
+#[test]
+fn download_extensions_are_attachments_without_asking() {
+    let (_root, server) = server(&["--download-ext", "PDF,zip"]);
+    assert_eq!(server.get("/report.pdf").header("Content-Disposition"), Some("attachment; filename=\"report.pdf\""));
+    assert_eq!(server.get("/notes.txt").header("Content-Disposition"), None);
+}
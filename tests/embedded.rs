@@ -0,0 +1,106 @@
+// --embedded serves what the build packed, with the fixture site in tests/embedded-site:
+//   HTTPSERVER_EMBED=tests/embedded-site cargo test --features embedded --test embedded
+mod common;
+
+#[cfg(not(feature = "embedded"))]
+#[test]
+fn embedded_needs_the_feature() {
+    let output = common::run(&["--embedded"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--embedded needs a build with --features embedded and HTTPSERVER_EMBED=DIR"));
+}
+
+#[cfg(feature = "embedded")]
+mod packed {
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use super::common::{Server, TempDir};
+
+    const SITE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/embedded-site");
+
+    fn site(name: &str) -> String {
+        std::fs::read_to_string(format!("{SITE}/{name}")).unwrap()
+    }
+
+    fn embedded(args: &[&str]) -> Server {
+        Server::start(&[&["--embedded"], args].concat())
+    }
+
+    #[test]
+    fn files_and_directory_indexes_are_served() {
+        let server = embedded(&[]);
+        let index = server.get("/");
+        assert_eq!((index.status, index.body.clone()), (200, site("index.html")));
+        assert!(index.header("Content-Type").unwrap().starts_with("text/html"));
+        assert_eq!(server.get("/docs/").body, site("docs/index.html"));
+        let css = server.get("/app/style.css");
+        assert!(css.header("Content-Type").unwrap().starts_with("text/css"));
+        assert_eq!(css.body, site("app/style.css"));
+        // There are no listings, a directory without an index is missing
+        assert_eq!(server.get("/app/").status, 404);
+        assert_eq!(server.get("/missing.txt").status, 404);
+    }
+
+    #[test]
+    fn etag_is_of_the_content_and_conditionals_work() {
+        let server = embedded(&[]);
+        let first = server.get("/app/digits.txt");
+        let etag = first.header("ETag").unwrap().to_string();
+        assert!(first.header("Last-Modified").is_some());
+        let again = embedded(&[]);
+        assert_eq!(again.get("/app/digits.txt").header("ETag"), Some(etag.as_str()));
+        assert_eq!(server.request("GET", "/app/digits.txt", &[("If-None-Match", &etag)], b"").status, 304);
+    }
+
+    #[test]
+    fn ranges_are_cut_from_the_packed_bytes() {
+        let server = embedded(&[]);
+        let response = server.request("GET", "/app/digits.txt", &[("Range", "bytes=2-4")], b"");
+        assert_eq!((response.status, response.body.as_str()), (206, "234"));
+        assert_eq!(response.header("Content-Range"), Some("bytes 2-4/10"));
+    }
+
+    #[test]
+    fn gzip_copy_goes_to_those_who_accept_it() {
+        let server = embedded(&[]);
+        let mut stream = server.connect();
+        let raw = {
+            use std::io::Write;
+            stream.write_all(b"GET /app/style.css HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n").unwrap();
+            let mut raw = Vec::new();
+            stream.read_to_end(&mut raw).unwrap();
+            raw
+        };
+        let split = raw.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&raw[..split]).to_lowercase();
+        assert!(head.contains("content-encoding: gzip") && head.contains("vary: accept-encoding"), "{head}");
+        let mut css = String::new();
+        GzDecoder::new(&raw[split + 4..]).read_to_string(&mut css).unwrap();
+        assert_eq!(css, site("app/style.css"));
+        // Not for the others
+        assert_eq!(server.get("/app/style.css").header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn mounts_are_served_from_the_disk_next_to_it() {
+        let disk = TempDir::new();
+        disk.write("a.txt", "from the disk");
+        let server = embedded(&["--mount", &format!("/files={}", disk.str())]);
+        assert_eq!(server.get("/files/a.txt").body, "from the disk");
+        assert_eq!(server.get("/").body, site("index.html"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nothing_on_the_disk_is_needed() {
+        // SAFETY: a plain syscall without arguments
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("skipped, chroot needs root");
+            return;
+        }
+        let empty = TempDir::new();
+        let server = Server::start(&["--embedded", "--chroot", empty.str(), "--user", "nobody"]);
+        assert_eq!(server.get("/").body, site("index.html"));
+        assert_eq!(server.get("/docs/").body, site("docs/index.html"));
+    }
+}
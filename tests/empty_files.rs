@@ -0,0 +1,58 @@
+// A zero-byte file is a 200 with Content-Length: 0 and nothing after the head
+mod common;
+
+use common::{send_and_close, Response, Server, TempDir};
+
+const REQUEST: &str = "GET /empty.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+fn server(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("empty.txt", "");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn empty_file_is_a_200_with_no_body() {
+    let (_root, server) = server(&[]);
+    let raw = server.send(REQUEST.as_bytes());
+    assert!(raw.ends_with("\r\n\r\n"), "bytes after the head: {raw:?}");
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("0"));
+    assert!(response.header("Content-Type").unwrap().starts_with("text/plain"));
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn next_response_follows_the_empty_one_right_away() {
+    let (_root, server) = server(&[]);
+    let raw = send_and_close(&mut server.connect(), format!("{REQUEST}{REQUEST}").as_bytes());
+    let (first, rest) = Response::parse_next(&raw);
+    assert_eq!((first.status, first.body.as_str()), (200, ""));
+    assert!(rest.starts_with("HTTP/1.1 200"), "not the second response: {rest:?}");
+    let (second, rest) = Response::parse_next(rest);
+    assert_eq!(second.header("Content-Length"), Some("0"));
+    assert!(rest.is_empty(), "bytes after the second response: {rest:?}");
+}
+
+#[test]
+fn empty_file_is_left_alone_by_compress() {
+    let (_root, server) = server(&["--compress"]);
+    let response = server.request("GET", "/empty.txt", &[("Accept-Encoding", "gzip")], b"");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Content-Length"), Some("0"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn head_of_an_empty_file_matches_the_get() {
+    let (_root, server) = server(&[]);
+    let raw = server.send(b"HEAD /empty.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(raw.ends_with("\r\n\r\n"));
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("0"));
+}
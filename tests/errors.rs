@@ -0,0 +1,73 @@
+// Mistakes of the client are 4xx, what goes wrong in the server is a 500
+mod common;
+
+use common::{run, Response, Server, TempDir};
+
+#[test]
+fn malformed_requests_are_client_errors() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    for (request, status) in [
+        (&b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nNoColon\r\n\r\n"[..], 400),
+        (b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nBad Name: x\r\n\r\n", 400),
+        (b"GARBAGE\r\n\r\n", 400),
+        (b"GET /a.txt HTTP/1.1 extra\r\nHost: localhost\r\n\r\n", 400),
+        (b"GET a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n", 400),
+        (b"GET /a.txt HTTP/3.0\r\nHost: localhost\r\n\r\n", 505),
+    ] {
+        let response = Response::parse(&server.send(request));
+        assert_eq!(response.status, status, "{:?}", String::from_utf8_lossy(request));
+    }
+}
+
+#[test]
+fn rewrites_going_in_circles_are_a_server_error() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--rewrite", "/a", "/b", "--rewrite", "/b", "/a"]);
+    assert_eq!(server.get("/a").status, 500);
+    assert!(server.wait_for_output("the rules are going in circles"));
+}
+
+#[test]
+fn class_template_gets_the_code_and_reason() {
+    let pages = TempDir::new();
+    let class = pages.write("4xx.html", "<h1>{{code}} {{reason}}</h1>");
+    let missing = pages.write("404.html", "<h1>nothing at all</h1>");
+    let root = TempDir::new();
+    std::fs::create_dir(root.path().join("closed")).unwrap();
+    let args = [root.str(), "--route", "/closed/", "listing=off", "--error-page", "4xx", class.to_str().unwrap()];
+    let server = Server::start(&args);
+    let response = server.get("/closed/");
+    assert_eq!(response.status, 403);
+    assert_eq!(response.body, "<h1>403 Forbidden</h1>");
+    assert!(response.header("Content-Type").unwrap().starts_with("text/html"));
+    assert_eq!(server.get("/nothing").body, "<h1>404 Not Found</h1>");
+    // The page of the status comes before the one of its class
+    let server = Server::start(&[&args[..], &["--error-page", "404", missing.to_str().unwrap()]].concat());
+    assert_eq!(server.get("/nothing").body, "<h1>nothing at all</h1>");
+    assert_eq!(server.get("/closed/").body, "<h1>403 Forbidden</h1>");
+}
+
+#[test]
+fn without_a_template_the_builtin_body_stays() {
+    let pages = TempDir::new();
+    let server_errors = pages.write("5xx.html", "{{code}}");
+    let root = TempDir::new();
+    std::fs::create_dir(root.path().join("closed")).unwrap();
+    let server = Server::start(&[root.str(), "--route", "/closed/", "listing=off", "--error-page", "5xx", server_errors.to_str().unwrap()]);
+    let response = server.get("/closed/");
+    assert_eq!(response.status, 403);
+    assert!(!response.body.is_empty() && response.body != "403", "{}", response.body);
+}
+
+#[test]
+fn bad_error_pages_are_refused_at_startup() {
+    let pages = TempDir::new();
+    let page = pages.write("page.html", "x");
+    let output = run(&["--error-page", "3xx", page.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid error page status '3xx'"));
+    let output = run(&["--error-page", "404", pages.path().join("gone.html").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("gone.html"));
+}
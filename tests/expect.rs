@@ -0,0 +1,91 @@
+// Expect: 100-continue the way curl sends it, the head first and the body only once
+// the server said to go on. A refusal comes instead, before any of the body
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use common::{read_all, Response, Server, TempDir};
+
+// One response head, without reading on into what follows
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => break,
+        }
+    }
+    String::from_utf8_lossy(&head).into_owned()
+}
+
+// The head of a request whose body is held back, and what the server said to it
+fn send_head(server: &Server, head: &str) -> (TcpStream, String, Duration) {
+    let mut stream = server.connect();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let started = Instant::now();
+    stream.write_all(head.as_bytes()).unwrap();
+    let answer = read_head(&mut stream);
+    (stream, answer, started.elapsed())
+}
+
+fn put(path: &str, length: u64, extra: &str) -> String {
+    format!("PUT {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\nExpect: 100-continue\r\n{extra}\r\n")
+}
+
+#[test]
+fn accepted_upload_gets_a_100_right_away() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--write"]);
+    let (mut stream, answer, waited) = send_head(&server, &put("/new.txt", 5, ""));
+    assert_eq!(answer, "HTTP/1.1 100 Continue\r\n\r\n");
+    assert!(waited < Duration::from_millis(500), "{waited:?}");
+    stream.write_all(b"hello").unwrap();
+    assert_eq!(Response::parse(&read_head(&mut stream)).status, 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("new.txt")).unwrap(), "hello");
+}
+
+// Each of them is answered with its final status and then the connection ends, the
+// body that may still come is no request
+fn assert_refused(server: &Server, head: &str, status: u16) {
+    let (mut stream, answer, _) = send_head(server, head);
+    let response = Response::parse(&answer);
+    assert_eq!(response.status, status, "{answer}");
+    assert!(!answer.contains("100 Continue"));
+    // The page of the error, then nothing more
+    let _ = stream.write_all(b"hello");
+    let rest = read_all(&mut stream);
+    assert!(!rest.contains("HTTP/1.1"), "{rest}");
+}
+
+#[test]
+fn refused_uploads_get_their_status_instead() {
+    let root = TempDir::new();
+    root.write("taken.txt", "x");
+    root.write("docs/readme.txt", "x");
+    let server = Server::start(&[root.str(), "--write", "--route", "/docs", "write=off"]);
+    // Not writable, no directory to put it in, a precondition that fails
+    assert_refused(&server, &put("/docs/readme.txt", 5, ""), 405);
+    assert_refused(&server, &put("/missing/new.txt", 5, ""), 404);
+    assert_refused(&server, &put("/taken.txt", 5, "If-None-Match: *\r\n"), 412);
+    // Too large to ever be read
+    assert_refused(&server, &put("/big.bin", 1 << 40, ""), 413);
+    // A method nothing here knows
+    assert_refused(&server, "BREW /pot HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n", 501);
+    assert_eq!(std::fs::read_to_string(root.path().join("docs/readme.txt")).unwrap(), "x");
+}
+
+#[test]
+fn missing_credentials_are_refused_before_the_body() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--write", "--auth", "user:secret"]);
+    assert_refused(&server, &put("/new.txt", 5, ""), 401);
+}
+
+#[test]
+fn unknown_expectation_is_a_417() {
+    let server = Server::start(&[]);
+    let (_stream, answer, _) = send_head(&server, "PUT /x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: something-else\r\n\r\n");
+    assert_eq!(Response::parse(&answer).status, 417);
+}
@@ -0,0 +1,49 @@
+// --favicon for a /favicon.ico the directory doesn't have
+mod common;
+
+use common::{run, Server, TempDir};
+
+#[test]
+fn missing_favicon_is_a_404_by_default() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str()]);
+    assert_eq!(server.get("/favicon.ico").status, 404);
+}
+
+#[test]
+fn builtin_favicon_is_an_icon() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--favicon", "builtin"]);
+    let response = server.get("/favicon.ico");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("image/x-icon"));
+    let size = std::fs::metadata(concat!(env!("CARGO_MANIFEST_DIR"), "/src/favicon.ico")).unwrap().len();
+    assert_eq!(response.header("Content-Length"), Some(size.to_string().as_str()));
+}
+
+#[test]
+fn empty_favicon_is_a_204() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--favicon", "empty"]);
+    let response = server.get("/favicon.ico");
+    assert_eq!(response.status, 204);
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn favicon_of_the_directory_wins() {
+    let root = TempDir::new();
+    root.write("favicon.ico", "mine");
+    for mode in ["builtin", "empty"] {
+        let server = Server::start(&[root.str(), "--favicon", mode]);
+        assert_eq!(server.get("/favicon.ico").body, "mine", "{mode}");
+    }
+}
+
+#[test]
+fn unknown_favicon_mode_is_refused() {
+    let output = run(&["--favicon", "fancy"]);
+    assert!(!output.status.success());
+    let printed = String::from_utf8_lossy(&output.stderr);
+    assert!(printed.contains("invalid favicon mode 'fancy'"), "{printed}");
+}
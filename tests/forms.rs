@@ -0,0 +1,202 @@
+// The forms of a listing, files posted as multipart/form-data and urlencoded actions
+mod common;
+
+use common::{Response, Server, TempDir};
+
+const BOUNDARY: &str = "----formBoundary7MA4YWxkTrZu0gW";
+
+// A form as a browser posts it, with the file parts named "file"
+fn form(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = String::new();
+    for (filename, data) in files {
+        body.push_str(&format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n{data}\r\n"));
+    }
+    body.push_str(&format!("--{BOUNDARY}--\r\n"));
+    body.into_bytes()
+}
+
+fn post(server: &Server, path: &str, body: &[u8]) -> Response {
+    let content_type = format!("multipart/form-data; boundary={BOUNDARY}");
+    server.request("POST", path, &[("Content-Type", &content_type)], body)
+}
+
+#[test]
+fn listing_has_the_form_only_with_write() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    assert!(server.get("/").body.contains("enctype=\"multipart/form-data\""));
+    let server = Server::start(&[root.str()]);
+    assert!(!server.get("/").body.contains("<form"));
+}
+
+#[test]
+fn posted_files_are_stored_and_redirect_to_the_listing() {
+    let root = TempDir::new();
+    root.write("sub/old.txt", "old");
+    let server = Server::start(&["--write", root.str()]);
+    let response = post(&server, "/sub", &form(&[("one.txt", "first\r\nfile"), ("two.txt", "second"), ("", "")]));
+    assert_eq!(response.status, 303);
+    assert_eq!(response.header("Location"), Some("/sub/"));
+    assert_eq!(std::fs::read_to_string(root.path().join("sub/one.txt")).unwrap(), "first\r\nfile");
+    assert_eq!(std::fs::read_to_string(root.path().join("sub/two.txt")).unwrap(), "second");
+    let listing = server.get("/sub/").body;
+    assert!(listing.contains("one.txt") && listing.contains("two.txt"));
+}
+
+#[test]
+fn paths_in_filenames_are_dropped() {
+    let root = TempDir::new();
+    root.write("up/.keep", "");
+    let server = Server::start(&["--write", root.str()]);
+    let response = post(&server, "/up/", &form(&[("../../escape.txt", "a"), (r"C:\Users\me\win.txt", "b")]));
+    assert_eq!(response.status, 303);
+    assert_eq!(std::fs::read_to_string(root.path().join("up/escape.txt")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(root.path().join("up/win.txt")).unwrap(), "b");
+    assert!(!root.path().join("escape.txt").exists());
+    // Nothing usable is left of these
+    assert_eq!(post(&server, "/up/", &form(&[("..", "x")])).status, 400);
+    assert_eq!(post(&server, "/up/", &form(&[("NUL.txt", "x")])).status, 400);
+}
+
+#[test]
+fn malformed_forms_are_400() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let mut unfinished = form(&[("a.txt", "data")]);
+    unfinished.truncate(unfinished.len() - format!("--{BOUNDARY}--\r\n").len() - 2);
+    let response = post(&server, "/", &unfinished);
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(!root.path().join("a.txt").exists(), "a part without its end was kept");
+    let headless = format!("--{BOUNDARY}\r\nContent-Type: text/plain\r\n\r\nx\r\n--{BOUNDARY}--\r\n");
+    assert_eq!(post(&server, "/", headless.as_bytes()).status, 400);
+    // Without a boundary it's no form we take
+    let response = server.request("POST", "/", &[("Content-Type", "multipart/form-data")], b"x");
+    assert_eq!(response.status, 415);
+}
+
+#[test]
+fn form_from_another_site_is_refused() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let content_type = format!("multipart/form-data; boundary={BOUNDARY}");
+    let response = server.request("POST", "/", &[("Content-Type", &content_type), ("Origin", "http://evil.example")], &form(&[("a.txt", "x")]));
+    assert_eq!(response.status, 403);
+    assert!(!root.path().join("a.txt").exists());
+}
+
+#[test]
+fn file_over_the_limit_is_413() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let big = "x".repeat(32 * 1024 * 1024 + 1);
+    let response = post(&server, "/", &form(&[("big.bin", &big)]));
+    assert_eq!(response.status, 413);
+    assert!(!root.path().join("big.bin").exists());
+}
+
+#[test]
+fn urlencoded_form_cut_short_is_400() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let raw = server.send(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 40\r\n\r\naction=mkdir&name=abc");
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(!root.path().join("abc").exists());
+    let broken = server.request("POST", "/", &[("Content-Type", "application/x-www-form-urlencoded")], b"action=mkdir&name=%zz");
+    assert_eq!(broken.status, 400);
+}
+
+#[test]
+fn urlencoded_form_over_its_limits_is_413() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", "--form-max-fields", "3", "--form-max-field-size", "8", root.str()]);
+    let form = [("Content-Type", "application/x-www-form-urlencoded")];
+    assert_eq!(server.request("POST", "/", &form, b"action=mkdir&name=a&x=1&y=2").status, 413);
+    assert_eq!(server.request("POST", "/", &form, b"action=mkdir&name=toolongname").status, 413);
+    assert_eq!(server.request("POST", "/", &form, b"action=mkdir&name=%41").status, 303);
+    assert!(root.path().join("A").is_dir());
+}
+
+fn action(server: &Server, path: &str, body: &str, headers: &[(&str, &str)]) -> Response {
+    let headers = [&[("Content-Type", "application/x-www-form-urlencoded")], headers].concat();
+    server.request("POST", path, &headers, body.as_bytes())
+}
+
+#[test]
+fn listing_has_rename_and_delete_for_each_entry() {
+    let root = TempDir::new();
+    root.write("a \"quoted\".txt", "a");
+    let server = Server::start(&["--write", root.str()]);
+    let listing = server.get("/").body;
+    assert!(listing.contains("name=\"from\" value=\"a &quot;quoted&quot;.txt\""), "{listing}");
+    assert!(listing.contains("value=\"delete\" /><input type=\"hidden\" name=\"name\" value=\"a &quot;quoted&quot;.txt\""), "{listing}");
+    let server = Server::start(&[root.str()]);
+    assert!(!server.get("/").body.contains("value=\"rename\""));
+}
+
+#[test]
+fn file_actions_change_the_directory_and_go_back_to_it() {
+    let root = TempDir::new();
+    root.write("dir/old.txt", "content");
+    root.write("dir/gone.txt", "g");
+    let login = format!("Basic {}", common::base64(b"ann:pw"));
+    let server = Server::start(&["--write", "--auth", "ann:pw", root.str()]);
+    let auth = [("Authorization", login.as_str())];
+    let renamed = action(&server, "/dir", "action=rename&from=old.txt&to=new+name.txt", &auth);
+    assert_eq!(renamed.status, 303);
+    assert_eq!(renamed.header("Location"), Some("/dir/"));
+    assert_eq!(std::fs::read_to_string(root.path().join("dir/new name.txt")).unwrap(), "content");
+    assert!(!root.path().join("dir/old.txt").exists());
+    assert_eq!(action(&server, "/dir/", "action=delete&name=gone.txt", &auth).status, 303);
+    assert!(!root.path().join("dir/gone.txt").exists());
+    assert_eq!(action(&server, "/dir/", "action=mkdir&name=sub", &auth).status, 303);
+    assert!(root.path().join("dir/sub").is_dir());
+    // Logged after the answer went out
+    for logged in ["rename in /dir as ann", "delete in /dir as ann", "mkdir in /dir as ann"] {
+        assert!(server.wait_for_output(logged), "{}", server.output());
+    }
+    // Not without the login
+    assert_eq!(action(&server, "/dir/", "action=delete&name=new+name.txt", &[]).status, 401);
+    assert!(root.path().join("dir/new name.txt").exists());
+}
+
+#[test]
+fn file_actions_take_plain_names_only() {
+    let root = TempDir::new();
+    root.write("dir/a.txt", "a");
+    root.write("dir/b.txt", "b");
+    root.write("outside.txt", "o");
+    let server = Server::start(&["--write", root.str()]);
+    for body in [
+        "action=delete&name=../outside.txt",
+        "action=delete&name=sub/a.txt",
+        "action=rename&from=a.txt&to=../a.txt",
+        "action=rename&from=a.txt",
+        "action=mkdir&name=..",
+        "action=mkdir&name=+padded",
+        "action=chmod&name=a.txt",
+        "name=a.txt",
+    ] {
+        assert_eq!(action(&server, "/dir/", body, &[]).status, 400, "{body}");
+    }
+    // Onto a name that's taken, or of what isn't there
+    assert_eq!(action(&server, "/dir/", "action=rename&from=a.txt&to=b.txt", &[]).status, 409);
+    assert_eq!(action(&server, "/dir/", "action=delete&name=missing.txt", &[]).status, 404);
+    assert_eq!(std::fs::read_to_string(root.path().join("dir/b.txt")).unwrap(), "b");
+    assert!(root.path().join("outside.txt").exists() && root.path().join("dir/a.txt").exists());
+}
+
+#[test]
+fn file_action_from_another_site_is_refused() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(action(&server, "/", "action=delete&name=a.txt", &[("Origin", "http://evil.example")]).status, 403);
+    assert_eq!(action(&server, "/", "action=delete&name=a.txt", &[("Referer", "https://evil.example/page")]).status, 403);
+    assert!(root.path().join("a.txt").exists());
+    // The listing's own page is fine
+    assert_eq!(action(&server, "/", "action=delete&name=a.txt", &[("Origin", "http://localhost")]).status, 303);
+    assert!(!root.path().join("a.txt").exists());
+}
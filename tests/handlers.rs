@@ -0,0 +1,67 @@
+// Endpoints answered by code, --echo takes a body and --metrics none
+mod common;
+
+use common::{Response, Server, TempDir};
+
+#[test]
+fn echo_sends_the_body_back() {
+    let server = Server::start(&["--echo", "/echo"]);
+    let response = server.request("POST", "/echo", &[("Content-Type", "text/plain")], b"hello there");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello there");
+    assert_eq!(response.header("Content-Type"), Some("text/plain"));
+}
+
+#[test]
+fn echo_dechunks_the_body() {
+    let server = Server::start(&["--echo", "/echo"]);
+    let raw = server.send(b"PUT /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n there\r\n0\r\n\r\n");
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello there");
+}
+
+#[test]
+fn pipelined_bodies_stay_with_their_requests() {
+    let root = TempDir::new();
+    root.write("after.txt", "the file");
+    let server = Server::start(&[root.str(), "--echo", "/echo"]);
+    let raw = server.send(concat!(
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nfirst",
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nsecond\r\n0\r\n\r\n",
+        "GET /after.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    ).as_bytes());
+    let (first, rest) = Response::parse_next(&raw);
+    let (second, rest) = Response::parse_next(rest);
+    let (third, rest) = Response::parse_next(rest);
+    assert_eq!((first.status, first.body.as_str()), (200, "first"));
+    assert_eq!((second.status, second.body.as_str()), (200, "second"));
+    assert_eq!((third.status, third.body.as_str()), (200, "the file"));
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn echo_of_a_body_cut_short_is_a_400() {
+    let server = Server::start(&["--echo", "/echo"]);
+    let response = server.send(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nshort");
+    assert_eq!(Response::parse(&response).status, 400);
+}
+
+#[test]
+fn echo_route_must_be_a_route() {
+    let output = common::run(&["--echo", "echo"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid route 'echo'"));
+}
+
+#[test]
+fn overlapping_prefixes_go_to_the_longest() {
+    let server = Server::start(&["--echo", "/api/*", "--echo", "/api/v2/*", "--echo", "/api/v2/health"]);
+    for (path, rest) in [("/api/v1/users", "v1/users"), ("/api/v2/users/1", "users/1"), ("/api/v2", ""), ("/api/v2/health", ""), ("/api/v2x", "v2x")] {
+        let response = server.request("POST", path, &[], b"x");
+        assert_eq!(response.status, 200, "{path}");
+        assert_eq!(response.header("X-Echo-Rest"), Some(rest), "{path}");
+    }
+    // What no prefix covers is looked for on the disk
+    assert_eq!(server.request("POST", "/apis", &[], b"x").status, 404);
+}
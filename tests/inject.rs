@@ -0,0 +1,113 @@
+// --delay, --throttle and --fail-rate, and --inject-path to keep them to some paths
+mod common;
+
+use std::time::{Duration, Instant};
+use common::{run, send_and_close, Response, Server, TempDir};
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "0123456789");
+    root.write("api/users", "[]");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+fn timed(server: &Server, path: &str) -> (Response, Duration) {
+    let start = Instant::now();
+    let response = server.get(path);
+    (response, start.elapsed())
+}
+
+#[test]
+fn delay_goes_before_every_response() {
+    let (_root, server) = site(&["--delay", "300ms"]);
+    for _ in 0..3 {
+        let (response, elapsed) = timed(&server, "/a.txt");
+        assert_eq!(response.body, "0123456789");
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+    }
+    assert!(server.wait_for_output("injecting a delay of 300ms"), "{}", server.output());
+}
+
+#[test]
+fn ranged_delay_is_at_least_its_lower_end() {
+    let (_root, server) = site(&["--delay", "200ms-400ms"]);
+    for _ in 0..5 {
+        let (response, elapsed) = timed(&server, "/a.txt");
+        assert_eq!(response.status, 200);
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+    }
+}
+
+#[test]
+fn inject_path_keeps_it_to_the_matching_paths() {
+    let (_root, server) = site(&["--delay", "500ms", "--fail-rate", "1", "--inject-path", "/api/*"]);
+    let (response, elapsed) = timed(&server, "/a.txt");
+    assert_eq!(response.status, 200);
+    assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+    let (response, elapsed) = timed(&server, "/api/users");
+    assert!([500, 503].contains(&response.status), "{}", response.status);
+    assert!(elapsed >= Duration::from_millis(500), "{elapsed:?}");
+}
+
+#[test]
+fn fail_rate_fails_about_that_share() {
+    let (_root, server) = site(&["--fail-rate", "0.3"]);
+    let statuses: Vec<u16> = (0..300).map(|_| server.get("/a.txt").status).collect();
+    let failed = statuses.iter().filter(|status| [500, 503].contains(status)).count();
+    assert_eq!(statuses.len() - failed, statuses.iter().filter(|status| **status == 200).count());
+    // 90 expected, this is more than four standard deviations either way
+    assert!((55..=125).contains(&failed), "{failed} out of 300");
+    assert!(statuses.contains(&500) && statuses.contains(&503));
+    assert!(server.wait_for_output("injecting a 50"), "{}", server.output());
+}
+
+#[test]
+fn failures_keep_the_connection_when_they_can() {
+    let (_root, server) = site(&["--fail-rate", "1"]);
+    let get = "GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let raw = send_and_close(&mut server.connect(), format!("{get}{get}").as_bytes());
+    let (first, rest) = Response::parse_next(&raw);
+    let (second, rest) = Response::parse_next(rest);
+    assert!([500, 503].contains(&first.status) && [500, 503].contains(&second.status));
+    assert_eq!((first.header("Connection"), rest), (None, ""));
+    // The body that wasn't read would be taken for the next request
+    let response = server.request("POST", "/a.txt", &[], b"x=1");
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn throttle_and_delay_leave_ranges_and_framing_alone() {
+    let root = TempDir::new();
+    let contents = "0123456789".repeat(300);
+    root.write("big.txt", &contents);
+    // 3000 bytes at 80kbps take about 0.3s
+    let server = Server::start(&[root.str(), "--throttle", "80kbps", "--delay", "50ms"]);
+    let start = Instant::now();
+    let raw = send_and_close(&mut server.connect(), b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\nGET /big.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=100-199\r\n\r\nGET /big.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=-5\r\n\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(400), "{:?}", start.elapsed());
+    let (whole, rest) = Response::parse_next(&raw);
+    assert_eq!((whole.status, whole.body == contents), (200, true));
+    let (part, rest) = Response::parse_next(rest);
+    assert_eq!((part.status, part.body.as_str()), (206, &contents[100..200]));
+    assert_eq!(part.header("Content-Range"), Some("bytes 100-199/3000"));
+    let (tail, rest) = Response::parse_next(rest);
+    assert_eq!((tail.status, tail.body.as_str(), rest), (206, "56789", ""));
+    assert!(server.wait_for_output("throttling the response to 10000 bytes a second"), "{}", server.output());
+}
+
+#[test]
+fn bad_values_are_refused() {
+    for (args, expected) in [
+        (["--delay", "soon"], "invalid delay 'soon'"),
+        (["--delay", "2s-1s"], "invalid delay '2s-1s'"),
+        (["--throttle", "fast"], "invalid rate 'fast'"),
+        (["--fail-rate", "2"], "invalid fail rate '2'"),
+        (["--inject-path", "/api/[a"], "/api/[a"),
+    ] {
+        let output = run(&args);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
@@ -0,0 +1,58 @@
+// Connections stay open for the next request unless the client says otherwise
+mod common;
+
+use std::io::Write;
+use common::{read_all, Response, Server, TempDir};
+
+const GET: &str = "GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+fn server() -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    (root, server)
+}
+
+// Everything answered on a connection whose sending side stays open, the server
+// has to be the one to end it
+fn answered(server: &Server, requests: &str) -> String {
+    let mut stream = server.connect();
+    stream.write_all(requests.as_bytes()).unwrap();
+    read_all(&mut stream)
+}
+
+#[test]
+fn connection_close_ends_it_after_the_response() {
+    let (_root, server) = server();
+    let raw = answered(&server, &format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n{GET}"));
+    let (response, rest) = Response::parse_next(&raw);
+    assert_eq!((response.status, response.body.as_str()), (200, "a"));
+    assert_eq!(response.header("Connection"), Some("close"));
+    // What came after it isn't answered
+    assert!(rest.is_empty(), "{rest:?}");
+    // A token among others counts too
+    let raw = answered(&server, "GET /a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: TE, Close\r\n\r\n");
+    assert_eq!(Response::parse(&raw).header("Connection"), Some("close"));
+}
+
+#[test]
+fn http10_closes_unless_it_asks_for_keep_alive() {
+    let (_root, server) = server();
+    let raw = answered(&server, "GET /a.txt HTTP/1.0\r\n\r\n");
+    assert_eq!(Response::parse(&raw).header("Connection"), Some("close"));
+    let raw = server.send(b"GET /a.txt HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /a.txt HTTP/1.0\r\n\r\n");
+    let (first, rest) = Response::parse_next(&raw);
+    assert_eq!((first.body.as_str(), first.header("Connection")), ("a", Some("keep-alive")));
+    let (second, rest) = Response::parse_next(rest);
+    assert_eq!((second.body.as_str(), second.header("Connection")), ("a", Some("close")));
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn http11_stays_open_by_default() {
+    let (_root, server) = server();
+    let raw = server.send(format!("{GET}{GET}").as_bytes());
+    let (first, rest) = Response::parse_next(&raw);
+    assert_eq!(first.header("Connection"), None);
+    assert_eq!(Response::parse(rest).body, "a");
+}
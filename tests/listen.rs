@@ -0,0 +1,71 @@
+// Several --listen addresses, v4 and v6, all served and all listed at startup
+mod common;
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+use common::{run, send_and_close, Response, Server, TempDir};
+
+// The address the startup output gives for the first listener of this family
+fn listed(server: &Server, v6: bool) -> SocketAddr {
+    server.output()
+        .lines()
+        .filter_map(|line| line.split("Listen on ").nth(1))
+        .filter_map(|addr| addr.parse::<SocketAddr>().ok())
+        .find(|addr| addr.is_ipv6() == v6)
+        .unwrap_or_else(|| panic!("no listen line for it in\n{}", server.output()))
+}
+
+fn fetch(addr: SocketAddr) -> Response {
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    Response::parse(&send_and_close(&mut stream, b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+}
+
+#[test]
+fn served_over_v4_and_v6() {
+    let root = TempDir::new();
+    root.write("a.txt", "both");
+    let server = Server::start_listening(&[root.str(), "--listen", "127.0.0.1:0", "--listen", "[::1]:0"]);
+    let v4 = listed(&server, false);
+    let v6 = listed(&server, true);
+    assert_eq!(v4, server.addr);
+    assert!(v6.ip().is_loopback() && v6.port() != 0, "{v6}");
+    for addr in [v4, v6] {
+        let response = fetch(addr);
+        assert_eq!((response.status, response.body.as_str()), (200, "both"), "over {addr}");
+    }
+    assert!(server.output().contains(&format!("Open http://[::1]:{}/", v6.port())), "{}", server.output());
+}
+
+#[test]
+fn v6_wildcard_does_not_take_v4() {
+    let root = TempDir::new();
+    root.write("a.txt", "v6");
+    let server = Server::start_listening(&[root.str(), "--listen", "127.0.0.1:0", "--listen", "[::]:0"]);
+    let port = listed(&server, true).port();
+    // V6ONLY everywhere, the same port on v4 is free for someone else
+    assert!(TcpListener::bind(("127.0.0.1", port)).is_ok(), "[::] also took v4 port {port}");
+    assert_eq!(fetch(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port))).body, "v6");
+}
+
+#[test]
+fn any_failed_bind_stops_the_start() {
+    let root = TempDir::new();
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = taken.local_addr().unwrap().to_string();
+    let output = run(&[root.str(), "--listen", "[::1]:0", "--listen", &taken]);
+    assert!(!output.status.success());
+    // Logged like everything at startup, nothing is served before
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("failed to listen on {taken} by")), "{stdout}");
+    assert!(!stdout.contains("Open http://"), "{stdout}");
+}
+
+#[test]
+fn bad_listen_addresses_are_refused() {
+    let output = run(&["--listen", "localhost"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid listen address 'localhost'"));
+    let output = run(&["--listen", "[fe80::1%no-such-if0]:8080"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown network interface 'no-such-if0'"));
+}
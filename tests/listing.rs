@@ -0,0 +1,260 @@
+// Directory listings, HTML and JSON
+mod common;
+
+use common::{decode_chunked, send_and_close, Response, Server, TempDir};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+
+#[test]
+fn listing_ends_with_its_digest_in_a_trailer() {
+    let root = TempDir::new();
+    for n in 0..300 {
+        root.write(&format!("file-{n:03}.txt"), "x");
+    }
+    let server = Server::start(&[root.str()]);
+    let response = server.get("/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(response.header("Trailer"), Some("Content-Digest"));
+    let (body, trailers) = decode_chunked(&response.body);
+    // More than one chunk, all the names made it
+    assert!(body.len() > 8192);
+    assert!(body.contains("file-000.txt") && body.contains("file-299.txt"));
+    let digest = common::base64(&Sha256::digest(body.as_bytes()));
+    assert_eq!(trailers, vec![format!("Content-Digest: sha-256=:{digest}:")]);
+}
+
+#[test]
+fn head_of_a_listing_has_no_body() {
+    let root = TempDir::new();
+    root.write("a.txt", "x");
+    let server = Server::start(&[root.str()]);
+    let response = server.request("HEAD", "/", &[], b"");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Trailer"), Some("Content-Digest"));
+    assert_eq!(response.body, "");
+}
+
+#[test]
+fn http10_gets_the_listing_unframed_up_to_the_close() {
+    let root = TempDir::new();
+    for n in 0..300 {
+        root.write(&format!("file-{n:03}.txt"), "x");
+    }
+    let server = Server::start(&[root.str()]);
+    // Even asked to keep the connection, the end of the listing is where it closes
+    let raw = send_and_close(&mut server.connect(), b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /file-000.txt HTTP/1.0\r\n\r\n");
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Connection"), Some("close"));
+    for name in ["Transfer-Encoding", "Trailer", "Content-Length"] {
+        assert_eq!(response.header(name), None, "{name}");
+    }
+    assert!(response.body.starts_with("<!DOCTYPE html>") && response.body.ends_with("</ul></body></html>"), "{:?}", &response.body[..50]);
+    assert!(response.body.contains("file-000.txt") && response.body.contains("file-299.txt"));
+    // The JSON one is sent whole, with its length, and keeps the connection
+    let raw = send_and_close(&mut server.connect(), b"GET /?format=json HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /file-000.txt HTTP/1.0\r\n\r\n");
+    let (json, rest) = Response::parse_next(&raw);
+    assert_eq!((json.header("Transfer-Encoding"), json.header("Connection")), (None, Some("keep-alive")));
+    let listing: serde_json::Value = serde_json::from_str(&json.body).unwrap();
+    assert_eq!(listing["entries"].as_array().unwrap().len(), 300);
+    assert_eq!(Response::parse(rest).body, "x");
+}
+
+#[test]
+fn links_of_odd_names_lead_back_to_the_files() {
+    let root = TempDir::new();
+    let names = ["a b%c#d 中?.txt", "100%.txt", "plus+sign.txt", "emoji 😀.txt"];
+    for name in names {
+        root.write(name, name);
+    }
+    let server = Server::start(&[root.str()]);
+    let listing = server.get("/");
+    let links: Vec<&str> = listing.body.split("href=\"").skip(1).filter_map(|rest| rest.split('"').next()).collect();
+    for name in names {
+        let link = links.iter().find(|link| server.get(link).body == name);
+        assert!(link.is_some(), "no link to {name:?} in {links:?}");
+        assert!(link.unwrap().split('%').skip(1).all(|hex| hex.len() >= 2 && hex[..2].bytes().all(|b| b.is_ascii_hexdigit())));
+    }
+}
+
+#[test]
+fn large_directory_is_listed_whole_in_many_chunks() {
+    let root = TempDir::new();
+    for n in 0..20000 {
+        std::fs::File::create(root.path().join(format!("entry-{n:05}"))).unwrap();
+    }
+    let server = Server::start(&[root.str()]);
+    let response = server.get("/");
+    assert_eq!(response.header("Content-Length"), None);
+    let (body, _) = decode_chunked(&response.body);
+    // Sent as it was read, not as one chunk at the end
+    assert!(response.body.len() > body.len() + 10 * "2000\r\n\r\n".len());
+    let mut names: Vec<&str> = body.split("</a>").filter_map(|row| row.rsplit('>').next()).filter(|name| name.starts_with("entry-")).collect();
+    // In the order they were read from the directory
+    names.sort_unstable();
+    let expected: Vec<String> = (0..20000).map(|n| format!("entry-{n:05}")).collect();
+    assert!(names == expected, "{} names listed", names.len());
+    assert!(body.ends_with("</ul></body></html>"));
+}
+
+#[test]
+fn each_entry_is_escaped() {
+    let root = TempDir::new();
+    root.write("<b>&\"q\".txt", "x");
+    let server = Server::start(&[root.str()]);
+    let (body, _) = decode_chunked(&server.get("/").body);
+    assert!(body.contains("<a href=\"/%3Cb%3E%26%22q%22.txt\">&lt;b&gt;&amp;&quot;q&quot;.txt</a>"), "{body}");
+    assert!(!body.contains("<b>"));
+}
+
+#[test]
+fn query_picks_the_listing_format() {
+    let root = TempDir::new();
+    root.write("a.txt", "x");
+    let server = Server::start(&[root.str()]);
+    let is_json = |target: &str| server.get(target).header("Content-Type").unwrap().starts_with("application/json");
+    assert!(is_json("/?format=json"));
+    // Decoded after the split, and the first of a repeated key counts
+    assert!(is_json("/?other=a%26format%3Dhtml&format=%6Ason"));
+    assert!(is_json("/?format=json&format=html"));
+    assert!(!is_json("/?format=html&format=json"));
+    assert!(!is_json("/?format"));
+    assert!(!is_json("/?x=format%3Djson"));
+    assert_eq!(server.get("/?format=%zz").status, 400);
+}
+
+#[test]
+fn excluded_names_are_neither_listed_nor_served() {
+    let root = TempDir::new();
+    root.write("public.txt", "public");
+    root.write("secret.key", "key");
+    root.write(".git/config", "config");
+    root.write("docs/notes.key", "nested");
+    let server = Server::start(&[root.str(), "--exclude", "*.key", "--exclude", ".git"]);
+    let (body, _) = decode_chunked(&server.get("/").body);
+    assert!(body.contains("public.txt"));
+    assert!(!body.contains("secret.key") && !body.contains(".git"), "{body}");
+    let (docs, _) = decode_chunked(&server.get("/docs/").body);
+    assert!(!docs.contains("notes.key"), "{docs}");
+    // The same as a missing file, nothing tells them apart
+    let missing = server.get("/missing.key");
+    for path in ["/secret.key", "/.git/config", "/.git/", "/docs/notes.key", "/docs/../secret.key"] {
+        let response = server.get(path);
+        assert_eq!((response.status, response.body.as_str()), (404, missing.body.as_str()), "{path}");
+    }
+}
+
+#[test]
+fn broken_exclude_pattern_is_refused_at_startup() {
+    let output = common::run(&["--exclude", "[abc"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unterminated character class in pattern '[abc'"));
+}
+
+#[test]
+fn pretty_json_is_the_same_listing_indented() {
+    let root = TempDir::new();
+    root.write("a.txt", "abc");
+    root.write("sub/b.txt", "b");
+    let server = Server::start(&[root.str()]);
+    let compact = server.get("/?format=json");
+    assert_eq!(compact.status, 200);
+    assert!(!compact.body.contains('\n'), "{}", compact.body);
+    let by_query = server.get("/?format=json&pretty");
+    let by_accept = server.request("GET", "/", &[("Accept", "application/json; pretty")], b"");
+    for pretty in [&by_query, &by_accept] {
+        assert_eq!(pretty.status, 200);
+        assert!(pretty.body.starts_with("{\n  \"entries\": [\n    {\n"), "{}", pretty.body);
+        assert!(pretty.body.len() > compact.body.len());
+        let (compact, pretty): (serde_json::Value, serde_json::Value) = (serde_json::from_str(&compact.body).unwrap(), serde_json::from_str(&pretty.body).unwrap());
+        assert_eq!(compact, pretty);
+    }
+    // Each is a representation of its own
+    assert_ne!(compact.header("ETag"), by_query.header("ETag"));
+    assert_eq!(by_query.header("ETag"), by_accept.header("ETag"));
+    // Pretty alone is still the HTML one
+    assert!(server.get("/?pretty").header("Content-Type").unwrap().starts_with("text/html"));
+}
+
+#[cfg(unix)]
+#[test]
+fn names_that_are_not_utf8_are_left_out() {
+    use std::os::unix::ffi::OsStrExt;
+    let root = TempDir::new();
+    root.write("fine.txt", "f");
+    let odd = root.path().join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+    // Some filesystems only take utf-8 names
+    if std::fs::write(&odd, "latin-1").is_err() {
+        return;
+    }
+    let server = Server::start(&["-v", root.str()]);
+    let listing = server.get("/");
+    assert_eq!(listing.status, 200);
+    assert!(listing.body.contains("fine.txt"));
+    assert!(!listing.body.contains("caf"), "a link that leads nowhere: {}", listing.body);
+    assert!(server.wait_for_output("its name is not utf-8"), "{}", server.output());
+    let json = server.get("/?format=json").body;
+    assert!(json.contains("fine.txt") && !json.contains("caf"), "{json}");
+    // Its bytes don't decode to a path either
+    assert_eq!(server.get("/caf%E9.txt").status, 400);
+}
+
+#[test]
+fn unchanged_listing_is_a_304() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let first = server.get("/");
+    let etag = first.header("ETag").expect("a listing has an ETag").to_string();
+    assert!(etag.starts_with("W/\""), "{etag}");
+    let again = server.request("GET", "/", &[("If-None-Match", &etag)], b"");
+    assert_eq!(again.status, 304);
+    assert_eq!(again.header("ETag"), Some(etag.as_str()));
+    assert!(again.body.is_empty());
+    // A file of the same size with another name changes it, whatever the mtime did
+    std::fs::rename(root.path().join("a.txt"), root.path().join("b.txt")).unwrap();
+    let changed = server.request("GET", "/", &[("If-None-Match", &etag)], b"");
+    assert_eq!(changed.status, 200);
+    assert_ne!(changed.header("ETag"), Some(etag.as_str()));
+    // The JSON listing has a tag of its own
+    let json = server.request("GET", "/?format=json", &[("If-None-Match", &etag)], b"");
+    assert_eq!(json.status, 200);
+}
+
+#[test]
+fn listing_dates_are_in_the_format_asked_for() {
+    let root = TempDir::new();
+    let old = root.write("old.txt", "x");
+    let recent = root.write("recent.txt", "x");
+    let modified = |path: &std::path::Path, time: SystemTime| std::fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    // The example date of RFC 7231, and two days back from now
+    modified(&old, UNIX_EPOCH + Duration::from_secs(784111777));
+    modified(&recent, SystemTime::now() - Duration::from_secs(2 * 86400 + 60));
+    for (format, expected) in [(None, "1994-11-06 08:49"), (Some("iso"), "1994-11-06 08:49"), (Some("text"), "6 Nov 1994 08:49"), (Some("relative"), "2 days ago")] {
+        let mut args = vec![root.str()];
+        args.extend(format.iter().flat_map(|format| ["--listing-dates", format]));
+        let server = Server::start(&args);
+        let (body, _) = decode_chunked(&server.get("/").body);
+        assert!(body.contains(expected), "{format:?}: {body}");
+    }
+    let output = common::run(&["--listing-dates", "locale"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid date format 'locale', expected iso, text or relative"));
+}
+
+#[test]
+fn listing_is_a_proper_document_in_the_language_asked_for() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let (body, _) = decode_chunked(&server.get("/").body);
+    assert!(body.starts_with("<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\" />"), "{body}");
+    let server = Server::start(&[root.str(), "--listing-lang", "pt-BR"]);
+    let (body, _) = decode_chunked(&server.get("/").body);
+    assert!(body.starts_with("<!DOCTYPE html><html lang=\"pt-BR\">"), "{body}");
+    // Nothing but a tag gets into the attribute
+    for bad in ["en\"><script>", "", "pt--BR", "toolongtag"] {
+        let output = common::run(&["--listing-lang", bad]);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("invalid language '{bad}', expected a tag like en or pt-BR")), "{bad}");
+    }
+}
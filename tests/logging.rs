@@ -0,0 +1,346 @@
+// The log output: levels, JSON, the access log and rotation of the log file
+mod common;
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+use common::{base64, wait_for_lines, Server, TempDir};
+
+// Every "method GET" line of the log file and its rotated ones, by request path
+fn logged_paths(dir: &TempDir) -> Vec<String> {
+    let mut logged = Vec::new();
+    for entry in std::fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.file_name().unwrap().to_str().unwrap().starts_with("log") {
+            continue;
+        }
+        let mut text = String::new();
+        let file = std::fs::File::open(&path).unwrap();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => flate2::read::GzDecoder::new(file).read_to_string(&mut text).unwrap(),
+            _ => { let mut file = file; file.read_to_string(&mut text).unwrap() }
+        };
+        for line in text.lines() {
+            if let Some((_, path)) = line.split_once(" method GET path ") {
+                logged.push(path.to_string());
+            }
+        }
+    }
+    logged
+}
+
+fn rotate(compress: bool) {
+    let root = TempDir::new();
+    let logs = TempDir::new();
+    for n in 0..400 {
+        root.write(&format!("{n}.txt"), "x");
+    }
+    let mut args = vec!["--log-max-size", "4000", "--log-keep", "100", root.str()];
+    if compress {
+        args.insert(0, "--log-compress");
+    }
+    let server = Server::start_logging_to(&logs.path().join("log"), &args);
+    // From several connections at once, the lines interleave
+    thread::scope(|scope| {
+        for thread in 0..4 {
+            let server = &server;
+            scope.spawn(move || {
+                for n in (thread..400).step_by(4) {
+                    assert_eq!(server.get(&format!("/{n}.txt")).status, 200);
+                }
+            });
+        }
+    });
+    // Written by the log thread, and compressed by another one
+    let expected: HashSet<String> = (0..400).map(|n| format!("/{n}.txt")).collect();
+    for _ in 0..100 {
+        let logged = logged_paths(&logs);
+        if logged.len() >= expected.len() {
+            assert_eq!(logged.len(), expected.len(), "lines logged twice");
+            assert_eq!(logged.into_iter().collect::<HashSet<_>>(), expected);
+            let rotated = std::fs::read_dir(logs.path()).unwrap().count() - 1;
+            assert!(rotated >= 3, "only {rotated} rotated files");
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("lines missing, {} of {}", logged_paths(&logs).len(), expected.len());
+}
+
+#[test]
+fn rotation_keeps_every_line() {
+    rotate(false);
+}
+
+#[test]
+fn compressed_rotation_keeps_every_line() {
+    rotate(true);
+}
+
+#[test]
+fn rotation_keeps_only_log_keep_files() {
+    let root = TempDir::new();
+    let logs = TempDir::new();
+    root.write("a.txt", "x");
+    let server = Server::start_logging_to(&logs.path().join("log"), &["--log-max-size", "500", "--log-keep", "2", root.str()]);
+    for _ in 0..100 {
+        server.get("/a.txt");
+    }
+    thread::sleep(Duration::from_millis(200));
+    let mut names: Vec<String> = std::fs::read_dir(logs.path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    names.sort();
+    assert_eq!(names, ["log", "log.1", "log.2"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn sigusr1_opens_the_log_file_again() {
+    let root = TempDir::new();
+    let logs = TempDir::new();
+    root.write("a.txt", "x");
+    let log = logs.path().join("log");
+    let server = Server::start_logging_to(&log, &[root.str()]);
+    // Like logrotate does it
+    std::fs::rename(&log, logs.path().join("log.old")).unwrap();
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGUSR1) }, 0);
+    for _ in 0..100 {
+        if std::fs::read_to_string(&log).is_ok_and(|text| text.contains("opened the log files again")) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    server.get("/a.txt");
+    thread::sleep(Duration::from_millis(100));
+    assert!(std::fs::read_to_string(&log).unwrap().contains("path /a.txt"));
+    assert!(!std::fs::read_to_string(logs.path().join("log.old")).unwrap().contains("path /a.txt"));
+}
+
+// What the log shows of one request at these flags and RUST_LOG
+fn logged_with(env: &[(&str, &str)], args: &[&str]) -> String {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start_with_env(env, &[&[root.str()], args].concat());
+    assert_eq!(server.get("/a.txt").status, 200);
+    assert!(server.wait_for_output("method GET path /a.txt"), "{}", server.output());
+    // The lines after the request line come right after it
+    thread::sleep(Duration::from_millis(100));
+    server.output()
+}
+
+#[test]
+fn verbosity_flags_pick_the_level() {
+    let normal = logged_with(&[], &[]);
+    assert!(!normal.contains("incoming client"), "{normal}");
+    let verbose = logged_with(&[], &["-v"]);
+    assert!(verbose.contains("incoming client") && !verbose.contains("headers: "), "{verbose}");
+    let very = logged_with(&[], &["-vv"]);
+    assert!(very.contains("headers: "), "{very}");
+}
+
+#[test]
+fn rust_log_overrides_the_flags() {
+    // -q alone would leave out the url the test waits for
+    let output = logged_with(&[("RUST_LOG", "httpserver=debug")], &["-q"]);
+    assert!(output.contains("incoming client"), "{output}");
+    let output = logged_with(&[("RUST_LOG", "info")], &["-vv"]);
+    assert!(!output.contains("incoming client"), "{output}");
+}
+
+// The escaped bytes --trace-io logged for one direction, in order
+fn traced(output: &str, direction: &str) -> String {
+    output.lines().filter_map(|line| line.split_once(&format!("] {direction} ")).map(|(_, bytes)| bytes)).collect()
+}
+
+#[test]
+fn trace_io_logs_the_bytes_both_ways() {
+    let root = TempDir::new();
+    root.write("a.txt", "hi\n");
+    let server = Server::start(&[root.str(), "--log-level", "trace", "--trace-io"]);
+    let response = server.get("/a.txt");
+    let id = response.header("X-Request-Id").unwrap().to_string();
+    assert!(server.wait_for_output(&format!("[{id}] > hi\\n")), "{}", server.output());
+    let output = server.output();
+    assert_eq!(traced(&output, "<"), "GET /a.txt HTTP/1.1\\r\\nHost: localhost\\r\\n\\r\\n");
+    let sent = traced(&output, ">");
+    assert!(sent.starts_with("HTTP/1.1 200 OK\\r\\n") && sent.contains(&format!("X-Request-Id: {id}\\r\\n")), "{sent}");
+    assert!(sent.ends_with("Content-Length: 3\\r\\n\\r\\nhi\\n"), "{sent}");
+}
+
+#[test]
+fn trace_io_leaves_out_what_is_past_the_limit() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--log-level", "trace", "--trace-io", "--write"]);
+    let body = "x".repeat(10000);
+    assert_eq!(server.request("PUT", "/big.txt", &[], body.as_bytes()).status, 201);
+    assert!(server.wait_for_output("< more than 4096 bytes, the rest is left out"), "{}", server.output());
+    // The head and as much of the body as makes 4096 bytes, once
+    let head = "PUT /big.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10000\r\n\r\n";
+    let received = traced(&server.output(), "<");
+    let body_shown = received.split("\\r\\n\\r\\n").nth(1).unwrap().trim_end_matches("more than 4096 bytes, the rest is left out");
+    assert_eq!(body_shown, "x".repeat(4096 - head.len()));
+    assert_eq!(server.output().matches("the rest is left out").count(), 1);
+    assert_eq!(std::fs::read_to_string(root.path().join("big.txt")).unwrap(), body);
+}
+
+#[test]
+fn bytes_are_not_traced_without_the_flag_or_the_level() {
+    let root = TempDir::new();
+    root.write("a.txt", "hi\n");
+    for args in [&["--log-level", "trace"][..], &["--trace-io"]] {
+        let server = Server::start(&[&[root.str()], args].concat());
+        server.get("/a.txt");
+        // A request later, the first one is logged if it is going to be
+        server.get("/a.txt");
+        assert!(server.wait_for_output("GET path /a.txt"));
+        assert_eq!(traced(&server.output(), "<"), "", "{args:?}");
+        assert_eq!(traced(&server.output(), ">"), "", "{args:?}");
+    }
+}
+
+// The fields of an access log line, quoted and bracketed ones whole and unescaped,
+// \xNN left as it is
+fn access_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        let field = match c {
+            ' ' => continue,
+            '"' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next().expect("an ending quote") {
+                        '"' => break,
+                        '\\' => match chars.next().unwrap() {
+                            c @ ('"' | '\\') => field.push(c),
+                            c => { field.push('\\'); field.push(c) }
+                        },
+                        c => field.push(c),
+                    }
+                }
+                field
+            }
+            '[' => chars.by_ref().take_while(|c| *c != ']').collect(),
+            c => std::iter::once(c).chain(chars.by_ref().take_while(|c| *c != ' ')).collect(),
+        };
+        fields.push(field);
+    }
+    fields
+}
+
+#[test]
+fn access_log_lines_parse_back_to_the_request() {
+    let (root, logs) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "hello");
+    let log = logs.path().join("access.log");
+    let server = Server::start(&[root.str(), "--access-log", log.to_str().unwrap(), "--access-log-format", "combined"]);
+    server.request("GET", "/a.txt", &[("Referer", "http://example.com/?q=\"x\""), ("User-Agent", "say \"hi\" \\o/")], b"");
+    server.send(b"GET /a\"b HTTP/1.1\r\nHost: localhost\r\nUser-Agent: \xc3\xa9\t1\r\n\r\n");
+    server.send(b"BROKEN\r\n\r\n");
+    // Each line is there as soon as its request is done, the server still running
+    let lines = wait_for_lines(&log, 3);
+    assert_eq!(lines.len(), 3, "{lines:?}");
+    let fields: Vec<Vec<String>> = lines.iter().map(|line| access_fields(line)).collect();
+    assert!(fields.iter().all(|fields| fields.len() == 9), "{fields:?}");
+    let first = &fields[0];
+    assert_eq!(&first[..3], ["127.0.0.1", "-", "-"]);
+    assert!(first[3].ends_with(" +0000") && first[3].len() == 26, "{}", first[3]);
+    assert_eq!(&first[4..], ["GET /a.txt HTTP/1.1", "200", "5", "http://example.com/?q=\"x\"", "say \"hi\" \\o/"]);
+    assert_eq!(&fields[1][4..], ["GET /a\"b HTTP/1.1", "404", &fields[1][6], "-", "\\xc3\\xa9\\x091"]);
+    assert_eq!(&fields[2][4..6], ["BROKEN", "400"]);
+}
+
+#[test]
+fn access_log_names_the_user_and_goes_to_stdout_with_a_dash() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str(), "--auth", "ann:pw", "--access-log", "-"]);
+    let basic = format!("Basic {}", base64(b"ann:pw"));
+    assert_eq!(server.request("GET", "/a.txt", &[("Authorization", &basic)], b"").status, 200);
+    assert_eq!(server.get("/a.txt").status, 401);
+    assert!(server.wait_for_output("127.0.0.1 - ann ["), "{}", server.output());
+    assert!(server.wait_for_output("\"GET /a.txt HTTP/1.1\" 401"), "{}", server.output());
+}
+
+#[test]
+fn aborted_download_is_logged_with_what_was_sent() {
+    let (root, logs) = (TempDir::new(), TempDir::new());
+    root.write("big.txt", "0123456789".repeat(100_000));
+    let log = logs.path().join("access.log");
+    let server = Server::start(&[root.str(), "--access-log", log.to_str().unwrap(), "--throttle", "80kbps"]);
+    let mut download = server.connect();
+    std::io::Write::write_all(&mut download, b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    // The head and some of the body
+    download.read_exact(&mut [0; 2000]).unwrap();
+    drop(download);
+    let lines = wait_for_lines(&log, 1);
+    assert_eq!(lines.len(), 1, "the abort was not logged");
+    let fields = access_fields(&lines[0]);
+    assert_eq!(&fields[4..6], ["GET /big.txt HTTP/1.1", "200"]);
+    let sent: u64 = fields[6].parse().unwrap_or_else(|_| panic!("{}", lines[0]));
+    assert!((1000..1_000_000).contains(&sent), "{}", lines[0]);
+}
+
+// Every line of the output as JSON, none of them anything else
+fn json_lines(output: &str) -> Vec<serde_json::Value> {
+    output.lines().map(|line| serde_json::from_str(line).unwrap_or_else(|err| panic!("{err} in {line:?}"))).collect()
+}
+
+fn has_fields(record: &serde_json::Value, names: &[&str]) -> bool {
+    names.iter().all(|name| record.get(name).is_some())
+}
+
+#[test]
+fn json_records_have_the_fields_of_their_kind() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    root.write("big.txt", "0123456789".repeat(100_000));
+    let server = Server::start(&[root.str(), "--log-format", "json", "--access-log", "-", "--auth", "ann:pw", "--throttle", "80kbps", "--inject-path", "/big.txt"]);
+    let basic = format!("Basic {}", base64(b"ann:pw"));
+    assert_eq!(server.request("GET", "/a.txt", &[("Authorization", &basic), ("User-Agent", "say \"hi\"")], b"").status, 200);
+    // A path with a quote in it and an agent that isn't UTF-8
+    server.send(&[&b"GET /a%22b HTTP/1.1\r\nHost: localhost\r\nAuthorization: "[..], basic.as_bytes(), b"\r\nUser-Agent: \xff\xfe\r\n\r\n"].concat());
+    server.send(b"BROKEN \xff\r\n\r\n");
+    let mut download = server.connect();
+    std::io::Write::write_all(&mut download, format!("GET /big.txt HTTP/1.1\r\nHost: localhost\r\nAuthorization: {basic}\r\n\r\n").as_bytes()).unwrap();
+    download.read_exact(&mut [0; 2000]).unwrap();
+    drop(download);
+    assert!(server.wait_for_output("\"err\":"), "{}", server.output());
+    let records = json_lines(&server.output());
+    // Events say what happened, requests have what the access log has
+    let (events, requests): (Vec<_>, Vec<_>) = records.iter().partition(|record| record.get("msg").is_some());
+    assert!(events.iter().all(|event| has_fields(event, &["ts", "level", "msg"])), "{events:?}");
+    assert!(events.iter().any(|event| event["msg"] == "method GET path /a.txt" && event.get("id").is_some()));
+    assert!(requests.iter().all(|request| has_fields(request, &["ts", "level", "id", "peer", "method", "path", "status", "bytes", "duration_ms"])), "{requests:?}");
+    let find = |path: &str| requests.iter().find(|request| request["path"] == path).unwrap_or_else(|| panic!("no {path} in {requests:?}"));
+    let ok = find("/a.txt");
+    assert_eq!((&ok["status"], &ok["bytes"], &ok["user"], &ok["agent"]), (&200.into(), &1.into(), &"ann".into(), &"say \"hi\"".into()));
+    assert_eq!(ok["peer"].as_str().unwrap().split(':').next(), Some("127.0.0.1"));
+    let quoted = find("/a\"b");
+    assert_eq!((&quoted["status"], &quoted["agent"]), (&404.into(), &"\u{fffd}\u{fffd}".into()));
+    let broken = find("\u{fffd}");
+    assert_eq!((&broken["method"], &broken["status"]), (&"BROKEN".into(), &400.into()));
+    let aborted = find("/big.txt");
+    assert!(aborted["err"].is_string() && aborted["bytes"].as_u64().unwrap() > 0, "{aborted}");
+}
+
+#[test]
+fn json_goes_to_the_log_file_too() {
+    let (root, logs) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let log = logs.path().join("log");
+    let server = Server::start_logging_to(&log, &[root.str(), "--log-format", "json"]);
+    server.get("/a.txt");
+    server.get("/missing");
+    let mut records = Vec::new();
+    for _ in 0..100 {
+        records = json_lines(&std::fs::read_to_string(&log).unwrap());
+        if records.iter().any(|record| record["msg"] == "method GET path /missing") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(records.iter().any(|record| record["msg"] == "method GET path /missing"), "{records:?}");
+    assert!(records.iter().all(|record| has_fields(record, &["ts", "level", "msg"])));
+}
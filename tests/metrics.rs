@@ -0,0 +1,44 @@
+// /metrics counts the connections, and every one that ends is counted out again
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use common::{Server, TempDir};
+
+fn gauge(server: &Server, name: &str) -> u64 {
+    let body = server.get("/metrics").body;
+    let line = body.lines().find(|line| line.split(' ').next() == Some(name)).unwrap_or_else(|| panic!("no {name} in\n{body}"));
+    line.rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+// The one asking for the metrics is active itself
+fn wait_for_active(server: &Server, others: u64) -> u64 {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let active = gauge(server, "httpserver_connections_active");
+        if active == others + 1 || Instant::now() > deadline {
+            return active - 1;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn gauge_is_back_to_zero_once_the_connections_close() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str(), "--metrics"]);
+    assert_eq!(wait_for_active(&server, 0), 0);
+    let idle: Vec<TcpStream> = (0..5).map(|_| server.connect()).collect();
+    assert_eq!(wait_for_active(&server, 5), 5);
+    // Ending every which way: served, refused, cut off in the middle of a body
+    assert_eq!(server.get("/a.txt").status, 200);
+    assert_eq!(server.send(b"GARBAGE\r\n\r\n").split(' ').nth(1), Some("400"));
+    let mut cut = server.connect();
+    cut.write_all(b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\nabc").unwrap();
+    drop(cut);
+    drop(idle);
+    assert_eq!(wait_for_active(&server, 0), 0);
+    assert!(gauge(&server, "httpserver_connections_total") >= 9);
+}
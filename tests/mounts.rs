@@ -0,0 +1,112 @@
+// --mount serves other directories below URL prefixes, each confined to its own
+mod common;
+
+use common::{decode_chunked, Server, TempDir};
+
+// A root and two mounts, one of them inside the other's prefix
+fn mounted(args: &[&str]) -> (Vec<TempDir>, Server) {
+    let (root, media, music) = (TempDir::new(), TempDir::new(), TempDir::new());
+    root.write("index.txt", "root");
+    root.write("media/shadowed.txt", "on the root's disk");
+    root.write("secret.txt", "root secret");
+    media.write("a.txt", "media");
+    media.write("music/inside.txt", "media's own music dir");
+    music.write("a.txt", "music");
+    let server = Server::start(&[
+        &[root.str(), "--mount", &format!("/media={}", media.str()), "--mount", &format!("/media/music={}", music.str())],
+        args,
+    ].concat());
+    (vec![root, media, music], server)
+}
+
+#[test]
+fn longest_matching_prefix_serves_it() {
+    let (_dirs, server) = mounted(&[]);
+    assert_eq!(server.get("/media/a.txt").body, "media");
+    assert_eq!(server.get("/media/music/a.txt").body, "music");
+    // Hidden by the inner mount
+    assert_eq!(server.get("/media/music/inside.txt").status, 404);
+    // And the one of the roots under the mount's name
+    assert_eq!(server.get("/media/shadowed.txt").status, 404);
+}
+
+#[test]
+fn unmounted_paths_fall_back_to_the_root() {
+    let (_dirs, server) = mounted(&[]);
+    assert_eq!(server.get("/index.txt").body, "root");
+    // Only whole segments are the mount's
+    assert_eq!(server.get("/mediafiles/a.txt").status, 404);
+    // Without the trailing slash a mount is a directory like any other
+    let (listing, _) = decode_chunked(&server.get("/media").body);
+    assert!(listing.contains("a.txt"), "{listing}");
+    drop(server);
+    let (_dirs, server) = mounted(&["--trailing-slash", "add"]);
+    let response = server.get("/media");
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("/media/"));
+}
+
+#[test]
+fn traversal_stays_inside_the_normalized_target() {
+    let (_dirs, server) = mounted(&[]);
+    // Normalized before the mount is picked, so this is the root's file
+    assert_eq!(server.get("/media/../secret.txt").body, "root secret");
+    assert_eq!(server.get("/media/%2e%2e/secret.txt").body, "root secret");
+    // From the inner mount into the outer one is the outer one's file, not a way out
+    assert_eq!(server.get("/media/music/../a.txt").body, "media");
+    // Above the root there is nothing
+    for path in ["/media/../../secret.txt", "/media/music/%2e%2e/%2e%2e/%2e%2e/etc/passwd", "/media/..%2f..%2fsecret.txt"] {
+        let response = server.get(path);
+        assert!(response.status == 400 || response.status == 404, "{path}: {}", response.status);
+        assert!(!response.body.contains("secret") && !response.body.contains("root:"), "{path}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn links_dont_lead_from_one_mount_into_another() {
+    let (dirs, server) = mounted(&[]);
+    std::os::unix::fs::symlink(dirs[2].path().join("a.txt"), dirs[1].path().join("link.txt")).unwrap();
+    std::os::unix::fs::symlink(dirs[0].path().join("secret.txt"), dirs[1].path().join("secret.txt")).unwrap();
+    assert_eq!(server.get("/media/link.txt").status, 404);
+    assert_eq!(server.get("/media/secret.txt").status, 404);
+}
+
+#[test]
+fn root_listing_shows_the_mounts() {
+    let (_dirs, server) = mounted(&[]);
+    let (root, _) = decode_chunked(&server.get("/").body);
+    assert!(root.contains("href=\"/media/\""), "{root}");
+    let (media, _) = decode_chunked(&server.get("/media/").body);
+    assert!(media.contains("href=\"/media/music/\"") && media.contains("a.txt"), "{media}");
+    // Listed once, though the mounted directory has a music dir of its own too
+    assert_eq!(media.matches("href=\"/media/music/\"").count(), 1, "{media}");
+}
+
+#[test]
+fn routes_of_a_mount_prefix_override_its_settings() {
+    let (dirs, server) = mounted(&["--auth", "ann:pw", "--route", "/media", "auth=off,listing=off"]);
+    assert_eq!(server.get("/index.txt").status, 401);
+    assert_eq!(server.get("/media/a.txt").body, "media");
+    assert_eq!(server.get("/media/").status, 403);
+    drop(server);
+    // The same from a config file
+    let config = dirs[0].write("site.toml", format!(
+        "[root]\npaths = [{:?}]\n\n[mounts]\n\"/media\" = {:?}\n\n[routes]\n\"/media\" = \"listing=off\"\n",
+        dirs[0].str(), dirs[1].str(),
+    ));
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    assert_eq!(server.get("/media/a.txt").body, "media");
+    assert_eq!(server.get("/media/").status, 403);
+    assert_eq!(server.get("/").status, 200);
+}
+
+#[test]
+fn missing_mount_directory_is_refused() {
+    let root = TempDir::new();
+    let missing = root.path().join("missing");
+    let output = common::run(&[root.str(), "--port", "0", "--mount", &format!("/m={}", missing.display())]);
+    assert!(!output.status.success());
+    let told = String::from_utf8_lossy(&output.stdout);
+    assert!(told.contains(&format!("{} does not exist, add --create-root", missing.display())), "{told}");
+}
@@ -0,0 +1,99 @@
+// OPTIONS for one resource and for the server, Allow lists what its methods really do
+mod common;
+
+use common::{Response, Server, TempDir};
+
+fn allow(server: &Server, path: &str) -> (u16, Option<String>) {
+    let response = server.request("OPTIONS", path, &[], b"");
+    assert!(response.status != 204 || response.body.is_empty(), "{path}: {response:?}");
+    (response.status, response.header("Allow").map(String::from))
+}
+
+fn tree() -> TempDir {
+    let root = TempDir::new();
+    root.write("index.html", "<p>hi</p>");
+    root.write("docs/readme.txt", "read me");
+    root
+}
+
+#[test]
+fn read_only_resources_allow_reading() {
+    let root = tree();
+    let server = Server::start(&[root.str()]);
+    let reading = Some(String::from("GET, HEAD, OPTIONS, PROPFIND"));
+    assert_eq!(allow(&server, "/index.html"), (204, reading.clone()));
+    assert_eq!(allow(&server, "/docs"), (204, reading));
+    assert_eq!(allow(&server, "/missing.txt").0, 404);
+    // What isn't allowed is a 405 with the same list
+    let response = server.request("DELETE", "/index.html", &[], b"");
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS, PROPFIND"));
+}
+
+#[test]
+fn writable_files_and_directories_differ() {
+    let root = tree();
+    let server = Server::start(&[root.str(), "--write", "--route", "/docs", "write=off"]);
+    assert_eq!(allow(&server, "/index.html"), (204, Some(String::from("GET, HEAD, OPTIONS, PROPFIND, PUT, DELETE, MOVE, COPY"))));
+    assert_eq!(allow(&server, "/"), (204, Some(String::from("GET, HEAD, OPTIONS, PROPFIND, POST, DELETE, MOVE, COPY"))));
+    // Only an upload can make something of a missing path
+    assert_eq!(allow(&server, "/new.txt"), (204, Some(String::from("OPTIONS, PUT, MKCOL"))));
+    // A route that doesn't write is read-only, whatever --write says
+    assert_eq!(allow(&server, "/docs/readme.txt"), (204, Some(String::from("GET, HEAD, OPTIONS, PROPFIND"))));
+    assert_eq!(allow(&server, "/docs/new.txt").0, 404);
+}
+
+#[test]
+fn handlers_tell_their_own_methods() {
+    let server = Server::start(&["--metrics", "--echo", "/echo"]);
+    assert_eq!(allow(&server, "/metrics"), (204, Some(String::from("GET, HEAD, OPTIONS"))));
+    assert_eq!(allow(&server, "/echo"), (204, Some(String::from("GET, HEAD, OPTIONS, POST, PUT, PATCH, DELETE"))));
+    let response = server.request("POST", "/metrics", &[], b"x");
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+}
+
+#[test]
+fn asterisk_covers_every_resource() {
+    let root = tree();
+    let server = Server::start(&[root.str(), "--write"]);
+    assert_eq!(allow(&server, "*"), (204, Some(String::from("GET, HEAD, OPTIONS, PROPFIND, POST, DELETE, MOVE, COPY, PUT, MKCOL"))));
+}
+
+#[test]
+fn refused_method_gets_the_allow_of_its_resource() {
+    let root = tree();
+    let server = Server::start(&[root.str(), "--write", "--route", "/docs", "write=off"]);
+    for (path, allowed) in [
+        ("/index.html", "GET, HEAD, OPTIONS, PROPFIND, PUT, DELETE, MOVE, COPY"),
+        ("/docs/readme.txt", "GET, HEAD, OPTIONS, PROPFIND"),
+    ] {
+        let response = server.request("POST", path, &[], b"x");
+        assert_eq!(response.status, 405, "{path}");
+        assert_eq!(response.header("Allow"), Some(allowed), "{path}");
+    }
+}
+
+#[test]
+fn trace_and_connect_are_refused_and_nothing_is_echoed() {
+    let root = tree();
+    // Even with everything on that takes other methods
+    let server = Server::start(&[root.str(), "--write", "--echo", "/echo"]);
+    for request in [
+        "TRACE / HTTP/1.1\r\nHost: localhost\r\nCookie: secret=1\r\n\r\n",
+        "TRACE /echo HTTP/1.1\r\nHost: localhost\r\nCookie: secret=1\r\n\r\n",
+        "TRACE * HTTP/1.1\r\nHost: localhost\r\nCookie: secret=1\r\n\r\n",
+        "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nCookie: secret=1\r\n\r\n",
+    ] {
+        let raw = server.send(request.as_bytes());
+        let response = Response::parse(&raw);
+        assert_eq!(response.status, 501, "{request}");
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert!(!raw.contains("secret"), "echoed: {raw}");
+        assert_ne!(response.header("Content-Type"), Some("message/http"));
+    }
+    assert!(server.wait_for_output("TRACE is disabled"));
+    // They aren't offered either
+    let (_, allowed) = allow(&server, "/index.html");
+    assert!(!allowed.unwrap().contains("TRACE"));
+}
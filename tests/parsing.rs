@@ -0,0 +1,222 @@
+// How the request head is read: its limits, line endings and folding
+mod common;
+
+use std::io::Write;
+use common::{read_all, Response, Server, TempDir};
+
+#[test]
+fn unterminated_header_line_gets_431() {
+    let root = TempDir::new();
+    let server = Server::start(&["--max-line-size", "1024", root.str()]);
+    let mut stream = server.connect();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Endless: ").unwrap();
+    // Far more than the limit, the server has to answer before it is all sent
+    let chunk = vec![b'a'; 4096];
+    for _ in 0..16 {
+        if stream.write_all(&chunk).is_err() {
+            break;
+        }
+    }
+    assert_eq!(Response::parse(&read_all(&mut stream)).status, 431);
+}
+
+#[test]
+fn long_request_line_gets_414() {
+    let root = TempDir::new();
+    let server = Server::start(&["--max-line-size", "1024", root.str()]);
+    let request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(2000));
+    assert_eq!(Response::parse(&server.send(request.as_bytes())).status, 414);
+}
+
+#[test]
+fn header_line_within_the_limit_is_served() {
+    let root = TempDir::new();
+    root.write("a.txt", "hello");
+    let server = Server::start(&["--max-line-size", "1024", root.str()]);
+    let request = format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n", "a".repeat(1000));
+    let response = Response::parse(&server.send(request.as_bytes()));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello");
+}
+
+#[test]
+fn endless_folding_gets_431() {
+    let root = TempDir::new();
+    let server = Server::start(&["--unfold-headers", "--max-headers", "50", root.str()]);
+    let mut stream = server.connect();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n").unwrap();
+    for _ in 0..1000 {
+        if stream.write_all(b" b\r\n").is_err() {
+            break;
+        }
+    }
+    assert_eq!(Response::parse(&read_all(&mut stream)).status, 431);
+}
+
+#[test]
+fn malformed_content_length_gets_400_and_the_connection_ends() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    for length in ["abc", "-5", "+3", "1,1", "99999999999999999999999"] {
+        // What follows would be the next request if the length were guessed
+        let request = format!("POST /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\n\r\nGET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let raw = server.send(request.as_bytes());
+        let (response, rest) = Response::parse_next(&raw);
+        assert_eq!(response.status, 400, "{length}");
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert!(rest.is_empty(), "{length}: {rest}");
+    }
+}
+
+#[test]
+fn malformed_content_length_is_answered_without_waiting_for_a_body() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    for method in ["GET", "PUT"] {
+        // The connection stays open, the server must not sit waiting for a body
+        let mut stream = server.connect();
+        stream.write_all(format!("{method} /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: abc\r\n\r\n").as_bytes()).unwrap();
+        let raw = read_all(&mut stream);
+        assert_eq!(Response::parse(&raw).status, 400, "{method}");
+    }
+    assert!(server.wait_for_output("Content-Length"), "{}", server.output());
+}
+
+#[test]
+fn ambiguous_framing_gets_400_and_nothing_smuggled_is_answered() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let smuggled = "GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    for framing in [
+        "Content-Length: 4\r\nTransfer-Encoding: chunked",
+        "Transfer-Encoding: chunked\r\nContent-Length: 4",
+        "Content-Length: 4\r\nContent-Length: 40",
+        "Transfer-Encoding: gzip, chunked",
+        "Transfer-Encoding: chunked\r\nTransfer-Encoding: identity",
+    ] {
+        let request = format!("POST /a.txt HTTP/1.1\r\nHost: localhost\r\n{framing}\r\n\r\n0\r\n\r\n{smuggled}");
+        let raw = server.send(request.as_bytes());
+        let (response, rest) = Response::parse_next(&raw);
+        assert_eq!(response.status, 400, "{framing}");
+        assert_eq!(response.header("Connection"), Some("close"), "{framing}");
+        assert!(rest.is_empty(), "{framing}: {rest}");
+    }
+}
+
+#[test]
+fn bare_lf_request_is_served_by_default() {
+    let root = TempDir::new();
+    root.write("a.txt", "abcdef");
+    let server = Server::start(&[root.str()]);
+    // The headers are all read, the Range is honoured, and the next request follows
+    let raw = server.send(b"GET /a.txt HTTP/1.1\nHost: localhost\nRange: bytes=1-2\n\nGET /a.txt HTTP/1.1\r\nHost: localhost\n\r\n");
+    let (first, rest) = Response::parse_next(&raw);
+    assert_eq!((first.status, first.body.as_str()), (206, "bc"));
+    let second = Response::parse(rest);
+    assert_eq!((second.status, second.body.as_str()), (200, "abcdef"));
+}
+
+#[test]
+fn strict_line_endings_refuse_a_bare_lf() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--strict-line-endings", root.str()]);
+    assert_eq!(Response::parse(&server.send(b"GET /a.txt HTTP/1.1\nHost: localhost\n\n")).status, 400);
+    assert_eq!(Response::parse(&server.send(b"GET /a.txt HTTP/1.1\r\nHost: localhost\n\r\n")).status, 400);
+    assert_eq!(server.get("/a.txt").body, "a");
+}
+
+#[test]
+fn stray_cr_gets_400_in_either_mode() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    for args in [&[root.str()][..], &["--strict-line-endings", root.str()]] {
+        let server = Server::start(args);
+        assert_eq!(Response::parse(&server.send(b"GET /a.txt HTTP/1.1\r\nHost: local\rhost\r\n\r\n")).status, 400, "{args:?}");
+        assert_eq!(Response::parse(&server.send(b"GET /a.txt\r HTTP/1.1\r\nHost: localhost\r\n\r\n")).status, 400, "{args:?}");
+    }
+}
+
+#[test]
+fn five_hundred_headers_get_431() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let headers: String = (0..500).map(|n| format!("X-Header-{n}: {n}\r\n")).collect();
+    let response = Response::parse(&server.send(format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n").as_bytes()));
+    assert_eq!(response.status, 431);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn max_headers_sets_the_limit() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--max-headers", "10", root.str()]);
+    let request = |count: usize| {
+        let headers: String = (1..count).map(|n| format!("X-Header-{n}: {n}\r\n")).collect();
+        Response::parse(&server.send(format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n").as_bytes())).status
+    };
+    // Host is one of them
+    assert_eq!(request(10), 200);
+    assert_eq!(request(11), 431);
+}
+
+#[test]
+fn folded_header_gets_400_by_default() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    for fold in [" continued", "\tcontinued"] {
+        let request = format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\nX-Folded: one\r\n{fold}\r\n\r\n");
+        let response = Response::parse(&server.send(request.as_bytes()));
+        assert_eq!(response.status, 400, "{fold:?}");
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+}
+
+#[test]
+fn trailing_whitespace_of_a_value_is_dropped() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let etag = server.get("/a.txt").header("ETag").unwrap().to_string();
+    // The tag would no longer match with the blanks kept
+    let request = format!("GET /a.txt HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {etag} \t \r\n\r\n");
+    assert_eq!(Response::parse(&server.send(request.as_bytes())).status, 304);
+}
+
+#[test]
+fn length_with_chunked_is_closed_by_the_server() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    // The client keeps its side open, only the server can end it
+    let mut stream = server.connect();
+    stream.write_all(b"POST /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let started = std::time::Instant::now();
+    let raw = read_all(&mut stream);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5), "still open");
+    let (response, rest) = Response::parse_next(&raw);
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(rest.is_empty(), "{rest}");
+}
+
+#[test]
+fn http11_needs_exactly_one_host() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    let response = Response::parse(&server.send(b"GET /a.txt HTTP/1.1\r\n\r\n"));
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+    let twice = server.send(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nHost: other\r\n\r\n");
+    assert_eq!(Response::parse(&twice).status, 400);
+    // HTTP/1.0 had no Host to begin with
+    let old = Response::parse(&server.send(b"GET /a.txt HTTP/1.0\r\n\r\n"));
+    assert_eq!((old.status, old.body.as_str()), (200, "a"));
+}
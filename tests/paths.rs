@@ -0,0 +1,74 @@
+// How the path of the target is decoded and normalized before it touches the disk
+mod common;
+
+use common::{Server, TempDir};
+
+fn server() -> (TempDir, TempDir, Server) {
+    let outside = TempDir::new();
+    outside.write("secret.txt", "secret");
+    let root = TempDir::new();
+    root.write("dir name/中文.txt", "unicode");
+    root.write("a+b.txt", "plus");
+    root.write("q?.txt", "question");
+    let server = Server::start(&[root.str()]);
+    (outside, root, server)
+}
+
+#[test]
+fn escaped_names_are_found() {
+    let (_outside, _root, server) = server();
+    assert_eq!(server.get("/dir%20name/%E4%B8%AD%E6%96%87.txt").body, "unicode");
+    assert_eq!(server.get("/dir%20name/%e4%b8%ad%e6%96%87.txt").body, "unicode");
+    // '+' is itself in the path, and an escaped '?' is part of the name
+    assert_eq!(server.get("/a+b.txt").body, "plus");
+    assert_eq!(server.get("/q%3F.txt").body, "question");
+}
+
+#[test]
+fn query_is_not_part_of_the_path() {
+    let (_outside, _root, server) = server();
+    let response = server.get("/a+b.txt?x=1&y=%2F");
+    assert_eq!((response.status, response.body.as_str()), (200, "plus"));
+}
+
+#[test]
+fn dots_inside_the_root_are_resolved() {
+    let (_outside, _root, server) = server();
+    assert_eq!(server.get("/dir%20name/../a+b.txt").body, "plus");
+    assert_eq!(server.get("/./dir%20name/%2e%2E/a+b.txt").body, "plus");
+    assert_eq!(server.get("//a+b.txt").body, "plus");
+}
+
+#[test]
+fn leaving_the_root_is_a_400() {
+    let (outside, _root, server) = server();
+    let name = outside.path().file_name().unwrap().to_str().unwrap();
+    for path in [
+        format!("/../{name}/secret.txt"),
+        format!("/%2e%2e/{name}/secret.txt"),
+        format!("/dir%20name/../../{name}/secret.txt"),
+        format!("/..%2F{name}%2Fsecret.txt"),
+    ] {
+        let response = server.get(&path);
+        assert_eq!(response.status, 400, "{path}");
+        assert!(!response.body.contains("secret"), "{path}");
+    }
+}
+
+#[test]
+fn broken_escapes_and_controls_are_a_400() {
+    let (_outside, _root, server) = server();
+    for path in ["/a%", "/a%zz", "/%ff", "/a%2Fb", "/a%00.txt", "/a%0A.txt"] {
+        assert_eq!(server.get(path).status, 400, "{path}");
+    }
+}
+
+#[test]
+fn invalid_utf8_in_the_path_is_a_400() {
+    let (_outside, root, server) = server();
+    root.write("😀.txt", "emoji");
+    assert_eq!(server.get("/%F0%9F%98%80.txt").body, "emoji");
+    for path in ["/%C0%AF..%C0%AFsecret.txt", "/%ED%A0%80.txt", "/%80.txt", "/%F0%9F%98.txt"] {
+        assert_eq!(server.get(path).status, 400, "{path}");
+    }
+}
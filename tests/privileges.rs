@@ -0,0 +1,98 @@
+// --user, --group and --chroot, which only root can do, the tests skip otherwise
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::thread;
+use std::time::Duration;
+use common::{run, Server, TempDir};
+
+fn is_root() -> bool {
+    // SAFETY: reads the id of this process
+    unsafe { libc::geteuid() == 0 }
+}
+
+// The Uid, Gid and Groups lines of the process
+fn ids(pid: u32) -> Vec<String> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap();
+    status.lines()
+        .filter(|line| line.starts_with("Uid:") || line.starts_with("Gid:") || line.starts_with("Groups:"))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect()
+}
+
+#[test]
+fn serves_as_the_user_without_roots_groups() {
+    if !is_root() {
+        return;
+    }
+    let root = TempDir::new();
+    root.write("a.txt", "as nobody");
+    let server = Server::start(&[root.str(), "--user", "nobody", "--group", "nogroup"]);
+    assert!(server.wait_for_output("Running as nobody, group nogroup"), "{}", server.output());
+    assert_eq!(server.get("/a.txt").body, "as nobody");
+    assert_eq!(ids(server.pid()), ["Uid: 65534 65534 65534 65534", "Gid: 65534 65534 65534 65534", "Groups: 65534"]);
+}
+
+#[test]
+fn roots_are_found_inside_the_chroot() {
+    if !is_root() {
+        return;
+    }
+    let jail = TempDir::new();
+    jail.write("site/a.txt", "jailed");
+    let server = Server::start(&["/site", "--user", "65534", "--chroot", jail.str()]);
+    assert_eq!(server.get("/a.txt").body, "jailed");
+    let seen_as_root = std::fs::read_link(format!("/proc/{}/root", server.pid())).unwrap();
+    assert_eq!(seen_as_root, jail.path());
+}
+
+#[test]
+fn sigusr1_inside_the_chroot_keeps_the_log_file() {
+    if !is_root() {
+        return;
+    }
+    let (jail, logs) = (TempDir::new(), TempDir::new());
+    jail.write("site/a.txt", "jailed");
+    let log = logs.path().join("log");
+    // The same directory inside the jail, where opening the path again would go
+    std::fs::create_dir_all(jail.path().join(logs.path().strip_prefix("/").unwrap())).unwrap();
+    std::fs::set_permissions(jail.path().join(logs.path().strip_prefix("/").unwrap()), Permissions::from_mode(0o777)).unwrap();
+    let server = Server::start_logging_to(&log, &["/site", "--user", "65534", "--chroot", jail.str()]);
+    assert_eq!(server.get("/a.txt").body, "jailed");
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGUSR1) }, 0);
+    let expected = format!("can't open the log file {} again from inside the chroot", log.display());
+    for _ in 0..100 {
+        if std::fs::read_to_string(&log).unwrap().contains(&expected) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(std::fs::read_to_string(&log).unwrap().contains(&expected));
+    assert!(!jail.path().join(log.strip_prefix("/").unwrap()).exists());
+}
+
+#[test]
+fn staying_root_is_refused() {
+    if !is_root() {
+        return;
+    }
+    let root = TempDir::new();
+    let output = run(&[root.str(), "--port", "0", "--user", "root"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("still running as root"), "{output:?}");
+}
+
+#[test]
+fn group_or_chroot_alone_is_refused() {
+    for args in [&["--group", "nogroup"][..], &["--chroot", "/tmp"]] {
+        let output = run(args);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--group and --chroot need --user"), "{args:?}");
+    }
+    let output = run(&["--check", "--user", "no-such-user-here"]);
+    assert!(!output.status.success());
+    assert!(format!("{output:?}").contains("no such user 'no-such-user-here'"), "{output:?}");
+}
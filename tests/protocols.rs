@@ -0,0 +1,71 @@
+// Only HTTP/1.x is spoken, upgrades and HTTP/2 get a clean refusal instead of garbage
+mod common;
+
+use std::io::{Read, Write};
+use common::{Response, Server, TempDir};
+
+const HANDSHAKE: &str = "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+fn server(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("chat", "not a socket");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn refused_websocket_handshake_gets_a_426() {
+    let (_root, server) = server(&["--upgrade", "refuse"]);
+    // A frame sent right after the head, it must not be read as a request
+    let raw = server.send(format!("{HANDSHAKE}\u{81}\u{05}hello").as_bytes());
+    let (response, rest) = Response::parse_next(&raw);
+    assert_eq!(response.status, 426);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(response.header("Upgrade"), None);
+    assert!(response.header("Content-Length").is_some());
+    assert!(rest.is_empty(), "{rest:?}");
+}
+
+#[test]
+fn handshake_is_answered_as_if_there_were_no_upgrade_by_default() {
+    let (_root, server) = server(&[]);
+    let raw = server.send(format!("{HANDSHAKE}GET /chat HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes());
+    let (response, rest) = Response::parse_next(&raw);
+    assert_eq!((response.status, response.body.as_str()), (200, "not a socket"));
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(rest.is_empty(), "{rest:?}");
+}
+
+#[test]
+fn upgrade_without_the_connection_token_is_a_plain_request() {
+    let (_root, server) = server(&[]);
+    let response = server.request("GET", "/chat", &[("Upgrade", "websocket")], b"");
+    assert_eq!((response.status, response.body.as_str()), (200, "not a socket"));
+}
+
+// The client preface, then an empty SETTINGS frame as a real client would send
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0";
+
+#[test]
+fn http2_preface_is_refused_in_frames() {
+    let (_root, server) = server(&[]);
+    let mut stream = server.connect();
+    stream.write_all(H2_PREFACE).unwrap();
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer).unwrap();
+    // SETTINGS, then GOAWAY with HTTP_1_1_REQUIRED, and the connection closed
+    let mut expected = vec![0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+    expected.extend([0, 0, 8, 0x7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xd]);
+    assert_eq!(answer, expected);
+    assert!(!String::from_utf8_lossy(&answer).contains("HTTP/1.1"));
+    assert!(server.wait_for_output("HTTP/2 connection preface"), "{}", server.output());
+}
+
+#[test]
+fn http2_request_line_gets_a_505() {
+    let (_root, server) = server(&[]);
+    let response = Response::parse(&server.send(b"GET /chat HTTP/2.0\r\nHost: localhost\r\n\r\n"));
+    assert_eq!(response.status, 505);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
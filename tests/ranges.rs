@@ -0,0 +1,155 @@
+// Range with the conditional headers, evaluated in the order of RFC 9110 13.2.2
+mod common;
+
+use std::io::{Read, Write};
+use common::{send_and_close, Response, Server, TempDir};
+
+fn server() -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("digits.txt", "0123456789");
+    let server = Server::start(&[root.str()]);
+    (root, server)
+}
+
+fn etag(server: &Server) -> String {
+    server.get("/digits.txt").header("ETag").unwrap().to_string()
+}
+
+#[test]
+fn range_alone_is_a_206() {
+    let (_root, server) = server();
+    let response = server.request("GET", "/digits.txt", &[("Range", "bytes=2-4")], b"");
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, "234");
+    assert_eq!(response.header("Content-Range"), Some("bytes 2-4/10"));
+}
+
+#[test]
+fn matching_if_none_match_is_a_304_with_or_without_range() {
+    let (_root, server) = server();
+    let etag = etag(&server);
+    for headers in [&[("If-None-Match", etag.as_str())][..], &[("If-None-Match", etag.as_str()), ("Range", "bytes=2-4")]] {
+        let response = server.request("GET", "/digits.txt", headers, b"");
+        assert_eq!(response.status, 304, "{headers:?}");
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        assert!(response.body.is_empty());
+    }
+}
+
+#[test]
+fn other_if_none_match_leaves_the_range() {
+    let (_root, server) = server();
+    let response = server.request("GET", "/digits.txt", &[("If-None-Match", "\"other\""), ("Range", "bytes=2-4")], b"");
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, "234");
+}
+
+#[test]
+fn if_range_decides_between_the_part_and_the_whole() {
+    let (_root, server) = server();
+    let etag = etag(&server);
+    let current = server.request("GET", "/digits.txt", &[("If-Range", &etag), ("Range", "bytes=2-4")], b"");
+    assert_eq!((current.status, current.body.as_str()), (206, "234"));
+    let changed = server.request("GET", "/digits.txt", &[("If-Range", "\"other\""), ("Range", "bytes=2-4")], b"");
+    assert_eq!((changed.status, changed.body.as_str()), (200, "0123456789"));
+    // Last-Modified works as well, it has a one second resolution
+    let modified = server.get("/digits.txt").header("Last-Modified").unwrap().to_string();
+    let dated = server.request("GET", "/digits.txt", &[("If-Range", &modified), ("Range", "bytes=0-0")], b"");
+    assert_eq!(dated.status, 206);
+}
+
+#[test]
+fn failed_if_match_goes_before_the_range() {
+    let (_root, server) = server();
+    let response = server.request("GET", "/digits.txt", &[("If-Match", "\"other\""), ("Range", "bytes=2-4")], b"");
+    assert_eq!(response.status, 412);
+    let etag = etag(&server);
+    let response = server.request("GET", "/digits.txt", &[("If-Match", &etag), ("Range", "bytes=2-4")], b"");
+    assert_eq!(response.status, 206);
+}
+
+#[test]
+fn range_past_the_end_is_a_416_with_the_size() {
+    let (_root, server) = server();
+    for range in ["bytes=20-30", "bytes=99999999-"] {
+        let response = server.request("GET", "/digits.txt", &[("Range", range)], b"");
+        assert_eq!(response.status, 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+        // What comes is the error, not a part of the file or anything about it
+        assert!(!response.body.contains("0123"));
+        assert_eq!(response.header("Content-Type"), None);
+        assert_eq!(response.header("ETag"), None);
+    }
+}
+
+#[test]
+fn error_page_of_a_416_has_its_own_type() {
+    let root = TempDir::new();
+    root.write("digits.txt", "0123456789");
+    let page = root.write("416.html", "<p>{{code}} {{reason}}</p>");
+    let server = Server::start(&[root.str(), "--error-page", "416", page.to_str().unwrap()]);
+    let response = server.request("GET", "/digits.txt", &[("Range", "bytes=99999999-")], b"");
+    assert_eq!(response.status, 416);
+    assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+    let types: Vec<&str> = response.headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("Content-Type")).map(|(_, value)| value.as_str()).collect();
+    assert_eq!(types, ["text/html; charset=utf-8"]);
+}
+
+#[test]
+fn range_is_cut_from_the_current_content() {
+    let (root, server) = server();
+    let old = etag(&server);
+    let mut stream = server.connect();
+    stream.write_all(b"GET /digits.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=2-4\r\n\r\n").unwrap();
+    let mut first = Vec::new();
+    while !first.ends_with(b"234") {
+        let mut buf = [0; 512];
+        let read = stream.read(&mut buf).unwrap();
+        assert!(read > 0, "closed after {:?}", String::from_utf8_lossy(&first));
+        first.extend_from_slice(&buf[..read]);
+    }
+    // Changed between two requests on the same connection, nothing of the old bytes is left
+    root.write("digits.txt", "abcdefghijklmnop");
+    let again = format!("GET /digits.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=2-4\r\n\r\nGET /digits.txt HTTP/1.1\r\nHost: localhost\r\nIf-Range: {old}\r\nRange: bytes=2-4\r\n\r\n");
+    let raw = send_and_close(&mut stream, again.as_bytes());
+    let (sliced, rest) = Response::parse_next(&raw);
+    assert_eq!((sliced.status, sliced.body.as_str()), (206, "cde"));
+    assert_eq!(sliced.header("Content-Range"), Some("bytes 2-4/16"));
+    let whole = Response::parse(rest);
+    assert_eq!((whole.status, whole.body.as_str()), (200, "abcdefghijklmnop"));
+}
+
+#[test]
+fn files_advertise_byte_ranges_by_default() {
+    let (_root, server) = server();
+    assert_eq!(server.get("/digits.txt").header("Accept-Ranges"), Some("bytes"));
+}
+
+#[test]
+fn route_without_ranges_says_none_and_sends_it_all() {
+    let root = TempDir::new();
+    root.write("digits.txt", "0123456789");
+    root.write("live/feed.txt", "0123456789");
+    let server = Server::start(&[root.str(), "--route", "/live", "ranges=off"]);
+    let response = server.request("GET", "/live/feed.txt", &[("Range", "bytes=2-4")], b"");
+    assert_eq!((response.status, response.body.as_str()), (200, "0123456789"));
+    assert_eq!(response.header("Accept-Ranges"), Some("none"));
+    assert_eq!(response.header("Content-Range"), None);
+    // Even a range past the end isn't a 416 there
+    assert_eq!(server.request("GET", "/live/feed.txt", &[("Range", "bytes=50-")], b"").status, 200);
+    // The rest of the tree still has them
+    let response = server.request("GET", "/digits.txt", &[("Range", "bytes=2-4")], b"");
+    assert_eq!((response.status, response.body.as_str()), (206, "234"));
+}
+
+#[test]
+fn no_ranges_turns_them_off_everywhere_but_a_route_can_turn_them_on() {
+    let root = TempDir::new();
+    root.write("digits.txt", "0123456789");
+    root.write("media/clip.txt", "0123456789");
+    let server = Server::start(&[root.str(), "--no-ranges", "--route", "/media", "ranges=on"]);
+    let response = server.request("GET", "/digits.txt", &[("Range", "bytes=0-0")], b"");
+    assert_eq!((response.status, response.header("Accept-Ranges")), (200, Some("none")));
+    let response = server.request("GET", "/media/clip.txt", &[("Range", "bytes=0-0")], b"");
+    assert_eq!((response.status, response.body.as_str(), response.header("Accept-Ranges")), (206, "0", Some("bytes")));
+}
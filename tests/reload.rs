@@ -0,0 +1,83 @@
+// SIGHUP and --watch-config read the config file again while serving
+#![cfg(unix)]
+mod common;
+
+use std::path::Path;
+use common::{base64, Server, TempDir};
+
+fn hangup(server: &Server) {
+    // The handlers are set up after the url is printed, once a request was served they are
+    server.get("/");
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGHUP) }, 0);
+}
+
+fn config(path: &Path, root: &TempDir, rest: &str) {
+    std::fs::write(path, format!("[root]\npaths = [{:?}]\n{rest}", root.str())).unwrap();
+}
+
+#[test]
+fn sighup_applies_the_rewritten_file() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let file = dir.path().join("site.toml");
+    config(&file, &root, "[auth]\nusers = [\"old:pw\"]\n");
+    let server = Server::start(&["--config", file.to_str().unwrap()]);
+    let old = format!("Basic {}", base64(b"old:pw"));
+    let new = format!("Basic {}", base64(b"new:pw"));
+    assert_eq!(server.request("GET", "/a.txt", &[("Authorization", &old)], b"").status, 200);
+    assert_eq!(server.request("GET", "/a.txt", &[("Authorization", &new)], b"").status, 401);
+
+    config(&file, &root, "[auth]\nusers = [\"new:pw\"]\n[security]\nframe_options = \"SAMEORIGIN\"\n");
+    hangup(&server);
+    assert!(server.wait_for_output("reloaded the settings"), "{}", server.output());
+    let response = server.request("GET", "/a.txt", &[("Authorization", &new)], b"");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert_eq!(server.request("GET", "/a.txt", &[("Authorization", &old)], b"").status, 401);
+}
+
+#[test]
+fn broken_file_keeps_the_old_settings() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let file = dir.path().join("site.toml");
+    config(&file, &root, "[security]\nframe_options = \"SAMEORIGIN\"\n");
+    let server = Server::start(&["--config", file.to_str().unwrap()]);
+    std::fs::write(&file, "[security\nframe_options = 1\n").unwrap();
+    hangup(&server);
+    assert!(server.wait_for_output("keeping the current settings"), "{}", server.output());
+    let response = server.get("/a.txt");
+    assert_eq!((response.status, response.header("X-Frame-Options")), (200, Some("SAMEORIGIN")));
+}
+
+#[test]
+fn listener_changes_wait_for_a_restart() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let file = dir.path().join("site.toml");
+    config(&file, &root, "[listener]\nthreads = 1\n");
+    let server = Server::start(&["--config", file.to_str().unwrap()]);
+    config(&file, &root, "[listener]\nthreads = 4\n[security]\nreferrer_policy = \"origin\"\n");
+    hangup(&server);
+    assert!(server.wait_for_output("changing the threads needs a restart"), "{}", server.output());
+    assert!(server.wait_for_output("reloaded the settings"));
+    // The rest is applied, on the same port as before
+    assert_eq!(server.get("/a.txt").header("Referrer-Policy"), Some("origin"));
+}
+
+#[test]
+fn watch_config_reloads_on_a_change() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let file = dir.path().join("site.toml");
+    config(&file, &root, "");
+    let server = Server::start(&["--config", file.to_str().unwrap(), "--watch-config"]);
+    assert_eq!(server.get("/a.txt").header("Referrer-Policy"), Some("no-referrer"));
+    // A new mtime, even on filesystems that keep whole seconds
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    config(&file, &root, "[security]\nreferrer_policy = \"origin\"\n");
+    assert!(server.wait_for_output("the config file changed"), "{}", server.output());
+    assert!(server.wait_for_output("reloaded the settings"));
+    assert_eq!(server.get("/a.txt").header("Referrer-Policy"), Some("origin"));
+}
@@ -0,0 +1,40 @@
+// Every response has an X-Request-Id, the same one its log lines start with
+mod common;
+
+use common::{Server, TempDir};
+
+fn server(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn response_carries_the_id_of_its_log_lines() {
+    let (_root, server) = server(&[]);
+    let first = server.get("/a.txt");
+    let id = first.header("X-Request-Id").expect("an X-Request-Id").to_string();
+    assert_eq!(id.len(), 16);
+    assert!(id.bytes().all(|b| b.is_ascii_hexdigit()), "{id}");
+    assert!(server.wait_for_output(&format!("[{id}] method GET path /a.txt")), "{}", server.output());
+    // Errors have one too, and no two are the same
+    let missing = server.get("/missing.txt");
+    assert_eq!(missing.status, 404);
+    assert_ne!(missing.header("X-Request-Id"), Some(id.as_str()));
+}
+
+#[test]
+fn incoming_id_is_only_taken_when_trusted() {
+    let (_root, untrusted) = server(&[]);
+    let response = untrusted.request("GET", "/a.txt", &[("X-Request-Id", "from-proxy-1")], b"");
+    assert_ne!(response.header("X-Request-Id"), Some("from-proxy-1"));
+    let (_root, trusted) = server(&["--trust-request-id", "-v"]);
+    let response = trusted.request("GET", "/a.txt", &[("X-Request-Id", "from-proxy-1")], b"");
+    assert_eq!(response.header("X-Request-Id"), Some("from-proxy-1"));
+    // The request line is logged before the headers are read, what follows goes by the new id
+    assert!(trusted.wait_for_output("continuing as request from-proxy-1"), "{}", trusted.output());
+    // Nothing that could break a header or a log line
+    let response = trusted.request("GET", "/a.txt", &[("X-Request-Id", "a b\"c")], b"");
+    assert_eq!(response.header("X-Request-Id").unwrap().len(), 16);
+}
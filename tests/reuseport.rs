@@ -0,0 +1,57 @@
+// --reuseport N acceptors on one address, the kernel spreads the connections
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::net::TcpListener;
+use common::{run, Server, TempDir};
+
+// Connections per acceptor by the metrics
+fn accepted(server: &Server) -> Vec<u64> {
+    server.get("/metrics").body
+        .lines()
+        .filter(|line| line.starts_with("httpserver_acceptor_connections_total{"))
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn connections_are_spread_over_the_acceptors() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--reuseport", "4", "--metrics", root.str()]);
+    for index in 0..4 {
+        assert!(server.output().contains(&format!("(acceptor {index})")), "{}", server.output());
+    }
+    // A new source port each time, that is what the kernel hashes
+    for _ in 0..200 {
+        assert_eq!(server.get("/a.txt").status, 200);
+    }
+    let counts = accepted(&server);
+    // The request for the metrics is counted before it is answered
+    assert_eq!(counts.iter().sum::<u64>(), 201, "{counts:?}");
+    let busy = counts.iter().filter(|&&count| count > 0).count();
+    assert!(busy >= 2, "all on one acceptor: {counts:?}");
+}
+
+#[test]
+fn another_process_can_share_the_port() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let old = Server::start(&["--reuseport", "1", root.str()]);
+    let port = old.addr.port().to_string();
+    // What a restart without downtime does, the new one binds before the old one goes
+    let new = Server::start_listening(&[root.str(), "--reuseport", "1", "--listen", &format!("127.0.0.1:{port}")]);
+    assert_eq!(new.addr, old.addr);
+    drop(old);
+    assert_eq!(new.get("/a.txt").status, 200);
+}
+
+#[test]
+fn port_held_without_reuseport_is_refused() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let root = TempDir::new();
+    let output = run(&[root.str(), "--reuseport", "2", "--listen", &taken.local_addr().unwrap().to_string()]);
+    assert!(!output.status.success());
+    let output = run(&["--reuseport", "0"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid socket count '0'"));
+}
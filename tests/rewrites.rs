@@ -0,0 +1,85 @@
+// --rewrite serves another path internally or redirects to it
+mod common;
+
+use common::{Server, TempDir};
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("new/page.txt", "new page");
+    root.write("new/a b.txt", "spaced");
+    root.write("search.txt", "search");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn internal_rewrite_serves_the_target() {
+    let (_root, server) = site(&["--rewrite", "/old/*", "/new/$1"]);
+    let response = server.get("/old/page.txt");
+    assert_eq!((response.status, response.body.as_str()), (200, "new page"));
+    assert_eq!(response.header("Location"), None);
+    // The capture is the decoded path, it is found again encoded
+    assert_eq!(server.get("/old/a%20b.txt").body, "spaced");
+    assert_eq!(server.get("/old/missing.txt").status, 404);
+}
+
+#[test]
+fn redirects_go_out_with_their_status() {
+    for code in [301, 302, 307, 308] {
+        let target = format!("{code} https://blog.example.com/$1");
+        let (_root, server) = site(&["--rewrite", "/blog/*", &target]);
+        let response = server.get("/blog/2024/hello%20world");
+        assert_eq!(response.status, code);
+        assert_eq!(response.header("Location"), Some("https://blog.example.com/2024/hello%20world"));
+    }
+}
+
+#[test]
+fn query_is_kept_unless_the_target_has_one() {
+    let (_root, server) = site(&["--rewrite", "/blog/*", "301 /new/$1", "--rewrite", "/tag/*", "302 /search?tag=$1"]);
+    assert_eq!(server.get("/blog/page.txt?utm=x&y=1").header("Location"), Some("/new/page.txt?utm=x&y=1"));
+    assert_eq!(server.get("/tag/a&b?utm=x").header("Location"), Some("/search?tag=a%26b"));
+}
+
+#[test]
+fn rewrites_see_the_normalized_path() {
+    let (_root, server) = site(&["--rewrite", "/old/*", "/new/$1"]);
+    assert_eq!(server.get("/x/../old/./page.txt").body, "new page");
+    assert_eq!(server.get("/%6fld/page.txt").body, "new page");
+}
+
+#[test]
+fn first_matching_rule_wins_and_chains_are_followed() {
+    let (_root, server) = site(&["--rewrite", "/a/*", "/b/$1", "--rewrite", "/b/*", "/new/$1", "--rewrite", "/a/page.txt", "/search.txt"]);
+    // Through /b/ to /new/, the later exact rule never gets its turn
+    assert_eq!(server.get("/a/page.txt").body, "new page");
+}
+
+#[test]
+fn rules_going_in_circles_are_stopped() {
+    let (_root, server) = site(&["--rewrite", "/ping/*", "/pong/$1", "--rewrite", "/pong/*", "/ping/$1"]);
+    let response = server.get("/ping/x");
+    assert_eq!(response.status, 500);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(server.wait_for_output("the rules are going in circles"), "{}", server.output());
+}
+
+#[test]
+fn rules_come_from_the_config_file_in_order() {
+    let root = TempDir::new();
+    root.write("new/page.txt", "new page");
+    let config = root.write("site.toml", format!(
+        "[root]\npaths = [{:?}]\n\n[rewrites]\n\"/old/*\" = \"/new/$1\"\n\"/blog/*\" = \"301 https://blog.example.com/$1\"\n",
+        root.str(),
+    ));
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    assert_eq!(server.get("/old/page.txt").body, "new page");
+    assert_eq!(server.get("/blog/x").header("Location"), Some("https://blog.example.com/x"));
+}
+
+#[test]
+fn bad_rules_are_refused() {
+    let output = common::run(&["--rewrite", "/a/*", "303 /b/$1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid redirect status '303', expected 301, 302, 307 or 308"));
+}
@@ -0,0 +1,86 @@
+// Several roots searched in order, the first that has a path serves it
+mod common;
+
+use common::{Server, TempDir};
+
+#[test]
+fn file_only_in_the_second_root_is_served() {
+    let (theme, base) = (TempDir::new(), TempDir::new());
+    theme.write("style.css", "themed");
+    base.write("style.css", "plain");
+    base.write("index.txt", "from base");
+    let server = Server::start(&[theme.str(), "--root", base.str()]);
+    let response = server.get("/index.txt");
+    assert_eq!((response.status, response.body.as_str()), (200, "from base"));
+    // The first one wins where both have it
+    assert_eq!(server.get("/style.css").body, "themed");
+    assert_eq!(server.get("/nowhere.txt").status, 404);
+}
+
+#[test]
+fn listing_comes_from_the_first_root_with_the_directory() {
+    let (first, second) = (TempDir::new(), TempDir::new());
+    first.write("docs/only-first.txt", "1");
+    second.write("docs/only-second.txt", "2");
+    second.write("extra/x.txt", "x");
+    let server = Server::start(&[first.str(), second.str()]);
+    let listing = server.get("/docs/").body;
+    assert!(listing.contains("only-first.txt") && !listing.contains("only-second.txt"), "{listing}");
+    // Files of the same directory in a later root are still found
+    assert_eq!(server.get("/docs/only-second.txt").body, "2");
+    assert!(server.get("/extra/").body.contains("x.txt"));
+}
+
+#[test]
+fn each_root_keeps_its_own_bounds() {
+    let parent = TempDir::new();
+    parent.write("first/a.txt", "a");
+    parent.write("second/b.txt", "b");
+    parent.write("secret.txt", "s");
+    let first = parent.path().join("first");
+    let second = parent.path().join("second");
+    let server = Server::start(&[first.to_str().unwrap(), second.to_str().unwrap()]);
+    // One root is no way into the other or out of both
+    assert_eq!(server.get("/../second/b.txt").status, 400);
+    assert_eq!(server.get("/%2e%2e/secret.txt").status, 400);
+    assert_eq!(server.get("/b.txt").body, "b");
+}
+
+// What went wrong at startup, it is logged like everything else then
+fn startup_error(args: &[&str]) -> String {
+    let output = common::run(&[&["--port", "0"], args].concat());
+    assert!(!output.status.success(), "{args:?} started");
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn missing_root_stops_the_start() {
+    let dir = TempDir::new();
+    let missing = dir.path().join("nonexistent");
+    let told = startup_error(&[missing.to_str().unwrap()]);
+    assert!(told.contains(&format!("{} does not exist, add --create-root to create it", missing.display())), "{told}");
+    // Any of several
+    let told = startup_error(&[dir.str(), "--root", missing.to_str().unwrap()]);
+    assert!(told.contains("does not exist"), "{told}");
+    assert!(!missing.exists());
+}
+
+#[test]
+fn file_as_root_is_not_a_directory() {
+    let dir = TempDir::new();
+    dir.write("sub/a.txt", "a");
+    let file = dir.path().join("sub/a.txt");
+    let told = startup_error(&[dir.str(), "--root", file.to_str().unwrap()]);
+    assert!(told.contains(&format!("{} is not a directory", file.display())), "{told}");
+}
+
+#[test]
+fn create_root_makes_the_missing_directories() {
+    let dir = TempDir::new();
+    let missing = dir.path().join("new/site");
+    let server = Server::start(&[missing.to_str().unwrap(), "--create-root"]);
+    // Once the privileges are dropped, after the url is printed
+    assert!(server.wait_for_output(&format!("created {}", missing.display())), "{}", server.output());
+    assert!(missing.is_dir());
+    assert_eq!(server.get("/").status, 200);
+}
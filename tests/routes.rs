@@ -0,0 +1,55 @@
+// Settings for the paths below a --route prefix, the longest one wins
+mod common;
+
+use common::{base64, Server, TempDir};
+
+#[test]
+fn two_routes_with_their_own_cache_and_auth() {
+    let root = TempDir::new();
+    root.write("assets/site.css", "body {}");
+    root.write("admin/users.txt", "alice");
+    root.write("page.html", "<p>");
+    let server = Server::start(&[
+        "--auth", "admin:secret",
+        "--route", "/assets/*", "cache=86400,auth=off",
+        "--route", "/admin", "cache=no",
+        root.str(),
+    ]);
+    let css = server.get("/assets/site.css");
+    assert_eq!(css.status, 200);
+    assert_eq!(css.header("Cache-Control"), Some("max-age=86400"));
+
+    let refused = server.get("/admin/users.txt");
+    assert_eq!(refused.status, 401);
+    assert!(refused.header("WWW-Authenticate").is_some());
+    let login = format!("Basic {}", base64(b"admin:secret"));
+    let admin = server.request("GET", "/admin/users.txt", &[("Authorization", &login)], b"");
+    assert_eq!(admin.status, 200);
+    assert_eq!(admin.header("Cache-Control"), Some("no-store"));
+    // Outside both it's the server wide auth and no Cache-Control
+    assert_eq!(server.get("/page.html").status, 401);
+    let page = server.request("GET", "/page.html", &[("Authorization", &login)], b"");
+    assert_eq!((page.status, page.header("Cache-Control")), (200, None));
+}
+
+#[test]
+fn nested_route_overrides_its_parent() {
+    let root = TempDir::new();
+    root.write("pub/a.txt", "a");
+    root.write("pub/locked/b.txt", "b");
+    let server = Server::start(&["--route", "/pub", "cache=60", "--route", "/pub/locked", "listing=off", root.str()]);
+    assert_eq!(server.get("/pub/").status, 200);
+    assert_eq!(server.get("/pub/locked/").status, 403);
+    let file = server.get("/pub/locked/b.txt");
+    // Only what the nearer route says, nothing is inherited from /pub
+    assert_eq!((file.status, file.header("Cache-Control")), (200, None));
+    // Errors aren't cached
+    assert_eq!(server.get("/pub/missing").header("Cache-Control"), None);
+}
+
+#[test]
+fn invalid_route_is_refused_at_startup() {
+    let output = common::run(&["--route", "/a", "cache=forever", "."]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid max-age 'forever' in route '/a'"));
+}
@@ -0,0 +1,116 @@
+// Headers and redirects that keep clients on https and out of trouble
+mod common;
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use common::{run, send_and_close, Response, Server, TempDir};
+
+#[test]
+fn hsts_only_goes_out_over_tls() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--trust-forwarded-proto", "--hsts", "600", "--hsts-subdomains", root.str()]);
+    let tls = server.request("GET", "/a.txt", &[("X-Forwarded-Proto", "https")], b"");
+    assert_eq!(tls.status, 200);
+    assert_eq!(tls.header("Strict-Transport-Security"), Some("max-age=600; includeSubDomains"));
+    let plain = server.request("GET", "/a.txt", &[("X-Forwarded-Proto", "http")], b"");
+    assert_eq!(plain.header("Strict-Transport-Security"), None);
+    let direct = server.get("/a.txt");
+    assert_eq!(direct.header("Strict-Transport-Security"), None);
+}
+
+#[test]
+fn forwarded_proto_is_ignored_without_trust() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--canonical-scheme", "https", root.str()]);
+    // Claiming https doesn't get it served, it is still http to us
+    let response = server.request("GET", "/a.txt", &[("X-Forwarded-Proto", "https")], b"");
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("https://localhost/a.txt"));
+}
+
+#[test]
+fn forwarded_https_is_canonical() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--trust-forwarded-proto", "--canonical-scheme", "https", root.str()]);
+    assert_eq!(server.request("GET", "/a.txt", &[("X-Forwarded-Proto", "https")], b"").status, 200);
+    assert_eq!(server.get("/a.txt").status, 301);
+}
+
+#[test]
+fn hsts_without_a_way_to_tls_is_refused() {
+    let root = TempDir::new();
+    let output = run(&["--hsts", "600", root.str()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--trust-forwarded-proto"));
+}
+
+// The port of the --redirect-https listener, from what it logged
+fn redirect_addr(server: &Server) -> SocketAddr {
+    assert!(server.wait_for_output("Redirecting to https from "), "{}", server.output());
+    let output = server.output();
+    let addr = output.split("Redirecting to https from ").nth(1).unwrap().lines().next().unwrap();
+    addr.parse().unwrap()
+}
+
+#[test]
+fn redirect_listener_sends_everything_to_https() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--redirect-https", "127.0.0.1:0", "--https-port", "8443", root.str()]);
+    let addr = redirect_addr(&server);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let raw = send_and_close(&mut stream, b"GET /dir/a.txt?x=1&y=%20 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n");
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("https://example.com:8443/dir/a.txt?x=1&y=%20"));
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(response.body, "<html>301</html>");
+    // Nothing is served there even for a file that exists, and without a Host there is nowhere to go
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let raw = send_and_close(&mut stream, b"GET /a.txt HTTP/1.0\r\n\r\n");
+    assert_eq!(Response::parse(&raw).status, 400);
+    // The main listener is unchanged
+    assert_eq!(server.get("/a.txt").status, 200);
+}
+
+#[test]
+fn redirect_to_the_default_port_leaves_it_out() {
+    let root = TempDir::new();
+    let server = Server::start(&["--redirect-https", "127.0.0.1:0", root.str()]);
+    let mut stream = TcpStream::connect(redirect_addr(&server)).unwrap();
+    let raw = send_and_close(&mut stream, b"GET / HTTP/1.1\r\nHost: [::1]:80\r\n\r\n");
+    assert_eq!(Response::parse(&raw).header("Location"), Some("https://[::1]/"));
+}
+
+#[test]
+fn taken_redirect_port_stops_the_start() {
+    let root = TempDir::new();
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = taken.local_addr().unwrap().to_string();
+    let output = run(&["--redirect-https", &taken, "--port", "0", root.str()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("failed to listen for https redirects on {taken} by")), "{stdout}");
+    assert!(!stdout.contains("Open http://"), "{stdout}");
+}
+
+#[test]
+fn security_headers_are_on_every_response() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--csp", "default-src 'self'", "--referrer-policy", "same-origin", root.str()]);
+    for response in [server.get("/a.txt"), server.get("/"), server.get("/missing")] {
+        assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(response.header("X-Frame-Options"), Some("DENY"));
+        assert_eq!(response.header("Referrer-Policy"), Some("same-origin"));
+        assert_eq!(response.header("Content-Security-Policy"), Some("default-src 'self'"));
+    }
+    let server = Server::start(&["--no-nosniff", "--frame-options", "off", root.str()]);
+    let response = server.get("/a.txt");
+    assert_eq!(response.header("X-Content-Type-Options"), None);
+    assert_eq!(response.header("X-Frame-Options"), None);
+    assert_eq!(response.header("Referrer-Policy"), Some("no-referrer"));
+    assert_eq!(response.header("Content-Security-Policy"), None);
+}
@@ -0,0 +1,52 @@
+// SIGTERM lets a running download finish while nobody new gets in
+#![cfg(unix)]
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use common::{read_all, Response, Server, TempDir};
+
+#[test]
+fn sigterm_drains_the_running_download() {
+    let root = TempDir::new();
+    let contents = "0123456789".repeat(2000);
+    root.write("big.txt", &contents);
+    // 20000 bytes at 80kbps take about two seconds
+    let mut server = Server::start(&[root.str(), "--throttle", "80kbps", "--drain-timeout", "30"]);
+    let mut download = server.connect();
+    download.write_all(b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut first = [0; 1];
+    download.read_exact(&mut first).unwrap();
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGTERM) }, 0);
+    assert!(server.wait_for_output("shutting down"));
+    // The listener is gone, or at least nothing is answered on it any more
+    if let Ok(mut late) = TcpStream::connect(server.addr) {
+        late.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let _ = late.write_all(b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(read_all(&mut late), "");
+    }
+    let raw = format!("{}{}", first[0] as char, read_all(&mut download));
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, contents);
+    let status = server.wait_exit(Duration::from_secs(10)).expect("it exits once the download is done");
+    assert!(status.success());
+    assert!(server.output().contains("1 connections drained, 0 aborted"), "{}", server.output());
+}
+
+#[test]
+fn stuck_connection_is_aborted_after_the_drain_timeout() {
+    let root = TempDir::new();
+    root.write("big.txt", "0123456789".repeat(20000));
+    let mut server = Server::start(&[root.str(), "--throttle", "80kbps", "--drain-timeout", "1"]);
+    let mut download = server.connect();
+    download.write_all(b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    download.read_exact(&mut [0; 1]).unwrap();
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGTERM) }, 0);
+    let status = server.wait_exit(Duration::from_secs(10)).expect("it exits after the drain timeout");
+    assert!(status.success());
+    assert!(server.output().contains("0 connections drained, 1 aborted"), "{}", server.output());
+}
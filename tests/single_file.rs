@@ -0,0 +1,58 @@
+// A file instead of a directory, served at / and under its name
+mod common;
+
+use std::time::Duration;
+use common::{Server, TempDir};
+
+fn archive(dir: &TempDir) -> String {
+    let contents: String = (0..2000).map(|n| format!("line {n}\r\n")).collect();
+    dir.write("build.tar.gz", &contents);
+    contents
+}
+
+#[test]
+fn file_is_served_at_the_root_and_its_name() {
+    let dir = TempDir::new();
+    let contents = archive(&dir);
+    dir.write("other.txt", "not served");
+    let server = Server::start(&[dir.path().join("build.tar.gz").to_str().unwrap()]);
+    for path in ["/", "/build.tar.gz"] {
+        let response = server.get(path);
+        assert_eq!(response.status, 200, "{path}");
+        assert_eq!(response.body, contents, "{path}");
+        assert_eq!(response.header("Content-Type"), Some("application/gzip"));
+    }
+    // At / it would be saved without a name otherwise
+    assert!(server.get("/").header("Content-Disposition").unwrap().contains("build.tar.gz"));
+    let range = server.request("GET", "/", &[("Range", "bytes=0-5")], b"");
+    assert_eq!((range.status, range.body.as_str()), (206, "line 0"));
+    for path in ["/other.txt", "/build.tar.gz/x"] {
+        assert_eq!(server.get(path).status, 404, "{path}");
+    }
+    // Out of the root is a bad path before it is anything else
+    assert_eq!(server.get("/../other.txt").status, 400);
+}
+
+#[test]
+fn handlers_are_there_next_to_the_file() {
+    let dir = TempDir::new();
+    archive(&dir);
+    let server = Server::start(&[dir.path().join("build.tar.gz").to_str().unwrap(), "--metrics"]);
+    let metrics = server.get("/metrics");
+    assert_eq!(metrics.status, 200);
+    assert!(metrics.body.contains("httpserver_connections_total"));
+}
+
+#[test]
+fn count_exits_after_the_downloads() {
+    let dir = TempDir::new();
+    let contents = archive(&dir);
+    let mut server = Server::start(&[dir.path().join("build.tar.gz").to_str().unwrap(), "--count", "2"]);
+    // A part that doesn't reach the end isn't a download
+    server.request("GET", "/", &[("Range", "bytes=0-5")], b"");
+    assert_eq!(server.get("/").body, contents);
+    assert!(server.wait_exit(Duration::from_millis(300)).is_none());
+    assert_eq!(server.get("/build.tar.gz").body, contents);
+    let status = server.wait_exit(Duration::from_secs(10)).expect("it exits after the second download");
+    assert!(status.success());
+}
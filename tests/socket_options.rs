@@ -0,0 +1,52 @@
+// NODELAY and the other socket options, as tiny keep-alive requests see them
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::{Duration, Instant};
+use common::{Server, TempDir};
+
+// Each request only once the last response is all in, the way a latency bound
+// client does. With Nagle and delayed ACKs every round could wait 40ms
+fn sequential_requests(server: &Server, count: usize) -> Duration {
+    let stream = server.connect();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let started = Instant::now();
+    for _ in 0..count {
+        writer.write_all(b"GET /tiny.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        assert_eq!(body, b"x");
+    }
+    started.elapsed()
+}
+
+#[test]
+fn tiny_sequential_requests_are_quick_with_nodelay() {
+    let root = TempDir::new();
+    root.write("tiny.txt", "x");
+    let server = Server::start(&[root.str()]);
+    let took = sequential_requests(&server, 300);
+    // 40ms each would be 12s
+    assert!(took < Duration::from_secs(3), "300 requests took {took:?}");
+}
+
+#[test]
+fn options_are_logged_at_startup() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--keepalive", "60", "--backlog", "16"]);
+    assert!(server.wait_for_output("Socket options: nodelay on, backlog 16, keepalive after 60s"), "{}", server.output());
+    let server = Server::start(&[root.str(), "--no-nodelay"]);
+    assert!(server.wait_for_output("Socket options: nodelay off"));
+}
@@ -0,0 +1,100 @@
+// --spa answers the app's routes, paths without a file, with its index
+mod common;
+
+use common::{Response, Server, TempDir};
+
+const HTML: (&str, &str) = ("Accept", "text/html,application/xhtml+xml,*/*;q=0.8");
+
+fn app(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("index.html", "<app>");
+    root.write("main.js", "js");
+    root.write("docs/readme.txt", "docs");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+fn navigate(server: &Server, path: &str) -> Response {
+    server.request("GET", path, &[HTML], b"")
+}
+
+#[test]
+fn app_route_serves_the_index() {
+    let (_root, server) = app(&["--spa"]);
+    let response = navigate(&server, "/some/app/route");
+    assert_eq!((response.status, response.body.as_str()), (200, "<app>"));
+    assert!(response.header("Content-Type").unwrap().starts_with("text/html"));
+    // Real files are still themselves
+    assert_eq!(navigate(&server, "/main.js").body, "js");
+    assert_eq!(navigate(&server, "/docs/readme.txt").body, "docs");
+}
+
+#[test]
+fn missing_asset_is_still_a_404() {
+    let (_root, server) = app(&["--spa"]);
+    assert_eq!(navigate(&server, "/missing.js").status, 404);
+    assert_eq!(navigate(&server, "/some/app/logo.png").status, 404);
+}
+
+#[test]
+fn without_spa_an_app_route_is_missing() {
+    let (_root, server) = app(&[]);
+    assert_eq!(navigate(&server, "/some/app/route").status, 404);
+}
+
+#[test]
+fn spa_index_names_another_file() {
+    let (root, server) = app(&["--spa-index", "shell.html"]);
+    root.write("shell.html", "<shell>");
+    assert_eq!(navigate(&server, "/route").body, "<shell>");
+    let output = common::run(&["--spa-index", "../index.html"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid SPA index '../index.html', expected a file below the root"));
+}
+
+#[test]
+fn fallback_is_for_html_navigations_only() {
+    let (_root, server) = app(&["--spa"]);
+    let response = navigate(&server, "/app/settings");
+    assert_eq!((response.status, response.body.as_str()), (200, "<app>"));
+    assert_eq!(navigate(&server, "/app/missing.js").status, 404);
+    // A script fetching JSON, or a client saying nothing, wants the real answer
+    assert_eq!(server.request("GET", "/app/settings", &[("Accept", "application/json")], b"").status, 404);
+    assert_eq!(server.get("/app/settings").status, 404);
+    // Nor anything but GET and HEAD
+    assert_eq!(server.request("POST", "/app/settings", &[HTML], b"x").status, 404);
+    let head = server.request("HEAD", "/app/settings", &[HTML], b"");
+    assert_eq!((head.status, head.header("Content-Length")), (200, Some("5")));
+}
+
+#[test]
+fn fallback_is_not_cached() {
+    let (_root, server) = app(&["--spa"]);
+    assert_eq!(navigate(&server, "/app/settings").header("Cache-Control"), Some("no-cache"));
+    assert_eq!(navigate(&server, "/main.js").header("Cache-Control"), None);
+    // A route's policy goes first
+    let (_root, server) = app(&["--spa", "--route", "/app", "cache=60"]);
+    assert_eq!(navigate(&server, "/app/settings").header("Cache-Control"), Some("max-age=60"));
+}
+
+#[test]
+fn scoped_fallback_serves_the_index_of_its_scope() {
+    let (root, server) = app(&["--spa-scope", "/shop"]);
+    root.write("shop/index.html", "<shop>");
+    assert_eq!(navigate(&server, "/shop/cart").body, "<shop>");
+    assert_eq!(navigate(&server, "/shop").body, "<shop>");
+    // Outside of it paths are missing as always
+    assert_eq!(navigate(&server, "/cart").status, 404);
+    assert_eq!(navigate(&server, "/shopping/cart").status, 404);
+    assert!(String::from_utf8_lossy(&common::run(&["--spa-scope", "shop"]).stderr).contains("invalid SPA scope 'shop', expected a path like /app"));
+}
+
+#[test]
+fn directories_are_app_routes_too() {
+    let (_root, server) = app(&["--spa"]);
+    // An app has no listings
+    assert_eq!(navigate(&server, "/docs/").body, "<app>");
+    // Without an index there is nothing to fall back to
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--spa"]);
+    assert_eq!(navigate(&server, "/app/settings").status, 404);
+}
@@ -0,0 +1,87 @@
+// What the server checks and tells before it serves the first request
+mod common;
+
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use common::{run, send_and_close, Response, Server, TempDir};
+
+#[test]
+fn thread_count_is_applied() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    for threads in ["1", "3"] {
+        let server = Server::start(&[root.str(), "--threads", threads]);
+        assert!(server.wait_for_output(&format!("Using {threads} worker threads")), "{}", server.output());
+        assert_eq!(server.get("/a.txt").body, "a");
+    }
+}
+
+#[test]
+fn thread_count_has_to_be_positive() {
+    for threads in ["0", "-1", "many"] {
+        let output = run(&["--threads", threads]);
+        assert!(!output.status.success());
+        let printed = String::from_utf8_lossy(&output.stderr);
+        assert!(printed.contains(&format!("invalid thread count '{threads}'")), "{printed}");
+    }
+}
+
+#[test]
+fn every_printed_url_works() {
+    let root = TempDir::new();
+    root.write("a.txt", "shared");
+    let server = Server::start_listening(&[root.str(), "--listen", "0.0.0.0:0"]);
+    assert!(server.wait_for_output("Socket options"));
+    let output = server.output();
+    let urls: Vec<&str> = output.lines()
+        .filter_map(|line| line.strip_prefix("Open ").or_else(|| line.trim_start().strip_prefix("or ")))
+        .collect();
+    // From the real port, and a machine with loopback only still gets the local one
+    assert!(urls.contains(&format!("http://127.0.0.1:{}/", server.addr.port()).as_str()), "{output}");
+    for url in urls {
+        let addr = url.strip_prefix("http://").unwrap().trim_end_matches('/');
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let raw = send_and_close(&mut stream, b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(Response::parse(&raw).body, "shared", "{url}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn open_starts_the_browser_once_at_the_real_port() {
+    use std::os::unix::fs::PermissionsExt;
+    let bin = TempDir::new();
+    let opened = bin.path().join("opened");
+    // xdg-open that only notes what it was asked to open
+    let script = bin.write("xdg-open", format!("#!/bin/sh\necho \"$1\" >> {}\n", opened.display()));
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.str(), std::env::var("PATH").unwrap_or_default());
+    let root = TempDir::new();
+    let server = Server::start_with_env(&[("PATH", &path)], &[root.str(), "--open"]);
+    assert!(server.wait_for_output("in the browser"));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !opened.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    let opened = std::fs::read_to_string(&opened).unwrap();
+    assert_eq!(opened, format!("http://127.0.0.1:{}/\n", server.addr.port()));
+}
+
+#[test]
+fn qr_code_is_of_the_real_port() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--qr"]);
+    let url = format!("http://127.0.0.1:{}/", server.addr.port());
+    assert!(server.wait_for_output("the QR code is for this machine only"), "{}", server.output());
+    assert!(server.wait_for_output(&format!("\n{url}\n")), "{}", server.output());
+    // Right under the code, two rows of modules a line with the quiet zone around
+    let output = server.output();
+    let lines: Vec<&str> = output.lines().collect();
+    let at = lines.iter().position(|line| *line == url).unwrap();
+    let code: Vec<&str> = lines[..at].iter().rev().take_while(|line| line.chars().all(|c| " ▀▄█".contains(c))).copied().collect();
+    let width = code[0].chars().count();
+    assert!(width >= 29 && code.iter().all(|line| line.chars().count() == width), "{output}");
+    assert_eq!(code.len(), width.div_ceil(2), "{output}");
+}
@@ -0,0 +1,52 @@
+// Links are served only while they stay inside the root, unless --follow-symlinks
+#![cfg(unix)]
+mod common;
+
+use std::os::unix::fs::symlink;
+use common::{Server, TempDir};
+
+// A root with links in it, and the secret they point to next to it
+fn linked() -> (TempDir, TempDir) {
+    let (root, outside) = (TempDir::new(), TempDir::new());
+    root.write("real.txt", "real");
+    root.write("dir/inner.txt", "inner");
+    outside.write("secret.txt", "secret");
+    symlink(outside.path().join("secret.txt"), root.path().join("leak.txt")).unwrap();
+    symlink(outside.path(), root.path().join("leakdir")).unwrap();
+    symlink("real.txt", root.path().join("alias.txt")).unwrap();
+    symlink("dir", root.path().join("dirlink")).unwrap();
+    (root, outside)
+}
+
+#[test]
+fn link_out_of_the_root_is_404_by_default() {
+    let (root, _outside) = linked();
+    let server = Server::start(&[root.str()]);
+    for path in ["/leak.txt", "/leakdir/secret.txt", "/leakdir/"] {
+        let response = server.get(path);
+        assert_eq!(response.status, 404, "{path}");
+        assert!(!response.body.contains("secret"), "{path}");
+    }
+    // Ones that stay inside are fine
+    assert_eq!(server.get("/alias.txt").body, "real");
+    assert_eq!(server.get("/dirlink/inner.txt").body, "inner");
+}
+
+#[test]
+fn follow_symlinks_serves_through_them() {
+    let (root, _outside) = linked();
+    let server = Server::start(&["--follow-symlinks", root.str()]);
+    assert_eq!(server.get("/leak.txt").body, "secret");
+    assert_eq!(server.get("/leakdir/secret.txt").body, "secret");
+    assert!(server.get("/leakdir/").body.contains("secret.txt"));
+}
+
+#[test]
+fn uploads_dont_write_through_a_link_out() {
+    let (root, outside) = linked();
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(server.request("PUT", "/leakdir/new.txt", &[], b"x").status, 404);
+    assert!(!outside.path().join("new.txt").exists());
+    assert_eq!(server.request("DELETE", "/leak.txt", &[], b"").status, 404);
+    assert!(outside.path().join("secret.txt").exists());
+}
@@ -0,0 +1,46 @@
+// --request-timeout ends a request that takes too long as a whole
+mod common;
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+use common::{read_all, Server, TempDir};
+
+#[test]
+fn slow_response_trips_the_timeout() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    // The delay stands in for a handler that is stuck
+    let server = Server::start(&["--request-timeout", "1", "--delay", "5s", root.str()]);
+    let started = Instant::now();
+    let raw = server.send(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let took = started.elapsed();
+    assert_eq!(raw, "", "nothing is answered after the timeout");
+    assert!(took >= Duration::from_millis(900) && took < Duration::from_secs(4), "{took:?}");
+    assert!(server.wait_for_output("not done after 1s, closing the connection"), "{}", server.output());
+}
+
+#[test]
+fn quick_requests_and_idle_time_dont_count() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&["--request-timeout", "1", root.str()]);
+    let mut stream = server.connect();
+    stream.write_all(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    // Waiting between requests is not handling one
+    std::thread::sleep(Duration::from_millis(1500));
+    stream.write_all(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let raw = read_all(&mut stream);
+    assert_eq!(raw.matches("HTTP/1.1 200 OK").count(), 2, "{raw}");
+}
+
+#[test]
+fn slow_upload_trips_the_timeout() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", "--request-timeout", "1", root.str()]);
+    let mut stream = server.connect();
+    stream.write_all(b"PUT /slow.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\nstart").unwrap();
+    // The rest never comes
+    assert_eq!(read_all(&mut stream), "");
+    assert!(server.wait_for_output("not done after 1s"));
+    assert!(!root.path().join("slow.txt").exists());
+}
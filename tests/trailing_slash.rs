@@ -0,0 +1,99 @@
+// --trailing-slash add|strip|ignore, for files and directories with and without the slash
+mod common;
+
+use common::{run, Server, TempDir};
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    root.write("dir/b.txt", "b");
+    root.write("a b/c.txt", "c");
+    let server = Server::start(&[&[root.str()], args].concat());
+    (root, server)
+}
+
+#[test]
+fn each_mode_redirects_only_its_own_case() {
+    let matrix = [
+        ("add", [(200, None), (200, None), (301, Some("/dir/")), (200, None)]),
+        ("strip", [(200, None), (301, Some("/a.txt")), (200, None), (200, None)]),
+        ("ignore", [(200, None), (200, None), (200, None), (200, None)]),
+    ];
+    for (mode, expected) in matrix {
+        let (_root, server) = site(&["--trailing-slash", mode]);
+        for (path, (status, location)) in ["/a.txt", "/a.txt/", "/dir", "/dir/"].into_iter().zip(expected) {
+            let response = server.get(path);
+            assert_eq!((response.status, response.header("Location")), (status, location), "{mode} {path}");
+        }
+    }
+}
+
+#[test]
+fn ignore_is_the_default() {
+    let (_root, server) = site(&[]);
+    assert_eq!(server.get("/dir").status, 200);
+    assert_eq!(server.get("/a.txt/").body, "a");
+}
+
+#[test]
+fn query_is_kept_and_the_path_encoded() {
+    let (_root, server) = site(&["--trailing-slash", "add"]);
+    assert_eq!(server.get("/dir?sort=name&order=desc").header("Location"), Some("/dir/?sort=name&order=desc"));
+    assert_eq!(server.get("/a%20b").header("Location"), Some("/a%20b/"));
+    let (_root, server) = site(&["--trailing-slash", "strip"]);
+    assert_eq!(server.get("/a.txt/?v=2").header("Location"), Some("/a.txt?v=2"));
+}
+
+#[test]
+fn root_is_never_redirected() {
+    for mode in ["add", "strip"] {
+        let (_root, server) = site(&["--trailing-slash", mode]);
+        let response = server.get("/");
+        assert_eq!((response.status, response.header("Location")), (200, None), "{mode}");
+    }
+}
+
+#[test]
+fn only_get_and_head_are_redirected() {
+    let (_root, server) = site(&["--trailing-slash", "add"]);
+    assert_eq!(server.request("HEAD", "/dir", &[], b"").status, 301);
+    let response = server.request("POST", "/dir", &[], b"x=1");
+    assert_ne!(response.status, 301);
+    assert_eq!(response.header("Location"), None);
+}
+
+#[test]
+fn missing_paths_are_not_redirected() {
+    for mode in ["add", "strip"] {
+        let (_root, server) = site(&["--trailing-slash", mode]);
+        assert_eq!(server.get("/missing").status, 404, "{mode}");
+        assert_eq!(server.get("/missing/").status, 404, "{mode}");
+    }
+}
+
+#[test]
+fn rewritten_paths_are_not_redirected_again() {
+    // The client asked for /old, a 301 to /dir/ would send it somewhere it never saw
+    let (_root, server) = site(&["--trailing-slash", "add", "--rewrite", "/old", "/dir"]);
+    let response = server.get("/old");
+    assert_eq!((response.status, response.header("Location")), (200, None));
+    let (_root, server) = site(&["--trailing-slash", "strip", "--rewrite", "/new/*", "/a.txt/"]);
+    let response = server.get("/new/x");
+    assert_eq!((response.status, response.body.as_str()), (200, "a"));
+}
+
+#[test]
+fn mode_comes_from_the_config_file_too() {
+    let (dir, root) = (TempDir::new(), TempDir::new());
+    root.write("a.txt", "a");
+    let config = dir.write("site.toml", format!("[root]\npaths = [{:?}]\ntrailing_slash = \"strip\"\n", root.str()));
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    assert_eq!(server.get("/a.txt/").header("Location"), Some("/a.txt"));
+}
+
+#[test]
+fn bad_mode_is_refused() {
+    let output = run(&["--trailing-slash", "always"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid trailing slash mode 'always', expected add, strip or ignore"));
+}
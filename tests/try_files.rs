@@ -0,0 +1,95 @@
+// --try-files serves the first candidate that exists, nginx style
+mod common;
+
+use common::{Server, TempDir};
+
+const CHAIN: &str = "$path,$path/index.html,$path.html,/404.html=404";
+
+fn site(args: &[&str]) -> (TempDir, Server) {
+    let root = TempDir::new();
+    root.write("exact.txt", "exact");
+    root.write("docs/index.html", "docs index");
+    root.write("about.html", "about");
+    root.write("404.html", "not here");
+    let server = Server::start(&[&[root.str(), "--try-files", CHAIN], args].concat());
+    (root, server)
+}
+
+#[test]
+fn first_candidate_is_the_path_itself() {
+    let (_root, server) = site(&[]);
+    let response = server.get("/exact.txt");
+    assert_eq!((response.status, response.body.as_str()), (200, "exact"));
+    assert!(response.header("Content-Type").unwrap().starts_with("text/plain"));
+}
+
+#[test]
+fn second_is_the_directory_index() {
+    let (_root, server) = site(&[]);
+    for path in ["/docs", "/docs/"] {
+        let response = server.get(path);
+        assert_eq!((response.status, response.body.as_str()), (200, "docs index"), "{path}");
+        assert!(response.header("Content-Type").unwrap().starts_with("text/html"));
+    }
+}
+
+#[test]
+fn third_is_the_pretty_url() {
+    let (_root, server) = site(&[]);
+    let response = server.get("/about");
+    assert_eq!((response.status, response.body.as_str()), (200, "about"));
+    // HEAD the same, without the body
+    let head = server.request("HEAD", "/about", &[], b"");
+    assert_eq!((head.status, head.header("Content-Length"), head.body.as_str()), (200, Some("5"), ""));
+}
+
+#[test]
+fn last_file_goes_out_with_its_status() {
+    let (_root, server) = site(&[]);
+    let response = server.get("/nothing/here");
+    assert_eq!((response.status, response.body.as_str()), (404, "not here"));
+}
+
+#[test]
+fn terminal_status_is_answered_as_it_is() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str(), "--try-files", "$path,$path.html,=403"]);
+    assert_eq!(server.get("/nothing").status, 403);
+    // Without one a miss is the usual 404
+    let server = Server::start(&[root.str(), "--try-files", "$path.html"]);
+    assert_eq!(server.get("/nothing").status, 404);
+}
+
+#[test]
+fn only_get_and_head_try() {
+    let (_root, server) = site(&[]);
+    assert_eq!(server.request("DELETE", "/about", &[], b"").status, 404);
+}
+
+#[test]
+fn candidates_have_to_be_servable_on_their_own() {
+    // An excluded one, or one behind a login the request hasn't got, is skipped
+    let (_root, server) = site(&["--exclude", "about.html"]);
+    assert_eq!(server.get("/about").body, "not here");
+    let (_root, server) = site(&["--auth", "ann:pw", "--route", "/", "auth=off", "--route", "/about.html", "auth=on"]);
+    assert_eq!(server.get("/about").body, "not here");
+    let login = format!("Basic {}", common::base64(b"ann:pw"));
+    assert_eq!(server.request("GET", "/about", &[("Authorization", &login)], b"").body, "about");
+}
+
+#[cfg(unix)]
+#[test]
+fn candidates_stay_inside_the_root() {
+    let (root, server) = site(&[]);
+    let outside = TempDir::new();
+    outside.write("secret.html", "secret");
+    std::os::unix::fs::symlink(outside.path().join("secret.html"), root.path().join("leak.html")).unwrap();
+    let response = server.get("/leak");
+    assert_eq!((response.status, response.body.as_str()), (404, "not here"));
+}
+
+#[test]
+fn bad_chains_are_refused() {
+    let output = common::run(&["--try-files", "=404,$path"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("a =CODE in --try-files has to be the last candidate"));
+}
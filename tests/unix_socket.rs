@@ -0,0 +1,79 @@
+// A unix socket has no peer address, its clients are served all the same
+#![cfg(unix)]
+mod common;
+
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+use common::{run, Response, Server, TempDir};
+
+fn get_over(socket: &Path, path: &str) -> Response {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    Response::parse(&raw)
+}
+
+#[test]
+fn client_without_a_peer_address_is_served() {
+    let root = TempDir::new();
+    root.write("a.txt", "over the socket");
+    let socket = root.path().join("http.sock");
+    let listen = format!("unix:{}", socket.display());
+    let server = Server::start_listening(&[root.str(), "--listen", "127.0.0.1:0", "--listen", &listen, "--access-log", "-"]);
+    let response = get_over(&socket, "/a.txt");
+    assert_eq!((response.status, response.body.as_str()), (200, "over the socket"));
+    // Logged by the socket it came in on instead
+    assert!(server.wait_for_output("\"GET /a.txt HTTP/1.1\" 200 15"));
+    let line = server.output().lines().find(|line| line.contains("GET /a.txt")).unwrap().to_string();
+    assert!(line.starts_with(&format!("unix:{}", socket.display())), "{line}");
+}
+
+#[test]
+fn socket_gets_its_mode() {
+    let root = TempDir::new();
+    let socket = root.path().join("http.sock");
+    let listen = format!("unix:{}", socket.display());
+    let _server = Server::start_listening(&[root.str(), "--listen", "127.0.0.1:0", "--listen", &listen, "--socket-mode", "600"]);
+    let meta = std::fs::symlink_metadata(&socket).unwrap();
+    assert!(meta.file_type().is_socket());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn stale_socket_is_replaced_and_removed_at_shutdown() {
+    let root = TempDir::new();
+    root.write("a.txt", "fresh");
+    let socket = root.path().join("http.sock");
+    // What a server that was killed leaves, a socket file nobody listens on
+    drop(UnixListener::bind(&socket).unwrap());
+    assert!(socket.exists());
+    let listen = format!("unix:{}", socket.display());
+    let mut server = Server::start_listening(&[root.str(), "--listen", "127.0.0.1:0", "--listen", &listen]);
+    assert!(server.output().contains("removing the stale socket"), "{}", server.output());
+    assert_eq!(get_over(&socket, "/a.txt").body, "fresh");
+    // SAFETY: a plain syscall to our child
+    assert_eq!(unsafe { libc::kill(server.pid() as i32, libc::SIGTERM) }, 0);
+    assert!(server.wait_exit(Duration::from_secs(10)).is_some());
+    assert!(!socket.exists(), "the socket file is left behind");
+}
+
+#[test]
+fn socket_in_use_or_other_files_are_left_alone() {
+    let root = TempDir::new();
+    let socket = root.path().join("http.sock");
+    let listening = UnixListener::bind(&socket).unwrap();
+    let output = run(&[root.str(), "--listen", &format!("unix:{}", socket.display())]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("another server is listening on it"));
+    drop(listening);
+    let file = root.write("plain", "not a socket");
+    let output = run(&[root.str(), "--listen", &format!("unix:{}", file.display())]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("something else than a socket is there"));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "not a socket");
+}
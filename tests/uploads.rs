@@ -0,0 +1,198 @@
+// PUT uploads with --write, whole and in resumable pieces
+mod common;
+
+use common::{Server, TempDir};
+
+fn put_piece(server: &Server, path: &str, body: &[u8], start: usize, total: usize) -> common::Response {
+    let range = format!("bytes {start}-{}/{total}", start + body.len() - 1);
+    server.request("PUT", path, &[("Content-Range", &range)], body)
+}
+
+#[test]
+fn pieces_out_of_order_make_the_file() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let content: Vec<u8> = (0..3000u32).map(|n| (n % 251) as u8).collect();
+    let first = put_piece(&server, "/big.bin", &content[2000..], 2000, 3000);
+    assert_eq!(first.status, 308);
+    assert_eq!(first.header("Range"), Some("bytes=2000-2999"));
+    let second = put_piece(&server, "/big.bin", &content[..1000], 0, 3000);
+    assert_eq!(second.status, 308);
+    assert_eq!(second.header("Range"), Some("bytes=0-999,2000-2999"));
+    let last = put_piece(&server, "/big.bin", &content[1000..2000], 1000, 3000);
+    assert_eq!(last.status, 201);
+    assert_eq!(std::fs::read(root.path().join("big.bin")).unwrap(), content);
+    assert!(!root.path().join(".big.bin.partial").exists());
+}
+
+#[test]
+fn partial_file_left_by_a_restart_is_started_over() {
+    let root = TempDir::new();
+    // A longer upload that was going on before the restart
+    root.write(".small.txt.partial", "x".repeat(100));
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(put_piece(&server, "/small.txt", b"world", 5, 10).status, 308);
+    assert_eq!(put_piece(&server, "/small.txt", b"hello", 0, 10).status, 201);
+    assert_eq!(std::fs::read(root.path().join("small.txt")).unwrap(), b"helloworld");
+}
+
+#[test]
+fn piece_of_another_total_conflicts() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(put_piece(&server, "/a.txt", b"hello", 0, 10).status, 308);
+    assert_eq!(put_piece(&server, "/a.txt", b"world", 5, 20).status, 409);
+}
+
+#[test]
+fn pieces_arriving_together_wait_for_the_file() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let content: Vec<u8> = (0..10000u32).map(|n| (n % 251) as u8).collect();
+    let statuses: Vec<u16> = std::thread::scope(|scope| {
+        let pieces: Vec<_> = content.chunks(1000).enumerate()
+            .map(|(n, piece)| scope.spawn({
+                let server = &server;
+                move || put_piece(server, "/together.bin", piece, n * 1000, 10000).status
+            }))
+            .collect();
+        pieces.into_iter().map(|piece| piece.join().unwrap()).collect()
+    });
+    assert_eq!(statuses.iter().filter(|status| **status == 201).count(), 1, "{statuses:?}");
+    assert!(statuses.iter().all(|status| [201, 308].contains(status)), "{statuses:?}");
+    assert_eq!(std::fs::read(root.path().join("together.bin")).unwrap(), content);
+}
+
+#[test]
+fn total_past_the_limit_is_a_413() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", "--partial-max-size", "1000", root.str()]);
+    assert_eq!(put_piece(&server, "/big.bin", b"hello", 0, 1001).status, 413);
+    assert!(!root.path().join(".big.bin.partial").exists());
+    assert_eq!(put_piece(&server, "/big.bin", b"hello", 0, 1000).status, 308);
+    // Whole uploads aren't what it is about
+    assert_eq!(server.request("PUT", "/whole.bin", &[], &[b'x'; 2000]).status, 201);
+    let output = common::run(&["--partial-max-size", "0"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid size '0'"));
+}
+
+#[test]
+fn put_with_if_match_replaces_only_the_version_it_names() {
+    let root = TempDir::new();
+    root.write("doc.txt", "first");
+    let server = Server::start(&["--write", root.str()]);
+    let etag = server.get("/doc.txt").header("ETag").unwrap().to_string();
+    assert_eq!(server.request("PUT", "/doc.txt", &[("If-Match", "\"other\"")], b"lost").status, 412);
+    assert_eq!(std::fs::read_to_string(root.path().join("doc.txt")).unwrap(), "first");
+    assert_eq!(server.request("PUT", "/doc.txt", &[("If-Match", &etag)], b"second").status, 204);
+    assert_eq!(std::fs::read_to_string(root.path().join("doc.txt")).unwrap(), "second");
+    // The tag went stale with the write
+    assert_eq!(server.request("PUT", "/doc.txt", &[("If-Match", &etag)], b"third").status, 412);
+}
+
+#[test]
+fn if_none_match_star_only_creates() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(server.request("PUT", "/new.txt", &[("If-None-Match", "*")], b"made").status, 201);
+    assert_eq!(server.request("PUT", "/new.txt", &[("If-None-Match", "*")], b"again").status, 412);
+    assert_eq!(std::fs::read_to_string(root.path().join("new.txt")).unwrap(), "made");
+    assert_eq!(server.request("PUT", "/missing.txt", &[("If-Match", "*")], b"x").status, 412);
+    assert!(!root.path().join("missing.txt").exists());
+}
+
+#[test]
+fn delete_with_a_stale_if_match_keeps_the_file() {
+    let root = TempDir::new();
+    root.write("doc.txt", "kept");
+    let server = Server::start(&["--write", root.str()]);
+    let etag = server.get("/doc.txt").header("ETag").unwrap().to_string();
+    assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", "\"stale\"")], b"").status, 412);
+    assert!(root.path().join("doc.txt").exists());
+    assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", &etag)], b"").status, 204);
+    assert!(!root.path().join("doc.txt").exists());
+}
+
+#[test]
+fn put_creates_and_then_replaces() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let created = server.request("PUT", "/new.txt", &[("Content-Type", "text/plain")], b"first");
+    assert_eq!(created.status, 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("new.txt")).unwrap(), "first");
+    let replaced = server.request("PUT", "/new.txt", &[], b"second, longer");
+    assert_eq!(replaced.status, 204);
+    assert_eq!(server.get("/new.txt").body, "second, longer");
+    // An empty body makes an empty file
+    assert_eq!(server.request("PUT", "/empty.txt", &[("Content-Length", "0")], b"").status, 201);
+    assert_eq!(std::fs::read(root.path().join("empty.txt")).unwrap(), b"");
+    // The directory has to be there already
+    assert_eq!(server.request("PUT", "/missing/new.txt", &[], b"x").status, 404);
+}
+
+#[test]
+fn oversized_put_is_refused_before_its_body() {
+    let root = TempDir::new();
+    root.write("kept.txt", "kept");
+    let server = Server::start(&["--write", root.str()]);
+    let over = (64u64 * 1024 * 1024 + 1).to_string();
+    for path in ["/big.bin", "/kept.txt"] {
+        let head = format!("PUT {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {over}\r\n\r\n");
+        let response = common::Response::parse(&server.send(head.as_bytes()));
+        assert_eq!(response.status, 413, "{path}");
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+    assert!(!root.path().join("big.bin").exists());
+    assert_eq!(std::fs::read_to_string(root.path().join("kept.txt")).unwrap(), "kept");
+}
+
+#[test]
+fn put_without_write_is_refused() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    let server = Server::start(&[root.str()]);
+    assert_eq!(server.request("PUT", "/a.txt", &[], b"changed").status, 405);
+    assert_eq!(server.request("PUT", "/new.txt", &[], b"new").status, 404);
+    assert_eq!(std::fs::read_to_string(root.path().join("a.txt")).unwrap(), "a");
+    assert!(!root.path().join("new.txt").exists());
+}
+
+#[test]
+fn chunked_put_after_100_continue_is_stored() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    // What curl -T sends for a larger file
+    let raw = server.send(b"PUT /chunked.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    let rest = raw.strip_prefix("HTTP/1.1 100 Continue\r\n\r\n").unwrap_or_else(|| panic!("no 100 first: {raw:?}"));
+    let response = common::Response::parse(rest);
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("Location"), Some("/chunked.txt"));
+    assert_eq!(std::fs::read_to_string(root.path().join("chunked.txt")).unwrap(), "hello world");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn full_disk_is_a_507_and_leaves_no_partial_file() {
+    use std::process::Command;
+    // A 64k tmpfs fills up partway through the upload
+    let root = TempDir::new();
+    // SAFETY: a plain syscall
+    if unsafe { libc::geteuid() } != 0 || !Command::new("mount").args(["-t", "tmpfs", "-o", "size=64k", "tmpfs", root.str()]).status().is_ok_and(|status| status.success()) {
+        eprintln!("skipped, a small tmpfs needs root");
+        return;
+    }
+    std::fs::write(root.path().join("kept.txt"), "kept").unwrap();
+    let server = Server::start(&["--write", root.str()]);
+    let body = vec![b'x'; 256 * 1024];
+    let created = server.request("PUT", "/big.bin", &[], &body);
+    let replaced = server.request("PUT", "/kept.txt", &[], &body);
+    let mut names: Vec<String> = std::fs::read_dir(root.path()).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    let kept = std::fs::read_to_string(root.path().join("kept.txt")).unwrap();
+    drop(server);
+    let _ = Command::new("umount").arg(root.str()).status();
+    assert_eq!((created.status, created.header("Connection")), (507, Some("close")));
+    assert_eq!(replaced.status, 507);
+    assert_eq!(names, ["kept.txt"]);
+    assert_eq!(kept, "kept");
+}
@@ -0,0 +1,89 @@
+// --vhost serves other roots on the same listener, picked by the Host header
+mod common;
+
+use common::{Response, Server, TempDir};
+
+fn on(server: &Server, host: &str, path: &str) -> Response {
+    Response::parse(&server.send(format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n").as_bytes()))
+}
+
+// The roots, files.lan and *.photos.lan, each with a file of its own and a shared name
+fn hosted(args: &[&str]) -> (Vec<TempDir>, Server) {
+    let (roots, files, photos) = (TempDir::new(), TempDir::new(), TempDir::new());
+    for (dir, name) in [(&roots, "roots"), (&files, "files"), (&photos, "photos")] {
+        dir.write("who.txt", name);
+        dir.write(&format!("only-{name}.txt"), name);
+    }
+    let server = Server::start(&[
+        &[roots.str(), "--vhost", "files.lan", &format!("root={}", files.str()), "--vhost", "*.photos.lan", &format!("root={},listing=off", photos.str())],
+        args,
+    ].concat());
+    (vec![roots, files, photos], server)
+}
+
+#[test]
+fn each_host_gets_its_own_root() {
+    let (_dirs, server) = hosted(&[]);
+    assert_eq!(on(&server, "files.lan", "/who.txt").body, "files");
+    // Names are case-insensitive, a port or a trailing dot changes nothing
+    assert_eq!(on(&server, "FILES.lan:8080", "/who.txt").body, "files");
+    assert_eq!(on(&server, "files.lan.", "/who.txt").body, "files");
+    assert_eq!(on(&server, "paris.photos.lan", "/who.txt").body, "photos");
+    assert_eq!(on(&server, "a.b.photos.lan", "/who.txt").body, "photos");
+    // The wildcard is for the names below it only
+    assert_eq!(on(&server, "photos.lan", "/who.txt").body, "roots");
+    assert_eq!(on(&server, "localhost", "/who.txt").body, "roots");
+}
+
+#[test]
+fn roots_are_isolated_from_each_other() {
+    let (_dirs, server) = hosted(&[]);
+    assert_eq!(on(&server, "files.lan", "/only-files.txt").status, 200);
+    for (host, other) in [("files.lan", "photos"), ("files.lan", "roots"), ("x.photos.lan", "files"), ("localhost", "files")] {
+        assert_eq!(on(&server, host, &format!("/only-{other}.txt")).status, 404, "{host} {other}");
+    }
+    // Nor by climbing out of one
+    let response = on(&server, "files.lan", "/../only-roots.txt");
+    assert!(response.status == 400 || response.status == 404, "{}", response.status);
+}
+
+#[test]
+fn settings_are_per_host() {
+    let (_dirs, server) = hosted(&[]);
+    assert_eq!(on(&server, "files.lan", "/").status, 200);
+    assert_eq!(on(&server, "x.photos.lan", "/").status, 403);
+}
+
+#[test]
+fn unknown_hosts_get_what_was_asked_for() {
+    for (mode, status) in [("404", 404), ("421", 421)] {
+        let (_dirs, server) = hosted(&["--unknown-host", mode]);
+        assert_eq!(on(&server, "other.lan", "/who.txt").status, status, "{mode}");
+        assert_eq!(on(&server, "files.lan", "/who.txt").body, "files");
+    }
+    // A bad Host is refused either way
+    let (_dirs, server) = hosted(&[]);
+    assert_eq!(on(&server, "bad host", "/who.txt").status, 400);
+}
+
+#[test]
+fn the_vhost_is_logged() {
+    let (_dirs, server) = hosted(&["--log-format", "json", "--access-log", "-"]);
+    on(&server, "files.lan", "/who.txt");
+    assert!(server.wait_for_output("\"vhost\":\"files.lan\""), "{}", server.output());
+}
+
+#[test]
+fn bad_vhosts_are_refused() {
+    for (name, options, expected) in [
+        ("files.lan", "listing=off", "host files.lan needs a root=DIR"),
+        ("files.lan:80", "root=/srv", "invalid host name 'files.lan:80' for --vhost"),
+        ("files.lan", "root=/srv,color=red", "invalid host option 'color=red', expected root, listing, auth or write"),
+        ("files.lan", "root=/srv,auth=yes", "invalid value 'yes' for host files.lan, expected on or off"),
+        ("files.lan", "root=/srv,auth=on", "a vhost requires auth but no --auth credentials are given"),
+    ] {
+        let output = common::run(&["--vhost", name, options]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(expected), "{name} {options}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
@@ -0,0 +1,303 @@
+// The WebDAV methods --write allows: DELETE, MKCOL, PROPFIND, MOVE and COPY
+mod common;
+
+use common::{Server, TempDir};
+
+#[test]
+fn delete_removes_a_file_and_404s_after() {
+    let root = TempDir::new();
+    root.write("dir/a.txt", "a");
+    let server = Server::start(&["--write", root.str()]);
+    let response = server.request("DELETE", "/dir/a.txt", &[], b"");
+    assert_eq!(response.status, 204);
+    assert!(!root.path().join("dir/a.txt").exists());
+    assert!(server.wait_for_output("/dir/a.txt as anonymous"), "{}", server.output());
+    assert_eq!(server.request("DELETE", "/dir/a.txt", &[], b"").status, 404);
+    assert_eq!(server.request("DELETE", "/never.txt", &[], b"").status, 404);
+    // The root is never deleted
+    assert_eq!(server.request("DELETE", "/", &[], b"").status, 403);
+}
+
+#[test]
+fn delete_of_a_directory_needs_it_empty() {
+    let root = TempDir::new();
+    root.write("full/a.txt", "a");
+    std::fs::create_dir(root.path().join("empty")).unwrap();
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(server.request("DELETE", "/full/", &[], b"").status, 409);
+    // Without --recursive-delete the query changes nothing
+    assert_eq!(server.request("DELETE", "/full/?recursive", &[], b"").status, 409);
+    assert!(root.path().join("full/a.txt").exists());
+    assert_eq!(server.request("DELETE", "/empty/", &[], b"").status, 204);
+    assert!(!root.path().join("empty").exists());
+    let server = Server::start(&["--write", "--recursive-delete", root.str()]);
+    assert_eq!(server.request("DELETE", "/full/", &[], b"").status, 409);
+    assert_eq!(server.request("DELETE", "/full/?recursive", &[], b"").status, 204);
+    assert!(!root.path().join("full").exists());
+}
+
+#[test]
+fn delete_honors_if_match() {
+    let root = TempDir::new();
+    root.write("doc.txt", "v1");
+    let server = Server::start(&["--write", root.str()]);
+    let old = server.get("/doc.txt").header("ETag").unwrap().to_string();
+    // Someone else replaced it in the meantime
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert_eq!(server.request("PUT", "/doc.txt", &[], b"version two").status, 204);
+    assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", &old)], b"").status, 412);
+    assert_eq!(std::fs::read_to_string(root.path().join("doc.txt")).unwrap(), "version two");
+    let new = server.get("/doc.txt").header("ETag").unwrap().to_string();
+    assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", &new)], b"").status, 204);
+}
+
+#[test]
+fn delete_without_write_or_of_excluded_names_is_refused() {
+    let root = TempDir::new();
+    root.write("a.txt", "a");
+    root.write(".secret", "s");
+    let server = Server::start(&[root.str()]);
+    assert_eq!(server.request("DELETE", "/a.txt", &[], b"").status, 405);
+    let server = Server::start(&["--write", "--exclude", ".*", root.str()]);
+    assert_eq!(server.request("DELETE", "/.secret", &[], b"").status, 404);
+    assert_eq!(server.request("DELETE", "/sub/../.secret", &[], b"").status, 404);
+    assert!(root.path().join("a.txt").exists() && root.path().join(".secret").exists());
+}
+
+#[test]
+fn mkcol_creates_one_directory() {
+    let root = TempDir::new();
+    root.write("file.txt", "f");
+    let server = Server::start(&["--write", root.str()]);
+    let response = server.request("MKCOL", "/new", &[], b"");
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("Location"), Some("/new/"));
+    assert!(root.path().join("new").is_dir());
+    // Only the last one, the ones leading to it have to be there
+    assert_eq!(server.request("MKCOL", "/missing/nested/", &[], b"").status, 409);
+    assert!(!root.path().join("missing").exists());
+    assert_eq!(server.request("MKCOL", "/file.txt/below", &[], b"").status, 409);
+    assert_eq!(server.request("MKCOL", "/new/nested", &[], b"").status, 201);
+}
+
+#[test]
+fn mkcol_of_what_exists_is_405() {
+    let root = TempDir::new();
+    root.write("file.txt", "f");
+    std::fs::create_dir(root.path().join("dir")).unwrap();
+    let server = Server::start(&["--write", root.str()]);
+    for path in ["/dir", "/dir/", "/file.txt"] {
+        let response = server.request("MKCOL", path, &[], b"");
+        assert_eq!(response.status, 405, "{path}");
+        assert!(response.header("Allow").is_some_and(|allow| !allow.contains("MKCOL")), "{path}");
+    }
+    assert_eq!(std::fs::read_to_string(root.path().join("file.txt")).unwrap(), "f");
+}
+
+#[test]
+fn mkcol_with_a_body_is_415() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    let response = server.request("MKCOL", "/new", &[("Content-Type", "text/xml")], b"<mkcol/>");
+    assert_eq!(response.status, 415);
+    assert!(!root.path().join("new").exists());
+}
+
+#[test]
+fn new_folder_form_makes_the_directory() {
+    let root = TempDir::new();
+    let server = Server::start(&["--write", root.str()]);
+    assert!(server.get("/").body.contains("value=\"mkdir\""));
+    let response = server.request("POST", "/", &[("Content-Type", "application/x-www-form-urlencoded")], b"action=mkdir&name=made+here");
+    assert_eq!(response.status, 303);
+    assert_eq!(response.header("Location"), Some("/"));
+    assert!(root.path().join("made here").is_dir());
+    // Taken already
+    let again = server.request("POST", "/", &[("Content-Type", "application/x-www-form-urlencoded")], b"action=mkdir&name=made+here");
+    assert_eq!(again.status, 409);
+}
+
+#[test]
+fn mkcol_needs_write_and_a_visible_name() {
+    let root = TempDir::new();
+    let server = Server::start(&[root.str()]);
+    assert_eq!(server.request("MKCOL", "/new", &[], b"").status, 404);
+    let server = Server::start(&["--write", "--exclude", ".*", root.str()]);
+    assert_eq!(server.request("MKCOL", "/.hidden", &[], b"").status, 404);
+    // Above the root is no path at all
+    assert_eq!(server.request("MKCOL", "/a/../../escape", &[], b"").status, 400);
+    assert!(!root.path().parent().unwrap().join("escape").exists());
+    assert_eq!(server.request("MKCOL", "/a/../inside", &[], b"").status, 201);
+    assert!(root.path().join("inside").is_dir());
+    assert!(!root.path().join(".hidden").exists());
+}
+
+// The <D:response> elements of a 207 body, by their href
+fn responses(body: &str) -> Vec<(String, String)> {
+    assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\">"), "{body}");
+    assert!(body.ends_with("</D:multistatus>"), "{body}");
+    let mut found: Vec<(String, String)> = body.split("<D:response>").skip(1)
+        .map(|response| {
+            assert!(response.ends_with("</D:response>") || response.ends_with("</D:response></D:multistatus>"), "{response}");
+            let href = response.split("<D:href>").nth(1).unwrap().split("</D:href>").next().unwrap();
+            (href.to_string(), response.to_string())
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+#[test]
+fn propfind_depth_0_and_1() {
+    let root = TempDir::new();
+    root.write("dir/a & b.txt", "12345");
+    root.write("dir/sub/x", "");
+    let server = Server::start(&[root.str()]);
+    let response = server.request("PROPFIND", "/dir", &[("Depth", "0")], b"");
+    assert_eq!(response.status, 207);
+    assert_eq!(response.header("Content-Type"), Some("application/xml; charset=utf-8"));
+    let found = responses(&response.body);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, "/dir/");
+    assert!(found[0].1.contains("<D:displayname>dir</D:displayname>"));
+    assert!(found[0].1.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(!found[0].1.contains("getcontentlength"));
+
+    let found = responses(&server.request("PROPFIND", "/dir/", &[("Depth", "1")], b"").body);
+    let hrefs: Vec<&str> = found.iter().map(|(href, _)| href.as_str()).collect();
+    assert_eq!(hrefs, ["/dir/", "/dir/a%20%26%20b.txt", "/dir/sub/"]);
+    let file = &found[1].1;
+    assert!(file.contains("<D:displayname>a &amp; b.txt</D:displayname>"), "{file}");
+    assert!(file.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(file.contains("<D:resourcetype></D:resourcetype>"));
+    assert!(file.contains("<D:getlastmodified>") && file.contains(" GMT</D:getlastmodified>"));
+    let etag = server.get("/dir/a%20%26%20b.txt").header("ETag").unwrap().to_string();
+    assert!(file.contains(&format!("<D:getetag>{}</D:getetag>", etag.replace('"', "&quot;"))), "{file}");
+}
+
+#[test]
+fn propfind_of_named_props() {
+    let root = TempDir::new();
+    root.write("a.txt", "abc");
+    let server = Server::start(&[root.str()]);
+    let body = br#"<?xml version="1.0"?><propfind xmlns="DAV:"><prop><getcontentlength/><quota-used-bytes/></prop></propfind>"#;
+    let response = server.request("PROPFIND", "/a.txt", &[("Depth", "0"), ("Content-Type", "application/xml")], body);
+    assert_eq!(response.status, 207);
+    let found = responses(&response.body);
+    assert_eq!(found[0].1, "<D:href>/a.txt</D:href>\
+        <D:propstat><D:prop><D:getcontentlength>3</D:getcontentlength></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>\
+        <D:propstat><D:prop><D:quota-used-bytes/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat></D:response></D:multistatus>");
+    let names = server.request("PROPFIND", "/a.txt", &[("Depth", "0")], br#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#);
+    assert!(names.body.contains("<D:prop><D:displayname/><D:getcontentlength/><D:getlastmodified/><D:resourcetype/><D:getetag/></D:prop>"), "{}", names.body);
+}
+
+#[test]
+fn propfind_refusals() {
+    let root = TempDir::new();
+    root.write("dir/a.txt", "a");
+    root.write(".hidden", "h");
+    let server = Server::start(&["--exclude", ".*", root.str()]);
+    // Without Depth it would be the whole tree
+    assert_eq!(server.request("PROPFIND", "/dir/", &[], b"").status, 403);
+    assert_eq!(server.request("PROPFIND", "/dir/", &[("Depth", "infinity")], b"").status, 403);
+    assert_eq!(server.request("PROPFIND", "/dir/", &[("Depth", "0")], b"<propfind").status, 400);
+    assert_eq!(server.request("PROPFIND", "/missing", &[("Depth", "0")], b"").status, 404);
+    assert_eq!(server.request("PROPFIND", "/.hidden", &[("Depth", "0")], b"").status, 404);
+    let found = responses(&server.request("PROPFIND", "/", &[("Depth", "1")], b"").body);
+    assert_eq!(found.iter().map(|(href, _)| href.as_str()).collect::<Vec<_>>(), ["/", "/dir/"]);
+}
+
+fn transfer(server: &Server, method: &str, from: &str, to: &str, overwrite: Option<&str>) -> u16 {
+    let mut headers = vec![("Destination", to)];
+    headers.extend(overwrite.map(|value| ("Overwrite", value)));
+    server.request(method, from, &headers, b"").status
+}
+
+#[test]
+fn move_renames_within_and_across_directories() {
+    let root = TempDir::new();
+    root.write("a/one.txt", "1");
+    root.write("b/.keep", "");
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(transfer(&server, "MOVE", "/a/one.txt", "/a/two.txt", None), 201);
+    assert!(!root.path().join("a/one.txt").exists());
+    assert_eq!(server.get("/a/two.txt").body, "1");
+    // As a full URL of this server, into another directory
+    assert_eq!(transfer(&server, "MOVE", "/a/two.txt", "http://localhost/b/three%20.txt", None), 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("b/three .txt")).unwrap(), "1");
+    // A whole directory
+    assert_eq!(transfer(&server, "MOVE", "/b/", "/a/b", None), 201);
+    assert!(root.path().join("a/b/three .txt").exists() && !root.path().join("b").exists());
+}
+
+#[test]
+fn copy_keeps_the_source() {
+    let root = TempDir::new();
+    root.write("dir/a.txt", "a");
+    root.write("dir/sub/b.txt", "b");
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(transfer(&server, "COPY", "/dir/a.txt", "/copy.txt", None), 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("copy.txt")).unwrap(), "a");
+    assert!(root.path().join("dir/a.txt").exists());
+    assert_eq!(transfer(&server, "COPY", "/dir", "/deep", None), 201);
+    assert_eq!(std::fs::read_to_string(root.path().join("deep/sub/b.txt")).unwrap(), "b");
+    // Depth 0 is the directory alone
+    assert_eq!(server.request("COPY", "/dir", &[("Destination", "/shallow"), ("Depth", "0")], b"").status, 201);
+    assert_eq!(std::fs::read_dir(root.path().join("shallow")).unwrap().count(), 0);
+    assert_eq!(server.request("COPY", "/dir", &[("Destination", "/other"), ("Depth", "1")], b"").status, 400);
+    assert_eq!(server.request("MOVE", "/dir", &[("Destination", "/other"), ("Depth", "0")], b"").status, 400);
+}
+
+#[test]
+fn overwrite_matrix() {
+    for method in ["MOVE", "COPY"] {
+        let root = TempDir::new();
+        root.write("src.txt", "new");
+        root.write("dst.txt", "old");
+        let server = Server::start(&["--write", root.str()]);
+        assert_eq!(transfer(&server, method, "/src.txt", "/dst.txt", Some("F")), 412, "{method}");
+        assert_eq!(std::fs::read_to_string(root.path().join("dst.txt")).unwrap(), "old");
+        assert_eq!(transfer(&server, method, "/src.txt", "/dst.txt", Some("X")), 400, "{method}");
+        assert_eq!(transfer(&server, method, "/src.txt", "/dst.txt", Some("T")), 204, "{method}");
+        assert_eq!(std::fs::read_to_string(root.path().join("dst.txt")).unwrap(), "new");
+        assert_eq!(root.path().join("src.txt").exists(), method == "COPY");
+        root.write("src.txt", "newer");
+        // Without the header it's T
+        assert_eq!(transfer(&server, method, "/src.txt", "/dst.txt", None), 204, "{method}");
+        assert_eq!(std::fs::read_to_string(root.path().join("dst.txt")).unwrap(), "newer");
+        // Overwrite F to a free name is fine
+        root.write("src.txt", "free");
+        assert_eq!(transfer(&server, method, "/src.txt", "/free.txt", Some("F")), 201, "{method}");
+    }
+}
+
+#[test]
+fn overwriting_a_directory_needs_recursive_delete() {
+    let root = TempDir::new();
+    root.write("src.txt", "s");
+    root.write("dst/inside.txt", "i");
+    let server = Server::start(&["--write", root.str()]);
+    assert_eq!(transfer(&server, "COPY", "/src.txt", "/dst", Some("T")), 403);
+    assert!(root.path().join("dst/inside.txt").exists());
+    let server = Server::start(&["--write", "--recursive-delete", root.str()]);
+    assert_eq!(transfer(&server, "COPY", "/src.txt", "/dst", Some("T")), 204);
+    assert_eq!(std::fs::read_to_string(root.path().join("dst")).unwrap(), "s");
+}
+
+#[test]
+fn bad_destinations() {
+    let root = TempDir::new();
+    root.write("dir/a.txt", "a");
+    root.write(".hidden", "h");
+    let server = Server::start(&["--write", "--exclude", ".*", root.str()]);
+    assert_eq!(server.request("MOVE", "/dir/a.txt", &[], b"").status, 400);
+    assert_eq!(transfer(&server, "MOVE", "/dir/a.txt", "http://elsewhere.com/a.txt", None), 502);
+    assert_eq!(transfer(&server, "MOVE", "/dir/a.txt", "/../outside.txt", None), 400);
+    assert_eq!(transfer(&server, "MOVE", "/dir/a.txt", "/dir/a.txt", None), 403);
+    assert_eq!(transfer(&server, "COPY", "/dir", "/dir/inner", None), 403);
+    assert_eq!(transfer(&server, "MOVE", "/dir/a.txt", "/.sneaky", None), 403);
+    assert_eq!(transfer(&server, "MOVE", "/.hidden", "/visible", None), 404);
+    assert_eq!(transfer(&server, "MOVE", "/dir/a.txt", "/missing/a.txt", None), 409);
+    assert_eq!(transfer(&server, "MOVE", "/missing.txt", "/b.txt", None), 404);
+    assert!(root.path().join("dir/a.txt").exists());
+}
@@ -0,0 +1,78 @@
+// --webhook against a receiver inside the test
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use common::{Server, TempDir};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+struct Delivery {
+    head: String,
+    body: String,
+}
+
+// Answers every POST with the status and hands over what came
+fn receiver(status: u16) -> (String, mpsc::Receiver<Delivery>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, deliveries) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") && reader.read_line(&mut head).unwrap() > 0 {}
+            let length = head.lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = reader.get_mut().write_all(format!("HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n").as_bytes());
+            let _ = sender.send(Delivery { head, body: String::from_utf8(body).unwrap() });
+        }
+    });
+    (url, deliveries)
+}
+
+fn event(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn changes_are_posted_with_a_signature() {
+    let root = TempDir::new();
+    let (url, deliveries) = receiver(200);
+    let server = Server::start(&[root.str(), "--write", "--webhook", &url, "--webhook-secret", "shh"]);
+    assert_eq!(server.request("PUT", "/new.txt", &[], b"hello").status, 201);
+    let put = deliveries.recv_timeout(Duration::from_secs(10)).unwrap();
+    let body = event(&put.body);
+    assert_eq!((body["event"].as_str(), body["path"].as_str(), body["size"].as_u64()), (Some("put"), Some("/new.txt"), Some(5)));
+    assert_eq!(body["client"], "127.0.0.1");
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"shh").unwrap();
+    mac.update(put.body.as_bytes());
+    let expected: String = mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+    assert!(put.head.contains(&format!("X-Hub-Signature-256: sha256={expected}\r\n")), "{}", put.head);
+
+    assert_eq!(server.request("DELETE", "/new.txt", &[], b"").status, 204);
+    let delete = deliveries.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(event(&delete.body)["event"], "delete");
+}
+
+#[test]
+fn deliveries_given_up_on_are_counted() {
+    let root = TempDir::new();
+    let (url, deliveries) = receiver(500);
+    let server = Server::start(&[root.str(), "--write", "--metrics", "--webhook", &url]);
+    assert_eq!(server.request("PUT", "/new.txt", &[], b"hello").status, 201);
+    // Every attempt, with the backoff in between
+    for _ in 0..3 {
+        deliveries.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+    assert!(server.wait_for_output("giving up"));
+    let metrics = server.get("/metrics").body;
+    assert!(metrics.contains("httpserver_webhook_failed_total 1\n"), "{metrics}");
+    assert!(metrics.contains("httpserver_webhook_dropped_total 0\n"), "{metrics}");
+}